@@ -0,0 +1,262 @@
+use crate::block_log::log_upsert;
+use crate::maintenance::ensure_writable;
+use crate::paging::{page_sorted, Page};
+use crate::terms::ensure_terms_accepted;
+use crate::tickets::{
+    open_ticket, tickets_matching, tickets_page, validate_ticket_text, MaintenanceTicket,
+    TicketSource, TicketStatus,
+};
+use crate::{ensure_green_space_exists, get_memory, next_id, Error, Memory};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const ACCESSIBILITY_AUDITS_MEMORY_ID: u8 = 8;
+
+const MAX_AUDITOR_LEN: usize = 100;
+const MAX_CHECKLIST_ITEMS: usize = 50;
+const MAX_CHECKLIST_ITEM_LEN: usize = 100;
+const MAX_REMEDIATION_ITEMS: usize = 20;
+// Checklist items are scored from 0 (inaccessible) to MAX_ITEM_SCORE (fully accessible)
+const MAX_ITEM_SCORE: u8 = 5;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum AccessibilityGrade {
+    A,
+    B,
+    C,
+    D,
+    F,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ChecklistScore {
+    item: String,
+    score: u8,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AccessibilityAudit {
+    id: u64,
+    pub(crate) space_id: u64,
+    auditor: String,
+    audited_at: u64,
+    checklist: Vec<ChecklistScore>,
+    // Maintenance tickets opened for the remediation items of this audit
+    remediation_ticket_ids: Vec<u64>,
+    score_percent: u8,
+    grade: AccessibilityGrade,
+    created_at: u64,
+}
+
+impl_storable!(AccessibilityAudit, 8192);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RemediationItem {
+    title: String,
+    description: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct AccessibilityAuditPayload {
+    space_id: u64,
+    auditor: String,
+    // Date the audit was carried out, in nanoseconds since the epoch
+    audited_at: u64,
+    checklist: Vec<ChecklistScore>,
+    remediation_items: Vec<RemediationItem>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct AccessibilityGradeSummary {
+    space_id: u64,
+    audit_id: u64,
+    audited_at: u64,
+    score_percent: u8,
+    grade: AccessibilityGrade,
+    unresolved_remediation_items: u64,
+}
+
+thread_local! {
+    pub(crate) static ACCESSIBILITY_AUDITS: RefCell<StableBTreeMap<u64, AccessibilityAudit, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(ACCESSIBILITY_AUDITS_MEMORY_ID)));
+}
+
+fn validate_audit(payload: &AccessibilityAuditPayload) -> Result<(), Error> {
+    if payload.auditor.trim().is_empty() || payload.auditor.len() > MAX_AUDITOR_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Auditor must be 1-{} characters", MAX_AUDITOR_LEN),
+        });
+    }
+    if payload.checklist.is_empty() || payload.checklist.len() > MAX_CHECKLIST_ITEMS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "An audit must score between 1 and {} checklist items",
+                MAX_CHECKLIST_ITEMS
+            ),
+        });
+    }
+    for entry in &payload.checklist {
+        if entry.item.trim().is_empty() || entry.item.len() > MAX_CHECKLIST_ITEM_LEN {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "Checklist item names must be 1-{} characters",
+                    MAX_CHECKLIST_ITEM_LEN
+                ),
+            });
+        }
+        if entry.score > MAX_ITEM_SCORE {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "Checklist item '{}' scored {}, the maximum is {}",
+                    entry.item, entry.score, MAX_ITEM_SCORE
+                ),
+            });
+        }
+    }
+    if payload.remediation_items.len() > MAX_REMEDIATION_ITEMS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "An audit can list at most {} remediation items",
+                MAX_REMEDIATION_ITEMS
+            ),
+        });
+    }
+    for item in &payload.remediation_items {
+        validate_ticket_text(&item.title, &item.description)?;
+    }
+    Ok(())
+}
+
+fn score_percent(checklist: &[ChecklistScore]) -> u8 {
+    let total: u64 = checklist.iter().map(|entry| entry.score as u64).sum();
+    let max = checklist.len() as u64 * MAX_ITEM_SCORE as u64;
+    (total * 100 / max) as u8
+}
+
+fn grade_for(score_percent: u8) -> AccessibilityGrade {
+    match score_percent {
+        90..=100 => AccessibilityGrade::A,
+        75..=89 => AccessibilityGrade::B,
+        60..=74 => AccessibilityGrade::C,
+        40..=59 => AccessibilityGrade::D,
+        _ => AccessibilityGrade::F,
+    }
+}
+
+fn is_unresolved_remediation(ticket: &MaintenanceTicket, space_id: u64) -> bool {
+    ticket.space_id == space_id
+        && ticket.status != TicketStatus::Resolved
+        && matches!(ticket.source, TicketSource::AccessibilityAudit { .. })
+}
+
+fn latest_audit_for_space(space_id: u64) -> Option<AccessibilityAudit> {
+    ACCESSIBILITY_AUDITS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, audit)| audit)
+            .filter(|audit| audit.space_id == space_id)
+            .max_by_key(|audit| audit.audited_at)
+    })
+}
+
+// Function to record an accessibility audit; every remediation item becomes a
+// maintenance ticket so it is tracked until resolved
+#[ic_cdk::update]
+fn add_accessibility_audit(
+    payload: AccessibilityAuditPayload,
+) -> Result<AccessibilityAudit, Error> {
+    ensure_writable()?;
+    ensure_terms_accepted()?;
+    ensure_green_space_exists(payload.space_id)?;
+    validate_audit(&payload)?;
+
+    let id = next_id(ACCESSIBILITY_AUDITS_MEMORY_ID);
+    let remediation_ticket_ids = payload
+        .remediation_items
+        .into_iter()
+        .map(|item| {
+            open_ticket(
+                payload.space_id,
+                item.title,
+                item.description,
+                TicketSource::AccessibilityAudit { audit_id: id },
+            )
+            .id
+        })
+        .collect();
+
+    let score_percent = score_percent(&payload.checklist);
+    let audit = AccessibilityAudit {
+        id,
+        space_id: payload.space_id,
+        auditor: payload.auditor,
+        audited_at: payload.audited_at,
+        checklist: payload.checklist,
+        remediation_ticket_ids,
+        score_percent,
+        grade: grade_for(score_percent),
+        created_at: time(),
+    };
+
+    ACCESSIBILITY_AUDITS.with(|s| s.borrow_mut().insert(audit.id, audit.clone()));
+    log_upsert("accessibility_audit", &audit.id, &audit);
+    Ok(audit)
+}
+
+#[ic_cdk::query]
+fn get_accessibility_audit(id: u64) -> Result<AccessibilityAudit, Error> {
+    ACCESSIBILITY_AUDITS
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("An accessibility audit with id={} not found", id),
+        })
+}
+
+#[ic_cdk::query]
+fn get_accessibility_audits_for_space(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<AccessibilityAudit>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let mut result: Vec<_> = ACCESSIBILITY_AUDITS.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, audit)| audit.space_id == space_id)
+            .map(|(id, audit)| ((audit.audited_at, id), audit))
+            .collect()
+    });
+    result.sort_by_key(|(key, _)| *key);
+    page_sorted(result, &next_token)
+}
+
+// Function to get the grade from the most recent audit of a space
+#[ic_cdk::query]
+fn get_accessibility_grade(space_id: u64) -> Result<AccessibilityGradeSummary, Error> {
+    ensure_green_space_exists(space_id)?;
+    let audit = latest_audit_for_space(space_id).ok_or_else(|| Error::NotFound {
+        msg: format!("The green space with id={} has not been audited", space_id),
+    })?;
+
+    Ok(AccessibilityGradeSummary {
+        space_id,
+        audit_id: audit.id,
+        audited_at: audit.audited_at,
+        score_percent: audit.score_percent,
+        grade: audit.grade,
+        unresolved_remediation_items: tickets_matching(|t| is_unresolved_remediation(t, space_id))
+            .len() as u64,
+    })
+}
+
+// Function to list the remediation tickets of a space that are still open
+#[ic_cdk::query]
+fn get_unresolved_remediation_items(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<MaintenanceTicket>, Error> {
+    ensure_green_space_exists(space_id)?;
+    tickets_page(&next_token, |ticket| {
+        is_unresolved_remediation(ticket, space_id)
+    })
+}
@@ -0,0 +1,214 @@
+use crate::block_log::log_upsert;
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::snapshot::sha256_hex;
+use crate::{ensure_controller, get_memory, next_id, Error, Memory};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::time;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+const API_KEYS_MEMORY_ID: u8 = 60;
+const API_KEY_HASHES_MEMORY_ID: u8 = 61;
+
+const MAX_API_KEY_NAME_LEN: usize = 100;
+const MAX_REQUESTS_PER_MINUTE: u32 = 10_000;
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+
+type KeyHash = Blob<32>;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum ApiScope {
+    // The JSON read endpoints
+    Read,
+    // Reporting issues, which open maintenance tickets
+    WriteIssues,
+}
+
+// A key a third-party app sends in the X-API-Key header; only a hash of the
+// secret is kept
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ApiKey {
+    id: u64,
+    // Who the key was issued to, e.g. the name of the app
+    name: String,
+    scopes: Vec<ApiScope>,
+    max_requests_per_minute: u32,
+    created_at: u64,
+    revoked_at: Option<u64>,
+    request_count: u64,
+    throttled_count: u64,
+    last_used_at: Option<u64>,
+    // Requests counted toward the limit since the start of the current minute
+    window_started_at: u64,
+    window_requests: u32,
+}
+
+impl_storable!(ApiKey, 1024);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct IssuedApiKey {
+    key: ApiKey,
+    // Shown once; it cannot be recovered later
+    secret: String,
+}
+
+// Why a request with an API key was turned away
+pub(crate) enum ApiKeyRejection {
+    Unknown,
+    Revoked,
+    MissingScope,
+    Throttled,
+}
+
+thread_local! {
+    pub(crate) static API_KEYS: RefCell<StableBTreeMap<u64, ApiKey, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(API_KEYS_MEMORY_ID)));
+
+    // Key ids by the SHA-256 of their secret
+    pub(crate) static API_KEY_HASHES: RefCell<StableBTreeMap<KeyHash, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(API_KEY_HASHES_MEMORY_ID)));
+}
+
+fn key_hash(secret: &str) -> KeyHash {
+    Blob::try_from(Sha256::digest(secret.as_bytes()).as_slice()).unwrap()
+}
+
+fn api_key(id: u64) -> Result<ApiKey, Error> {
+    API_KEYS
+        .with(|k| k.borrow().get(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("An API key with id={} not found", id),
+        })
+}
+
+// The key of a secret, unless it is unknown, revoked or lacks the scope
+fn valid_api_key(secret: &str, scope: ApiScope) -> Result<ApiKey, ApiKeyRejection> {
+    let id = API_KEY_HASHES
+        .with(|h| h.borrow().get(&key_hash(secret)))
+        .ok_or(ApiKeyRejection::Unknown)?;
+    let key = api_key(id).map_err(|_| ApiKeyRejection::Unknown)?;
+    if key.revoked_at.is_some() {
+        return Err(ApiKeyRejection::Revoked);
+    }
+    if !key.scopes.contains(&scope) {
+        return Err(ApiKeyRejection::MissingScope);
+    }
+    Ok(key)
+}
+
+// Checks a secret against a scope without counting the request, for reads
+// served while writes are frozen; returns the key id
+pub(crate) fn check_api_key(secret: &str, scope: ApiScope) -> Result<u64, ApiKeyRejection> {
+    valid_api_key(secret, scope).map(|key| key.id)
+}
+
+// Checks a secret against a scope and counts the request toward the usage
+// and the per-minute limit of its key; returns the key id
+pub(crate) fn use_api_key(secret: &str, scope: ApiScope) -> Result<u64, ApiKeyRejection> {
+    let mut key = valid_api_key(secret, scope)?;
+    let id = key.id;
+    let now = time();
+    if now.saturating_sub(key.window_started_at) >= NANOS_PER_MINUTE {
+        key.window_started_at = now;
+        key.window_requests = 0;
+    }
+    let result = if key.window_requests >= key.max_requests_per_minute {
+        key.throttled_count += 1;
+        Err(ApiKeyRejection::Throttled)
+    } else {
+        key.window_requests += 1;
+        key.request_count += 1;
+        key.last_used_at = Some(now);
+        Ok(id)
+    };
+    // Usage counters are metering, not a change to the key; they are not logged
+    API_KEYS.with(|k| k.borrow_mut().insert(id, key));
+    result
+}
+
+// Function for controllers to issue an API key for a third-party app
+#[ic_cdk::update]
+async fn issue_api_key(
+    name: String,
+    scopes: Vec<ApiScope>,
+    max_requests_per_minute: u32,
+) -> Result<IssuedApiKey, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let name = name.trim().to_string();
+    if name.is_empty() || name.len() > MAX_API_KEY_NAME_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "API key names must be 1-{} characters",
+                MAX_API_KEY_NAME_LEN
+            ),
+        });
+    }
+    if scopes.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "An API key needs at least one scope".to_string(),
+        });
+    }
+    if max_requests_per_minute == 0 || max_requests_per_minute > MAX_REQUESTS_PER_MINUTE {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The request limit must be between 1 and {} per minute",
+                MAX_REQUESTS_PER_MINUTE
+            ),
+        });
+    }
+    let (random,) = raw_rand()
+        .await
+        .map_err(|(code, msg)| Error::OutcallFailed {
+            msg: format!("Cannot get randomness: {:?} {}", code, msg),
+        })?;
+    let secret = sha256_hex(&random);
+    let mut unique_scopes = Vec::new();
+    for scope in scopes {
+        if !unique_scopes.contains(&scope) {
+            unique_scopes.push(scope);
+        }
+    }
+    let key = ApiKey {
+        id: next_id(API_KEYS_MEMORY_ID),
+        name,
+        scopes: unique_scopes,
+        max_requests_per_minute,
+        created_at: time(),
+        revoked_at: None,
+        request_count: 0,
+        throttled_count: 0,
+        last_used_at: None,
+        window_started_at: 0,
+        window_requests: 0,
+    };
+    API_KEYS.with(|k| k.borrow_mut().insert(key.id, key.clone()));
+    log_upsert("api_key", &key.id, &key);
+    API_KEY_HASHES.with(|h| h.borrow_mut().insert(key_hash(&secret), key.id));
+    Ok(IssuedApiKey { key, secret })
+}
+
+// Function to revoke an API key; its usage counters are kept
+#[ic_cdk::update]
+fn revoke_api_key(id: u64) -> Result<ApiKey, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut key = api_key(id)?;
+    if key.revoked_at.is_none() {
+        key.revoked_at = Some(time());
+        API_KEYS.with(|k| k.borrow_mut().insert(id, key.clone()));
+        log_upsert("api_key", &id, &key);
+    }
+    Ok(key)
+}
+
+// Function to list the issued API keys with their usage
+#[ic_cdk::query]
+fn get_api_keys(next_token: Option<String>) -> Result<Page<ApiKey>, Error> {
+    ensure_controller()?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(API_KEYS.with(|k| page_by_size(k.borrow().range(range))))
+}
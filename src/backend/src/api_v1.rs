@@ -0,0 +1,142 @@
+use crate::geo::Coordinates;
+use crate::spatial_index::spaces_around;
+use crate::stats::category_counts;
+use crate::{_get_green_space, Error, GreenSpace, GreenSpaceStatus};
+
+// The interface other canisters can rely on, described by
+// green_space_api_v1.did: methods prefixed v1_ keep their names, arguments and
+// results for as long as version 1 is served, whatever happens to the rest of
+// the interface. Its types are its own, so changes to the stored records do
+// not reach callers, and they only ever gain optional fields
+const API_VERSION: &str = "1.0.0";
+
+const MAX_NEARBY_LIMIT: u32 = 100;
+const MAX_NEARBY_RADIUS_M: f64 = 50_000.0;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum ApiErrorV1 {
+    NotFound { msg: String },
+    InvalidInput { msg: String },
+    // Any other failure; retrying later may help
+    Unavailable { msg: String },
+}
+
+impl From<Error> for ApiErrorV1 {
+    fn from(error: Error) -> Self {
+        let msg = error.message().to_string();
+        match error {
+            Error::NotFound { .. } => ApiErrorV1::NotFound { msg },
+            Error::InvalidInput { .. } | Error::InvalidGeometry { .. } => {
+                ApiErrorV1::InvalidInput { msg }
+            }
+            _ => ApiErrorV1::Unavailable { msg },
+        }
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct GreenSpaceV1 {
+    id: u64,
+    ulid: Option<String>,
+    name: String,
+    location: String,
+    description: String,
+    lat: Option<f64>,
+    lng: Option<f64>,
+    // A name from v1_list_categories; text rather than a variant, so new
+    // categories do not break decoding for existing callers
+    category: Option<String>,
+    city_id: Option<u64>,
+    district_id: Option<u64>,
+}
+
+impl From<GreenSpace> for GreenSpaceV1 {
+    fn from(space: GreenSpace) -> Self {
+        GreenSpaceV1 {
+            id: space.id,
+            ulid: space.ulid,
+            name: space.name,
+            location: space.location,
+            description: space.description,
+            lat: space.coordinates.map(|c| c.lat),
+            lng: space.coordinates.map(|c| c.lng),
+            category: space.category.map(|category| format!("{:?}", category)),
+            city_id: space.city_id,
+            district_id: space.district_id,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct NearbyGreenSpaceV1 {
+    space: GreenSpaceV1,
+    distance_m: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CategoryV1 {
+    name: String,
+    space_count: u64,
+}
+
+// Archived spaces are history rather than places to visit
+fn is_listed(space: &GreenSpace) -> bool {
+    space.status != Some(GreenSpaceStatus::Archived)
+}
+
+#[ic_cdk::query]
+fn v1_api_version() -> String {
+    API_VERSION.to_string()
+}
+
+#[ic_cdk::query]
+fn v1_get_green_space(id: u64) -> Result<GreenSpaceV1, ApiErrorV1> {
+    _get_green_space(&id)
+        .filter(is_listed)
+        .map(GreenSpaceV1::from)
+        .ok_or_else(|| ApiErrorV1::NotFound {
+            msg: format!("A green space with id={} not found", id),
+        })
+}
+
+// Up to `limit` spaces within a radius of a point, nearest first
+#[ic_cdk::query]
+fn v1_search_nearby(
+    lat: f64,
+    lng: f64,
+    radius_m: f64,
+    limit: u32,
+) -> Result<Vec<NearbyGreenSpaceV1>, ApiErrorV1> {
+    let point = Coordinates { lat, lng };
+    point.validate()?;
+    if !radius_m.is_finite() || radius_m <= 0.0 || radius_m > MAX_NEARBY_RADIUS_M {
+        return Err(ApiErrorV1::InvalidInput {
+            msg: format!(
+                "The radius must be positive and at most {} m",
+                MAX_NEARBY_RADIUS_M
+            ),
+        });
+    }
+    if limit == 0 || limit > MAX_NEARBY_LIMIT {
+        return Err(ApiErrorV1::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_NEARBY_LIMIT),
+        });
+    }
+    Ok(spaces_around(&point, radius_m, None)
+        .into_iter()
+        .filter(|nearby| nearby.distance_m <= radius_m && is_listed(&nearby.space))
+        .take(limit as usize)
+        .map(|nearby| NearbyGreenSpaceV1 {
+            distance_m: nearby.distance_m,
+            space: nearby.space.into(),
+        })
+        .collect())
+}
+
+#[ic_cdk::query]
+fn v1_list_categories() -> Vec<CategoryV1> {
+    category_counts()
+        .into_iter()
+        .map(|(name, space_count)| CategoryV1 { name, space_count })
+        .collect()
+}
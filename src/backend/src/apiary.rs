@@ -0,0 +1,308 @@
+use crate::block_log::log_upsert;
+use crate::maintenance::ensure_writable;
+use crate::notifications::{notify, NotificationKind};
+use crate::paging::{keys_after_token, page_by_size, page_sorted, Page};
+use crate::{
+    _get_green_space, authenticated_caller, caller, ensure_controller, ensure_green_space_exists,
+    ensure_space_admin, get_memory, next_id, Error, GreenSpace, Memory, NANOS_PER_DAY,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const APIARIES_MEMORY_ID: u8 = 16;
+
+const MAX_NOTES_LEN: usize = 300;
+const MAX_INSPECTION_INTERVAL_DAYS: u32 = 3650;
+// Keepers are reminded again at most once per this many days
+const REMINDER_INTERVAL_DAYS: u64 = 7;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Apiary {
+    id: u64,
+    pub(crate) space_id: u64,
+    // None once the keeper's data was erased; only admins of the space manage
+    // the apiary then
+    keeper: Option<Principal>,
+    hive_count: u32,
+    inspection_interval_days: u32,
+    last_inspected_at: Option<u64>,
+    last_inspection_notes: String,
+    last_reminder_at: Option<u64>,
+    active: bool,
+    registered_at: u64,
+}
+
+impl_storable!(Apiary, 1024);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ApiaryPayload {
+    space_id: u64,
+    hive_count: u32,
+    inspection_interval_days: u32,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct DueApiaryInspection {
+    apiary: Apiary,
+    due_at: u64,
+}
+
+thread_local! {
+    pub(crate) static APIARIES: RefCell<StableBTreeMap<u64, Apiary, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(APIARIES_MEMORY_ID)));
+}
+
+// Removes a user as keeper, leaving the apiaries to the admins of their
+// spaces; returns the number of apiaries changed
+pub(crate) fn anonymize_keeper(user: &Principal) -> u64 {
+    let kept: Vec<Apiary> = APIARIES.with(|a| {
+        a.borrow()
+            .iter()
+            .map(|(_, apiary)| apiary)
+            .filter(|apiary| apiary.keeper == Some(*user))
+            .collect()
+    });
+    for apiary in kept.iter().cloned() {
+        do_insert_apiary(&Apiary {
+            keeper: None,
+            ..apiary
+        });
+    }
+    kept.len() as u64
+}
+
+impl Apiary {
+    // Hives that were never inspected are due one interval after registration
+    fn next_inspection_due_at(&self) -> u64 {
+        let interval = (self.inspection_interval_days as u64).saturating_mul(NANOS_PER_DAY);
+        self.last_inspected_at
+            .unwrap_or(self.registered_at)
+            .saturating_add(interval)
+    }
+}
+
+fn apiary_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("An apiary with id={} not found", id),
+    }
+}
+
+fn do_insert_apiary(apiary: &Apiary) {
+    APIARIES.with(|s| s.borrow_mut().insert(apiary.id, apiary.clone()));
+    log_upsert("apiary", &apiary.id, apiary);
+}
+
+// Loads an apiary kept by the caller or in a space the caller administers;
+// the anonymous principal keeps none, even of apiaries registered before
+// keepers had to sign in
+fn get_kept_apiary(id: u64) -> Result<Apiary, Error> {
+    let apiary = APIARIES
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| apiary_not_found(id))?;
+    let caller = caller();
+    let is_keeper = caller != Principal::anonymous() && apiary.keeper == Some(caller);
+    if !is_keeper && ensure_space_admin(apiary.space_id).is_err() {
+        return Err(Error::Unauthorized {
+            msg: format!("Caller is not the keeper of apiary id={}", id),
+        });
+    }
+    Ok(apiary)
+}
+
+fn due_apiaries(now: u64, keeper: Option<Principal>) -> Vec<DueApiaryInspection> {
+    APIARIES.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, apiary)| apiary)
+            .filter(|apiary| apiary.active && keeper.is_none_or(|k| apiary.keeper == Some(k)))
+            .filter_map(|apiary| {
+                let due_at = apiary.next_inspection_due_at();
+                (due_at <= now).then_some(DueApiaryInspection { apiary, due_at })
+            })
+            .collect()
+    })
+}
+
+fn validate_apiary(hive_count: u32, inspection_interval_days: u32) -> Result<(), Error> {
+    if hive_count == 0 {
+        return Err(Error::InvalidInput {
+            msg: "An apiary must have at least one hive".to_string(),
+        });
+    }
+    if inspection_interval_days == 0 || inspection_interval_days > MAX_INSPECTION_INTERVAL_DAYS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Inspection interval must be 1-{} days",
+                MAX_INSPECTION_INTERVAL_DAYS
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Function for admins of a green space to register hives in it; the caller
+// becomes their keeper
+#[ic_cdk::update]
+fn register_apiary(payload: ApiaryPayload) -> Result<Apiary, Error> {
+    ensure_writable()?;
+    let keeper = authenticated_caller()?;
+    ensure_space_admin(payload.space_id)?;
+    validate_apiary(payload.hive_count, payload.inspection_interval_days)?;
+
+    let apiary = Apiary {
+        id: next_id(APIARIES_MEMORY_ID),
+        space_id: payload.space_id,
+        keeper: Some(keeper),
+        hive_count: payload.hive_count,
+        inspection_interval_days: payload.inspection_interval_days,
+        last_inspected_at: None,
+        last_inspection_notes: String::new(),
+        last_reminder_at: None,
+        active: true,
+        registered_at: time(),
+    };
+
+    do_insert_apiary(&apiary);
+    Ok(apiary)
+}
+
+#[ic_cdk::query]
+fn get_apiary(id: u64) -> Result<Apiary, Error> {
+    APIARIES
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| apiary_not_found(id))
+}
+
+#[ic_cdk::query]
+fn get_apiaries_for_space(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<Apiary>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(APIARIES.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, apiary)| apiary.space_id == space_id),
+        )
+    }))
+}
+
+// Function for a keeper to change the number of hives or the inspection interval
+#[ic_cdk::update]
+fn update_apiary(id: u64, hive_count: u32, inspection_interval_days: u32) -> Result<Apiary, Error> {
+    ensure_writable()?;
+    let mut apiary = get_kept_apiary(id)?;
+    validate_apiary(hive_count, inspection_interval_days)?;
+
+    apiary.hive_count = hive_count;
+    apiary.inspection_interval_days = inspection_interval_days;
+    do_insert_apiary(&apiary);
+    Ok(apiary)
+}
+
+// Function for a keeper to log an inspection of their hives
+#[ic_cdk::update]
+fn record_apiary_inspection(id: u64, notes: String) -> Result<Apiary, Error> {
+    ensure_writable()?;
+    let mut apiary = get_kept_apiary(id)?;
+    if notes.len() > MAX_NOTES_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Inspection notes must be at most {} characters",
+                MAX_NOTES_LEN
+            ),
+        });
+    }
+
+    apiary.last_inspected_at = Some(time());
+    apiary.last_inspection_notes = notes;
+    apiary.last_reminder_at = None;
+    do_insert_apiary(&apiary);
+    Ok(apiary)
+}
+
+// Function for a keeper to mark their hives as removed from the space
+#[ic_cdk::update]
+fn retire_apiary(id: u64) -> Result<Apiary, Error> {
+    ensure_writable()?;
+    let mut apiary = get_kept_apiary(id)?;
+    apiary.active = false;
+    do_insert_apiary(&apiary);
+    Ok(apiary)
+}
+
+// Function to list the green spaces that currently host active hives
+#[ic_cdk::query]
+fn get_spaces_with_active_apiaries(next_token: Option<String>) -> Result<Page<GreenSpace>, Error> {
+    let range = keys_after_token::<u64>(&next_token)?;
+    let space_ids: BTreeSet<u64> = APIARIES.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, apiary)| apiary.active)
+            .map(|(_, apiary)| apiary.space_id)
+            .collect()
+    });
+    Ok(page_by_size(space_ids.range(range).filter_map(|id| {
+        _get_green_space(id).map(|space| (*id, space))
+    })))
+}
+
+// Function for a keeper to see which of their apiaries are due for inspection
+#[ic_cdk::query]
+fn get_my_due_apiary_inspections(
+    next_token: Option<String>,
+) -> Result<Page<DueApiaryInspection>, Error> {
+    let due = due_apiaries(time(), Some(authenticated_caller()?));
+    page_sorted(
+        due.into_iter().map(|due| (due.apiary.id, due)).collect(),
+        &next_token,
+    )
+}
+
+// Sends an inspection reminder to every keeper with a due apiary, skipping
+// apiaries whose keeper was reminded recently; returns the number of reminders sent
+pub(crate) fn send_due_apiary_reminders() -> u64 {
+    let now = time();
+    let mut sent = 0;
+    for due in due_apiaries(now, None) {
+        let mut apiary = due.apiary;
+        let recently_reminded = apiary
+            .last_reminder_at
+            .is_some_and(|at| now.saturating_sub(at) < REMINDER_INTERVAL_DAYS * NANOS_PER_DAY);
+        if recently_reminded {
+            continue;
+        }
+
+        let Some(keeper) = apiary.keeper.filter(|k| *k != Principal::anonymous()) else {
+            continue;
+        };
+        notify(
+            &keeper,
+            NotificationKind::ApiaryInspectionDue {
+                apiary_id: apiary.id,
+            },
+            Some(apiary.space_id),
+            format!(
+                "Your {} hive(s) in green space id={} are due for inspection",
+                apiary.hive_count, apiary.space_id
+            ),
+        );
+        apiary.last_reminder_at = Some(now);
+        do_insert_apiary(&apiary);
+        sent += 1;
+    }
+    sent
+}
+
+// Function to remind keepers about due inspections
+#[ic_cdk::update]
+fn send_apiary_inspection_reminders() -> Result<u64, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    Ok(send_due_apiary_reminders())
+}
@@ -0,0 +1,134 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::maintenance::ensure_writable;
+use crate::paging::{page_sorted, Page};
+use crate::{
+    caller, ensure_controller, get_memory, is_controller, principal_key, Error, Memory,
+    PrincipalKey,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const BANS_MEMORY_ID: u8 = 78;
+
+const MAX_REASON_LEN: usize = 500;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum BanKind {
+    // Every write is refused
+    Ban,
+    // Writes go through, but content that can be moderated waits for a
+    // moderator, without the author being told why
+    ShadowBan,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PrincipalBan {
+    principal: Principal,
+    kind: BanKind,
+    reason: String,
+    banned_by: Principal,
+    banned_at: u64,
+    // Lifted automatically from then on; None bans until lifted by hand
+    expires_at: Option<u64>,
+}
+
+impl_storable!(PrincipalBan, 1024);
+
+thread_local! {
+    pub(crate) static BANS: RefCell<StableBTreeMap<PrincipalKey, PrincipalBan, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(BANS_MEMORY_ID)));
+}
+
+fn active_ban(principal: &Principal) -> Option<PrincipalBan> {
+    BANS.with(|b| b.borrow().get(&principal_key(principal)))
+        .filter(|ban| ban.expires_at.is_none_or(|expires_at| expires_at > time()))
+}
+
+// Fails for a banned caller; ensure_writable applies it to every update
+pub(crate) fn ensure_not_banned() -> Result<(), Error> {
+    match active_ban(&caller()) {
+        Some(ban) if ban.kind == BanKind::Ban => Err(Error::Unauthorized {
+            msg: "This principal is banned from making changes".to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn is_shadow_banned(principal: &Principal) -> bool {
+    active_ban(principal).is_some_and(|ban| ban.kind == BanKind::ShadowBan)
+}
+
+// Function for controllers to ban or shadow-ban a principal, replacing any
+// ban it has
+#[ic_cdk::update]
+fn ban_principal(
+    principal: Principal,
+    kind: BanKind,
+    reason: String,
+    expires_at: Option<u64>,
+) -> Result<PrincipalBan, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if is_controller(&principal) {
+        return Err(Error::InvalidInput {
+            msg: "Controllers cannot be banned".to_string(),
+        });
+    }
+    if reason.trim().is_empty() || reason.len() > MAX_REASON_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Reasons must be 1-{} bytes", MAX_REASON_LEN),
+        });
+    }
+    if expires_at.is_some_and(|expires_at| expires_at <= time()) {
+        return Err(Error::InvalidInput {
+            msg: "A ban must expire in the future".to_string(),
+        });
+    }
+    let ban = PrincipalBan {
+        principal,
+        kind,
+        reason,
+        banned_by: caller(),
+        banned_at: time(),
+        expires_at,
+    };
+    BANS.with(|b| {
+        b.borrow_mut()
+            .insert(principal_key(&principal), ban.clone())
+    });
+    log_upsert("ban", &principal, &ban);
+    Ok(ban)
+}
+
+// Function for controllers to lift the ban of a principal
+#[ic_cdk::update]
+fn lift_ban(principal: Principal) -> Result<PrincipalBan, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let ban = BANS
+        .with(|b| b.borrow_mut().remove(&principal_key(&principal)))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("{} is not banned", principal),
+        })?;
+    log_delete("ban", &principal);
+    Ok(ban)
+}
+
+// Function for controllers to list the bans in force
+#[ic_cdk::query]
+fn get_bans(next_token: Option<String>) -> Result<Page<PrincipalBan>, Error> {
+    ensure_controller()?;
+    let now = time();
+    let mut bans: Vec<_> = BANS.with(|b| {
+        b.borrow()
+            .iter()
+            .map(|(_, ban)| ban)
+            .filter(|ban| ban.expires_at.is_none_or(|expires_at| expires_at > now))
+            .map(|ban| (ban.principal, ban))
+            .collect()
+    });
+    bans.sort_by_key(|(principal, _)| *principal);
+    page_sorted(bans, &next_token)
+}
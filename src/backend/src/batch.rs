@@ -0,0 +1,251 @@
+use crate::cities::{ensure_city_admin, ensure_city_write};
+use crate::deletion::has_attached_records;
+use crate::events::{
+    create_event, do_insert_event, do_remove_event, ensure_event_manager, get_event, revise_event,
+    validate_event, Event, EventPayload, EventStatus,
+};
+use crate::geo::Polygon;
+use crate::governance::ensure_not_governed;
+use crate::maintenance::ensure_writable;
+use crate::quotas::{count_quota_use, ensure_quota_left, QuotaOp};
+use crate::shards::shard_for_city;
+use crate::terms::ensure_terms_accepted;
+use crate::{
+    _get_green_space, apply_payload, authenticated_caller, do_insert_green_space, ensure_version,
+    next_green_space_id, remove_green_space, set_green_space_boundary, validate_payload_fields,
+    validate_payload_references, Error, GreenSpace, GreenSpaceUpdatePayload,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use std::collections::BTreeMap;
+
+const MAX_BATCH_OPS: usize = 100;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum Op {
+    CreateGreenSpace(GreenSpaceUpdatePayload),
+    UpdateGreenSpace {
+        id: u64,
+        payload: GreenSpaceUpdatePayload,
+        expected_version: Option<u64>,
+    },
+    DeleteGreenSpace {
+        id: u64,
+    },
+    CreateEvent(EventPayload),
+    UpdateEvent {
+        id: u64,
+        payload: EventPayload,
+    },
+    DeleteEvent {
+        id: u64,
+    },
+}
+
+// The record an operation created, updated or deleted
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum OpResult {
+    GreenSpace(GreenSpace),
+    Event(Event),
+}
+
+// A write that passed its checks against the staged state; none is applied
+// before every operation of the batch has passed
+enum StagedWrite {
+    CreateGreenSpace(GreenSpace, Option<Polygon>),
+    UpdateGreenSpace(GreenSpace, Option<Polygon>),
+    DeleteGreenSpace(u64),
+    CreateEvent(EventPayload, Principal, EventStatus),
+    UpdateEvent(Event),
+    DeleteEvent(Event),
+}
+
+// The batch as applied so far, over the stored records
+#[derive(Default)]
+struct Staging {
+    // Spaces and events the batch wrote, None once deleted
+    spaces: BTreeMap<u64, Option<GreenSpace>>,
+    events: BTreeMap<u64, Option<Event>>,
+    writes: Vec<StagedWrite>,
+}
+
+impl Staging {
+    fn space(&self, id: u64) -> Result<GreenSpace, Error> {
+        match self.spaces.get(&id) {
+            Some(staged) => staged.clone(),
+            None => _get_green_space(&id),
+        }
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("A green space with id={} not found", id),
+        })
+    }
+
+    fn event(&self, id: u64) -> Result<Event, Error> {
+        match self.events.get(&id) {
+            Some(staged) => staged.clone().ok_or_else(|| Error::NotFound {
+                msg: format!("An event with id={} not found", id),
+            }),
+            None => get_event(id),
+        }
+    }
+
+    fn stage(&mut self, op: Op) -> Result<(), Error> {
+        match op {
+            Op::CreateGreenSpace(payload) => {
+                validate_payload_fields(&payload)?;
+                ensure_city_write(payload.city_id)?;
+                ensure_local(payload.city_id)?;
+                let boundary = validate_payload_references(&payload)?;
+                // The id and ULID are assigned when the batch is applied
+                let mut space = GreenSpace {
+                    created_at: Some(time()),
+                    ..Default::default()
+                };
+                apply_payload(&mut space, payload);
+                self.writes
+                    .push(StagedWrite::CreateGreenSpace(space, boundary));
+            }
+            Op::UpdateGreenSpace {
+                id,
+                payload,
+                expected_version,
+            } => {
+                let mut space = self.space(id)?;
+                ensure_city_write(space.city_id)?;
+                ensure_city_write(payload.city_id)?;
+                ensure_local(payload.city_id)?;
+                validate_payload_fields(&payload)?;
+                let boundary = validate_payload_references(&payload)?;
+                ensure_version(&space, expected_version)?;
+                apply_payload(&mut space, payload);
+                space.version = Some(space.version.unwrap_or(0) + 1);
+                self.spaces.insert(id, Some(space.clone()));
+                self.writes
+                    .push(StagedWrite::UpdateGreenSpace(space, boundary));
+            }
+            Op::DeleteGreenSpace { id } => {
+                let space = self.space(id)?;
+                ensure_city_write(space.city_id)?;
+                ensure_not_governed("Deleting a green space")?;
+                // A batch cannot hand out a confirmation token, so spaces
+                // with records attached, stored or staged, are left to
+                // delete_green_space
+                let stages_events = self.writes.iter().any(|write| {
+                    matches!(write, StagedWrite::CreateEvent(payload, ..) if payload.space_id == id)
+                }) || self
+                    .events
+                    .values()
+                    .any(|event| event.as_ref().is_some_and(|event| event.space_id == id));
+                if stages_events || has_attached_records(id) {
+                    return Err(Error::InvalidInput {
+                        msg: format!(
+                            "Green space {} has records attached; delete it with delete_green_space to confirm",
+                            id
+                        ),
+                    });
+                }
+                self.spaces.insert(id, None);
+                self.writes.push(StagedWrite::DeleteGreenSpace(id));
+            }
+            Op::CreateEvent(payload) => {
+                let organizer = authenticated_caller()?;
+                ensure_terms_accepted()?;
+                let space = self.space(payload.space_id)?;
+                validate_event(&payload)?;
+                let status = if ensure_city_admin(space.city_id).is_ok() {
+                    EventStatus::Approved
+                } else {
+                    EventStatus::Pending
+                };
+                self.writes
+                    .push(StagedWrite::CreateEvent(payload, organizer, status));
+            }
+            Op::UpdateEvent { id, payload } => {
+                let event = revise_event(&self.event(id)?, payload)?;
+                self.space(event.space_id)?;
+                self.events.insert(id, Some(event.clone()));
+                self.writes.push(StagedWrite::UpdateEvent(event));
+            }
+            Op::DeleteEvent { id } => {
+                let event = self.event(id)?;
+                ensure_event_manager(&event)?;
+                self.events.insert(id, None);
+                self.writes.push(StagedWrite::DeleteEvent(event));
+            }
+        }
+        Ok(())
+    }
+
+    // Applies the staged writes in order; they were all checked, so a
+    // failure here is a bug, and trapping discards the writes made so far
+    fn apply(self) -> Vec<OpResult> {
+        self.writes
+            .into_iter()
+            .map(|write| match write {
+                StagedWrite::CreateGreenSpace(space, boundary) => {
+                    let space = do_insert_green_space(&GreenSpace {
+                        id: next_green_space_id(),
+                        ..space
+                    });
+                    set_green_space_boundary(space.id, boundary);
+                    OpResult::GreenSpace(space)
+                }
+                StagedWrite::UpdateGreenSpace(space, boundary) => {
+                    let space = do_insert_green_space(&space);
+                    set_green_space_boundary(space.id, boundary);
+                    OpResult::GreenSpace(space)
+                }
+                StagedWrite::DeleteGreenSpace(id) => OpResult::GreenSpace(
+                    remove_green_space(id).expect("A staged space disappeared"),
+                ),
+                StagedWrite::CreateEvent(payload, organizer, status) => {
+                    OpResult::Event(create_event(payload, organizer, status))
+                }
+                StagedWrite::UpdateEvent(event) => {
+                    do_insert_event(&event);
+                    OpResult::Event(event)
+                }
+                StagedWrite::DeleteEvent(event) => {
+                    do_remove_event(&event);
+                    OpResult::Event(event)
+                }
+            })
+            .collect()
+    }
+}
+
+// Spaces of a city on a shard are written there, which a batch cannot do
+// atomically
+fn ensure_local(city_id: Option<u64>) -> Result<(), Error> {
+    match shard_for_city(city_id) {
+        Some(_) => Err(Error::InvalidInput {
+            msg: "Spaces of a city on a shard cannot be written in a batch".to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+// Function for import tools and scripts to create, update and delete spaces
+// and events together: every operation is checked against the outcome of
+// those before it, and nothing is written unless all of them pass. Spaces
+// created in a batch keep the location they are given, without geocoding
+#[ic_cdk::update]
+fn apply_batch(ops: Vec<Op>) -> Result<Vec<OpResult>, Error> {
+    ensure_writable()?;
+    if ops.is_empty() || ops.len() > MAX_BATCH_OPS {
+        return Err(Error::InvalidInput {
+            msg: format!("A batch must have 1-{} operations", MAX_BATCH_OPS),
+        });
+    }
+    ensure_quota_left(QuotaOp::Batch)?;
+    let mut staging = Staging::default();
+    for (index, op) in ops.into_iter().enumerate() {
+        staging
+            .stage(op)
+            .map_err(|err| err.prefixed(&format!("Operation {}", index)))?;
+    }
+    let results = staging.apply();
+    // Rejected batches write nothing, so only applied ones count
+    count_quota_use(QuotaOp::Batch);
+    Ok(results)
+}
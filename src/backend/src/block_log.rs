@@ -0,0 +1,299 @@
+use crate::certification::block_tip_witness;
+#[cfg(not(test))]
+use crate::certification::certify_data;
+use crate::changelog::ChangeOp;
+#[cfg(test)]
+use crate::test_caller::{certify_data, time};
+use crate::{caller, get_memory, Error, GreenSpace, Memory};
+use candid::{CandidType, Encode, Int, Nat, Principal};
+#[cfg(not(test))]
+use ic_cdk::api::time;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+const BLOCKS_MEMORY_ID: u8 = 30;
+
+const MAX_BLOCKS_PER_PAGE: u64 = 500;
+// Blocks are capped at 4KB; larger records are logged by hash
+const MAX_INLINE_RECORD_BYTES: usize = 3072;
+
+// The generic value of ICRC-3; blocks are maps of these
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl_storable!(Value, 4096);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BlockWithId {
+    id: u64,
+    block: Value,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct GetBlocksResult {
+    log_length: u64,
+    blocks: Vec<BlockWithId>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BlockTip {
+    last_block_index: u64,
+    last_block_hash: Vec<u8>,
+    // System certificate over the canister's certified data, the root hash of
+    // the tree below; only available in queries
+    certificate: Option<Vec<u8>>,
+    // The certified hash tree as CBOR, revealing the index and hash of the
+    // last block under "last_block_index" and "last_block_hash" as ICRC-3 has it
+    hash_tree: Vec<u8>,
+}
+
+thread_local! {
+    // Blocks keyed by their index; every block holds the hash of its predecessor.
+    // Writes to records and configuration append one; indexes, caches and
+    // counters derived from them are not logged, nor are the private data of
+    // users (favorites, visits, saved searches, notifications), which erasure
+    // must be able to remove. Records dropped by the cascade of a logged
+    // space deletion or by the logged retention policy are not logged again
+    pub(crate) static BLOCKS: RefCell<StableBTreeMap<u64, Value, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(BLOCKS_MEMORY_ID)));
+}
+
+impl Value {
+    // Representation-independent hash as defined by ICRC-3
+    fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        match self {
+            Value::Blob(bytes) => hasher.update(bytes),
+            Value::Text(text) => hasher.update(text.as_bytes()),
+            Value::Nat(nat) => {
+                let mut leb128 = vec![];
+                nat.encode(&mut leb128).expect("Cannot encode a nat");
+                hasher.update(leb128);
+            }
+            Value::Int(int) => {
+                let mut sleb128 = vec![];
+                int.encode(&mut sleb128).expect("Cannot encode an int");
+                hasher.update(sleb128);
+            }
+            Value::Array(values) => {
+                for value in values {
+                    hasher.update(value.hash());
+                }
+            }
+            Value::Map(entries) => {
+                let mut pairs: Vec<Vec<u8>> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let mut pair = Sha256::digest(key.as_bytes()).to_vec();
+                        pair.extend_from_slice(&value.hash());
+                        pair
+                    })
+                    .collect();
+                pairs.sort();
+                for pair in pairs {
+                    hasher.update(pair);
+                }
+            }
+        }
+        hasher.finalize().into()
+    }
+}
+
+fn nat(value: u64) -> Value {
+    Value::Nat(Nat::from(value))
+}
+
+fn last_block() -> Option<(u64, Value)> {
+    BLOCKS.with(|b| b.borrow().last_key_value())
+}
+
+// The index and hash of the last block, unless the log is empty
+pub(crate) fn block_tip() -> Option<(u64, [u8; 32])> {
+    last_block().map(|(index, block)| (index, block.hash()))
+}
+
+// The key a record is stored under, as it appears in the blocks of its writes
+pub(crate) trait BlockKey {
+    fn to_value(&self) -> Value;
+}
+
+impl BlockKey for u64 {
+    fn to_value(&self) -> Value {
+        nat(*self)
+    }
+}
+
+impl<A: BlockKey, B: BlockKey> BlockKey for (A, B) {
+    fn to_value(&self) -> Value {
+        Value::Array(vec![self.0.to_value(), self.1.to_value()])
+    }
+}
+
+impl BlockKey for Principal {
+    fn to_value(&self) -> Value {
+        Value::Blob(self.as_slice().to_vec())
+    }
+}
+
+impl<const N: usize> BlockKey for Blob<N> {
+    fn to_value(&self) -> Value {
+        Value::Blob(self.as_slice().to_vec())
+    }
+}
+
+impl BlockKey for str {
+    fn to_value(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+}
+
+impl BlockKey for String {
+    fn to_value(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+// Appends a block of the given type for a transaction, chained to the last block
+fn append_block(btype: String, mut tx: Vec<(String, Value)>) {
+    let (index, phash) = match last_block() {
+        Some((index, block)) => (index + 1, Some(block.hash())),
+        None => (0, None),
+    };
+    tx.push((
+        "caller".to_string(),
+        Value::Blob(caller().as_slice().to_vec()),
+    ));
+    let mut block = vec![
+        ("btype".to_string(), Value::Text(btype)),
+        ("ts".to_string(), nat(time())),
+        ("tx".to_string(), Value::Map(tx)),
+    ];
+    if let Some(phash) = phash {
+        block.push(("phash".to_string(), Value::Blob(phash.to_vec())));
+    }
+    BLOCKS.with(|b| b.borrow_mut().insert(index, Value::Map(block)));
+    certify_data();
+}
+
+// The candid-encoded record, so the history can be replayed; records too
+// large for a block are represented by their SHA-256 instead
+fn record_entry(record: &impl CandidType) -> (String, Value) {
+    let bytes = Encode!(record).expect("Cannot encode a record for the block log");
+    if bytes.len() > MAX_INLINE_RECORD_BYTES {
+        (
+            "record_hash".to_string(),
+            Value::Blob(Sha256::digest(&bytes).to_vec()),
+        )
+    } else {
+        ("record".to_string(), Value::Blob(bytes))
+    }
+}
+
+// Appends a `<kind>_upsert` block for a record written to a store
+pub(crate) fn log_upsert<K: BlockKey + ?Sized>(kind: &str, key: &K, record: &impl CandidType) {
+    append_block(
+        format!("{}_upsert", kind),
+        vec![("key".to_string(), key.to_value()), record_entry(record)],
+    );
+}
+
+// Appends a `<kind>_delete` block for a record removed from a store
+pub(crate) fn log_delete<K: BlockKey + ?Sized>(kind: &str, key: &K) {
+    append_block(
+        format!("{}_delete", kind),
+        vec![("key".to_string(), key.to_value())],
+    );
+}
+
+// Appends a `<kind>_set` block for a change to a configuration cell
+pub(crate) fn log_config(kind: &str, config: &impl CandidType) {
+    append_block(format!("{}_set", kind), vec![record_entry(config)]);
+}
+
+// Appends a block for a write that replaced every store at once, e.g. a reset
+pub(crate) fn log_bulk_write(btype: &str, tx: Vec<(String, Value)>) {
+    append_block(btype.to_string(), tx);
+}
+
+// Appends a block for a write to a green space
+pub(crate) fn append_green_space_block(op: ChangeOp, space_id: u64, space: Option<&GreenSpace>) {
+    let mut tx = vec![("space_id".to_string(), nat(space_id))];
+    if let Some(space) = space {
+        tx.push((
+            "space".to_string(),
+            Value::Blob(space.to_bytes().into_owned()),
+        ));
+    }
+    let btype = match op {
+        ChangeOp::Upsert => "gs_upsert",
+        ChangeOp::Delete => "gs_delete",
+    };
+    append_block(btype.to_string(), tx);
+}
+
+// Function to page through the block log, ICRC-3 style
+#[ic_cdk::query]
+fn get_blocks(start: u64, length: u64) -> Result<GetBlocksResult, Error> {
+    let length = length.min(MAX_BLOCKS_PER_PAGE);
+    BLOCKS.with(|b| {
+        let blocks = b.borrow();
+        Ok(GetBlocksResult {
+            log_length: blocks.len(),
+            blocks: blocks
+                .range(start..start.saturating_add(length))
+                .map(|(id, block)| BlockWithId { id, block })
+                .collect(),
+        })
+    })
+}
+
+// Function to get the last block's index and hash, certified when called as a query
+#[ic_cdk::query]
+fn get_block_tip() -> Result<BlockTip, Error> {
+    let (last_block_index, block) = last_block().ok_or_else(|| Error::NotFound {
+        msg: "The block log is empty".to_string(),
+    })?;
+    Ok(BlockTip {
+        last_block_index,
+        last_block_hash: block.hash().to_vec(),
+        certificate: ic_cdk::api::data_certificate(),
+        hash_tree: block_tip_witness(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field<'a>(block: &'a Value, name: &str) -> Option<&'a Value> {
+        match block {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn writes_append_typed_blocks_chained_to_the_last() {
+        log_upsert("tree", &7u64, &"oak".to_string());
+        log_delete("tree", &7u64);
+        let result = get_blocks(0, 10).unwrap_or_else(|e| panic!("{}", e.message()));
+        assert_eq!(result.log_length, 2);
+        let (first, second) = (&result.blocks[0].block, &result.blocks[1].block);
+        assert!(matches!(field(first, "btype"), Some(Value::Text(t)) if t == "tree_upsert"));
+        assert!(matches!(field(second, "btype"), Some(Value::Text(t)) if t == "tree_delete"));
+        assert!(field(first, "phash").is_none());
+        assert!(matches!(field(second, "phash"), Some(Value::Blob(hash)) if *hash == first.hash()));
+    }
+}
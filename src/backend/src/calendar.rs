@@ -0,0 +1,84 @@
+use crate::{Error, NANOS_PER_DAY};
+
+// Civil (proleptic Gregorian, UTC) date of a day counted from 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// Year and month (1-12) of a timestamp in nanoseconds
+pub(crate) fn year_month_of(timestamp: u64) -> (i64, u32) {
+    let (year, month, _) = civil_from_days((timestamp / NANOS_PER_DAY) as i64);
+    (year, month)
+}
+
+// Civil date and time of day (hours, minutes, seconds), in UTC, of a
+// timestamp in nanoseconds
+fn utc_date_time(timestamp: u64) -> ((i64, u32, u32), (u64, u64, u64)) {
+    let date = civil_from_days((timestamp / NANOS_PER_DAY) as i64);
+    let seconds = timestamp % NANOS_PER_DAY / 1_000_000_000;
+    (date, (seconds / 3600, seconds / 60 % 60, seconds % 60))
+}
+
+// A timestamp in nanoseconds as "YYYY-MM-DD HH:MM UTC"
+pub(crate) fn format_utc(timestamp: u64) -> String {
+    let ((year, month, day), (hours, minutes, _)) = utc_date_time(timestamp);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year, month, day, hours, minutes
+    )
+}
+
+// A timestamp in nanoseconds as an RFC 3339 date-time, e.g. "2024-05-01T09:30:00Z"
+pub(crate) fn format_rfc3339(timestamp: u64) -> String {
+    let ((year, month, day), (hours, minutes, seconds)) = utc_date_time(timestamp);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hours, minutes, seconds
+    )
+}
+
+// A timestamp in nanoseconds as an RFC 5545 UTC date-time, e.g. "20240501T093000Z"
+pub(crate) fn format_ical_utc(timestamp: u64) -> String {
+    let ((year, month, day), (hours, minutes, seconds)) = utc_date_time(timestamp);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year, month, day, hours, minutes, seconds
+    )
+}
+
+// Days from 1970-01-01 to a civil date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// Start and end, in nanoseconds, of a calendar month
+pub(crate) fn month_range(year: u32, month: u32) -> Result<(u64, u64), Error> {
+    if !(1..=12).contains(&month) || !(1970..=2500).contains(&year) {
+        return Err(Error::InvalidInput {
+            msg: "The month must be between 1 and 12 of a year from 1970 to 2500".to_string(),
+        });
+    }
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    let start = days_from_civil(year.into(), month, 1) as u64 * NANOS_PER_DAY;
+    let end = days_from_civil(next_year.into(), next_month, 1) as u64 * NANOS_PER_DAY;
+    Ok((start, end))
+}
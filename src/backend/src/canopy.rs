@@ -0,0 +1,155 @@
+use crate::trees::trees_in_space;
+use crate::{
+    _get_green_space_boundary, ensure_green_space_exists, get_memory, Error, Memory,
+    GREEN_SPACE_STORAGE,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const CANOPY_STATS_MEMORY_ID: u8 = 94;
+
+#[derive(
+    candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub(crate) enum CanopyScope {
+    Space(u64),
+    // Spaces assigned to the district itself, not to its neighborhoods
+    District(u64),
+    City(u64),
+    All,
+}
+
+impl CanopyScope {
+    fn key(self) -> (u8, u64) {
+        match self {
+            CanopyScope::Space(id) => (0, id),
+            CanopyScope::District(id) => (1, id),
+            CanopyScope::City(id) => (2, id),
+            CanopyScope::All => (3, 0),
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CanopyStats {
+    scope: CanopyScope,
+    tree_count: u64,
+    // Crown areas summed, each space's capped at its own area since crowns overlap
+    canopy_area_m2: f64,
+    // Area of the spaces with a boundary; coverage only counts their canopy
+    area_m2: Option<f64>,
+    coverage_percent: Option<f64>,
+    computed_at: u64,
+}
+
+impl_storable!(CanopyStats, 256);
+
+#[derive(Default)]
+struct CanopyTotals {
+    tree_count: u64,
+    canopy_area_m2: f64,
+    // Canopy and area of the spaces with a boundary
+    measured_canopy_m2: f64,
+    area_m2: f64,
+}
+
+impl CanopyTotals {
+    fn add(&mut self, other: &CanopyTotals) {
+        self.tree_count += other.tree_count;
+        self.canopy_area_m2 += other.canopy_area_m2;
+        self.measured_canopy_m2 += other.measured_canopy_m2;
+        self.area_m2 += other.area_m2;
+    }
+
+    fn stats(&self, scope: CanopyScope, computed_at: u64) -> CanopyStats {
+        let area_m2 = (self.area_m2 > 0.0).then_some(self.area_m2);
+        CanopyStats {
+            scope,
+            tree_count: self.tree_count,
+            canopy_area_m2: self.canopy_area_m2,
+            area_m2,
+            coverage_percent: area_m2.map(|area| self.measured_canopy_m2 / area * 100.0),
+            computed_at,
+        }
+    }
+}
+
+thread_local! {
+    // Derived from the tree inventory, so recomputed rather than snapshotted
+    static CANOPY_STATS: RefCell<StableBTreeMap<(u8, u64), CanopyStats, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(CANOPY_STATS_MEMORY_ID)));
+}
+
+// The number of trees of a space, their canopy capped at the space's area,
+// and that area when it has a boundary
+pub(crate) fn space_canopy(id: u64) -> (u64, f64, Option<f64>) {
+    let trees = trees_in_space(id);
+    let crowns: f64 = trees.iter().map(|tree| tree.crown_area_m2()).sum();
+    let area = _get_green_space_boundary(&id)
+        .map(|boundary| boundary.area_m2())
+        .filter(|area| *area > 0.0);
+    let canopy = area.map_or(crowns, |area| crowns.min(area));
+    (trees.len() as u64, canopy, area)
+}
+
+fn space_totals(id: u64) -> CanopyTotals {
+    let (tree_count, canopy, area) = space_canopy(id);
+    CanopyTotals {
+        tree_count,
+        canopy_area_m2: canopy,
+        measured_canopy_m2: if area.is_some() { canopy } else { 0.0 },
+        area_m2: area.unwrap_or(0.0),
+    }
+}
+
+// Recomputes the canopy of every space, district and city and the overall
+// total; returns the number of scopes stored
+pub(crate) fn compute_canopy_stats() -> u64 {
+    let now = time();
+    let mut totals: BTreeMap<CanopyScope, CanopyTotals> = BTreeMap::new();
+    let spaces: Vec<(u64, Option<u64>, Option<u64>)> = GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(id, space)| (id, space.district_id, space.city_id))
+            .collect()
+    });
+    for (id, district_id, city_id) in spaces {
+        let space = space_totals(id);
+        let scopes = [
+            district_id.map(CanopyScope::District),
+            city_id.map(CanopyScope::City),
+            Some(CanopyScope::All),
+        ];
+        for scope in scopes.into_iter().flatten() {
+            totals.entry(scope).or_default().add(&space);
+        }
+        totals.insert(CanopyScope::Space(id), space);
+    }
+    CANOPY_STATS.with(|c| {
+        let keys: Vec<(u8, u64)> = c.borrow().iter().map(|(key, _)| key).collect();
+        let mut stats = c.borrow_mut();
+        for key in keys {
+            stats.remove(&key);
+        }
+        for (scope, totals) in &totals {
+            stats.insert(scope.key(), totals.stats(*scope, now));
+        }
+    });
+    totals.len() as u64
+}
+
+// Function to get the tree canopy of a space, a district, a city or all spaces,
+// as of the last daily computation
+#[ic_cdk::query]
+fn get_canopy_stats(scope: CanopyScope) -> Result<CanopyStats, Error> {
+    if let CanopyScope::Space(id) = scope {
+        ensure_green_space_exists(id)?;
+    }
+    CANOPY_STATS
+        .with(|c| c.borrow().get(&scope.key()))
+        .ok_or_else(|| Error::NotFound {
+            msg: "No canopy has been computed for this scope yet".to_string(),
+        })
+}
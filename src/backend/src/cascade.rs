@@ -0,0 +1,388 @@
+use crate::accessibility::ACCESSIBILITY_AUDITS;
+use crate::apiary::APIARIES;
+use crate::compost::COMPOST_SITES;
+use crate::contractors::WORK_ORDERS;
+use crate::events::EVENTS;
+use crate::expenses::EXPENSES;
+use crate::favorites::FAVORITES;
+use crate::moderation::{ContentRef, MODERATION_QUEUE};
+use crate::noise::{NOISE_HOURLY, NOISE_SENSORS};
+use crate::playground::{EQUIPMENT_INSPECTIONS, PLAYGROUND_EQUIPMENT};
+use crate::reviews::REVIEWS;
+use crate::soil::{SAMPLING_POINTS, SOIL_TESTS};
+use crate::tickets::MAINTENANCE_TICKETS;
+use crate::trees::{TREES, TREES_BY_SPACE};
+use crate::visits::VISITS;
+use crate::water::{WATER_FEATURES, WATER_QUALITY_TESTS};
+use crate::{_get_green_space, get_memory, Memory};
+use ic_cdk::api::time;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::ops::Bound;
+use std::thread::LocalKey;
+use std::time::Duration;
+
+const CASCADE_QUEUE_MEMORY_ID: u8 = 69;
+
+type Store<K, V> = LocalKey<RefCell<StableBTreeMap<K, V, Memory>>>;
+type KeyRange<K> = (Bound<K>, Bound<K>);
+
+thread_local! {
+    // Deleted spaces whose records are still being removed, with the time
+    // they were queued
+    pub(crate) static CASCADE_QUEUE: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(CASCADE_QUEUE_MEMORY_ID)));
+
+    static BATCH_SCHEDULED: Cell<bool> = const { Cell::new(false) };
+
+    // Kept on the heap; after an upgrade the space is swept from the start
+    static CURSOR: RefCell<Option<CascadeCursor>> = const { RefCell::new(None) };
+}
+
+// Records removed and records looked at per message, well within the
+// instruction limit
+const MAX_REMOVED_PER_BATCH: usize = 500;
+const MAX_SCANNED_PER_BATCH: usize = 5_000;
+
+struct Budget {
+    removals: usize,
+    scans: usize,
+}
+
+impl Budget {
+    fn spent(&self) -> bool {
+        self.removals == 0 || self.scans == 0
+    }
+}
+
+// Where the removal of the first queued space stands: the stage it is in and
+// the last key that stage looked at
+struct CascadeCursor {
+    space_id: u64,
+    stage: usize,
+    last_key: Option<Vec<u8>>,
+}
+
+// Removes the records of a space from one store, resuming after the cursor;
+// returns whether the store is done
+type Stage = fn(u64, &mut Option<Vec<u8>>, &mut Budget) -> bool;
+
+// Scans a range of a store from the cursor on and removes the records that
+// match, until the range or the budget runs out; returns whether the range is done
+fn sweep<K, V>(
+    store: &'static Store<K, V>,
+    range: KeyRange<K>,
+    cursor: &mut Option<Vec<u8>>,
+    matches: impl Fn(&K, &V) -> bool,
+    budget: &mut Budget,
+) -> bool
+where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable,
+{
+    let start = match cursor {
+        Some(last) => Bound::Excluded(K::from_bytes(Cow::Borrowed(last))),
+        None => range.0,
+    };
+    let mut removed = vec![];
+    let mut done = true;
+    store.with(|s| {
+        for (key, value) in s.borrow().range((start, range.1)) {
+            if budget.scans == 0 || removed.len() == budget.removals {
+                done = false;
+                break;
+            }
+            budget.scans -= 1;
+            if matches(&key, &value) {
+                removed.push(key.clone());
+            }
+            *cursor = Some(key.to_bytes().into_owned());
+        }
+    });
+    store.with(|s| {
+        let mut records = s.borrow_mut();
+        for key in &removed {
+            records.remove(key);
+        }
+    });
+    budget.removals -= removed.len();
+    done
+}
+
+fn all<K>() -> KeyRange<K> {
+    (Bound::Unbounded, Bound::Unbounded)
+}
+
+// The keys of a store keyed by space first
+fn of_space(space_id: u64) -> KeyRange<(u64, u64)> {
+    (
+        Bound::Included((space_id, 0)),
+        Bound::Included((space_id, u64::MAX)),
+    )
+}
+
+fn in_space<V: BoundedStorable>(
+    store: &'static Store<u64, V>,
+    id: &u64,
+    space_id: u64,
+    record_space_id: fn(&V) -> u64,
+) -> bool {
+    store.with(|s| {
+        s.borrow()
+            .get(id)
+            .is_some_and(|record| record_space_id(&record) == space_id)
+    })
+}
+
+// The stores of a deleted space in the order they are cleared; records of
+// records go before those they belong to, as they are matched through them
+fn stages() -> Vec<Stage> {
+    vec![
+        |space_id, cursor, budget| {
+            sweep(
+                &WATER_QUALITY_TESTS,
+                all(),
+                cursor,
+                |(feature, _), _| in_space(&WATER_FEATURES, feature, space_id, |f| f.space_id),
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &EQUIPMENT_INSPECTIONS,
+                all(),
+                cursor,
+                |(item, _), _| in_space(&PLAYGROUND_EQUIPMENT, item, space_id, |e| e.space_id),
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &SOIL_TESTS,
+                all(),
+                cursor,
+                |_, t| t.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &NOISE_HOURLY,
+                of_space(space_id),
+                cursor,
+                |_, _| true,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &WATER_FEATURES,
+                all(),
+                cursor,
+                |_, f| f.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &PLAYGROUND_EQUIPMENT,
+                all(),
+                cursor,
+                |_, e| e.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &SAMPLING_POINTS,
+                all(),
+                cursor,
+                |_, p| p.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &NOISE_SENSORS,
+                all(),
+                cursor,
+                |_, n| n.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &MODERATION_QUEUE,
+                all(),
+                cursor,
+                |(_, content), _| match content {
+                    ContentRef::Review(id) => in_space(&REVIEWS, id, space_id, |r| r.space_id),
+                    ContentRef::Event(id) => in_space(&EVENTS, id, space_id, |e| e.space_id),
+                },
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &REVIEWS,
+                all(),
+                cursor,
+                |_, r| r.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &EVENTS,
+                all(),
+                cursor,
+                |_, e| e.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &WORK_ORDERS,
+                all(),
+                cursor,
+                |_, w| w.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &MAINTENANCE_TICKETS,
+                all(),
+                cursor,
+                |_, t| t.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &EXPENSES,
+                all(),
+                cursor,
+                |_, e| e.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &APIARIES,
+                all(),
+                cursor,
+                |_, a| a.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &COMPOST_SITES,
+                all(),
+                cursor,
+                |_, c| c.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &ACCESSIBILITY_AUDITS,
+                all(),
+                cursor,
+                |_, a| a.space_id == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &FAVORITES,
+                all(),
+                cursor,
+                |(_, space), _| *space == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &VISITS,
+                all(),
+                cursor,
+                |(_, space), _| *space == space_id,
+                budget,
+            )
+        },
+        |space_id, cursor, budget| {
+            sweep(&TREES, all(), cursor, |_, t| t.space_id == space_id, budget)
+        },
+        |space_id, cursor, budget| {
+            sweep(
+                &TREES_BY_SPACE,
+                of_space(space_id),
+                cursor,
+                |_, _| true,
+                budget,
+            )
+        },
+    ]
+}
+
+// Runs one batch of removals; returns the number of records removed
+pub(crate) fn run_cascade_batch() -> u64 {
+    let stages = stages();
+    let mut budget = Budget {
+        removals: MAX_REMOVED_PER_BATCH,
+        scans: MAX_SCANNED_PER_BATCH,
+    };
+    while !budget.spent() {
+        let Some((space_id, _)) = CASCADE_QUEUE.with(|q| q.borrow().iter().next()) else {
+            break;
+        };
+        let mut cursor = CURSOR
+            .with(|c| c.borrow_mut().take())
+            .filter(|cursor| cursor.space_id == space_id)
+            .unwrap_or(CascadeCursor {
+                space_id,
+                stage: 0,
+                last_key: None,
+            });
+        // A space stored again under its id, e.g. by replication, keeps its records
+        if _get_green_space(&space_id).is_some() {
+            cursor.stage = stages.len();
+        }
+        while cursor.stage < stages.len()
+            && stages[cursor.stage](space_id, &mut cursor.last_key, &mut budget)
+        {
+            cursor.stage += 1;
+            cursor.last_key = None;
+        }
+        if cursor.stage < stages.len() {
+            CURSOR.with(|c| *c.borrow_mut() = Some(cursor));
+        } else {
+            CASCADE_QUEUE.with(|q| q.borrow_mut().remove(&space_id));
+        }
+    }
+    (MAX_REMOVED_PER_BATCH - budget.removals) as u64
+}
+
+// Runs batches one message after another until the queue is empty; the
+// scheduler picks up a queue left over by an upgrade
+fn continue_cascades_soon() {
+    if BATCH_SCHEDULED.with(|s| s.replace(true)) {
+        return;
+    }
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        BATCH_SCHEDULED.with(|s| s.set(false));
+        run_cascade_batch();
+        if !CASCADE_QUEUE.with(|q| q.borrow().is_empty()) {
+            continue_cascades_soon();
+        }
+    });
+}
+
+// Queues the records of a deleted space for removal
+pub(crate) fn queue_cascade(space_id: u64) {
+    CASCADE_QUEUE.with(|q| q.borrow_mut().insert(space_id, time()));
+    continue_cascades_soon();
+}
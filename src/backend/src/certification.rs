@@ -0,0 +1,313 @@
+use crate::block_log::block_tip;
+use crate::{get_memory, Error, GreenSpace, Memory, GREEN_SPACE_STORAGE};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+const SPACE_TREE_MEMORY_ID: u8 = 85;
+
+// Spaces sit in a binary trie over the bits of their id, most significant
+// first, so the trie's in-order traversal is sorted by label as the IC's
+// lookups require
+const ID_BITS: u64 = 64;
+
+type Hash = [u8; 32];
+type NodeHash = Blob<32>;
+
+// An IC hash tree, as in the interface specification's certification section
+enum HashTree {
+    Empty,
+    Fork(Box<HashTree>, Box<HashTree>),
+    Labeled(Vec<u8>, Box<HashTree>),
+    Leaf(Vec<u8>),
+    Pruned(Hash),
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CertifiedGreenSpace {
+    space: GreenSpace,
+    // The candid encoding of the space as stored; its SHA-256 is the leaf at
+    // ["green_spaces", id as 8 big-endian bytes] of the tree
+    encoded: Vec<u8>,
+    // The certified data's hash tree with everything but that path pruned,
+    // as CBOR
+    witness: Vec<u8>,
+    // System certificate over the tree's root hash; only available in queries
+    certificate: Option<Vec<u8>>,
+}
+
+thread_local! {
+    // Hashes of the trie's non-empty nodes keyed by (depth, id prefix); derived
+    // from the spaces, so rebuilt rather than snapshotted
+    static SPACE_TREE: RefCell<StableBTreeMap<(u64, u64), NodeHash, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SPACE_TREE_MEMORY_ID)));
+}
+
+fn domain_hash(domain: &str, parts: &[&[u8]]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain.as_bytes());
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn empty_hash() -> Hash {
+    domain_hash("ic-hashtree-empty", &[])
+}
+
+fn fork_hash(left: &Hash, right: &Hash) -> Hash {
+    domain_hash("ic-hashtree-fork", &[left, right])
+}
+
+fn labeled_hash(label: &[u8], tree: &Hash) -> Hash {
+    domain_hash("ic-hashtree-labeled", &[label, tree])
+}
+
+fn leaf_hash(value: &[u8]) -> Hash {
+    domain_hash("ic-hashtree-leaf", &[value])
+}
+
+impl HashTree {
+    fn digest(&self) -> Hash {
+        match self {
+            HashTree::Empty => empty_hash(),
+            HashTree::Fork(left, right) => fork_hash(&left.digest(), &right.digest()),
+            HashTree::Labeled(label, tree) => labeled_hash(label, &tree.digest()),
+            HashTree::Leaf(value) => leaf_hash(value),
+            HashTree::Pruned(hash) => *hash,
+        }
+    }
+
+    fn fork(left: HashTree, right: HashTree) -> HashTree {
+        HashTree::Fork(Box::new(left), Box::new(right))
+    }
+
+    fn labeled(label: &[u8], tree: HashTree) -> HashTree {
+        HashTree::Labeled(label.to_vec(), Box::new(tree))
+    }
+
+    fn write_cbor(&self, out: &mut Vec<u8>) {
+        match self {
+            HashTree::Empty => {
+                cbor_head(out, 4, 1);
+                cbor_head(out, 0, 0);
+            }
+            HashTree::Fork(left, right) => {
+                cbor_head(out, 4, 3);
+                cbor_head(out, 0, 1);
+                left.write_cbor(out);
+                right.write_cbor(out);
+            }
+            HashTree::Labeled(label, tree) => {
+                cbor_head(out, 4, 3);
+                cbor_head(out, 0, 2);
+                cbor_bytes(out, label);
+                tree.write_cbor(out);
+            }
+            HashTree::Leaf(value) => {
+                cbor_head(out, 4, 2);
+                cbor_head(out, 0, 3);
+                cbor_bytes(out, value);
+            }
+            HashTree::Pruned(hash) => {
+                cbor_head(out, 4, 2);
+                cbor_head(out, 0, 4);
+                cbor_bytes(out, hash);
+            }
+        }
+    }
+
+    // CBOR with the self-describing tag, as agents expect
+    fn to_cbor(&self) -> Vec<u8> {
+        let mut out = vec![0xd9, 0xd9, 0xf7];
+        self.write_cbor(&mut out);
+        out
+    }
+}
+
+fn cbor_head(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xff => out.extend([major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(value.to_be_bytes());
+        }
+    }
+}
+
+fn cbor_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    cbor_head(out, 2, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn leb128(mut value: u64) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn prefix_of(id: u64, depth: u64) -> u64 {
+    id.checked_shr((ID_BITS - depth) as u32).unwrap_or(0)
+}
+
+fn node_hash(depth: u64, prefix: u64) -> Option<Hash> {
+    SPACE_TREE
+        .with(|t| t.borrow().get(&(depth, prefix)))
+        .map(|hash| hash.as_slice().try_into().unwrap())
+}
+
+fn record_hash(space: &GreenSpace) -> Hash {
+    Sha256::digest(space.to_bytes()).into()
+}
+
+fn space_leaf(id: u64, space: &GreenSpace) -> HashTree {
+    HashTree::labeled(
+        &id.to_be_bytes(),
+        HashTree::Leaf(record_hash(space).to_vec()),
+    )
+}
+
+// Updates the trie for a space written or, with None, removed; the certified
+// data is set with the block the write appends
+pub(crate) fn certify_space(id: u64, space: Option<&GreenSpace>) {
+    SPACE_TREE.with(|t| {
+        let mut tree = t.borrow_mut();
+        let mut current = space.map(|space| space_leaf(id, space).digest());
+        for depth in (0..=ID_BITS).rev() {
+            let key = (depth, prefix_of(id, depth));
+            if depth < ID_BITS {
+                let child = |bit| {
+                    tree.get(&(depth + 1, (key.1 << 1) | bit))
+                        .map(|hash| -> Hash { hash.as_slice().try_into().unwrap() })
+                };
+                let (left, right) = (child(0), child(1));
+                current = (left.is_some() || right.is_some()).then(|| {
+                    fork_hash(
+                        &left.unwrap_or_else(empty_hash),
+                        &right.unwrap_or_else(empty_hash),
+                    )
+                });
+            }
+            match current {
+                Some(hash) => tree.insert(key, NodeHash::try_from(&hash[..]).unwrap()),
+                None => tree.remove(&key),
+            };
+        }
+    });
+}
+
+// Recomputes the trie from the spaces when it is missing or stale, e.g. after
+// an upgrade to a version with it or a restore
+pub(crate) fn rebuild_certified_spaces() {
+    SPACE_TREE.with(|t| {
+        let keys: Vec<(u64, u64)> = t.borrow().iter().map(|(key, _)| key).collect();
+        let mut tree = t.borrow_mut();
+        for key in keys {
+            tree.remove(&key);
+        }
+    });
+    let spaces: Vec<GreenSpace> =
+        GREEN_SPACE_STORAGE.with(|s| s.borrow().iter().map(|(_, space)| space).collect());
+    for space in &spaces {
+        certify_space(space.id, Some(space));
+    }
+}
+
+pub(crate) fn ensure_certified_spaces() {
+    let missing = SPACE_TREE.with(|t| t.borrow().is_empty())
+        && GREEN_SPACE_STORAGE.with(|s| !s.borrow().is_empty());
+    if missing {
+        rebuild_certified_spaces();
+    }
+}
+
+// The spaces subtree with only the path to one id revealed
+fn space_witness(id: u64, depth: u64) -> HashTree {
+    if depth == ID_BITS {
+        return match crate::_get_green_space(&id) {
+            Some(space) => space_leaf(id, &space),
+            None => HashTree::Empty,
+        };
+    }
+    if node_hash(depth, prefix_of(id, depth)).is_none() {
+        return HashTree::Empty;
+    }
+    let bit = (id >> (ID_BITS - 1 - depth)) & 1;
+    let sibling = (prefix_of(id, depth) << 1) | (bit ^ 1);
+    let pruned = node_hash(depth + 1, sibling).map_or(HashTree::Empty, HashTree::Pruned);
+    let revealed = space_witness(id, depth + 1);
+    if bit == 0 {
+        HashTree::fork(revealed, pruned)
+    } else {
+        HashTree::fork(pruned, revealed)
+    }
+}
+
+// The certified tree: the spaces under "green_spaces", and the tip of the
+// block log under "last_block_hash" and "last_block_index" as ICRC-3 has it.
+// Each part is pruned unless revealed
+fn certified_tree(space_id: Option<u64>, reveal_tip: bool) -> HashTree {
+    let spaces = match space_id {
+        Some(id) => space_witness(id, 0),
+        None => HashTree::Pruned(node_hash(0, 0).unwrap_or_else(empty_hash)),
+    };
+    let spaces = HashTree::labeled(b"green_spaces", spaces);
+    let Some((index, hash)) = block_tip() else {
+        return spaces;
+    };
+    let tip = HashTree::fork(
+        HashTree::labeled(b"last_block_hash", HashTree::Leaf(hash.to_vec())),
+        HashTree::labeled(b"last_block_index", HashTree::Leaf(leb128(index))),
+    );
+    let tip = if reveal_tip {
+        tip
+    } else {
+        HashTree::Pruned(tip.digest())
+    };
+    HashTree::fork(spaces, tip)
+}
+
+// Publishes the root of the certified tree as the canister's certified data
+pub(crate) fn certify_data() {
+    ic_cdk::api::set_certified_data(&certified_tree(None, false).digest());
+}
+
+// The certified tree revealing only the block tip, as CBOR
+pub(crate) fn block_tip_witness() -> Vec<u8> {
+    certified_tree(None, true).to_cbor()
+}
+
+// Function to get a green space with a witness that its contents are
+// certified by the subnet; call it as a query
+#[ic_cdk::query]
+fn get_green_space_certified(id: u64) -> Result<CertifiedGreenSpace, Error> {
+    let space = crate::_get_green_space(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("A green space with id={} not found", id),
+    })?;
+    Ok(CertifiedGreenSpace {
+        encoded: space.to_bytes().into_owned(),
+        space,
+        witness: certified_tree(Some(id), false).to_cbor(),
+        certificate: ic_cdk::api::data_certificate(),
+    })
+}
@@ -0,0 +1,187 @@
+use crate::block_log::log_config;
+use crate::maintenance::ensure_writable;
+use crate::ulid::new_ulid;
+use crate::{ensure_controller, get_memory, Error, Memory};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::Cell;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+const CHALLENGE_CONFIG_MEMORY_ID: u8 = 77;
+
+const MAX_DIFFICULTY_BITS: u8 = 32;
+const MAX_TTL_SECS: u64 = 60 * 60;
+// Outstanding challenges kept at once; the oldest go first beyond this
+const MAX_OUTSTANDING: usize = 10_000;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct ChallengeConfig {
+    // Leading zero bits a solution's hash needs; each bit doubles the work
+    difficulty_bits: u8,
+    // How long a challenge can be solved and used
+    ttl_secs: u64,
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        ChallengeConfig {
+            difficulty_bits: 20,
+            ttl_secs: 10 * 60,
+        }
+    }
+}
+
+impl_storable!(ChallengeConfig, 32);
+
+// Find a solution whose SHA-256 of "<nonce>:<solution>", with the solution in
+// decimal, starts with difficulty_bits zero bits
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReportChallenge {
+    nonce: String,
+    difficulty_bits: u8,
+    expires_at: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ChallengeSolution {
+    nonce: String,
+    solution: u64,
+}
+
+thread_local! {
+    static CHALLENGE_CONFIG: RefCell<Cell<ChallengeConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(CHALLENGE_CONFIG_MEMORY_ID), ChallengeConfig::default())
+            .expect("Cannot initialize the challenge config")
+    );
+
+    // Challenges handed out and not used yet, by nonce and by expiry; kept on
+    // the heap, as an upgrade merely means asking for another
+    static OUTSTANDING: RefCell<BTreeMap<String, ReportChallenge>> =
+        const { RefCell::new(BTreeMap::new()) };
+    static BY_EXPIRY: RefCell<BTreeSet<(u64, String)>> =
+        const { RefCell::new(BTreeSet::new()) };
+}
+
+fn challenge_config() -> ChallengeConfig {
+    CHALLENGE_CONFIG.with(|c| *c.borrow().get())
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+fn forget_challenge(challenge: &ReportChallenge) {
+    OUTSTANDING.with(|o| o.borrow_mut().remove(&challenge.nonce));
+    BY_EXPIRY.with(|e| {
+        e.borrow_mut()
+            .remove(&(challenge.expires_at, challenge.nonce.clone()))
+    });
+}
+
+// Drops expired challenges, and the oldest when too many are outstanding
+fn expire_challenges(now: u64) {
+    loop {
+        let oldest = BY_EXPIRY.with(|e| e.borrow().first().cloned());
+        let Some((expires_at, nonce)) = oldest else {
+            break;
+        };
+        let outstanding = OUTSTANDING.with(|o| o.borrow().len());
+        if expires_at > now && outstanding < MAX_OUTSTANDING {
+            break;
+        }
+        OUTSTANDING.with(|o| o.borrow_mut().remove(&nonce));
+        BY_EXPIRY.with(|e| e.borrow_mut().remove(&(expires_at, nonce)));
+    }
+}
+
+// Fails unless a caller signed in, or the anonymous principal brings a
+// solved challenge, which is used up
+pub(crate) fn ensure_challenge_solved(solution: Option<ChallengeSolution>) -> Result<(), Error> {
+    if ic_cdk::caller() != Principal::anonymous() {
+        return Ok(());
+    }
+    let Some(solution) = solution else {
+        return Err(Error::Unauthorized {
+            msg: "Sign in, or solve a challenge from request_report_challenge".to_string(),
+        });
+    };
+    let now = time();
+    expire_challenges(now);
+    let challenge = OUTSTANDING
+        .with(|o| o.borrow().get(&solution.nonce).cloned())
+        .ok_or_else(|| Error::Unauthorized {
+            msg: "The challenge expired or was used already".to_string(),
+        })?;
+    let hash = Sha256::digest(format!("{}:{}", solution.nonce, solution.solution).as_bytes());
+    if leading_zero_bits(&hash) < u32::from(challenge.difficulty_bits) {
+        return Err(Error::Unauthorized {
+            msg: "The solution does not solve the challenge".to_string(),
+        });
+    }
+    forget_challenge(&challenge);
+    Ok(())
+}
+
+// Function for anonymous reporters to get a one-time proof-of-work challenge
+#[ic_cdk::update]
+fn request_report_challenge() -> Result<ReportChallenge, Error> {
+    ensure_writable()?;
+    let config = challenge_config();
+    let now = time();
+    expire_challenges(now);
+    let challenge = ReportChallenge {
+        nonce: new_ulid(),
+        difficulty_bits: config.difficulty_bits,
+        expires_at: now + config.ttl_secs * 1_000_000_000,
+    };
+    OUTSTANDING.with(|o| {
+        o.borrow_mut()
+            .insert(challenge.nonce.clone(), challenge.clone())
+    });
+    BY_EXPIRY.with(|e| {
+        e.borrow_mut()
+            .insert((challenge.expires_at, challenge.nonce.clone()))
+    });
+    Ok(challenge)
+}
+
+// Function for controllers to tune how much work anonymous reports take
+#[ic_cdk::update]
+fn set_challenge_config(config: ChallengeConfig) -> Result<ChallengeConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if config.difficulty_bits > MAX_DIFFICULTY_BITS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The difficulty must be at most {} bits",
+                MAX_DIFFICULTY_BITS
+            ),
+        });
+    }
+    if config.ttl_secs == 0 || config.ttl_secs > MAX_TTL_SECS {
+        return Err(Error::InvalidInput {
+            msg: format!("The TTL must be between 1 and {} seconds", MAX_TTL_SECS),
+        });
+    }
+    CHALLENGE_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config)
+            .expect("Cannot store the challenge config")
+    });
+    log_config("challenge_config", &config);
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_challenge_config() -> Result<ChallengeConfig, Error> {
+    Ok(challenge_config())
+}
@@ -0,0 +1,79 @@
+use crate::block_log::append_green_space_block;
+use crate::{get_memory, next_id, Error, GreenSpace, Memory};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const CHANGE_LOG_MEMORY_ID: u8 = 27;
+
+const MAX_CHANGES_PER_PAGE: u32 = 500;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum ChangeOp {
+    Upsert,
+    Delete,
+}
+
+// One write to a green space, in the order writes happened; upserts carry the
+// record as written (boundaries are not included)
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ChangeLogEntry {
+    pub(crate) seq: u64,
+    pub(crate) recorded_at: u64,
+    pub(crate) op: ChangeOp,
+    pub(crate) space_id: u64,
+    pub(crate) space: Option<GreenSpace>,
+}
+
+impl_storable!(ChangeLogEntry, 1536);
+
+thread_local! {
+    // Entries keyed by their sequence number
+    pub(crate) static CHANGE_LOG: RefCell<StableBTreeMap<u64, ChangeLogEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(CHANGE_LOG_MEMORY_ID)));
+}
+
+pub(crate) fn record_change(op: ChangeOp, space_id: u64, space: Option<GreenSpace>) {
+    append_green_space_block(op, space_id, space.as_ref());
+    let entry = ChangeLogEntry {
+        seq: next_id(CHANGE_LOG_MEMORY_ID),
+        recorded_at: time(),
+        op,
+        space_id,
+        space,
+    };
+    CHANGE_LOG.with(|log| log.borrow_mut().insert(entry.seq, entry));
+}
+
+// Up to `limit` entries following `after_seq`, or from the start without one
+pub(crate) fn changes_after(after_seq: Option<u64>, limit: usize) -> Vec<ChangeLogEntry> {
+    let start = after_seq.map_or(0, |seq| seq + 1);
+    CHANGE_LOG.with(|log| {
+        log.borrow()
+            .range(start..)
+            .take(limit)
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
+
+pub(crate) fn last_change_seq() -> Option<u64> {
+    CHANGE_LOG.with(|log| log.borrow().last_key_value().map(|(seq, _)| seq))
+}
+
+// Number of entries following `after_seq`
+pub(crate) fn count_changes_after(after_seq: Option<u64>) -> u64 {
+    let start = after_seq.map_or(0, |seq| seq + 1);
+    CHANGE_LOG.with(|log| log.borrow().range(start..).count() as u64)
+}
+
+// Function to page through the change log
+#[ic_cdk::query]
+fn get_change_log(after_seq: Option<u64>, limit: u32) -> Result<Vec<ChangeLogEntry>, Error> {
+    if limit == 0 || limit > MAX_CHANGES_PER_PAGE {
+        return Err(Error::InvalidInput {
+            msg: format!("limit must be between 1 and {}", MAX_CHANGES_PER_PAGE),
+        });
+    }
+    Ok(changes_after(after_seq, limit as usize))
+}
@@ -0,0 +1,190 @@
+use crate::block_log::log_upsert;
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::{caller, ensure_controller, get_memory, next_id, Error, Memory};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const CITIES_MEMORY_ID: u8 = 24;
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_CITY_ADMINS: usize = 50;
+
+// A municipality served by this canister; records carrying its id can only be
+// written by its admins and the canister controllers
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct City {
+    id: u64,
+    name: String,
+    admins: Vec<Principal>,
+    created_at: u64,
+    updated_at: u64,
+}
+
+impl_storable!(City, 4096);
+
+thread_local! {
+    pub(crate) static CITIES: RefCell<StableBTreeMap<u64, City, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(CITIES_MEMORY_ID)));
+}
+
+fn city_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A city with id={} not found", id),
+    }
+}
+
+fn _get_city(id: &u64) -> Option<City> {
+    CITIES.with(|s| s.borrow().get(id))
+}
+
+fn do_insert_city(city: &City) {
+    CITIES.with(|s| s.borrow_mut().insert(city.id, city.clone()));
+    log_upsert("city", &city.id, city);
+}
+
+fn validate_city_name(name: &str) -> Result<(), Error> {
+    if name.trim().is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("City name must be 1-{} characters", MAX_NAME_LEN),
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn ensure_city_exists(id: u64) -> Result<(), Error> {
+    _get_city(&id).map(|_| ()).ok_or_else(|| city_not_found(id))
+}
+
+// Fails unless the caller is a controller or, for a city, one of its admins;
+// operations on unscoped records (None) stay with the controllers
+pub(crate) fn ensure_city_admin(city_id: Option<u64>) -> Result<(), Error> {
+    let Some(city_id) = city_id else {
+        return ensure_controller();
+    };
+    let city = _get_city(&city_id).ok_or_else(|| city_not_found(city_id))?;
+    if city.admins.contains(&caller()) || ensure_controller().is_ok() {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: format!(
+                "Only admins of city id={} can perform this operation",
+                city_id
+            ),
+        })
+    }
+}
+
+// Guards writes to records of a city; records without a city stay open to
+// everyone, as all records were before cities existed
+pub(crate) fn ensure_city_write(city_id: Option<u64>) -> Result<(), Error> {
+    match city_id {
+        Some(_) => ensure_city_admin(city_id),
+        None => Ok(()),
+    }
+}
+
+// Whether a record of `city_id` passes an optional city filter
+pub(crate) fn in_city(filter: Option<u64>, city_id: Option<u64>) -> bool {
+    filter.is_none() || filter == city_id
+}
+
+#[ic_cdk::update]
+fn create_city(name: String) -> Result<City, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    validate_city_name(&name)?;
+
+    let now = time();
+    let city = City {
+        id: next_id(CITIES_MEMORY_ID),
+        name,
+        admins: vec![],
+        created_at: now,
+        updated_at: now,
+    };
+
+    do_insert_city(&city);
+    Ok(city)
+}
+
+#[ic_cdk::query]
+fn get_city(id: u64) -> Result<City, Error> {
+    _get_city(&id).ok_or_else(|| city_not_found(id))
+}
+
+#[ic_cdk::query]
+fn get_all_cities(next_token: Option<String>) -> Result<Page<City>, Error> {
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(CITIES.with(|s| page_by_size(s.borrow().range(range))))
+}
+
+#[ic_cdk::update]
+fn rename_city(id: u64, name: String) -> Result<City, Error> {
+    ensure_writable()?;
+    ensure_city_admin(Some(id))?;
+    validate_city_name(&name)?;
+    let mut city = _get_city(&id).ok_or_else(|| city_not_found(id))?;
+    city.name = name;
+    city.updated_at = time();
+    do_insert_city(&city);
+    Ok(city)
+}
+
+// Function to grant a principal admin rights over one city
+#[ic_cdk::update]
+fn add_city_admin(id: u64, admin: Principal) -> Result<City, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut city = _get_city(&id).ok_or_else(|| city_not_found(id))?;
+    if !city.admins.contains(&admin) {
+        if city.admins.len() >= MAX_CITY_ADMINS {
+            return Err(Error::InvalidInput {
+                msg: format!("A city can have at most {} admins", MAX_CITY_ADMINS),
+            });
+        }
+        city.admins.push(admin);
+        city.updated_at = time();
+        do_insert_city(&city);
+    }
+    Ok(city)
+}
+
+#[ic_cdk::update]
+fn remove_city_admin(id: u64, admin: Principal) -> Result<City, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut city = _get_city(&id).ok_or_else(|| city_not_found(id))?;
+    city.admins.retain(|a| *a != admin);
+    city.updated_at = time();
+    do_insert_city(&city);
+    Ok(city)
+}
+
+// Function to get the cities the caller administers
+#[ic_cdk::query]
+fn get_my_admin_cities(next_token: Option<String>) -> Result<Page<City>, Error> {
+    let caller = ic_cdk::caller();
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(CITIES.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, city)| city.admins.contains(&caller)),
+        )
+    }))
+}
+
+// Stores a city run by the given admins, for tests of access checks
+#[cfg(test)]
+pub(crate) fn insert_test_city(id: u64, admins: Vec<Principal>) {
+    do_insert_city(&City {
+        id,
+        name: format!("City {}", id),
+        admins,
+        created_at: 0,
+        updated_at: 0,
+    });
+}
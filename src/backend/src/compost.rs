@@ -0,0 +1,261 @@
+use crate::block_log::log_upsert;
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, page_sorted, Page};
+use crate::{
+    authenticated_caller, ensure_green_space_exists, ensure_space_admin, get_memory, next_id,
+    Error, Memory,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const COMPOST_SITES_MEMORY_ID: u8 = 14;
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_STEWARDS: usize = 10;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum CompostMaterial {
+    FruitAndVegetableScraps,
+    CoffeeGrounds,
+    Eggshells,
+    YardTrimmings,
+    Leaves,
+    Paper,
+    Cardboard,
+    WoodChips,
+    Manure,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CompostSite {
+    id: u64,
+    pub(crate) space_id: u64,
+    name: String,
+    capacity_liters: u64,
+    accepted_materials: Vec<CompostMaterial>,
+    fill_level_percent: u8,
+    // Principals allowed to update the site; the registering principal is the first
+    stewards: Vec<Principal>,
+    fill_level_updated_at: u64,
+    created_at: u64,
+}
+
+impl_storable!(CompostSite, 1024);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CompostSitePayload {
+    space_id: u64,
+    name: String,
+    capacity_liters: u64,
+    accepted_materials: Vec<CompostMaterial>,
+}
+
+thread_local! {
+    pub(crate) static COMPOST_SITES: RefCell<StableBTreeMap<u64, CompostSite, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(COMPOST_SITES_MEMORY_ID)));
+}
+
+fn compost_site_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A composting site with id={} not found", id),
+    }
+}
+
+fn do_insert_compost_site(site: &CompostSite) {
+    COMPOST_SITES.with(|s| s.borrow_mut().insert(site.id, site.clone()));
+    log_upsert("compost_site", &site.id, site);
+}
+
+// Takes a user off the stewards of every site; returns the number of sites
+pub(crate) fn remove_steward(user: &Principal) -> u64 {
+    let stewarded: Vec<CompostSite> = COMPOST_SITES.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, site)| site)
+            .filter(|site| site.stewards.contains(user))
+            .collect()
+    });
+    for mut site in stewarded.iter().cloned() {
+        site.stewards.retain(|steward| steward != user);
+        do_insert_compost_site(&site);
+    }
+    stewarded.len() as u64
+}
+
+// Loads a site the caller is a steward of; the anonymous principal never is,
+// even on sites registered before stewards had to sign in
+fn get_stewarded_site(id: u64) -> Result<CompostSite, Error> {
+    let caller = authenticated_caller()?;
+    let site = COMPOST_SITES
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| compost_site_not_found(id))?;
+    if !site.stewards.contains(&caller) {
+        return Err(Error::Unauthorized {
+            msg: format!("Caller is not a steward of composting site id={}", id),
+        });
+    }
+    Ok(site)
+}
+
+fn validate_compost_site(payload: &CompostSitePayload) -> Result<(), Error> {
+    if payload.name.trim().is_empty() || payload.name.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Composting site name must be 1-{} characters", MAX_NAME_LEN),
+        });
+    }
+    if payload.capacity_liters == 0 {
+        return Err(Error::InvalidInput {
+            msg: "Composting site capacity must be greater than zero".to_string(),
+        });
+    }
+    if payload.accepted_materials.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "A composting site must accept at least one material".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Function for admins of a green space to register a community composting
+// site in it; the caller becomes its steward
+#[ic_cdk::update]
+fn add_compost_site(payload: CompostSitePayload) -> Result<CompostSite, Error> {
+    ensure_writable()?;
+    let steward = authenticated_caller()?;
+    ensure_space_admin(payload.space_id)?;
+    validate_compost_site(&payload)?;
+
+    let mut accepted_materials = Vec::new();
+    for material in payload.accepted_materials {
+        if !accepted_materials.contains(&material) {
+            accepted_materials.push(material);
+        }
+    }
+
+    let now = time();
+    let site = CompostSite {
+        id: next_id(COMPOST_SITES_MEMORY_ID),
+        space_id: payload.space_id,
+        name: payload.name,
+        capacity_liters: payload.capacity_liters,
+        accepted_materials,
+        fill_level_percent: 0,
+        stewards: vec![steward],
+        fill_level_updated_at: now,
+        created_at: now,
+    };
+
+    do_insert_compost_site(&site);
+    Ok(site)
+}
+
+#[ic_cdk::query]
+fn get_compost_site(id: u64) -> Result<CompostSite, Error> {
+    COMPOST_SITES
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| compost_site_not_found(id))
+}
+
+#[ic_cdk::query]
+fn get_compost_sites_for_space(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<CompostSite>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(COMPOST_SITES.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, site)| site.space_id == space_id),
+        )
+    }))
+}
+
+// Function for a steward to report how full a composting site is
+#[ic_cdk::update]
+fn update_compost_fill_level(id: u64, fill_level_percent: u8) -> Result<CompostSite, Error> {
+    ensure_writable()?;
+    let mut site = get_stewarded_site(id)?;
+    if fill_level_percent > 100 {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Fill level must be between 0 and 100%, got {}",
+                fill_level_percent
+            ),
+        });
+    }
+
+    site.fill_level_percent = fill_level_percent;
+    site.fill_level_updated_at = time();
+    do_insert_compost_site(&site);
+    Ok(site)
+}
+
+// Function for a steward to change the materials a site accepts
+#[ic_cdk::update]
+fn update_compost_accepted_materials(
+    id: u64,
+    accepted_materials: Vec<CompostMaterial>,
+) -> Result<CompostSite, Error> {
+    ensure_writable()?;
+    let mut site = get_stewarded_site(id)?;
+    if accepted_materials.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "A composting site must accept at least one material".to_string(),
+        });
+    }
+
+    site.accepted_materials.clear();
+    for material in accepted_materials {
+        if !site.accepted_materials.contains(&material) {
+            site.accepted_materials.push(material);
+        }
+    }
+    do_insert_compost_site(&site);
+    Ok(site)
+}
+
+// Function for a steward to share stewardship of a site with another principal
+#[ic_cdk::update]
+fn add_compost_steward(id: u64, steward: Principal) -> Result<CompostSite, Error> {
+    ensure_writable()?;
+    let mut site = get_stewarded_site(id)?;
+    if site.stewards.contains(&steward) {
+        return Ok(site);
+    }
+    if site.stewards.len() >= MAX_STEWARDS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "A composting site can have at most {} stewards",
+                MAX_STEWARDS
+            ),
+        });
+    }
+
+    site.stewards.push(steward);
+    do_insert_compost_site(&site);
+    Ok(site)
+}
+
+// Function to find composting sites that accept a material and still have room,
+// emptiest first
+#[ic_cdk::query]
+fn find_compost_sites_by_material(
+    material: CompostMaterial,
+    next_token: Option<String>,
+) -> Result<Page<CompostSite>, Error> {
+    let mut result: Vec<_> = COMPOST_SITES.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, site)| {
+                site.accepted_materials.contains(&material) && site.fill_level_percent < 100
+            })
+            .map(|(id, site)| ((site.fill_level_percent, id), site))
+            .collect()
+    });
+    result.sort_by_key(|(key, _)| *key);
+    page_sorted(result, &next_token)
+}
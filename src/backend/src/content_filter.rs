@@ -0,0 +1,146 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::maintenance::ensure_writable;
+use crate::{ensure_controller, get_memory, Error, Memory};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const BLOCKED_TERMS_MEMORY_ID: u8 = 76;
+
+const MAX_TERM_LEN: usize = 50;
+const MAX_BLOCKED_TERMS: u64 = 1000;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum TermSeverity {
+    // Submissions containing the term are refused
+    Reject,
+    // Submissions containing the term wait for a moderator
+    Hold,
+}
+
+impl_storable!(TermSeverity, 32);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct BlockedTerm(String);
+
+impl_storable!(BlockedTerm, 64);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BlockedTermEntry {
+    term: String,
+    severity: TermSeverity,
+}
+
+thread_local! {
+    // Terms in their normalized form, with what a match leads to
+    pub(crate) static BLOCKED_TERMS: RefCell<StableBTreeMap<BlockedTerm, TermSeverity, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(BLOCKED_TERMS_MEMORY_ID)));
+}
+
+// Lowercase words separated by single spaces, so terms match whole words
+// whatever the punctuation around them
+fn normalize_words(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The blocked terms of the given severity that occur in any of the texts
+fn matching_terms(texts: &[&str], severity: TermSeverity) -> Vec<String> {
+    let texts: Vec<String> = texts
+        .iter()
+        .map(|text| format!(" {} ", normalize_words(text)))
+        .collect();
+    BLOCKED_TERMS.with(|t| {
+        t.borrow()
+            .iter()
+            .filter(|(_, s)| *s == severity)
+            .map(|(BlockedTerm(term), _)| term)
+            .filter(|term| {
+                let padded = format!(" {} ", term);
+                texts.iter().any(|text| text.contains(&padded))
+            })
+            .collect()
+    })
+}
+
+// Fails when any of the texts contains a term that is refused
+pub(crate) fn ensure_no_rejected_terms(texts: &[&str]) -> Result<(), Error> {
+    match matching_terms(texts, TermSeverity::Reject).first() {
+        Some(term) => Err(Error::InvalidInput {
+            msg: format!("The term \"{}\" is not allowed", term),
+        }),
+        None => Ok(()),
+    }
+}
+
+// Why content containing held terms waits for a moderator, if it does
+pub(crate) fn held_term_signals(texts: &[&str]) -> Vec<String> {
+    matching_terms(texts, TermSeverity::Hold)
+        .into_iter()
+        .map(|term| format!("Contains the held term \"{}\"", term))
+        .collect()
+}
+
+// Function for controllers to block a term, or change what a match leads to
+#[ic_cdk::update]
+fn set_blocked_term(term: String, severity: TermSeverity) -> Result<BlockedTermEntry, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let normalized = normalize_words(&term);
+    if normalized.is_empty() || normalized.len() > MAX_TERM_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Terms must have 1-{} bytes of letters and digits",
+                MAX_TERM_LEN
+            ),
+        });
+    }
+    let key = BlockedTerm(normalized.clone());
+    let full = BLOCKED_TERMS.with(|t| {
+        let terms = t.borrow();
+        !terms.contains_key(&key) && terms.len() >= MAX_BLOCKED_TERMS
+    });
+    if full {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} terms can be blocked", MAX_BLOCKED_TERMS),
+        });
+    }
+    BLOCKED_TERMS.with(|t| t.borrow_mut().insert(key, severity));
+    log_upsert("blocked_term", &normalized, &severity);
+    Ok(BlockedTermEntry {
+        term: normalized,
+        severity,
+    })
+}
+
+#[ic_cdk::update]
+fn remove_blocked_term(term: String) -> Result<BlockedTermEntry, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let normalized = normalize_words(&term);
+    let severity = BLOCKED_TERMS
+        .with(|t| t.borrow_mut().remove(&BlockedTerm(normalized.clone())))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("The term \"{}\" is not blocked", term),
+        })?;
+    log_delete("blocked_term", &normalized);
+    Ok(BlockedTermEntry {
+        term: normalized,
+        severity,
+    })
+}
+
+// Function for controllers to list the blocked terms; the list is not public,
+// so it cannot be worked around by reading it
+#[ic_cdk::query]
+fn get_blocked_terms() -> Result<Vec<BlockedTermEntry>, Error> {
+    ensure_controller()?;
+    Ok(BLOCKED_TERMS.with(|t| {
+        t.borrow()
+            .iter()
+            .map(|(BlockedTerm(term), severity)| BlockedTermEntry { term, severity })
+            .collect()
+    }))
+}
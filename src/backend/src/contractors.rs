@@ -0,0 +1,511 @@
+use crate::block_log::log_upsert;
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::tickets::{get_ticket, set_ticket_status, TicketStatus};
+use crate::{
+    ensure_controller, ensure_space_admin, get_memory, next_id, Error, Memory, NANOS_PER_DAY,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const CONTRACTORS_MEMORY_ID: u8 = 99;
+const WORK_ORDERS_MEMORY_ID: u8 = 100;
+
+const MAX_COMPANY_LEN: usize = 150;
+const MAX_CONTACT_LEN: usize = 200;
+const MAX_CERTIFICATIONS: usize = 10;
+const MAX_CERTIFICATION_LEN: usize = 100;
+const MAX_NOTES_LEN: usize = 500;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Contractor {
+    id: u64,
+    company: String,
+    certifications: Vec<String>,
+    // How the city reaches the company, such as an email address or phone number
+    contact: String,
+    // The principal the contractor reports completed work with
+    principal: Option<Principal>,
+    // Inactive contractors keep their history but get no new work orders
+    active: bool,
+    created_at: u64,
+    updated_at: u64,
+}
+
+impl_storable!(Contractor, 2048);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ContractorPayload {
+    company: String,
+    certifications: Vec<String>,
+    contact: String,
+    principal: Option<Principal>,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum WorkOrderStatus {
+    Assigned,
+    // Reported done by the contractor, waiting for the city to confirm
+    Completed,
+    Confirmed,
+    Cancelled,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WorkOrder {
+    id: u64,
+    ticket_id: u64,
+    pub(crate) space_id: u64,
+    contractor_id: u64,
+    // In the city's currency, in cents
+    estimated_cost_cents: u64,
+    actual_cost_cents: Option<u64>,
+    due_at: Option<u64>,
+    status: WorkOrderStatus,
+    assigned_by: Principal,
+    assigned_at: u64,
+    completed_at: Option<u64>,
+    confirmed_at: Option<u64>,
+    // Times a reported completion was sent back for more work
+    rework_count: u32,
+    notes: String,
+}
+
+impl_storable!(WorkOrder, 2048);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct WorkOrderPayload {
+    ticket_id: u64,
+    contractor_id: u64,
+    estimated_cost_cents: u64,
+    due_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ContractorPerformance {
+    contractor: Contractor,
+    // Oldest first
+    work_orders: Vec<WorkOrder>,
+    open: u64,
+    confirmed: u64,
+    cancelled: u64,
+    reworks: u64,
+    // Confirmed work orders completed by their due date, or without one
+    on_time: u64,
+    average_days_to_complete: Option<f64>,
+    // Over confirmed work orders
+    estimated_cents: u64,
+    actual_cents: u64,
+    cost_overrun_percent: Option<f64>,
+}
+
+thread_local! {
+    pub(crate) static CONTRACTORS: RefCell<StableBTreeMap<u64, Contractor, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(CONTRACTORS_MEMORY_ID)));
+
+    pub(crate) static WORK_ORDERS: RefCell<StableBTreeMap<u64, WorkOrder, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(WORK_ORDERS_MEMORY_ID)));
+}
+
+fn contractor_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A contractor with id={} not found", id),
+    }
+}
+
+fn work_order_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A work order with id={} not found", id),
+    }
+}
+
+fn _get_contractor(id: u64) -> Result<Contractor, Error> {
+    CONTRACTORS
+        .with(|c| c.borrow().get(&id))
+        .ok_or_else(|| contractor_not_found(id))
+}
+
+fn _get_work_order(id: u64) -> Result<WorkOrder, Error> {
+    WORK_ORDERS
+        .with(|w| w.borrow().get(&id))
+        .ok_or_else(|| work_order_not_found(id))
+}
+
+fn do_insert_contractor(contractor: &Contractor) {
+    CONTRACTORS.with(|c| c.borrow_mut().insert(contractor.id, contractor.clone()));
+    log_upsert("contractor", &contractor.id, contractor);
+}
+
+fn do_insert_work_order(order: &WorkOrder) {
+    WORK_ORDERS.with(|w| w.borrow_mut().insert(order.id, order.clone()));
+    log_upsert("work_order", &order.id, order);
+}
+
+// Pages through the work orders, in id order, that match
+fn work_orders_page(
+    next_token: &Option<String>,
+    filter: impl Fn(&WorkOrder) -> bool,
+) -> Result<Page<WorkOrder>, Error> {
+    let range = keys_after_token::<u64>(next_token)?;
+    Ok(WORK_ORDERS
+        .with(|w| page_by_size(w.borrow().range(range).filter(|(_, order)| filter(order)))))
+}
+
+fn work_orders_matching(filter: impl Fn(&WorkOrder) -> bool) -> Vec<WorkOrder> {
+    WORK_ORDERS.with(|w| {
+        w.borrow()
+            .iter()
+            .map(|(_, order)| order)
+            .filter(|order| filter(order))
+            .collect()
+    })
+}
+
+// The id of the work order of a ticket that is assigned or awaiting
+// confirmation, if any
+pub(crate) fn open_work_order_id(ticket_id: u64) -> Option<u64> {
+    work_orders_matching(|order| {
+        order.ticket_id == ticket_id
+            && matches!(
+                order.status,
+                WorkOrderStatus::Assigned | WorkOrderStatus::Completed
+            )
+    })
+    .first()
+    .map(|order| order.id)
+}
+
+// The contractor whose linked principal is the caller
+fn caller_contractor() -> Result<Contractor, Error> {
+    let caller = ic_cdk::caller();
+    CONTRACTORS
+        .with(|c| {
+            c.borrow()
+                .iter()
+                .map(|(_, contractor)| contractor)
+                .find(|contractor| contractor.principal == Some(caller))
+        })
+        .ok_or_else(|| Error::Unauthorized {
+            msg: "The caller is not linked to a contractor".to_string(),
+        })
+}
+
+// `id` is that of the contractor being updated, if any
+fn validate_contractor(payload: &ContractorPayload, id: Option<u64>) -> Result<(), Error> {
+    if payload.company.trim().is_empty() || payload.company.len() > MAX_COMPANY_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Company name must be 1-{} characters", MAX_COMPANY_LEN),
+        });
+    }
+    if payload.contact.trim().is_empty() || payload.contact.len() > MAX_CONTACT_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Contact details must be 1-{} characters", MAX_CONTACT_LEN),
+        });
+    }
+    if payload.certifications.len() > MAX_CERTIFICATIONS
+        || payload
+            .certifications
+            .iter()
+            .any(|c| c.trim().is_empty() || c.len() > MAX_CERTIFICATION_LEN)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "At most {} certifications of 1-{} characters each can be listed",
+                MAX_CERTIFICATIONS, MAX_CERTIFICATION_LEN
+            ),
+        });
+    }
+    if payload.principal == Some(Principal::anonymous()) {
+        return Err(Error::InvalidInput {
+            msg: "A contractor cannot be linked to the anonymous principal".to_string(),
+        });
+    }
+    // Completions are attributed by principal, so it links one contractor only
+    if let Some(principal) = payload.principal {
+        let taken = CONTRACTORS.with(|c| {
+            c.borrow().iter().any(|(other, contractor)| {
+                Some(other) != id && contractor.principal == Some(principal)
+            })
+        });
+        if taken {
+            return Err(Error::Conflict {
+                msg: format!("{} is already linked to a contractor", principal),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Function for controllers to add a contractor to the registry
+#[ic_cdk::update]
+fn register_contractor(payload: ContractorPayload) -> Result<Contractor, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    validate_contractor(&payload, None)?;
+    let now = time();
+    let contractor = Contractor {
+        id: next_id(CONTRACTORS_MEMORY_ID),
+        company: payload.company,
+        certifications: payload.certifications,
+        contact: payload.contact,
+        principal: payload.principal,
+        active: true,
+        created_at: now,
+        updated_at: now,
+    };
+    do_insert_contractor(&contractor);
+    Ok(contractor)
+}
+
+// Function for controllers to change the details of a contractor
+#[ic_cdk::update]
+fn update_contractor(id: u64, payload: ContractorPayload) -> Result<Contractor, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut contractor = _get_contractor(id)?;
+    validate_contractor(&payload, Some(id))?;
+    contractor.company = payload.company;
+    contractor.certifications = payload.certifications;
+    contractor.contact = payload.contact;
+    contractor.principal = payload.principal;
+    contractor.updated_at = time();
+    do_insert_contractor(&contractor);
+    Ok(contractor)
+}
+
+// Function for controllers to stop or resume giving work to a contractor
+#[ic_cdk::update]
+fn set_contractor_active(id: u64, active: bool) -> Result<Contractor, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut contractor = _get_contractor(id)?;
+    contractor.active = active;
+    contractor.updated_at = time();
+    do_insert_contractor(&contractor);
+    Ok(contractor)
+}
+
+#[ic_cdk::query]
+fn get_contractor(id: u64) -> Result<Contractor, Error> {
+    _get_contractor(id)
+}
+
+#[ic_cdk::query]
+fn list_contractors(
+    include_inactive: bool,
+    next_token: Option<String>,
+) -> Result<Page<Contractor>, Error> {
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(CONTRACTORS.with(|c| {
+        page_by_size(
+            c.borrow()
+                .range(range)
+                .filter(|(_, contractor)| include_inactive || contractor.active),
+        )
+    }))
+}
+
+// Function for city admins to assign an unresolved ticket to a contractor;
+// the ticket moves to InProgress
+#[ic_cdk::update]
+fn assign_work_order(payload: WorkOrderPayload) -> Result<WorkOrder, Error> {
+    ensure_writable()?;
+    let ticket = get_ticket(payload.ticket_id)?;
+    ensure_space_admin(ticket.space_id)?;
+    if ticket.status == TicketStatus::Resolved {
+        return Err(Error::InvalidInput {
+            msg: format!("Ticket id={} is already resolved", ticket.id),
+        });
+    }
+    let contractor = _get_contractor(payload.contractor_id)?;
+    if !contractor.active {
+        return Err(Error::InvalidInput {
+            msg: format!("Contractor id={} is inactive", contractor.id),
+        });
+    }
+    let now = time();
+    if payload.due_at.is_some_and(|due_at| due_at <= now) {
+        return Err(Error::InvalidInput {
+            msg: "A work order must be due in the future".to_string(),
+        });
+    }
+    if let Some(order_id) = open_work_order_id(ticket.id) {
+        return Err(Error::Conflict {
+            msg: format!(
+                "Ticket id={} is already assigned in work order id={}",
+                ticket.id, order_id
+            ),
+        });
+    }
+    let order = WorkOrder {
+        id: next_id(WORK_ORDERS_MEMORY_ID),
+        ticket_id: ticket.id,
+        space_id: ticket.space_id,
+        contractor_id: contractor.id,
+        estimated_cost_cents: payload.estimated_cost_cents,
+        actual_cost_cents: None,
+        due_at: payload.due_at,
+        status: WorkOrderStatus::Assigned,
+        assigned_by: ic_cdk::caller(),
+        assigned_at: now,
+        completed_at: None,
+        confirmed_at: None,
+        rework_count: 0,
+        notes: String::new(),
+    };
+    do_insert_work_order(&order);
+    set_ticket_status(ticket.id, TicketStatus::InProgress)?;
+    Ok(order)
+}
+
+// Function for a contractor to report a work order done, with what it cost
+#[ic_cdk::update]
+fn complete_work_order(id: u64, actual_cost_cents: u64, notes: String) -> Result<WorkOrder, Error> {
+    ensure_writable()?;
+    let contractor = caller_contractor()?;
+    let mut order = _get_work_order(id)?;
+    if order.contractor_id != contractor.id {
+        return Err(Error::Unauthorized {
+            msg: format!("Work order id={} is assigned to another contractor", id),
+        });
+    }
+    if order.status != WorkOrderStatus::Assigned {
+        return Err(Error::InvalidInput {
+            msg: format!("Work order id={} is not awaiting work", id),
+        });
+    }
+    if notes.len() > MAX_NOTES_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Completion notes must be at most {} characters",
+                MAX_NOTES_LEN
+            ),
+        });
+    }
+    order.status = WorkOrderStatus::Completed;
+    order.actual_cost_cents = Some(actual_cost_cents);
+    order.completed_at = Some(time());
+    order.notes = notes;
+    do_insert_work_order(&order);
+    Ok(order)
+}
+
+// Function for city admins to confirm reported work, which resolves the
+// ticket, or send it back to the contractor
+#[ic_cdk::update]
+fn confirm_work_order(id: u64, accepted: bool) -> Result<WorkOrder, Error> {
+    ensure_writable()?;
+    let mut order = _get_work_order(id)?;
+    ensure_space_admin(order.space_id)?;
+    if order.status != WorkOrderStatus::Completed {
+        return Err(Error::InvalidInput {
+            msg: format!("Work order id={} has not been reported complete", id),
+        });
+    }
+    if accepted {
+        order.status = WorkOrderStatus::Confirmed;
+        order.confirmed_at = Some(time());
+        set_ticket_status(order.ticket_id, TicketStatus::Resolved)?;
+    } else {
+        order.status = WorkOrderStatus::Assigned;
+        order.completed_at = None;
+        order.rework_count += 1;
+    }
+    do_insert_work_order(&order);
+    Ok(order)
+}
+
+// Function for city admins to withdraw a work order; the ticket reopens
+#[ic_cdk::update]
+fn cancel_work_order(id: u64) -> Result<WorkOrder, Error> {
+    ensure_writable()?;
+    let mut order = _get_work_order(id)?;
+    ensure_space_admin(order.space_id)?;
+    if matches!(
+        order.status,
+        WorkOrderStatus::Confirmed | WorkOrderStatus::Cancelled
+    ) {
+        return Err(Error::InvalidInput {
+            msg: format!("Work order id={} is already closed", id),
+        });
+    }
+    order.status = WorkOrderStatus::Cancelled;
+    do_insert_work_order(&order);
+    set_ticket_status(order.ticket_id, TicketStatus::Open)?;
+    Ok(order)
+}
+
+// Function for city admins to list the work orders of a ticket, oldest first
+#[ic_cdk::query]
+fn get_work_orders_for_ticket(
+    ticket_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<WorkOrder>, Error> {
+    let ticket = get_ticket(ticket_id)?;
+    ensure_space_admin(ticket.space_id)?;
+    work_orders_page(&next_token, |order| order.ticket_id == ticket_id)
+}
+
+// Function for a contractor to list the work orders assigned to them,
+// oldest first
+#[ic_cdk::query]
+fn get_my_work_orders(next_token: Option<String>) -> Result<Page<WorkOrder>, Error> {
+    let contractor = caller_contractor()?;
+    work_orders_page(&next_token, |order| order.contractor_id == contractor.id)
+}
+
+// Function for controllers, or the contractor itself, to review how a
+// contractor delivered on its work orders
+#[ic_cdk::query]
+fn get_contractor_performance(contractor_id: u64) -> Result<ContractorPerformance, Error> {
+    let contractor = _get_contractor(contractor_id)?;
+    if contractor.principal != Some(ic_cdk::caller()) {
+        ensure_controller()?;
+    }
+    let work_orders = work_orders_matching(|order| order.contractor_id == contractor_id);
+    let mut performance = ContractorPerformance {
+        contractor,
+        work_orders: vec![],
+        open: 0,
+        confirmed: 0,
+        cancelled: 0,
+        reworks: 0,
+        on_time: 0,
+        average_days_to_complete: None,
+        estimated_cents: 0,
+        actual_cents: 0,
+        cost_overrun_percent: None,
+    };
+    let mut days_to_complete = 0.0;
+    for order in &work_orders {
+        performance.reworks += u64::from(order.rework_count);
+        match order.status {
+            WorkOrderStatus::Assigned | WorkOrderStatus::Completed => performance.open += 1,
+            WorkOrderStatus::Cancelled => performance.cancelled += 1,
+            WorkOrderStatus::Confirmed => {
+                performance.confirmed += 1;
+                let completed_at = order.completed_at.unwrap_or(order.assigned_at);
+                if order.due_at.is_none_or(|due_at| completed_at <= due_at) {
+                    performance.on_time += 1;
+                }
+                days_to_complete +=
+                    (completed_at - order.assigned_at) as f64 / NANOS_PER_DAY as f64;
+                performance.estimated_cents += order.estimated_cost_cents;
+                performance.actual_cents += order.actual_cost_cents.unwrap_or(0);
+            }
+        }
+    }
+    if performance.confirmed > 0 {
+        performance.average_days_to_complete =
+            Some(days_to_complete / performance.confirmed as f64);
+    }
+    if performance.estimated_cents > 0 {
+        performance.cost_overrun_percent = Some(
+            (performance.actual_cents as f64 / performance.estimated_cents as f64 - 1.0) * 100.0,
+        );
+    }
+    performance.work_orders = work_orders;
+    Ok(performance)
+}
@@ -0,0 +1,69 @@
+use crate::geo::Coordinates;
+use crate::spatial_index::spaces_around;
+use crate::water::WATER_FEATURES;
+use crate::{Error, GreenSpace, GreenSpaceStatus};
+use std::collections::BTreeSet;
+
+const MAX_COOL_RADIUS_M: f64 = 20_000.0;
+const MAX_COOL_SPACES: usize = 50;
+// What water adds to a space's coolness, on the scale of its shade percentage
+const WATER_BONUS: u32 = 40;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CoolSpace {
+    space: GreenSpace,
+    distance_m: f64,
+    // Shade percentage plus a bonus for water; spaces are ranked by it
+    coolness: u32,
+    // Flagged on the space or with a water feature recorded
+    has_water: bool,
+}
+
+// A space is open unless its status says otherwise
+fn is_open(space: &GreenSpace) -> bool {
+    matches!(space.status, None | Some(GreenSpaceStatus::Open))
+}
+
+// Function to find open spaces to cool down in on a hot day within a radius of
+// a point, the shadiest and those with water first, then the nearest
+#[ic_cdk::query]
+fn find_cool_spaces(lat: f64, lng: f64, radius_m: f64) -> Result<Vec<CoolSpace>, Error> {
+    let point = Coordinates { lat, lng };
+    point.validate()?;
+    if !radius_m.is_finite() || radius_m <= 0.0 || radius_m > MAX_COOL_RADIUS_M {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The radius must be positive and at most {} m",
+                MAX_COOL_RADIUS_M
+            ),
+        });
+    }
+    let with_water_features: BTreeSet<u64> = WATER_FEATURES.with(|w| {
+        w.borrow()
+            .iter()
+            .map(|(_, feature)| feature.space_id)
+            .collect()
+    });
+    let mut cool: Vec<CoolSpace> = spaces_around(&point, radius_m, None)
+        .into_iter()
+        .filter(|nearby| nearby.distance_m <= radius_m && is_open(&nearby.space))
+        .map(|nearby| {
+            let has_water = nearby.space.has_water == Some(true)
+                || with_water_features.contains(&nearby.space.id);
+            let shade = u32::from(nearby.space.shade_coverage_percent.unwrap_or(0));
+            CoolSpace {
+                coolness: shade + if has_water { WATER_BONUS } else { 0 },
+                has_water,
+                distance_m: nearby.distance_m,
+                space: nearby.space,
+            }
+        })
+        .collect();
+    cool.sort_by(|a, b| {
+        b.coolness
+            .cmp(&a.coolness)
+            .then(a.distance_m.total_cmp(&b.distance_m))
+    });
+    cool.truncate(MAX_COOL_SPACES);
+    Ok(cool)
+}
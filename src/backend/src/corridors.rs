@@ -0,0 +1,143 @@
+use crate::cities::in_city;
+use crate::geo::{distance_m, BoundingBox, Coordinates, Polygon};
+use crate::paging::{page_sorted, Page};
+use crate::spatial_index::{candidate_ids, expand_bbox};
+use crate::{_get_green_space_boundary, Error, GREEN_SPACE_STORAGE};
+use std::collections::BTreeMap;
+
+// Corridors are about walkable or flyable gaps; beyond this the index lookups
+// would cover most of a city for every space
+const MAX_CORRIDOR_GAP_M: f64 = 5_000.0;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct GreenCorridor {
+    space_ids: Vec<u64>,
+}
+
+// The geometry of a space: its marker and the vertices of its boundary
+struct SpaceGeometry {
+    points: Vec<Coordinates>,
+    boundary: Option<Polygon>,
+}
+
+impl SpaceGeometry {
+    fn bounds(&self) -> BoundingBox {
+        let mut bounds = BoundingBox {
+            south: f64::MAX,
+            west: f64::MAX,
+            north: f64::MIN,
+            east: f64::MIN,
+        };
+        for c in &self.points {
+            bounds.south = bounds.south.min(c.lat);
+            bounds.west = bounds.west.min(c.lng);
+            bounds.north = bounds.north.max(c.lat);
+            bounds.east = bounds.east.max(c.lng);
+        }
+        bounds
+    }
+
+    // Smallest distance between the two geometries, zero when one lies within
+    // the boundary of the other
+    fn gap_m(&self, other: &SpaceGeometry) -> f64 {
+        let inside = |points: &[Coordinates], boundary: &Option<Polygon>| {
+            boundary
+                .as_ref()
+                .is_some_and(|b| points.iter().any(|c| b.contains(c)))
+        };
+        if inside(&self.points, &other.boundary) || inside(&other.points, &self.boundary) {
+            return 0.0;
+        }
+        self.points
+            .iter()
+            .flat_map(|a| other.points.iter().map(move |b| distance_m(a, b)))
+            .fold(f64::MAX, f64::min)
+    }
+}
+
+fn geometries(city_id: Option<u64>) -> BTreeMap<u64, SpaceGeometry> {
+    GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, space)| in_city(city_id, space.city_id))
+            .filter_map(|(id, space)| {
+                let boundary = _get_green_space_boundary(&id);
+                let mut points: Vec<_> = space.coordinates.into_iter().collect();
+                if let Some(boundary) = &boundary {
+                    points.extend(boundary.exterior.iter().copied());
+                }
+                (!points.is_empty()).then_some((id, SpaceGeometry { points, boundary }))
+            })
+            .collect()
+    })
+}
+
+fn find_root(parents: &mut BTreeMap<u64, u64>, id: u64) -> u64 {
+    let mut root = id;
+    while parents[&root] != root {
+        root = parents[&root];
+    }
+    // Path compression keeps later lookups short
+    let mut current = id;
+    while parents[&current] != root {
+        let next = parents[&current];
+        parents.insert(current, root);
+        current = next;
+    }
+    root
+}
+
+// Function to group spaces into corridors: clusters in which every space is
+// within `max_gap_m` of another space of the same cluster, largest first
+#[ic_cdk::query]
+fn get_green_corridors(
+    max_gap_m: f64,
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<GreenCorridor>, Error> {
+    if !(0.0..=MAX_CORRIDOR_GAP_M).contains(&max_gap_m) {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The maximum gap must be between 0 and {} metres",
+                MAX_CORRIDOR_GAP_M
+            ),
+        });
+    }
+
+    let geometries = geometries(city_id);
+    let mut parents: BTreeMap<u64, u64> = geometries.keys().map(|id| (*id, *id)).collect();
+    for (id, geometry) in &geometries {
+        for other_id in candidate_ids(&expand_bbox(&geometry.bounds(), max_gap_m)) {
+            if other_id <= *id {
+                continue;
+            }
+            let Some(other) = geometries.get(&other_id) else {
+                continue;
+            };
+            if geometry.gap_m(other) <= max_gap_m {
+                let (a, b) = (
+                    find_root(&mut parents, *id),
+                    find_root(&mut parents, other_id),
+                );
+                parents.insert(a.max(b), a.min(b));
+            }
+        }
+    }
+
+    let mut clusters: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for id in geometries.keys() {
+        let root = find_root(&mut parents, *id);
+        clusters.entry(root).or_default().push(*id);
+    }
+    // Keyed by size descending, then by the cluster's lowest space id, so a
+    // token keeps its place as long as the clusters stay the same
+    let mut corridors: Vec<_> = clusters
+        .into_iter()
+        .map(|(root, space_ids)| {
+            let key = (u64::MAX - space_ids.len() as u64, root);
+            (key, GreenCorridor { space_ids })
+        })
+        .collect();
+    corridors.sort_by_key(|(key, _)| *key);
+    page_sorted(corridors, &next_token)
+}
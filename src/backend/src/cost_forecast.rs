@@ -0,0 +1,163 @@
+use crate::calendar::year_month_of;
+use crate::expenses::{ExpenseCategory, EXPENSES};
+use crate::playground::equipment_condition;
+use crate::{_get_green_space, ensure_controller, Error};
+use ic_cdk::api::time;
+use std::collections::BTreeMap;
+
+// Years of expenses a projection is based on, the last one before the forecast
+const HISTORY_YEARS: i64 = 3;
+
+// How much each share of the equipment in a condition raises the repairs
+// projection; equipment in good repair leaves it as is
+const FAILED_WEIGHT: f64 = 0.5;
+const DEFECTS_WEIGHT: f64 = 0.25;
+const OVERDUE_WEIGHT: f64 = 0.25;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CategoryCostForecast {
+    category: ExpenseCategory,
+    // Spent in each year of the history, oldest first
+    history_cents: Vec<(i64, u64)>,
+    // Applied to the linear trend over the history; 1 for other than repairs
+    condition_factor: f64,
+    projected_cents: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SpaceCostForecast {
+    space_id: u64,
+    name: String,
+    categories: Vec<CategoryCostForecast>,
+    projected_cents: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct MaintenanceCostForecast {
+    year: i64,
+    generated_at: u64,
+    spaces: Vec<SpaceCostForecast>,
+    by_category: Vec<(ExpenseCategory, u64)>,
+    projected_cents: u64,
+}
+
+// The least-squares line through the yearly totals, extended to `year`; a
+// single year stays flat and a falling trend stops at zero
+fn project(history: &[(i64, u64)], year: i64) -> f64 {
+    let n = history.len() as f64;
+    let mean_x = history.iter().map(|&(x, _)| x as f64).sum::<f64>() / n;
+    let mean_y = history.iter().map(|&(_, y)| y as f64).sum::<f64>() / n;
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for &(x, y) in history {
+        covariance += (x as f64 - mean_x) * (y as f64 - mean_y);
+        variance += (x as f64 - mean_x).powi(2);
+    }
+    let slope = if variance > 0.0 {
+        covariance / variance
+    } else {
+        0.0
+    };
+    (mean_y + slope * (year as f64 - mean_x)).max(0.0)
+}
+
+// Repairs grow with the share of equipment that failed its last inspection,
+// passed with defects or is overdue for one
+fn condition_factor(space_id: u64) -> f64 {
+    let condition = equipment_condition(space_id);
+    if condition.total == 0 {
+        return 1.0;
+    }
+    let share = |count: u64| count as f64 / condition.total as f64;
+    1.0 + FAILED_WEIGHT * share(condition.failed)
+        + DEFECTS_WEIGHT * share(condition.with_defects)
+        + OVERDUE_WEIGHT * share(condition.overdue)
+}
+
+// Function for controllers to project each space's maintenance costs in a
+// year from its recent expenses, per category
+#[ic_cdk::query]
+fn forecast_maintenance_costs(year: i64) -> Result<MaintenanceCostForecast, Error> {
+    ensure_controller()?;
+    let next_year = year_month_of(time()).0 + 1;
+    if !(1971..=next_year).contains(&year) {
+        return Err(Error::InvalidInput {
+            msg: format!("The year must be between 1971 and {}", next_year),
+        });
+    }
+    let first_year = year - HISTORY_YEARS;
+
+    // Spent by (space, category, year) within the history, and the first
+    // year each space has expenses in
+    let mut spent: BTreeMap<(u64, ExpenseCategory, i64), u64> = BTreeMap::new();
+    let mut first_recorded: BTreeMap<u64, i64> = BTreeMap::new();
+    EXPENSES.with(|e| {
+        for (_, expense) in e.borrow().iter() {
+            let expense_year = expense.year();
+            if expense_year >= year {
+                continue;
+            }
+            let first = first_recorded
+                .entry(expense.space_id)
+                .or_insert(expense_year);
+            *first = (*first).min(expense_year);
+            if expense_year >= first_year {
+                *spent
+                    .entry((expense.space_id, expense.category, expense_year))
+                    .or_insert(0) += expense.amount_cents;
+            }
+        }
+    });
+
+    let mut histories: BTreeMap<(u64, ExpenseCategory), Vec<(i64, u64)>> = BTreeMap::new();
+    for &(space_id, category, _) in spent.keys() {
+        if histories.contains_key(&(space_id, category)) {
+            continue;
+        }
+        // Years without expenses since the space's first one count as zero
+        let start = first_recorded[&space_id].max(first_year);
+        let history = (start..year)
+            .map(|y| (y, spent.get(&(space_id, category, y)).copied().unwrap_or(0)))
+            .collect();
+        histories.insert((space_id, category), history);
+    }
+
+    let mut forecast = MaintenanceCostForecast {
+        year,
+        generated_at: time(),
+        spaces: vec![],
+        by_category: vec![],
+        projected_cents: 0,
+    };
+    let mut by_category: BTreeMap<ExpenseCategory, u64> = BTreeMap::new();
+    for ((space_id, category), history_cents) in histories {
+        let Some(space) = _get_green_space(&space_id) else {
+            continue;
+        };
+        if forecast.spaces.last().map(|s| s.space_id) != Some(space_id) {
+            forecast.spaces.push(SpaceCostForecast {
+                space_id,
+                name: space.name,
+                categories: vec![],
+                projected_cents: 0,
+            });
+        }
+        let condition_factor = if category == ExpenseCategory::Repairs {
+            condition_factor(space_id)
+        } else {
+            1.0
+        };
+        let projected_cents = (project(&history_cents, year) * condition_factor).round() as u64;
+        let entry = forecast.spaces.last_mut().unwrap();
+        entry.projected_cents += projected_cents;
+        entry.categories.push(CategoryCostForecast {
+            category,
+            history_cents,
+            condition_factor,
+            projected_cents,
+        });
+        *by_category.entry(category).or_insert(0) += projected_cents;
+        forecast.projected_cents += projected_cents;
+    }
+    forecast.by_category = by_category.into_iter().collect();
+    Ok(forecast)
+}
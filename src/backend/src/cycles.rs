@@ -0,0 +1,208 @@
+use crate::block_log::log_config;
+use crate::maintenance::ensure_writable;
+use crate::outcalls::http_post_json;
+use crate::webhooks::validate_webhook_url;
+use crate::{ensure_controller, get_memory, next_id, Error, Memory};
+use ic_cdk::api::{canister_balance128, time};
+use ic_stable_structures::{Cell, StableBTreeMap};
+use std::cell::RefCell;
+
+const CYCLE_CONFIG_MEMORY_ID: u8 = 79;
+const CYCLE_ALERTS_MEMORY_ID: u8 = 80;
+
+const MAX_ALERTS_KEPT: u64 = 100;
+const MAX_RESPONSE_BYTES: u64 = 16 * 1024;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CycleConfig {
+    // Balance under which an alert is raised
+    threshold: u128,
+    // Receives a JSON notice when an alert is raised
+    alert_webhook_url: Option<String>,
+    // Whether outcalls, webhook deliveries and analytics pause during an alert
+    conserve: bool,
+}
+
+impl Default for CycleConfig {
+    fn default() -> Self {
+        CycleConfig {
+            threshold: 1_000_000_000_000,
+            alert_webhook_url: None,
+            conserve: true,
+        }
+    }
+}
+
+impl_storable!(CycleConfig, 1024);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CycleAlert {
+    id: u64,
+    balance: u128,
+    threshold: u128,
+    raised_at: u64,
+    // Set once the balance is back at or above the threshold
+    resolved_at: Option<u64>,
+    // Why the webhook could not be notified, if it could not
+    notify_error: Option<String>,
+}
+
+impl_storable!(CycleAlert, 1024);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CycleStatus {
+    balance: u128,
+    threshold: u128,
+    conserving: bool,
+}
+
+#[derive(Serialize)]
+struct CycleAlertNotice<'a> {
+    event: &'a str,
+    balance: String,
+    threshold: String,
+    raised_at: u64,
+}
+
+thread_local! {
+    static CYCLE_CONFIG: RefCell<Cell<CycleConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(CYCLE_CONFIG_MEMORY_ID), CycleConfig::default())
+            .expect("Cannot initialize the cycle config")
+    );
+
+    static CYCLE_ALERTS: RefCell<StableBTreeMap<u64, CycleAlert, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(CYCLE_ALERTS_MEMORY_ID)));
+}
+
+fn cycle_config() -> CycleConfig {
+    CYCLE_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+fn open_alert() -> Option<CycleAlert> {
+    CYCLE_ALERTS
+        .with(|a| a.borrow().last_key_value().map(|(_, alert)| alert))
+        .filter(|alert| alert.resolved_at.is_none())
+}
+
+fn do_insert_alert(alert: &CycleAlert) {
+    CYCLE_ALERTS.with(|a| {
+        let mut alerts = a.borrow_mut();
+        alerts.insert(alert.id, alert.clone());
+        while alerts.len() > MAX_ALERTS_KEPT {
+            let Some((oldest, _)) = alerts.iter().next() else {
+                break;
+            };
+            alerts.remove(&oldest);
+        }
+    });
+}
+
+// Whether non-essential features are paused to save cycles
+pub(crate) fn conserving_cycles() -> bool {
+    cycle_config().conserve && open_alert().is_some()
+}
+
+async fn notify(url: String, alert: &CycleAlert) -> Result<(), Error> {
+    let body = serde_json::to_vec(&CycleAlertNotice {
+        event: "low_cycle_balance",
+        // As strings, since JSON numbers lose precision beyond 2^53
+        balance: alert.balance.to_string(),
+        threshold: alert.threshold.to_string(),
+        raised_at: alert.raised_at,
+    })
+    .expect("Cannot encode a cycle alert");
+    http_post_json(
+        url,
+        body,
+        format!("cycle-alert-{}", alert.id),
+        MAX_RESPONSE_BYTES,
+        "transform_webhook_response",
+    )
+    .await
+}
+
+// Raises an alert when the balance drops under the threshold and resolves it
+// once the balance recovers; returns what happened
+pub(crate) async fn check_cycle_balance() -> Result<String, Error> {
+    let config = cycle_config();
+    let balance = canister_balance128();
+    let open = open_alert();
+    if balance >= config.threshold {
+        return Ok(match open {
+            Some(mut alert) => {
+                alert.resolved_at = Some(time());
+                do_insert_alert(&alert);
+                format!("Balance of {} cycles recovered", balance)
+            }
+            None => format!("Balance of {} cycles", balance),
+        });
+    }
+    if open.is_some() {
+        return Ok(format!("Balance still low at {} cycles", balance));
+    }
+    let mut alert = CycleAlert {
+        id: next_id(CYCLE_ALERTS_MEMORY_ID),
+        balance,
+        threshold: config.threshold,
+        raised_at: time(),
+        resolved_at: None,
+        notify_error: None,
+    };
+    do_insert_alert(&alert);
+    if let Some(url) = config.alert_webhook_url {
+        if let Err(error) = notify(url, &alert).await {
+            // The alert may have been resolved while awaiting
+            if let Some(current) = CYCLE_ALERTS.with(|a| a.borrow().get(&alert.id)) {
+                alert = current;
+                alert.notify_error = Some(error.message().to_string());
+                do_insert_alert(&alert);
+            }
+        }
+    }
+    Ok(format!("Alert raised at {} cycles", balance))
+}
+
+// Function for controllers to set the low-balance threshold, the alert
+// webhook and whether to conserve cycles during an alert
+#[ic_cdk::update]
+fn set_cycle_config(config: CycleConfig) -> Result<CycleConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if let Some(url) = &config.alert_webhook_url {
+        validate_webhook_url(url)?;
+    }
+    CYCLE_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the cycle config")
+    });
+    log_config("cycle_config", &config);
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_cycle_config() -> Result<CycleConfig, Error> {
+    ensure_controller()?;
+    Ok(cycle_config())
+}
+
+#[ic_cdk::query]
+fn get_cycle_status() -> Result<CycleStatus, Error> {
+    ensure_controller()?;
+    Ok(CycleStatus {
+        balance: canister_balance128(),
+        threshold: cycle_config().threshold,
+        conserving: conserving_cycles(),
+    })
+}
+
+// Function for controllers to list recent alerts, newest first
+#[ic_cdk::query]
+fn get_cycle_alerts() -> Result<Vec<CycleAlert>, Error> {
+    ensure_controller()?;
+    Ok(CYCLE_ALERTS.with(|a| {
+        let mut alerts: Vec<CycleAlert> = a.borrow().iter().map(|(_, alert)| alert).collect();
+        alerts.reverse();
+        alerts
+    }))
+}
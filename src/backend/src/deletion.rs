@@ -0,0 +1,163 @@
+use crate::accessibility::ACCESSIBILITY_AUDITS;
+use crate::apiary::APIARIES;
+use crate::compost::COMPOST_SITES;
+use crate::contractors::WORK_ORDERS;
+use crate::events::EVENTS;
+use crate::expenses::EXPENSES;
+use crate::noise::NOISE_SENSORS;
+use crate::playground::PLAYGROUND_EQUIPMENT;
+use crate::reviews::REVIEWS;
+use crate::snapshot::sha256_hex;
+use crate::soil::SAMPLING_POINTS;
+use crate::tickets::MAINTENANCE_TICKETS;
+use crate::trees::TREES;
+use crate::water::WATER_FEATURES;
+use crate::{Error, GreenSpace, Memory};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::thread::LocalKey;
+
+// How long a confirmation token can be used
+const CONFIRMATION_WINDOW: u64 = 10 * 60 * 1_000_000_000;
+
+// The records attached to a space, which are removed with it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub(crate) struct DeletionSummary {
+    events: u64,
+    reviews: u64,
+    maintenance_tickets: u64,
+    work_orders: u64,
+    playground_equipment: u64,
+    water_features: u64,
+    trees: u64,
+    expenses: u64,
+    apiaries: u64,
+    compost_sites: u64,
+    noise_sensors: u64,
+    sampling_points: u64,
+    accessibility_audits: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum DeleteOutcome {
+    Deleted(GreenSpace),
+    // Nothing was deleted; calling again with the token confirms the deletion
+    ConfirmationRequired {
+        summary: DeletionSummary,
+        confirmation_token: String,
+        expires_at: u64,
+    },
+}
+
+struct PendingDeletion {
+    token: String,
+    caller: Principal,
+    summary: DeletionSummary,
+    expires_at: u64,
+}
+
+thread_local! {
+    // Tokens handed out by space; kept on the heap, as an upgrade merely
+    // means asking again
+    static PENDING_DELETIONS: RefCell<BTreeMap<u64, PendingDeletion>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+type RecordStore<V> = LocalKey<RefCell<StableBTreeMap<u64, V, Memory>>>;
+
+fn count_for_space<V: BoundedStorable>(
+    store: &'static RecordStore<V>,
+    space_id: u64,
+    record_space_id: fn(&V) -> u64,
+) -> u64 {
+    store.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, record)| record_space_id(record) == space_id)
+            .count() as u64
+    })
+}
+
+pub(crate) fn deletion_summary(space_id: u64) -> DeletionSummary {
+    DeletionSummary {
+        events: count_for_space(&EVENTS, space_id, |e| e.space_id),
+        reviews: count_for_space(&REVIEWS, space_id, |r| r.space_id),
+        maintenance_tickets: count_for_space(&MAINTENANCE_TICKETS, space_id, |t| t.space_id),
+        work_orders: count_for_space(&WORK_ORDERS, space_id, |w| w.space_id),
+        playground_equipment: count_for_space(&PLAYGROUND_EQUIPMENT, space_id, |e| e.space_id),
+        water_features: count_for_space(&WATER_FEATURES, space_id, |f| f.space_id),
+        trees: count_for_space(&TREES, space_id, |t| t.space_id),
+        expenses: count_for_space(&EXPENSES, space_id, |e| e.space_id),
+        apiaries: count_for_space(&APIARIES, space_id, |a| a.space_id),
+        compost_sites: count_for_space(&COMPOST_SITES, space_id, |c| c.space_id),
+        noise_sensors: count_for_space(&NOISE_SENSORS, space_id, |n| n.space_id),
+        sampling_points: count_for_space(&SAMPLING_POINTS, space_id, |p| p.space_id),
+        accessibility_audits: count_for_space(&ACCESSIBILITY_AUDITS, space_id, |a| a.space_id),
+    }
+}
+
+pub(crate) fn has_attached_records(space_id: u64) -> bool {
+    deletion_summary(space_id) != DeletionSummary::default()
+}
+
+// Decides whether a space may be deleted now. Spaces without attached records
+// may; for others the first call returns a summary with a token, and a second
+// call with that token, by the same caller and before the records change,
+// proceeds. The token guards against mistakes rather than attackers, who
+// would need write access to the space anyway
+pub(crate) fn confirm_deletion(
+    space_id: u64,
+    confirmation_token: Option<String>,
+) -> Result<Option<DeleteOutcome>, Error> {
+    if !has_attached_records(space_id) {
+        return Ok(None);
+    }
+    let summary = deletion_summary(space_id);
+    let now = time();
+    let caller = ic_cdk::caller();
+    if let Some(token) = confirmation_token {
+        let pending = PENDING_DELETIONS.with(|p| p.borrow_mut().remove(&space_id));
+        let valid = pending.as_ref().is_some_and(|pending| {
+            pending.token == token && pending.caller == caller && pending.expires_at > now
+        });
+        if !valid {
+            return Err(Error::InvalidInput {
+                msg: "The confirmation token is invalid or has expired".to_string(),
+            });
+        }
+        // Records added since the summary was shown need a new confirmation
+        if pending.is_some_and(|pending| pending.summary == summary) {
+            return Ok(None);
+        }
+    }
+    let token = sha256_hex(
+        &[
+            &space_id.to_be_bytes()[..],
+            caller.as_slice(),
+            &now.to_be_bytes(),
+        ]
+        .concat(),
+    );
+    let expires_at = now + CONFIRMATION_WINDOW;
+    PENDING_DELETIONS.with(|p| {
+        let mut pending = p.borrow_mut();
+        pending.retain(|_, pending| pending.expires_at > now);
+        pending.insert(
+            space_id,
+            PendingDeletion {
+                token: token.clone(),
+                caller,
+                summary: summary.clone(),
+                expires_at,
+            },
+        );
+    });
+    Ok(Some(DeleteOutcome::ConfirmationRequired {
+        summary,
+        confirmation_token: token,
+        expires_at,
+    }))
+}
@@ -0,0 +1,180 @@
+use crate::favorites::favorite_ids;
+use crate::maintenance::ensure_writable;
+use crate::notifications::{notify, NotificationKind};
+use crate::tickets::{TicketStatus, MAINTENANCE_TICKETS};
+use crate::{
+    _get_green_space, authenticated_caller, get_memory, principal_key, Error, Memory, PrincipalKey,
+    GREEN_SPACE_STORAGE, NANOS_PER_DAY,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const DIGEST_SUBSCRIPTIONS_MEMORY_ID: u8 = 59;
+
+const DIGEST_PERIOD: u64 = 7 * NANOS_PER_DAY;
+// Items of each kind listed in one digest
+const MAX_DIGEST_ITEMS: usize = 20;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct DigestSubscription {
+    subscribed_at: u64,
+    last_sent_at: Option<u64>,
+}
+
+impl_storable!(DigestSubscription, 64);
+
+// What happened around a user's favorite spaces in the past week
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct WeeklyDigest {
+    // Spaces created in the cities of the favorites
+    new_space_ids: Vec<u64>,
+    // Maintenance tickets of the favorites that were resolved
+    resolved_ticket_ids: Vec<u64>,
+}
+
+thread_local! {
+    // Users who opted into the weekly digest
+    pub(crate) static DIGEST_SUBSCRIPTIONS: RefCell<StableBTreeMap<PrincipalKey, DigestSubscription, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(DIGEST_SUBSCRIPTIONS_MEMORY_ID)));
+}
+
+impl WeeklyDigest {
+    fn is_empty(&self) -> bool {
+        self.new_space_ids.is_empty() && self.resolved_ticket_ids.is_empty()
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "This week near your favorite spaces: {} new space(s), {} resolved issue(s)",
+            self.new_space_ids.len(),
+            self.resolved_ticket_ids.len()
+        )
+    }
+}
+
+// Spaces created and tickets resolved since a time, as (id, city id) and
+// (id, space id); computed once per run and shared by every digest
+struct RecentActivity {
+    new_spaces: Vec<(u64, Option<u64>)>,
+    resolved_tickets: Vec<(u64, u64)>,
+}
+
+fn recent_activity(since: u64) -> RecentActivity {
+    let new_spaces = GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, space)| space.created_at.is_some_and(|at| at >= since))
+            .map(|(id, space)| (id, space.city_id))
+            .collect()
+    });
+    let resolved_tickets = MAINTENANCE_TICKETS.with(|t| {
+        t.borrow()
+            .iter()
+            .filter(|(_, ticket)| {
+                ticket.status == TicketStatus::Resolved
+                    && ticket.resolved_at.is_some_and(|at| at >= since)
+            })
+            .map(|(id, ticket)| (id, ticket.space_id))
+            .collect()
+    });
+    RecentActivity {
+        new_spaces,
+        resolved_tickets,
+    }
+}
+
+fn digest_for(user: &Principal, activity: &RecentActivity) -> WeeklyDigest {
+    let favorites = favorite_ids(user);
+    let cities: Vec<u64> = favorites
+        .iter()
+        .filter_map(_get_green_space)
+        .filter_map(|space| space.city_id)
+        .collect();
+    WeeklyDigest {
+        new_space_ids: activity
+            .new_spaces
+            .iter()
+            .filter(|(id, city_id)| {
+                !favorites.contains(id) && city_id.is_some_and(|city| cities.contains(&city))
+            })
+            .map(|(id, _)| *id)
+            .take(MAX_DIGEST_ITEMS)
+            .collect(),
+        resolved_ticket_ids: activity
+            .resolved_tickets
+            .iter()
+            .filter(|(_, space_id)| favorites.contains(space_id))
+            .map(|(id, _)| *id)
+            .take(MAX_DIGEST_ITEMS)
+            .collect(),
+    }
+}
+
+// Queues a digest notification for every subscriber whose last one is a
+// week old; weeks with nothing to report send nothing. Returns the number sent
+pub(crate) fn send_weekly_digests() -> u64 {
+    let now = time();
+    let week_ago = now.saturating_sub(DIGEST_PERIOD);
+    let due: Vec<(PrincipalKey, DigestSubscription)> = DIGEST_SUBSCRIPTIONS.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, subscription)| {
+                subscription
+                    .last_sent_at
+                    .unwrap_or(subscription.subscribed_at)
+                    <= week_ago
+            })
+            .collect()
+    });
+    if due.is_empty() {
+        return 0;
+    }
+    let activity = recent_activity(week_ago);
+    let mut sent = 0;
+    for (key, mut subscription) in due {
+        let user = Principal::from_slice(key.as_slice());
+        let digest = digest_for(&user, &activity);
+        if !digest.is_empty() {
+            let message = digest.summary();
+            notify(&user, NotificationKind::WeeklyDigest(digest), None, message);
+            sent += 1;
+        }
+        subscription.last_sent_at = Some(now);
+        DIGEST_SUBSCRIPTIONS.with(|s| s.borrow_mut().insert(key, subscription));
+    }
+    sent
+}
+
+// Function to opt in or out of the weekly digest of the caller's favorites
+#[ic_cdk::update]
+fn set_weekly_digest(enabled: bool) -> Result<bool, Error> {
+    ensure_writable()?;
+    let key = principal_key(&authenticated_caller()?);
+    DIGEST_SUBSCRIPTIONS.with(|s| {
+        let mut subscriptions = s.borrow_mut();
+        if !enabled {
+            subscriptions.remove(&key);
+        } else if !subscriptions.contains_key(&key) {
+            subscriptions.insert(
+                key,
+                DigestSubscription {
+                    subscribed_at: time(),
+                    last_sent_at: None,
+                },
+            );
+        }
+    });
+    Ok(enabled)
+}
+
+// Function to see the digest the caller would get for the past week
+#[ic_cdk::query]
+fn preview_weekly_digest() -> Result<WeeklyDigest, Error> {
+    let user = authenticated_caller()?;
+    Ok(digest_for(
+        &user,
+        &recent_activity(time().saturating_sub(DIGEST_PERIOD)),
+    ))
+}
@@ -0,0 +1,306 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::cities::{ensure_city_admin, in_city};
+use crate::geo::Polygon;
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::{
+    _get_green_space_boundary, get_memory, next_id, parse_boundary, Error, GreenSpace, Memory,
+    GREEN_SPACE_STORAGE,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const DISTRICTS_MEMORY_ID: u8 = 23;
+
+const MAX_NAME_LEN: usize = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct District {
+    pub(crate) id: u64,
+    pub(crate) name: String,
+    // Neighborhoods are districts nested in another district
+    parent_id: Option<u64>,
+    pub(crate) city_id: Option<u64>,
+    boundary: Option<Polygon>,
+    created_at: u64,
+    updated_at: u64,
+}
+
+impl_storable!(District, 66 * 1024);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct DistrictPayload {
+    name: String,
+    parent_id: Option<u64>,
+    city_id: Option<u64>,
+    // Boundary as a WKT `POLYGON((lng lat, ...))` string
+    boundary_wkt: Option<String>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct DistrictGreenAreaStats {
+    district_id: u64,
+    name: String,
+    space_count: u64,
+    // Only spaces with a boundary contribute to the green area
+    green_area_m2: f64,
+    district_area_m2: Option<f64>,
+    green_area_percent: Option<f64>,
+}
+
+thread_local! {
+    pub(crate) static DISTRICTS: RefCell<StableBTreeMap<u64, District, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(DISTRICTS_MEMORY_ID)));
+}
+
+fn district_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A district with id={} not found", id),
+    }
+}
+
+fn _get_district(id: &u64) -> Option<District> {
+    DISTRICTS.with(|s| s.borrow().get(id))
+}
+
+fn do_insert_district(district: &District) {
+    DISTRICTS.with(|s| s.borrow_mut().insert(district.id, district.clone()));
+    log_upsert("district", &district.id, district);
+}
+
+// Fails with NotFound unless a district with the given id exists
+fn ensure_district_exists(id: u64) -> Result<(), Error> {
+    _get_district(&id)
+        .map(|_| ())
+        .ok_or_else(|| district_not_found(id))
+}
+
+// Fails unless the district exists and belongs to the given city
+pub(crate) fn ensure_district_in_city(id: u64, city_id: Option<u64>) -> Result<(), Error> {
+    let district = _get_district(&id).ok_or_else(|| district_not_found(id))?;
+    if district.city_id != city_id {
+        return Err(Error::InvalidInput {
+            msg: format!("District id={} belongs to a different city", id),
+        });
+    }
+    Ok(())
+}
+
+fn validate_district_name(name: &str) -> Result<(), Error> {
+    if name.trim().is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("District name must be 1-{} characters", MAX_NAME_LEN),
+        });
+    }
+    Ok(())
+}
+
+// Fails unless `parent_id` names a district of the same city that is not `id`
+// itself or one of its descendants
+fn validate_parent(
+    id: Option<u64>,
+    parent_id: Option<u64>,
+    city_id: Option<u64>,
+) -> Result<(), Error> {
+    if let Some(parent_id) = parent_id {
+        ensure_district_in_city(parent_id, city_id)?;
+    }
+    let mut current = parent_id;
+    while let Some(ancestor_id) = current {
+        if Some(ancestor_id) == id {
+            return Err(Error::InvalidInput {
+                msg: "A district cannot be nested inside itself".to_string(),
+            });
+        }
+        current = _get_district(&ancestor_id)
+            .ok_or_else(|| district_not_found(ancestor_id))?
+            .parent_id;
+    }
+    Ok(())
+}
+
+fn spaces_in_district(district_id: u64) -> Vec<GreenSpace> {
+    GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, space)| space.district_id == Some(district_id))
+            .map(|(_, space)| space)
+            .collect()
+    })
+}
+
+fn has_neighborhoods(id: u64) -> bool {
+    DISTRICTS.with(|s| s.borrow().iter().any(|(_, d)| d.parent_id == Some(id)))
+}
+
+fn green_area_stats(district: District) -> DistrictGreenAreaStats {
+    let spaces = spaces_in_district(district.id);
+    let green_area_m2: f64 = spaces
+        .iter()
+        .filter_map(|space| _get_green_space_boundary(&space.id))
+        .map(|boundary| boundary.area_m2())
+        .sum();
+    let district_area_m2 = district.boundary.as_ref().map(Polygon::area_m2);
+    DistrictGreenAreaStats {
+        district_id: district.id,
+        name: district.name,
+        space_count: spaces.len() as u64,
+        green_area_m2,
+        district_area_m2,
+        green_area_percent: district_area_m2
+            .filter(|area| *area > 0.0)
+            .map(|area| green_area_m2 / area * 100.0),
+    }
+}
+
+// Function to create a district or, with a parent, a neighborhood
+#[ic_cdk::update]
+fn create_district(payload: DistrictPayload) -> Result<District, Error> {
+    ensure_writable()?;
+    ensure_city_admin(payload.city_id)?;
+    validate_district_name(&payload.name)?;
+    validate_parent(None, payload.parent_id, payload.city_id)?;
+
+    let now = time();
+    let district = District {
+        id: next_id(DISTRICTS_MEMORY_ID),
+        name: payload.name,
+        parent_id: payload.parent_id,
+        city_id: payload.city_id,
+        boundary: parse_boundary(&payload.boundary_wkt)?,
+        created_at: now,
+        updated_at: now,
+    };
+
+    do_insert_district(&district);
+    Ok(district)
+}
+
+#[ic_cdk::query]
+fn get_district(id: u64) -> Result<District, Error> {
+    _get_district(&id).ok_or_else(|| district_not_found(id))
+}
+
+#[ic_cdk::query]
+fn get_all_districts(
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<District>, Error> {
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(DISTRICTS.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, district)| in_city(city_id, district.city_id)),
+        )
+    }))
+}
+
+// Function to get the neighborhoods directly nested in a district
+#[ic_cdk::query]
+fn get_child_districts(id: u64, next_token: Option<String>) -> Result<Page<District>, Error> {
+    ensure_district_exists(id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(DISTRICTS.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, district)| district.parent_id == Some(id)),
+        )
+    }))
+}
+
+#[ic_cdk::update]
+fn update_district(id: u64, payload: DistrictPayload) -> Result<District, Error> {
+    ensure_writable()?;
+    let mut district = _get_district(&id).ok_or_else(|| district_not_found(id))?;
+    ensure_city_admin(district.city_id)?;
+    ensure_city_admin(payload.city_id)?;
+    validate_district_name(&payload.name)?;
+    validate_parent(Some(id), payload.parent_id, payload.city_id)?;
+    if payload.city_id != district.city_id
+        && (!spaces_in_district(id).is_empty() || has_neighborhoods(id))
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "District id={} must be emptied before moving it to another city",
+                id
+            ),
+        });
+    }
+
+    district.name = payload.name;
+    district.parent_id = payload.parent_id;
+    district.city_id = payload.city_id;
+    district.boundary = parse_boundary(&payload.boundary_wkt)?;
+    district.updated_at = time();
+    do_insert_district(&district);
+    Ok(district)
+}
+
+// Function to delete a district; spaces and neighborhoods must be moved out first
+#[ic_cdk::update]
+fn delete_district(id: u64) -> Result<District, Error> {
+    ensure_writable()?;
+    let district = _get_district(&id).ok_or_else(|| district_not_found(id))?;
+    ensure_city_admin(district.city_id)?;
+    if !spaces_in_district(id).is_empty() {
+        return Err(Error::InvalidInput {
+            msg: format!("District id={} still has green spaces assigned", id),
+        });
+    }
+    if has_neighborhoods(id) {
+        return Err(Error::InvalidInput {
+            msg: format!("District id={} still has neighborhoods", id),
+        });
+    }
+    let district = DISTRICTS
+        .with(|s| s.borrow_mut().remove(&id))
+        .ok_or_else(|| district_not_found(id))?;
+    log_delete("district", &id);
+    Ok(district)
+}
+
+#[ic_cdk::query]
+fn get_green_spaces_in_district(
+    id: u64,
+    next_token: Option<String>,
+) -> Result<Page<GreenSpace>, Error> {
+    ensure_district_exists(id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    GREEN_SPACE_STORAGE.with(|s| {
+        Ok(page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, space)| space.district_id == Some(id)),
+        ))
+    })
+}
+
+// Function to get the green area aggregates of one district
+#[ic_cdk::query]
+fn get_district_green_area(id: u64) -> Result<DistrictGreenAreaStats, Error> {
+    let district = _get_district(&id).ok_or_else(|| district_not_found(id))?;
+    Ok(green_area_stats(district))
+}
+
+// Function to get the green area aggregates of every district
+#[ic_cdk::query]
+fn get_all_district_green_areas(
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<DistrictGreenAreaStats>, Error> {
+    let range = keys_after_token::<u64>(&next_token)?;
+    let districts: Vec<_> = DISTRICTS.with(|s| {
+        s.borrow()
+            .range(range)
+            .filter(|(_, district)| in_city(city_id, district.city_id))
+            .collect()
+    });
+    Ok(page_by_size(
+        districts
+            .into_iter()
+            .map(|(id, district)| (id, green_area_stats(district))),
+    ))
+}
@@ -0,0 +1,108 @@
+use crate::apiary::anonymize_keeper;
+use crate::compost::remove_steward;
+use crate::digest::DIGEST_SUBSCRIPTIONS;
+use crate::events::anonymize_organizer;
+use crate::favorites::FAVORITES;
+use crate::idempotency::forget_idempotency_keys;
+use crate::maintenance::ensure_writable;
+use crate::notifications::NOTIFICATIONS;
+use crate::renames::anonymize_renames;
+use crate::reviews::remove_reviews_by;
+use crate::saved_searches::SAVED_SEARCHES;
+use crate::search_alerts::forget_alerted_spaces;
+use crate::terms::forget_terms_acceptance;
+use crate::visits::VISITS;
+use crate::{authenticated_caller, ensure_controller, principal_key, Error, Memory, PrincipalKey};
+use candid::Principal;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+type UserStore<V> = LocalKey<RefCell<StableBTreeMap<(PrincipalKey, u64), V, Memory>>>;
+
+// What was removed, and what was kept without the user: events and apiaries
+// lose their owner, renames are credited to the anonymous principal
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct ErasureReport {
+    favorites_removed: u64,
+    visits_removed: u64,
+    saved_searches_removed: u64,
+    notifications_removed: u64,
+    digest_subscription_removed: bool,
+    idempotency_results_removed: u64,
+    reviews_removed: u64,
+    events_anonymized: u64,
+    renames_anonymized: u64,
+    apiaries_anonymized: u64,
+    compost_stewardships_removed: u64,
+    terms_acceptance_removed: bool,
+}
+
+// Removes the records of a user from a store keyed by (user, id); returns the ids
+fn remove_user_records<V: BoundedStorable>(
+    store: &'static UserStore<V>,
+    user: &PrincipalKey,
+) -> Vec<u64> {
+    let keys: Vec<(PrincipalKey, u64)> = store.with(|s| {
+        s.borrow()
+            .range((*user, 0)..=(*user, u64::MAX))
+            .map(|(key, _)| key)
+            .collect()
+    });
+    store.with(|s| {
+        let mut records = s.borrow_mut();
+        for key in &keys {
+            records.remove(key);
+        }
+    });
+    keys.into_iter().map(|(_, id)| id).collect()
+}
+
+// Removes what only concerns the user and anonymizes what the public still
+// relies on, such as events and the name history of spaces. City admin and
+// governance roles are left alone, as removing them would change who runs
+// the canister
+fn erase_user_data(user: &Principal) -> ErasureReport {
+    let key = principal_key(user);
+    let saved_searches = remove_user_records(&SAVED_SEARCHES, &key);
+    for id in &saved_searches {
+        forget_alerted_spaces(*id);
+    }
+    ErasureReport {
+        favorites_removed: remove_user_records(&FAVORITES, &key).len() as u64,
+        visits_removed: remove_user_records(&VISITS, &key).len() as u64,
+        saved_searches_removed: saved_searches.len() as u64,
+        notifications_removed: remove_user_records(&NOTIFICATIONS, &key).len() as u64,
+        digest_subscription_removed: DIGEST_SUBSCRIPTIONS
+            .with(|s| s.borrow_mut().remove(&key))
+            .is_some(),
+        idempotency_results_removed: forget_idempotency_keys(&key),
+        reviews_removed: remove_reviews_by(user),
+        events_anonymized: anonymize_organizer(user),
+        renames_anonymized: anonymize_renames(user),
+        apiaries_anonymized: anonymize_keeper(user),
+        compost_stewardships_removed: remove_steward(user),
+        terms_acceptance_removed: forget_terms_acceptance(&key),
+    }
+}
+
+// Function for a user to erase the data tied to their principal
+#[ic_cdk::update]
+fn delete_my_data() -> Result<ErasureReport, Error> {
+    ensure_writable()?;
+    let user = authenticated_caller()?;
+    Ok(erase_user_data(&user))
+}
+
+// Function for controllers to erase the data of a user on their request
+#[ic_cdk::update]
+fn delete_user_data(user: Principal) -> Result<ErasureReport, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if user == Principal::anonymous() {
+        return Err(Error::InvalidInput {
+            msg: "The anonymous principal has no data of its own".to_string(),
+        });
+    }
+    Ok(erase_user_data(&user))
+}
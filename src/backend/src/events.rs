@@ -0,0 +1,364 @@
+use crate::bans::is_shadow_banned;
+use crate::block_log::{log_delete, log_upsert};
+use crate::content_filter::{ensure_no_rejected_terms, held_term_signals};
+use crate::maintenance::ensure_writable;
+use crate::moderation::{track_moderation, ContentRef, ModerationDecision};
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::spam::screen_submission;
+use crate::terms::ensure_terms_accepted;
+use crate::{
+    authenticated_caller, caller, ensure_green_space_exists, ensure_space_admin, get_memory,
+    next_id, Error, Memory,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const EVENTS_MEMORY_ID: u8 = 62;
+
+const MAX_TITLE_LEN: usize = 100;
+const MAX_DESCRIPTION_LEN: usize = 2000;
+const MAX_EVENTS_PER_PAGE: u32 = 100;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum EventStatus {
+    // Proposed by the public, not shown until an admin approves it
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Event {
+    pub(crate) id: u64,
+    pub(crate) space_id: u64,
+    pub(crate) title: String,
+    pub(crate) description: String,
+    pub(crate) starts_at: u64,
+    pub(crate) ends_at: u64,
+    // None once the organizer's data was erased; only admins manage the event then
+    organizer: Option<Principal>,
+    pub(crate) status: EventStatus,
+    pub(crate) created_at: u64,
+    // The latest approval or rejection
+    moderation: Option<ModerationDecision>,
+    // Why the event was held for a moderator automatically, as likely spam or
+    // for a held term
+    spam_signals: Option<Vec<String>>,
+}
+
+impl_storable!(Event, 4096);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct EventPayload {
+    pub(crate) space_id: u64,
+    title: String,
+    description: String,
+    starts_at: u64,
+    ends_at: u64,
+}
+
+thread_local! {
+    pub(crate) static EVENTS: RefCell<StableBTreeMap<u64, Event, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(EVENTS_MEMORY_ID)));
+}
+
+fn event_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("An event with id={} not found", id),
+    }
+}
+
+pub(crate) fn get_event(id: u64) -> Result<Event, Error> {
+    EVENTS
+        .with(|e| e.borrow().get(&id))
+        .ok_or_else(|| event_not_found(id))
+}
+
+pub(crate) fn do_insert_event(event: &Event) {
+    EVENTS.with(|e| e.borrow_mut().insert(event.id, event.clone()));
+    log_upsert("event", &event.id, event);
+    track_moderation(
+        ContentRef::Event(event.id),
+        event.created_at,
+        event.status == EventStatus::Pending,
+    );
+}
+
+pub(crate) fn do_remove_event(event: &Event) {
+    EVENTS.with(|e| e.borrow_mut().remove(&event.id));
+    log_delete("event", &event.id);
+    track_moderation(ContentRef::Event(event.id), event.created_at, false);
+}
+
+// Records a moderator's decision on an event
+pub(crate) fn decide_event(id: u64, decision: ModerationDecision) -> Result<Event, Error> {
+    let mut event = get_event(id)?;
+    event.status = if decision.approved() {
+        EventStatus::Approved
+    } else {
+        EventStatus::Rejected
+    };
+    event.moderation = Some(decision);
+    do_insert_event(&event);
+    Ok(event)
+}
+
+// Removes a user as organizer, leaving the events to the admins of their
+// spaces; returns the number of events changed
+pub(crate) fn anonymize_organizer(user: &Principal) -> u64 {
+    let organized: Vec<Event> = EVENTS.with(|e| {
+        e.borrow()
+            .iter()
+            .map(|(_, event)| event)
+            .filter(|event| event.organizer == Some(*user))
+            .collect()
+    });
+    for event in &organized {
+        do_insert_event(&Event {
+            organizer: None,
+            ..event.clone()
+        });
+    }
+    organized.len() as u64
+}
+
+fn next_event_id() -> u64 {
+    next_id(EVENTS_MEMORY_ID)
+}
+
+fn new_event(id: u64, payload: EventPayload, organizer: Principal, status: EventStatus) -> Event {
+    Event {
+        id,
+        space_id: payload.space_id,
+        title: payload.title,
+        description: payload.description,
+        starts_at: payload.starts_at,
+        ends_at: payload.ends_at,
+        organizer: Some(organizer),
+        status,
+        created_at: time(),
+        moderation: None,
+        spam_signals: None,
+    }
+}
+
+// Stores a new event; one that looks like spam or contains a held term waits for a moderator even
+// when its organizer could publish it
+pub(crate) fn create_event(
+    payload: EventPayload,
+    organizer: Principal,
+    status: EventStatus,
+) -> Event {
+    let mut signals = screen_submission(
+        organizer,
+        &format!("{}\n{}", payload.title, payload.description),
+    );
+    signals.extend(held_term_signals(&[&payload.title, &payload.description]));
+    let mut event = new_event(next_event_id(), payload, organizer, status);
+    if !signals.is_empty() {
+        event.status = EventStatus::Pending;
+        event.spam_signals = Some(signals);
+    }
+    if is_shadow_banned(&organizer) {
+        event.status = EventStatus::Pending;
+    }
+    do_insert_event(&event);
+    event
+}
+
+// Events of admins of the space's city are published without review
+pub(crate) fn initial_status(space_id: u64) -> EventStatus {
+    if ensure_space_admin(space_id).is_ok() {
+        EventStatus::Approved
+    } else {
+        EventStatus::Pending
+    }
+}
+
+// Fails unless the caller organizes the event or administers its space
+pub(crate) fn ensure_event_manager(event: &Event) -> Result<(), Error> {
+    if event.organizer != Some(caller()) {
+        ensure_space_admin(event.space_id)?;
+    }
+    Ok(())
+}
+
+// The event with the details of a payload; an edit by someone other than an
+// admin, or adding a held term, sends it back to review
+pub(crate) fn revise_event(event: &Event, payload: EventPayload) -> Result<Event, Error> {
+    ensure_event_manager(event)?;
+    if payload.space_id != event.space_id {
+        return Err(Error::InvalidInput {
+            msg: "An event cannot move to another space".to_string(),
+        });
+    }
+    validate_event(&payload)?;
+    let signals = held_term_signals(&[&payload.title, &payload.description]);
+    let status = match initial_status(event.space_id) {
+        EventStatus::Approved if signals.is_empty() => event.status,
+        EventStatus::Approved => EventStatus::Pending,
+        status => status,
+    };
+    Ok(Event {
+        title: payload.title,
+        description: payload.description,
+        starts_at: payload.starts_at,
+        ends_at: payload.ends_at,
+        status,
+        spam_signals: if signals.is_empty() {
+            event.spam_signals.clone()
+        } else {
+            Some(signals)
+        },
+        ..event.clone()
+    })
+}
+
+pub(crate) fn validate_event(payload: &EventPayload) -> Result<(), Error> {
+    if payload.title.trim().is_empty() || payload.title.len() > MAX_TITLE_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Event titles must be 1-{} characters", MAX_TITLE_LEN),
+        });
+    }
+    if payload.description.len() > MAX_DESCRIPTION_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Event descriptions must be at most {} characters",
+                MAX_DESCRIPTION_LEN
+            ),
+        });
+    }
+    ensure_no_rejected_terms(&[&payload.title, &payload.description])?;
+    if payload.ends_at < payload.starts_at || payload.ends_at <= time() {
+        return Err(Error::InvalidInput {
+            msg: "An event must end after it starts, and in the future".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Approved events ending at or after a time, soonest first, of one space or of all
+pub(crate) fn approved_events(space_id: Option<u64>, ending_from: u64) -> Vec<Event> {
+    let mut events: Vec<Event> = EVENTS.with(|e| {
+        e.borrow()
+            .iter()
+            .map(|(_, event)| event)
+            .filter(|event| {
+                event.status == EventStatus::Approved
+                    && event.ends_at >= ending_from
+                    && space_id.is_none_or(|id| event.space_id == id)
+            })
+            .collect()
+    });
+    events.sort_by_key(|event| (event.starts_at, event.id));
+    events
+}
+
+// Approved events not over yet, soonest first
+pub(crate) fn upcoming_events(space_id: Option<u64>, limit: usize) -> Vec<Event> {
+    let mut events = approved_events(space_id, time());
+    events.truncate(limit);
+    events
+}
+
+// Function to propose an event in a green space; admins of its city publish
+// their own events directly, others wait for approval
+#[ic_cdk::update]
+fn propose_event(payload: EventPayload) -> Result<Event, Error> {
+    ensure_writable()?;
+    let organizer = authenticated_caller()?;
+    ensure_terms_accepted()?;
+    ensure_green_space_exists(payload.space_id)?;
+    validate_event(&payload)?;
+    let status = initial_status(payload.space_id);
+    Ok(create_event(payload, organizer, status))
+}
+
+// Function for admins of the space's city to approve or reject an event;
+// moderate_content also takes a reason
+#[ic_cdk::update]
+fn review_event(id: u64, approve: bool) -> Result<Event, Error> {
+    ensure_writable()?;
+    let event = get_event(id)?;
+    ensure_space_admin(event.space_id)?;
+    decide_event(id, ModerationDecision::new(approve, None))
+}
+
+// Function for the organizer or an admin to call an event off
+#[ic_cdk::update]
+fn cancel_event(id: u64) -> Result<Event, Error> {
+    ensure_writable()?;
+    let event = get_event(id)?;
+    ensure_event_manager(&event)?;
+    do_remove_event(&event);
+    Ok(event)
+}
+
+#[ic_cdk::query]
+fn get_upcoming_events(space_id: Option<u64>, limit: u32) -> Result<Vec<Event>, Error> {
+    if limit == 0 || limit > MAX_EVENTS_PER_PAGE {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_EVENTS_PER_PAGE),
+        });
+    }
+    if let Some(space_id) = space_id {
+        ensure_green_space_exists(space_id)?;
+    }
+    Ok(upcoming_events(space_id, limit as usize))
+}
+
+// Function for admins to see the events of a space waiting for review
+#[ic_cdk::query]
+fn get_pending_events(space_id: u64, next_token: Option<String>) -> Result<Page<Event>, Error> {
+    ensure_space_admin(space_id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(EVENTS.with(|e| {
+        page_by_size(e.borrow().range(range).filter(|(_, event)| {
+            event.space_id == space_id && event.status == EventStatus::Pending
+        }))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cities::insert_test_city;
+    use crate::test_caller::set_caller;
+    use crate::{GreenSpace, GREEN_SPACE_STORAGE};
+
+    #[test]
+    fn events_of_erased_organizers_are_left_to_admins() {
+        let organizer = Principal::from_slice(&[3]);
+        insert_test_city(1, vec![Principal::from_slice(&[1])]);
+        GREEN_SPACE_STORAGE.with(|s| {
+            s.borrow_mut().insert(
+                1,
+                GreenSpace {
+                    id: 1,
+                    city_id: Some(1),
+                    ..Default::default()
+                },
+            )
+        });
+        do_insert_event(&Event {
+            id: 1,
+            space_id: 1,
+            title: "Picnic".to_string(),
+            description: String::new(),
+            starts_at: 0,
+            ends_at: 0,
+            organizer: Some(organizer),
+            status: EventStatus::Approved,
+            created_at: 0,
+            moderation: None,
+            spam_signals: None,
+        });
+        assert_eq!(anonymize_organizer(&organizer), 1);
+
+        set_caller(Principal::anonymous(), false);
+        assert!(matches!(cancel_event(1), Err(Error::Unauthorized { .. })));
+        assert!(get_event(1).is_ok());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::calendar::year_month_of;
+use crate::maintenance::ensure_writable;
+use crate::paging::{page_sorted, Page};
+use crate::{
+    _get_green_space, ensure_green_space_exists, ensure_space_admin, get_memory, next_id, Error,
+    Memory,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const EXPENSES_MEMORY_ID: u8 = 98;
+
+const MAX_DESCRIPTION_LEN: usize = 300;
+
+#[derive(
+    candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub(crate) enum ExpenseCategory {
+    Mowing,
+    Planting,
+    Cleaning,
+    Irrigation,
+    // Repairs of equipment, paths and furniture; forecasts weigh them by the
+    // condition of the space's playground equipment
+    Repairs,
+    Other,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Expense {
+    id: u64,
+    pub(crate) space_id: u64,
+    pub(crate) category: ExpenseCategory,
+    // In the city's currency, in cents
+    pub(crate) amount_cents: u64,
+    pub(crate) incurred_at: u64,
+    description: String,
+    recorded_by: candid::Principal,
+    recorded_at: u64,
+}
+
+impl_storable!(Expense, 1024);
+
+impl Expense {
+    pub(crate) fn year(&self) -> i64 {
+        year_month_of(self.incurred_at).0
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ExpensePayload {
+    space_id: u64,
+    category: ExpenseCategory,
+    amount_cents: u64,
+    incurred_at: u64,
+    description: String,
+}
+
+thread_local! {
+    pub(crate) static EXPENSES: RefCell<StableBTreeMap<u64, Expense, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(EXPENSES_MEMORY_ID)));
+}
+
+fn expense_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("An expense with id={} not found", id),
+    }
+}
+
+fn validate_expense(payload: &ExpensePayload) -> Result<(), Error> {
+    if payload.amount_cents == 0 {
+        return Err(Error::InvalidInput {
+            msg: "The amount of an expense must be above 0".to_string(),
+        });
+    }
+    if payload.incurred_at > time() {
+        return Err(Error::InvalidInput {
+            msg: "Expenses cannot be recorded ahead of when they are incurred".to_string(),
+        });
+    }
+    if payload.description.len() > MAX_DESCRIPTION_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Expense description must be at most {} characters",
+                MAX_DESCRIPTION_LEN
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Function for city admins to record money spent maintaining a space
+#[ic_cdk::update]
+fn record_maintenance_expense(payload: ExpensePayload) -> Result<Expense, Error> {
+    ensure_writable()?;
+    ensure_space_admin(payload.space_id)?;
+    validate_expense(&payload)?;
+    let expense = Expense {
+        id: next_id(EXPENSES_MEMORY_ID),
+        space_id: payload.space_id,
+        category: payload.category,
+        amount_cents: payload.amount_cents,
+        incurred_at: payload.incurred_at,
+        description: payload.description,
+        recorded_by: ic_cdk::caller(),
+        recorded_at: time(),
+    };
+    EXPENSES.with(|e| e.borrow_mut().insert(expense.id, expense.clone()));
+    log_upsert("expense", &expense.id, &expense);
+    Ok(expense)
+}
+
+// Function to remove an expense recorded by mistake
+#[ic_cdk::update]
+fn delete_maintenance_expense(id: u64) -> Result<Expense, Error> {
+    ensure_writable()?;
+    let expense = EXPENSES
+        .with(|e| e.borrow().get(&id))
+        .ok_or_else(|| expense_not_found(id))?;
+    // Expenses of a deleted space are left to the cascade
+    if _get_green_space(&expense.space_id).is_some() {
+        ensure_space_admin(expense.space_id)?;
+    }
+    EXPENSES.with(|e| e.borrow_mut().remove(&id));
+    log_delete("expense", &id);
+    Ok(expense)
+}
+
+// Function for city admins to list the expenses of a space, oldest first
+#[ic_cdk::query]
+fn get_maintenance_expenses(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<Expense>, Error> {
+    ensure_green_space_exists(space_id)?;
+    ensure_space_admin(space_id)?;
+    let mut expenses: Vec<_> = EXPENSES.with(|e| {
+        e.borrow()
+            .iter()
+            .filter(|(_, expense)| expense.space_id == space_id)
+            .map(|(id, expense)| ((expense.incurred_at, id), expense))
+            .collect()
+    });
+    expenses.sort_by_key(|(key, _)| *key);
+    page_sorted(expenses, &next_token)
+}
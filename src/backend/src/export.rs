@@ -0,0 +1,278 @@
+use crate::snapshot::{sha256_hex, snapshot_stores};
+use crate::{ensure_controller, Error};
+use candid::Encode;
+use ic_cdk::api::time;
+use sha2::{Digest, Sha256};
+
+// Bumped whenever the layout of an export record changes
+const EXPORT_FORMAT_VERSION: u32 = 1;
+// Records are appended to a chunk until it would grow past this, which keeps
+// responses below the 2MB message limit; stored values are at most 66KB
+const EXPORT_CHUNK_BYTES: usize = 1536 * 1024;
+
+// One stored entry; `key` and `value` are in their stable memory encoding,
+// which for records is candid
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ExportRecord {
+    memory_id: u8,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ExportChunk {
+    chunk_index: u64,
+    record_count: u64,
+    // Records as a 4-byte big-endian length followed by the candid-encoded
+    // ExportRecord
+    data: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ExportManifest {
+    format_version: u32,
+    generated_at: u64,
+    total_chunks: u64,
+    total_records: u64,
+    total_bytes: u64,
+    // Hex SHA-256 over the data of every chunk, in order
+    sha256: String,
+    chunk_sha256: Vec<String>,
+    // Hex root of the Merkle tree over the records, as get_dataset_root_hash
+    merkle_root: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct DatasetRootHash {
+    // Hex root of a Merkle tree whose leaves are the export records in
+    // order: a leaf hashes 0x00 and the record's candid encoding, a node 0x01
+    // and its two children. A node without a sibling moves up a level as is
+    root_hash: String,
+    record_count: u64,
+    generated_at: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ProofStep {
+    // Hex hash to combine with the one computed so far
+    sibling: String,
+    sibling_on_left: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct RecordProof {
+    leaf_index: u64,
+    record_count: u64,
+    // The candid-encoded ExportRecord, as it appears in the export's chunks
+    record: Vec<u8>,
+    // From the leaf up to the root
+    path: Vec<ProofStep>,
+    root_hash: String,
+}
+
+// Hands every record to `visit` in export order, with its memory id and key,
+// until it returns false
+fn for_each_export_record(mut visit: impl FnMut(u8, &[u8], Vec<u8>) -> bool) {
+    for (memory_id, store) in snapshot_stores() {
+        for (key, value) in store.dump() {
+            let record = Encode!(&ExportRecord {
+                memory_id,
+                key: key.clone(),
+                value
+            })
+            .expect("Cannot encode an export record");
+            if !visit(memory_id, &key, record) {
+                return;
+            }
+        }
+    }
+}
+
+// Cuts the dataset into chunks, handing each one to `visit` until it returns
+// false. Chunk boundaries only depend on the data, so every call sees the same
+// chunks as long as nothing is written in between
+fn for_each_export_chunk(mut visit: impl FnMut(ExportChunk) -> bool) {
+    let mut chunk = ExportChunk {
+        chunk_index: 0,
+        record_count: 0,
+        data: vec![],
+    };
+    let mut stopped = false;
+    for_each_export_record(|_, _, record| {
+        if chunk.record_count > 0 && chunk.data.len() + 4 + record.len() > EXPORT_CHUNK_BYTES {
+            let next = ExportChunk {
+                chunk_index: chunk.chunk_index + 1,
+                record_count: 0,
+                data: vec![],
+            };
+            if !visit(std::mem::replace(&mut chunk, next)) {
+                stopped = true;
+                return false;
+            }
+        }
+        chunk
+            .data
+            .extend_from_slice(&(record.len() as u32).to_be_bytes());
+        chunk.data.extend_from_slice(&record);
+        chunk.record_count += 1;
+        true
+    });
+    if !stopped && chunk.record_count > 0 {
+        visit(chunk);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn merkle_leaf(record: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update(record);
+    hasher.finalize().into()
+}
+
+fn merkle_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// The next level up of a Merkle tree
+fn merkle_parents(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => merkle_node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// The root over the leaves, and the path from the leaf at `index` if given;
+// the root of an empty dataset is the SHA-256 of nothing
+fn merkle_root(mut level: Vec<[u8; 32]>, mut index: Option<usize>) -> ([u8; 32], Vec<ProofStep>) {
+    if level.is_empty() {
+        return (Sha256::digest([]).into(), vec![]);
+    }
+    let mut path = vec![];
+    while level.len() > 1 {
+        if let Some(i) = index {
+            let sibling = i ^ 1;
+            if sibling < level.len() {
+                path.push(ProofStep {
+                    sibling: hex(&level[sibling]),
+                    sibling_on_left: sibling < i,
+                });
+            }
+            index = Some(i / 2);
+        }
+        level = merkle_parents(&level);
+    }
+    (level[0], path)
+}
+
+fn record_leaves() -> Vec<[u8; 32]> {
+    let mut leaves = vec![];
+    for_each_export_record(|_, _, record| {
+        leaves.push(merkle_leaf(&record));
+        true
+    });
+    leaves
+}
+
+// Describes the export as it would be streamed now
+pub(crate) fn export_manifest() -> ExportManifest {
+    let mut hasher = Sha256::new();
+    let mut manifest = ExportManifest {
+        format_version: EXPORT_FORMAT_VERSION,
+        generated_at: time(),
+        total_chunks: 0,
+        total_records: 0,
+        total_bytes: 0,
+        sha256: String::new(),
+        chunk_sha256: vec![],
+        merkle_root: String::new(),
+    };
+    for_each_export_chunk(|chunk| {
+        hasher.update(&chunk.data);
+        manifest.total_chunks += 1;
+        manifest.total_records += chunk.record_count;
+        manifest.total_bytes += chunk.data.len() as u64;
+        manifest.chunk_sha256.push(sha256_hex(&chunk.data));
+        true
+    });
+    manifest.sha256 = hex(&hasher.finalize());
+    manifest.merkle_root = hex(&merkle_root(record_leaves(), None).0);
+    manifest
+}
+
+// Function to describe the export so off-chain backups can check the chunks
+// they downloaded
+#[ic_cdk::query]
+fn get_export_manifest() -> Result<ExportManifest, Error> {
+    ensure_controller()?;
+    Ok(export_manifest())
+}
+
+// Function to get the Merkle root of the dataset, against which a copy of
+// the export, or single records with their proofs, can be checked
+#[ic_cdk::query]
+fn get_dataset_root_hash() -> Result<DatasetRootHash, Error> {
+    let leaves = record_leaves();
+    let record_count = leaves.len() as u64;
+    Ok(DatasetRootHash {
+        root_hash: hex(&merkle_root(leaves, None).0),
+        record_count,
+        generated_at: time(),
+    })
+}
+
+// Function to prove that one record, by its memory id and stored key, is part
+// of the dataset with the current root hash
+#[ic_cdk::query]
+fn get_record_proof(memory_id: u8, key: Vec<u8>) -> Result<RecordProof, Error> {
+    ensure_controller()?;
+    let mut leaves = vec![];
+    let mut found = None;
+    for_each_export_record(|id, record_key, record| {
+        leaves.push(merkle_leaf(&record));
+        if id == memory_id && record_key == key.as_slice() {
+            found = Some((leaves.len() - 1, record));
+        }
+        true
+    });
+    let (leaf_index, record) = found.ok_or_else(|| Error::NotFound {
+        msg: format!("No record in memory {} has that key", memory_id),
+    })?;
+    let record_count = leaves.len() as u64;
+    let (root, path) = merkle_root(leaves, Some(leaf_index));
+    Ok(RecordProof {
+        leaf_index: leaf_index as u64,
+        record_count,
+        record,
+        path,
+        root_hash: hex(&root),
+    })
+}
+
+// Function to stream the whole dataset, one chunk per call
+#[ic_cdk::query]
+fn export_data(chunk_index: u64) -> Result<ExportChunk, Error> {
+    ensure_controller()?;
+    let mut found = None;
+    for_each_export_chunk(|chunk| {
+        if chunk.chunk_index == chunk_index {
+            found = Some(chunk);
+            return false;
+        }
+        true
+    });
+    found.ok_or_else(|| Error::NotFound {
+        msg: format!("Export chunk {} not found", chunk_index),
+    })
+}
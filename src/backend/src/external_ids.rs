@@ -0,0 +1,177 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::maintenance::ensure_writable;
+use crate::{
+    _get_green_space, ensure_green_space_exists, ensure_space_admin, get_memory, Error, GreenSpace,
+    Memory,
+};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const EXTERNAL_IDS_MEMORY_ID: u8 = 46;
+const EXTERNAL_ID_LOOKUP_MEMORY_ID: u8 = 47;
+
+const MAX_EXTERNAL_ID_LEN: usize = 64;
+
+// The systems outside this canister that keep their own record of a space
+#[derive(
+    candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+pub(crate) enum ExternalSystem {
+    Osm,
+    Cadastral,
+    MunicipalAsset,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ExternalId {
+    system: ExternalSystem,
+    external_id: String,
+}
+
+impl_storable!(ExternalId, 128);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct SpaceSystemKey {
+    space_id: u64,
+    system: ExternalSystem,
+}
+
+impl_storable!(SpaceSystemKey, 64);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ExternalIdKey {
+    system: ExternalSystem,
+    external_id: String,
+}
+
+impl_storable!(ExternalIdKey, 128);
+
+thread_local! {
+    // The id of each space in each external system, at most one per system
+    pub(crate) static EXTERNAL_IDS: RefCell<StableBTreeMap<SpaceSystemKey, ExternalId, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(EXTERNAL_IDS_MEMORY_ID)));
+
+    // The reverse direction, for lookups by external id
+    pub(crate) static EXTERNAL_ID_LOOKUP: RefCell<StableBTreeMap<ExternalIdKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(EXTERNAL_ID_LOOKUP_MEMORY_ID)));
+}
+
+fn space_ids(space_id: u64) -> Vec<(SpaceSystemKey, ExternalId)> {
+    let start = SpaceSystemKey {
+        space_id,
+        system: ExternalSystem::Osm,
+    };
+    EXTERNAL_IDS.with(|ids| {
+        ids.borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.space_id == space_id)
+            .collect()
+    })
+}
+
+// The key of an external id as it appears in the block log
+fn block_key(key: &SpaceSystemKey) -> (u64, String) {
+    (key.space_id, format!("{:?}", key.system))
+}
+
+fn unmap(key: &SpaceSystemKey) -> Option<ExternalId> {
+    let previous = EXTERNAL_IDS.with(|ids| ids.borrow_mut().remove(key))?;
+    log_delete("external_id", &block_key(key));
+    EXTERNAL_ID_LOOKUP.with(|lookup| {
+        lookup.borrow_mut().remove(&ExternalIdKey {
+            system: previous.system,
+            external_id: previous.external_id.clone(),
+        })
+    });
+    Some(previous)
+}
+
+// Drops every external id of a removed space
+pub(crate) fn remove_external_ids(space_id: u64) {
+    for (key, _) in space_ids(space_id) {
+        unmap(&key);
+    }
+}
+
+// Function to record the id a space has in an external system, replacing the
+// one recorded before; an external id can only map to a single space
+#[ic_cdk::update]
+fn set_external_id(
+    space_id: u64,
+    system: ExternalSystem,
+    external_id: String,
+) -> Result<Vec<ExternalId>, Error> {
+    ensure_writable()?;
+    ensure_space_admin(space_id)?;
+    let external_id = external_id.trim().to_string();
+    if external_id.is_empty() || external_id.len() > MAX_EXTERNAL_ID_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("External ids must be 1-{} bytes", MAX_EXTERNAL_ID_LEN),
+        });
+    }
+    let lookup_key = ExternalIdKey {
+        system,
+        external_id: external_id.clone(),
+    };
+    if let Some(other) = EXTERNAL_ID_LOOKUP.with(|lookup| lookup.borrow().get(&lookup_key)) {
+        if other != space_id {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "The {:?} id {} already belongs to the green space with id={}",
+                    system, external_id, other
+                ),
+            });
+        }
+    }
+    let key = SpaceSystemKey { space_id, system };
+    unmap(&key);
+    let record = ExternalId {
+        system,
+        external_id,
+    };
+    log_upsert("external_id", &block_key(&key), &record);
+    EXTERNAL_IDS.with(|ids| ids.borrow_mut().insert(key, record));
+    EXTERNAL_ID_LOOKUP.with(|lookup| lookup.borrow_mut().insert(lookup_key, space_id));
+    get_external_ids(space_id)
+}
+
+// Function to forget the id a space has in an external system
+#[ic_cdk::update]
+fn remove_external_id(space_id: u64, system: ExternalSystem) -> Result<ExternalId, Error> {
+    ensure_writable()?;
+    ensure_space_admin(space_id)?;
+    unmap(&SpaceSystemKey { space_id, system }).ok_or_else(|| Error::NotFound {
+        msg: format!(
+            "The green space with id={} has no {:?} id",
+            space_id, system
+        ),
+    })
+}
+
+// Function to get the ids a space has in every external system
+#[ic_cdk::query]
+fn get_external_ids(space_id: u64) -> Result<Vec<ExternalId>, Error> {
+    ensure_green_space_exists(space_id)?;
+    Ok(space_ids(space_id)
+        .into_iter()
+        .map(|(_, external_id)| external_id)
+        .collect())
+}
+
+// Function to get the space an external system knows under the given id
+#[ic_cdk::query]
+fn get_green_space_by_external_id(
+    system: ExternalSystem,
+    external_id: String,
+) -> Result<GreenSpace, Error> {
+    let key = ExternalIdKey {
+        system,
+        external_id: external_id.trim().to_string(),
+    };
+    EXTERNAL_ID_LOOKUP
+        .with(|lookup| lookup.borrow().get(&key))
+        .and_then(|id| _get_green_space(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("No green space has the {:?} id {}", system, external_id),
+        })
+}
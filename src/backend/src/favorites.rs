@@ -0,0 +1,67 @@
+use crate::maintenance::ensure_writable;
+use crate::{
+    _get_green_space, authenticated_caller, ensure_green_space_exists, get_memory, principal_key,
+    Error, GreenSpace, Memory, PrincipalKey,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const FAVORITES_MEMORY_ID: u8 = 58;
+
+const MAX_FAVORITES: usize = 200;
+
+thread_local! {
+    // When each user favorited each space, keyed by (user, space id); entries
+    // of deleted spaces are skipped on read
+    pub(crate) static FAVORITES: RefCell<StableBTreeMap<(PrincipalKey, u64), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(FAVORITES_MEMORY_ID)));
+}
+
+// Ids of the spaces a user has favorited, deleted ones included
+pub(crate) fn favorite_ids(user: &Principal) -> Vec<u64> {
+    let key = principal_key(user);
+    FAVORITES.with(|f| {
+        f.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    })
+}
+
+// Function to add a space to the caller's favorites
+#[ic_cdk::update]
+fn favorite_green_space(id: u64) -> Result<(), Error> {
+    ensure_writable()?;
+    let user = authenticated_caller()?;
+    ensure_green_space_exists(id)?;
+    let key = (principal_key(&user), id);
+    if FAVORITES.with(|f| f.borrow().contains_key(&key)) {
+        return Ok(());
+    }
+    if favorite_ids(&user).len() >= MAX_FAVORITES {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} spaces can be favorited", MAX_FAVORITES),
+        });
+    }
+    FAVORITES.with(|f| f.borrow_mut().insert(key, time()));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn unfavorite_green_space(id: u64) -> Result<(), Error> {
+    ensure_writable()?;
+    let user = authenticated_caller()?;
+    FAVORITES.with(|f| f.borrow_mut().remove(&(principal_key(&user), id)));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_my_favorites() -> Result<Vec<GreenSpace>, Error> {
+    let user = authenticated_caller()?;
+    Ok(favorite_ids(&user)
+        .iter()
+        .filter_map(_get_green_space)
+        .collect())
+}
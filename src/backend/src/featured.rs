@@ -0,0 +1,298 @@
+use crate::maintenance::ensure_writable;
+use crate::views::{today, views_since};
+use crate::{
+    _get_green_space, ensure_controller, get_memory, Error, GreenSpace, GreenSpaceStatus, Memory,
+    GREEN_SPACE_STORAGE,
+};
+use candid::Principal;
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const FEATURED_SPACES_MEMORY_ID: u8 = 87;
+
+// Days of features kept and shown, today included; spaces featured within
+// them are not picked again while others remain
+const FEATURE_HISTORY_DAYS: u64 = 30;
+// Days ahead a controller can pin a space to
+const MAX_PIN_DAYS_AHEAD: u64 = 366;
+// A space's weight is this divided by one more than its views in the history
+// window, so rarely visited spaces come up more often
+const UNVISITED_WEIGHT: u128 = 1_000_000_000;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct FeaturedSpace {
+    // Days since the epoch, UTC
+    day: u64,
+    space_id: u64,
+    // Set when a controller chose the space rather than the rotation
+    pinned_by: Option<Principal>,
+    selected_at: u64,
+}
+
+impl_storable!(FeaturedSpace, 256);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct FeaturedToday {
+    feature: FeaturedSpace,
+    space: GreenSpace,
+}
+
+// Randomness from raw_rand for one round, with the number of draws made from
+// it; time() stays the same throughout a round, so it identifies the round
+struct RoundSeed {
+    round_time: u64,
+    seed: [u8; 32],
+    draws: u64,
+}
+
+thread_local! {
+    static ROUND_SEED: RefCell<Option<RoundSeed>> = const { RefCell::new(None) };
+
+    // The featured space of each day: past ones for the history, future ones
+    // pinned by controllers
+    pub(crate) static FEATURED_SPACES: RefCell<StableBTreeMap<u64, FeaturedSpace, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(FEATURED_SPACES_MEMORY_ID)));
+}
+
+// 128 random bits, from the seed of the current round when there is one;
+// draws within a round hash its seed with a counter, so they differ
+async fn random_u128() -> Result<u128, Error> {
+    let cached = ROUND_SEED.with(|s| s.borrow().as_ref().is_some_and(|s| s.round_time == time()));
+    if !cached {
+        let (random,) = raw_rand()
+            .await
+            .map_err(|(code, msg)| Error::OutcallFailed {
+                msg: format!("Cannot get randomness: {:?} {}", code, msg),
+            })?;
+        let seed = Sha256::digest(&random).into();
+        ROUND_SEED.with(|s| {
+            *s.borrow_mut() = Some(RoundSeed {
+                round_time: time(),
+                seed,
+                draws: 0,
+            })
+        });
+    }
+    Ok(ROUND_SEED.with(|s| {
+        let mut s = s.borrow_mut();
+        let s = s.as_mut().unwrap();
+        let mut hasher = Sha256::new();
+        hasher.update(s.seed);
+        hasher.update(s.draws.to_be_bytes());
+        s.draws += 1;
+        u128::from_be_bytes(hasher.finalize()[..16].try_into().unwrap())
+    }))
+}
+
+fn is_listed(space: &GreenSpace) -> bool {
+    space.status != Some(GreenSpaceStatus::Archived)
+}
+
+fn listed_space_ids() -> Vec<u64> {
+    GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, space)| is_listed(space))
+            .map(|(id, _)| id)
+            .collect()
+    })
+}
+
+fn feature_of(day: u64) -> Option<FeaturedSpace> {
+    FEATURED_SPACES.with(|f| f.borrow().get(&day))
+}
+
+fn is_current(feature: &FeaturedSpace) -> bool {
+    _get_green_space(&feature.space_id).is_some_and(|space| is_listed(&space))
+}
+
+// Picks a space by weight from those not featured recently, or from all
+// when every one was
+fn pick_weighted(random: u128) -> Option<u64> {
+    let first_day = today().saturating_sub(FEATURE_HISTORY_DAYS - 1);
+    let recent: BTreeSet<u64> = FEATURED_SPACES.with(|f| {
+        f.borrow()
+            .range(first_day..today())
+            .map(|(_, feature)| feature.space_id)
+            .collect()
+    });
+    let listed = listed_space_ids();
+    let fresh: Vec<u64> = listed
+        .iter()
+        .copied()
+        .filter(|id| !recent.contains(id))
+        .collect();
+    let candidates = if fresh.is_empty() { listed } else { fresh };
+    let views = views_since(first_day);
+    let weights: Vec<(u64, u128)> = candidates
+        .into_iter()
+        .map(|id| {
+            let views = u128::from(views.get(&id).copied().unwrap_or(0));
+            (id, (UNVISITED_WEIGHT / (views + 1)).max(1))
+        })
+        .collect();
+    let total: u128 = weights.iter().map(|(_, weight)| weight).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut target = random % total;
+    for (id, weight) in weights {
+        if target < weight {
+            return Some(id);
+        }
+        target -= weight;
+    }
+    None
+}
+
+fn prune_feature_history() {
+    let first_day = today().saturating_sub(FEATURE_HISTORY_DAYS - 1);
+    let old: Vec<u64> =
+        FEATURED_SPACES.with(|f| f.borrow().range(..first_day).map(|(day, _)| day).collect());
+    FEATURED_SPACES.with(|f| {
+        let mut features = f.borrow_mut();
+        for day in old {
+            features.remove(&day);
+        }
+    });
+}
+
+// Features a space today unless one is, picking again when the featured
+// space was deleted or archived since; drops features older than the history
+pub(crate) async fn rotate_featured_space() -> Result<String, Error> {
+    prune_feature_history();
+    if feature_of(today()).is_some_and(|feature| is_current(&feature)) {
+        return Ok("Today's space is already featured".to_string());
+    }
+    let random = random_u128().await?;
+    // The day may have turned while raw_rand was awaited
+    let day = today();
+    let Some(space_id) = pick_weighted(random) else {
+        return Ok("No green space to feature".to_string());
+    };
+    FEATURED_SPACES.with(|f| {
+        f.borrow_mut().insert(
+            day,
+            FeaturedSpace {
+                day,
+                space_id,
+                pinned_by: None,
+                selected_at: time(),
+            },
+        )
+    });
+    Ok(format!("Green space {} featured", space_id))
+}
+
+// Function to get a green space picked uniformly at random, leaving out
+// archived ones; an update since queries cannot get randomness, though it
+// changes nothing
+#[ic_cdk::update]
+async fn get_random_green_space() -> Result<GreenSpace, Error> {
+    let random = random_u128().await?;
+    let listed = listed_space_ids();
+    if listed.is_empty() {
+        return Err(Error::NotFound {
+            msg: "There are no green spaces yet".to_string(),
+        });
+    }
+    // With 128 bits the modulo bias is negligible
+    let id = listed[(random % listed.len() as u128) as usize];
+    Ok(_get_green_space(&id).unwrap())
+}
+
+// Function to get the space of the day, as picked by the rotation or pinned
+#[ic_cdk::query]
+fn get_featured_today() -> Result<FeaturedToday, Error> {
+    feature_of(today())
+        .filter(is_current)
+        .map(|feature| FeaturedToday {
+            space: _get_green_space(&feature.space_id).unwrap(),
+            feature,
+        })
+        .ok_or_else(|| Error::NotFound {
+            msg: "No green space is featured today yet".to_string(),
+        })
+}
+
+// Function to list the features of the last 30 days, today included, newest
+// first
+#[ic_cdk::query]
+fn get_featured_history() -> Result<Vec<FeaturedSpace>, Error> {
+    let first_day = today().saturating_sub(FEATURE_HISTORY_DAYS - 1);
+    Ok(FEATURED_SPACES.with(|f| {
+        f.borrow()
+            .range(first_day..=today())
+            .map(|(_, feature)| feature)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect()
+    }))
+}
+
+// Function to list the spaces pinned to coming days
+#[ic_cdk::query]
+fn get_featured_schedule() -> Result<Vec<FeaturedSpace>, Error> {
+    Ok(FEATURED_SPACES.with(|f| {
+        f.borrow()
+            .range(today() + 1..)
+            .map(|(_, feature)| feature)
+            .collect()
+    }))
+}
+
+// Function for controllers to pin a space to today or a coming day, replacing
+// whatever the rotation picked
+#[ic_cdk::update]
+fn pin_featured_space(space_id: u64, day: u64) -> Result<FeaturedSpace, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if day < today() || day > today() + MAX_PIN_DAYS_AHEAD {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The day must be between today ({}) and {} days ahead",
+                today(),
+                MAX_PIN_DAYS_AHEAD
+            ),
+        });
+    }
+    let space = _get_green_space(&space_id).ok_or_else(|| Error::NotFound {
+        msg: format!("A green space with id={} not found", space_id),
+    })?;
+    if !is_listed(&space) {
+        return Err(Error::InvalidInput {
+            msg: "Archived spaces cannot be featured".to_string(),
+        });
+    }
+    let feature = FeaturedSpace {
+        day,
+        space_id,
+        pinned_by: Some(ic_cdk::caller()),
+        selected_at: time(),
+    };
+    FEATURED_SPACES.with(|f| f.borrow_mut().insert(day, feature.clone()));
+    Ok(feature)
+}
+
+// Function for controllers to unpin a space from a coming day; today's
+// feature stays
+#[ic_cdk::update]
+fn unpin_featured_space(day: u64) -> Result<FeaturedSpace, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if day <= today() {
+        return Err(Error::InvalidInput {
+            msg: "Only features of coming days can be unpinned".to_string(),
+        });
+    }
+    FEATURED_SPACES
+        .with(|f| f.borrow_mut().remove(&day))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("No space is pinned to day {}", day),
+        })
+}
@@ -0,0 +1,187 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::geo::BoundingBox;
+use crate::maintenance::ensure_writable;
+use crate::paging::{decode_token, encode_token, Page};
+use crate::shards::{call_failed, shard_canisters};
+use crate::spatial_index::spaces_in_bbox_page;
+use crate::{
+    ensure_controller, get_memory, principal_key, Error, GreenSpace, Memory, PrincipalKey,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const PEERS_MEMORY_ID: u8 = 26;
+
+const MAX_PEERS: u64 = 50;
+const MAX_LABEL_LEN: usize = 100;
+
+// Another deployment of this canister, e.g. a neighbouring city's
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PeerCanister {
+    canister_id: Principal,
+    label: String,
+    registered_at: u64,
+}
+
+impl_storable!(PeerCanister, 256);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct RegionalGreenSpace {
+    // The canister holding the space; ids are only unique per canister
+    source: Principal,
+    space: GreenSpace,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct FailedSource {
+    canister_id: Principal,
+    error: Error,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct RegionalMap {
+    spaces: Vec<RegionalGreenSpace>,
+    // Peers that could not be queried; their spaces are missing from the map
+    failed_sources: Vec<FailedSource>,
+    // Pass back to get the spaces of the next page or source; None once every
+    // source was listed
+    next_token: Option<String>,
+}
+
+thread_local! {
+    // Peers keyed by their canister id
+    pub(crate) static PEERS: RefCell<StableBTreeMap<PrincipalKey, PeerCanister, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(PEERS_MEMORY_ID)));
+}
+
+// Function to register a peer green space canister for regional queries
+#[ic_cdk::update]
+fn register_peer_canister(canister_id: Principal, label: String) -> Result<PeerCanister, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if label.len() > MAX_LABEL_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Peer label must be at most {} characters", MAX_LABEL_LEN),
+        });
+    }
+    if canister_id == ic_cdk::id() {
+        return Err(Error::InvalidInput {
+            msg: "A canister cannot be its own peer".to_string(),
+        });
+    }
+    let is_new = !PEERS.with(|p| p.borrow().contains_key(&principal_key(&canister_id)));
+    if is_new && PEERS.with(|p| p.borrow().len()) >= MAX_PEERS {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} peers can be registered", MAX_PEERS),
+        });
+    }
+
+    let peer = PeerCanister {
+        canister_id,
+        label,
+        registered_at: time(),
+    };
+    PEERS.with(|p| {
+        p.borrow_mut()
+            .insert(principal_key(&canister_id), peer.clone())
+    });
+    log_upsert("peer", &canister_id, &peer);
+    Ok(peer)
+}
+
+#[ic_cdk::update]
+fn unregister_peer_canister(canister_id: Principal) -> Result<PeerCanister, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let peer = PEERS
+        .with(|p| p.borrow_mut().remove(&principal_key(&canister_id)))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("Canister {} is not a registered peer", canister_id),
+        })?;
+    log_delete("peer", &canister_id);
+    Ok(peer)
+}
+
+#[ic_cdk::query]
+fn get_peer_canisters() -> Result<Vec<PeerCanister>, Error> {
+    Ok(PEERS.with(|p| p.borrow().iter().map(|(_, peer)| peer).collect()))
+}
+
+// Function to build a regional map: the spaces in a bounding box from this
+// canister, its city shards and every registered peer. Each call returns one
+// page of one source; pass next_token back for the rest. Composite queries can
+// only call canisters on the same subnet; others end up in failed_sources
+#[ic_cdk::query(composite = true)]
+async fn get_regional_green_spaces(
+    bbox: BoundingBox,
+    next_token: Option<String>,
+) -> Result<RegionalMap, Error> {
+    bbox.validate()?;
+    let own_id = ic_cdk::id();
+    let mut sources = shard_canisters();
+    sources.extend(PEERS.with(|p| {
+        p.borrow()
+            .iter()
+            .map(|(_, peer)| peer.canister_id)
+            .collect::<Vec<_>>()
+    }));
+    sources.retain(|source| *source != own_id);
+    sources.sort();
+    sources.dedup();
+    sources.insert(0, own_id);
+
+    // The token names the source being listed and the token of its next page
+    let (source, source_token) =
+        decode_token::<(Principal, Option<String>)>(&next_token)?.unwrap_or((own_id, None));
+    let position =
+        sources
+            .iter()
+            .position(|s| *s == source)
+            .ok_or_else(|| Error::InvalidInput {
+                msg: "Invalid continuation token".to_string(),
+            })?;
+    let mut failed_sources = Vec::new();
+    let mut page = Page {
+        items: vec![],
+        next_token: None,
+    };
+    if source == own_id {
+        page = spaces_in_bbox_page(&bbox, None, &source_token)?;
+    } else {
+        let result: Result<(Result<Page<GreenSpace>, Error>,), _> = ic_cdk::call(
+            source,
+            "get_green_spaces_in_bbox",
+            (bbox, None::<u64>, source_token),
+        )
+        .await;
+        match result {
+            Ok((Ok(found),)) => page = found,
+            Ok((Err(error),)) => failed_sources.push(FailedSource {
+                canister_id: source,
+                error,
+            }),
+            Err((code, msg)) => failed_sources.push(FailedSource {
+                canister_id: source,
+                error: call_failed(source, "get_green_spaces_in_bbox", code, msg),
+            }),
+        }
+    }
+
+    let next_token = match page.next_token {
+        Some(token) => Some(encode_token(&(source, Some(token)))),
+        None => sources
+            .get(position + 1)
+            .map(|next| encode_token(&(*next, None::<String>))),
+    };
+    Ok(RegionalMap {
+        spaces: page
+            .items
+            .into_iter()
+            .map(|space| RegionalGreenSpace { source, space })
+            .collect(),
+        failed_sources,
+        next_token,
+    })
+}
@@ -0,0 +1,95 @@
+use crate::calendar::{format_rfc3339, format_utc};
+use crate::events::upcoming_events;
+use crate::kml::escape_xml;
+use crate::{GreenSpace, GREEN_SPACE_STORAGE, NANOS_PER_DAY};
+
+// Spaces count as new for a month; the feed lists the newest of them
+const NEW_SPACE_WINDOW: u64 = 30 * NANOS_PER_DAY;
+const MAX_FEED_SPACES: usize = 50;
+const MAX_FEED_EVENTS: usize = 50;
+
+struct FeedEntry {
+    id: String,
+    title: String,
+    path: String,
+    updated: u64,
+    summary: String,
+}
+
+fn new_spaces(now: u64) -> Vec<GreenSpace> {
+    let since = now.saturating_sub(NEW_SPACE_WINDOW);
+    let mut spaces: Vec<GreenSpace> = GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, space)| space)
+            .filter(|space| space.created_at.is_some_and(|at| at >= since))
+            .collect()
+    });
+    spaces.sort_by_key(|space| std::cmp::Reverse(space.created_at));
+    spaces.truncate(MAX_FEED_SPACES);
+    spaces
+}
+
+fn entries(now: u64) -> Vec<FeedEntry> {
+    let spaces = new_spaces(now).into_iter().map(|space| FeedEntry {
+        id: format!("urn:green-spaces:space:{}", space.id),
+        title: format!("New green space: {}", space.name),
+        path: format!("/spaces/{}", space.id),
+        updated: space.created_at.unwrap_or(now),
+        summary: if space.location.is_empty() {
+            space.description
+        } else {
+            format!("{}. {}", space.location, space.description)
+        },
+    });
+    let events = upcoming_events(None, MAX_FEED_EVENTS)
+        .into_iter()
+        .map(|event| FeedEntry {
+            id: format!("urn:green-spaces:event:{}", event.id),
+            title: format!("{} ({})", event.title, format_utc(event.starts_at)),
+            path: format!("/spaces/{}", event.space_id),
+            updated: event.created_at,
+            summary: event.description,
+        });
+    let mut entries: Vec<FeedEntry> = spaces.chain(events).collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.updated));
+    entries
+}
+
+// An Atom (RFC 4287) feed of recently added spaces and upcoming events; links
+// are made absolute with the base URL the request came in on
+pub(crate) fn atom_feed(base_url: &str, now: u64) -> String {
+    let entries = entries(now);
+    let updated = entries.first().map_or(now, |entry| entry.updated);
+    let mut feed = format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+            "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+            "<id>urn:green-spaces:feed</id>\n",
+            "<title>Green spaces: new spaces and upcoming events</title>\n",
+            "<updated>{updated}</updated>\n",
+            "<author><name>Green spaces</name></author>\n",
+            "<link rel=\"self\" type=\"application/atom+xml\" href=\"{base}/feed.xml\"/>\n",
+            "<link rel=\"alternate\" type=\"text/html\" href=\"{base}/\"/>\n",
+        ),
+        base = escape_xml(base_url),
+        updated = format_rfc3339(updated),
+    );
+    for entry in entries {
+        feed.push_str(&format!(
+            concat!(
+                "<entry>\n<id>{}</id>\n<title>{}</title>\n<updated>{}</updated>\n",
+                "<link rel=\"alternate\" type=\"text/html\" href=\"{}{}\"/>\n",
+                "<summary>{}</summary>\n</entry>\n",
+            ),
+            entry.id,
+            escape_xml(&entry.title),
+            format_rfc3339(entry.updated),
+            escape_xml(base_url),
+            entry.path,
+            escape_xml(&entry.summary)
+        ));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
@@ -0,0 +1,97 @@
+use crate::geo::{Coordinates, Polygon};
+use crate::{_get_green_space_boundary, GreenSpace};
+use serde_json::{json, Value};
+
+const CSV_HEADER: &str =
+    "id,ulid,name,location,description,lat,lng,city_id,district_id,category,status,external_ref,shade_coverage_percent,has_water\r\n";
+
+// GeoJSON orders positions as [longitude, latitude]
+fn position(c: &Coordinates) -> Value {
+    json!([c.lng, c.lat])
+}
+
+fn polygon_geometry(polygon: &Polygon) -> Value {
+    let rings: Vec<Value> = std::iter::once(&polygon.exterior)
+        .chain(&polygon.holes)
+        .map(|ring| ring.iter().map(position).collect())
+        .collect();
+    json!({ "type": "Polygon", "coordinates": rings })
+}
+
+// A space as a feature; its boundary when it has one, else its point
+pub(crate) fn feature(space: &GreenSpace) -> Value {
+    let geometry = match _get_green_space_boundary(&space.id) {
+        Some(boundary) => polygon_geometry(&boundary),
+        None => space.coordinates.map_or(
+            Value::Null,
+            |c| json!({ "type": "Point", "coordinates": position(&c) }),
+        ),
+    };
+    json!({
+        "type": "Feature",
+        "id": space.id,
+        "geometry": geometry,
+        "properties": {
+            "ulid": space.ulid,
+            "name": space.name,
+            "location": space.location,
+            "description": space.description,
+            "city_id": space.city_id,
+            "district_id": space.district_id,
+            "category": space.category,
+            "status": space.status,
+            "external_ref": space.external_ref,
+            "shade_coverage_percent": space.shade_coverage_percent,
+            "has_water": space.has_water,
+        },
+    })
+}
+
+pub(crate) fn geojson_feature(space: &GreenSpace) -> Vec<u8> {
+    serde_json::to_vec(&feature(space)).expect("Cannot encode a feature")
+}
+
+pub(crate) fn geojson_feature_collection(spaces: &[GreenSpace]) -> Vec<u8> {
+    let features: Vec<Value> = spaces.iter().map(feature).collect();
+    serde_json::to_vec(&json!({ "type": "FeatureCollection", "features": features }))
+        .expect("Cannot encode a feature collection")
+}
+
+// Quotes a field when it holds a separator, a quote or a line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// One row per space under a header row, lines ended by CRLF as in RFC 4180
+pub(crate) fn csv_table(spaces: &[GreenSpace]) -> Vec<u8> {
+    let mut csv = CSV_HEADER.to_string();
+    for space in spaces {
+        let row = [
+            space.id.to_string(),
+            optional(space.ulid.as_ref()),
+            csv_field(&space.name),
+            csv_field(&space.location),
+            csv_field(&space.description),
+            optional(space.coordinates.map(|c| c.lat)),
+            optional(space.coordinates.map(|c| c.lng)),
+            optional(space.city_id),
+            optional(space.district_id),
+            optional(space.category.map(|category| format!("{:?}", category))),
+            optional(space.status.map(|status| format!("{:?}", status))),
+            csv_field(space.external_ref.as_deref().unwrap_or_default()),
+            optional(space.shade_coverage_percent),
+            optional(space.has_water),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv.into_bytes()
+}
@@ -0,0 +1,315 @@
+use crate::block_log::log_config;
+use crate::maintenance::ensure_writable;
+use crate::{ensure_controller, get_memory, Error, GreenSpace, Memory, GREEN_SPACE_STORAGE};
+use ic_stable_structures::{Cell, StableBTreeMap};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const TOKENIZER_CONFIG_MEMORY_ID: u8 = 52;
+const TERM_INDEX_MEMORY_ID: u8 = 53;
+
+// Longer terms are neither indexed nor matched
+const MAX_TERM_CHARS: usize = 64;
+const MAX_TERM_BYTES: usize = 256;
+const MAX_STOPWORDS: usize = 500;
+const MAX_WORD_CHARS: usize = 16;
+
+// How names, tags and descriptions are split into the terms search matches
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TokenizerConfig {
+    // Language of the deployment, e.g. "en"
+    language: String,
+    // Characters besides letters and digits that belong to a term, e.g. "-'"
+    word_chars: String,
+    min_term_chars: u32,
+    // Strips common suffixes; only rules for English exist
+    stemming: bool,
+    // Terms never indexed nor matched, e.g. "the", "park"
+    stopwords: Vec<String>,
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        TokenizerConfig {
+            language: "en".to_string(),
+            word_chars: String::new(),
+            min_term_chars: 1,
+            stemming: false,
+            stopwords: vec![],
+        }
+    }
+}
+
+impl_storable!(TokenizerConfig, 65536);
+
+// A space whose indexed text contains the term
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct TermKey {
+    term: String,
+    id: u64,
+}
+
+impl_storable!(TermKey, 512);
+
+thread_local! {
+    static TOKENIZER_CONFIG: RefCell<Cell<TokenizerConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(TOKENIZER_CONFIG_MEMORY_ID), TokenizerConfig::default())
+            .expect("Cannot initialize the tokenizer config")
+    );
+
+    // Entries left from text tokenized under an earlier config only add
+    // candidates that then score nothing, until the index is rebuilt
+    static TERM_INDEX: RefCell<StableBTreeMap<TermKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(TERM_INDEX_MEMORY_ID)));
+}
+
+pub(crate) fn tokenizer() -> TokenizerConfig {
+    TOKENIZER_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+fn stem(term: &str) -> String {
+    let len = term.chars().count();
+    if let Some(stripped) = term.strip_suffix("ies").filter(|_| len > 4) {
+        return format!("{}y", stripped);
+    }
+    if let Some(stripped) = term.strip_suffix("sses") {
+        return format!("{}ss", stripped);
+    }
+    for (suffix, min_len) in [("ing", 6), ("ed", 5)] {
+        if let Some(stripped) = term.strip_suffix(suffix).filter(|_| len >= min_len) {
+            return stripped.to_string();
+        }
+    }
+    match term.strip_suffix('s') {
+        Some(stripped) if len > 3 && !stripped.ends_with('s') && !stripped.ends_with('u') => {
+            stripped.to_string()
+        }
+        _ => term.to_string(),
+    }
+}
+
+impl TokenizerConfig {
+    fn is_word_char(&self, c: char) -> bool {
+        c.is_alphanumeric() || self.word_chars.contains(c)
+    }
+
+    // The normalized form search compares, None for terms it ignores
+    fn normalize(&self, raw: &str) -> Option<String> {
+        let chars = raw.chars().count();
+        if chars < self.min_term_chars as usize || chars > MAX_TERM_CHARS {
+            return None;
+        }
+        let lowered = raw.to_lowercase();
+        // Lowercasing can grow a term; keys stay within their bound
+        if lowered.len() > MAX_TERM_BYTES || self.stopwords.contains(&lowered) {
+            return None;
+        }
+        Some(if self.stemming {
+            stem(&lowered)
+        } else {
+            lowered
+        })
+    }
+
+    // The terms of a text with their character ranges, end exclusive
+    pub(crate) fn spans(&self, text: &str) -> Vec<((u32, u32), String)> {
+        let mut spans = Vec::new();
+        let mut start = None;
+        let mut raw = String::new();
+        let mut end = 0;
+        for (i, c) in text.chars().enumerate() {
+            end = i + 1;
+            if self.is_word_char(c) {
+                start.get_or_insert(i);
+                raw.push(c);
+            } else if let Some(start) = start.take() {
+                if let Some(term) = self.normalize(&std::mem::take(&mut raw)) {
+                    spans.push(((start as u32, i as u32), term));
+                }
+            }
+        }
+        if let Some(start) = start {
+            if let Some(term) = self.normalize(&raw) {
+                spans.push(((start as u32, end as u32), term));
+            }
+        }
+        spans
+    }
+
+    pub(crate) fn terms(&self, text: &str) -> Vec<String> {
+        self.spans(text).into_iter().map(|(_, term)| term).collect()
+    }
+}
+
+// Every metadata value is indexed; search only scores those under searchable
+// keys, so defining keys needs no rebuild
+fn term_keys(space: &GreenSpace, config: &TokenizerConfig) -> BTreeSet<TermKey> {
+    let values = space.metadata.iter().flatten().map(|(_, value)| value);
+    [&space.name, &space.description]
+        .into_iter()
+        .chain(values)
+        .flat_map(|text| config.terms(text))
+        .map(|term| TermKey { term, id: space.id })
+        .collect()
+}
+
+// Brings the term index in line with a stored, replaced or removed space
+pub(crate) fn reindex_terms(previous: Option<&GreenSpace>, current: Option<&GreenSpace>) {
+    let config = tokenizer();
+    let stale = previous
+        .map(|space| term_keys(space, &config))
+        .unwrap_or_default();
+    let fresh = current
+        .map(|space| term_keys(space, &config))
+        .unwrap_or_default();
+    TERM_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in stale.difference(&fresh) {
+            index.remove(key);
+        }
+        for key in fresh.difference(&stale) {
+            index.insert(key.clone(), ());
+        }
+    });
+}
+
+// Ids of the spaces whose indexed text contains any of the terms
+pub(crate) fn candidate_ids_for_terms(terms: &[String]) -> BTreeSet<u64> {
+    TERM_INDEX.with(|index| {
+        let index = index.borrow();
+        terms
+            .iter()
+            .flat_map(|term| {
+                let start = TermKey {
+                    term: term.clone(),
+                    id: 0,
+                };
+                let end = TermKey {
+                    term: term.clone(),
+                    id: u64::MAX,
+                };
+                index
+                    .range(start..=end)
+                    .map(|(key, _)| key.id)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    })
+}
+
+// Reindexes every space and returns how many terms were indexed
+pub(crate) fn rebuild_term_index() -> u64 {
+    TERM_INDEX.with(|index| {
+        let keys: Vec<TermKey> = index.borrow().iter().map(|(key, _)| key).collect();
+        let mut index = index.borrow_mut();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    let config = tokenizer();
+    GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, space)| {
+                let keys = term_keys(&space, &config);
+                let count = keys.len() as u64;
+                TERM_INDEX.with(|index| {
+                    let mut index = index.borrow_mut();
+                    for key in keys {
+                        index.insert(key, ());
+                    }
+                });
+                count
+            })
+            .sum()
+    })
+}
+
+// Builds the term index on the first upgrade to a version with it
+pub(crate) fn ensure_term_index() {
+    let empty = TERM_INDEX.with(|index| index.borrow().is_empty());
+    if empty && !GREEN_SPACE_STORAGE.with(|s| s.borrow().is_empty()) {
+        rebuild_term_index();
+    }
+}
+
+// Function to change how text is split into search terms; call
+// rebuild_search_index afterwards so stored spaces are indexed the same way
+#[ic_cdk::update]
+fn set_tokenizer_config(config: TokenizerConfig) -> Result<TokenizerConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut config = config;
+    config.language = config.language.trim().to_lowercase();
+    if config.language.is_empty() || config.language.len() > 8 {
+        return Err(Error::InvalidInput {
+            msg: "The language must be a code of 1-8 characters, e.g. \"en\"".to_string(),
+        });
+    }
+    if config.stemming && config.language != "en" {
+        return Err(Error::InvalidInput {
+            msg: format!("There is no stemmer for the language {}", config.language),
+        });
+    }
+    if config.word_chars.chars().count() > MAX_WORD_CHARS
+        || config.word_chars.chars().any(|c| c.is_whitespace())
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Word characters must be at most {} characters, none of them spaces",
+                MAX_WORD_CHARS
+            ),
+        });
+    }
+    if config.min_term_chars == 0 || config.min_term_chars as usize > MAX_TERM_CHARS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The minimum term length must be between 1 and {}",
+                MAX_TERM_CHARS
+            ),
+        });
+    }
+    if config.stopwords.len() > MAX_STOPWORDS
+        || config
+            .stopwords
+            .iter()
+            .any(|word| word.chars().count() > MAX_TERM_CHARS)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "At most {} stopwords of at most {} characters can be set",
+                MAX_STOPWORDS, MAX_TERM_CHARS
+            ),
+        });
+    }
+    let mut stopwords: Vec<String> = config
+        .stopwords
+        .iter()
+        .map(|word| word.trim().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect();
+    stopwords.sort();
+    stopwords.dedup();
+    config.stopwords = stopwords;
+    TOKENIZER_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the tokenizer config")
+    });
+    log_config("tokenizer_config", &config);
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_tokenizer_config() -> Result<TokenizerConfig, Error> {
+    Ok(tokenizer())
+}
+
+// Function to reindex every space under the current tokenizer config;
+// returns the number of indexed terms
+#[ic_cdk::update]
+fn rebuild_search_index() -> Result<u64, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    Ok(rebuild_term_index())
+}
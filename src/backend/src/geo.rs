@@ -0,0 +1,276 @@
+use crate::Error;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct Coordinates {
+    pub(crate) lat: f64,
+    pub(crate) lng: f64,
+}
+
+impl Coordinates {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if !(-90.0..=90.0).contains(&self.lat) || !(-180.0..=180.0).contains(&self.lng) {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "Coordinates ({}, {}) are outside the valid latitude/longitude range",
+                    self.lat, self.lng
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+// Great-circle distance in metres between two points
+pub(crate) fn distance_m(a: &Coordinates, b: &Coordinates) -> f64 {
+    let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lng = (b.lng - a.lng).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct BoundingBox {
+    pub(crate) south: f64,
+    pub(crate) west: f64,
+    pub(crate) north: f64,
+    pub(crate) east: f64,
+}
+
+impl BoundingBox {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        Coordinates {
+            lat: self.south,
+            lng: self.west,
+        }
+        .validate()?;
+        Coordinates {
+            lat: self.north,
+            lng: self.east,
+        }
+        .validate()?;
+        if self.south > self.north || self.west > self.east {
+            return Err(Error::InvalidInput {
+                msg: "Bounding box south/west corner must not exceed its north/east corner"
+                    .to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn contains(&self, point: &Coordinates) -> bool {
+        (self.south..=self.north).contains(&point.lat)
+            && (self.west..=self.east).contains(&point.lng)
+    }
+
+    pub(crate) fn intersects(&self, other: &BoundingBox) -> bool {
+        self.south <= other.north
+            && other.south <= self.north
+            && self.west <= other.east
+            && other.west <= self.east
+    }
+}
+
+// Upper bound on the vertices of a boundary, keeping stored polygons and the
+// geometry checks on them within bounds
+pub(crate) const MAX_BOUNDARY_VERTICES: usize = 2_000;
+
+// A polygon whose rings are closed, i.e. the first vertex is repeated as the last
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct Polygon {
+    pub(crate) exterior: Vec<Coordinates>,
+    pub(crate) holes: Vec<Vec<Coordinates>>,
+}
+
+impl_storable!(Polygon, 64 * 1024);
+
+fn cross(o: &Coordinates, a: &Coordinates, b: &Coordinates) -> f64 {
+    (a.lng - o.lng) * (b.lat - o.lat) - (a.lat - o.lat) * (b.lng - o.lng)
+}
+
+fn on_segment(a: &Coordinates, b: &Coordinates, p: &Coordinates) -> bool {
+    p.lng >= a.lng.min(b.lng)
+        && p.lng <= a.lng.max(b.lng)
+        && p.lat >= a.lat.min(b.lat)
+        && p.lat <= a.lat.max(b.lat)
+}
+
+// Whether segments a-b and c-d share at least one point
+pub(crate) fn segments_intersect(
+    a: &Coordinates,
+    b: &Coordinates,
+    c: &Coordinates,
+    d: &Coordinates,
+) -> bool {
+    let d1 = cross(c, d, a);
+    let d2 = cross(c, d, b);
+    let d3 = cross(a, b, c);
+    let d4 = cross(a, b, d);
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+    {
+        return true;
+    }
+    (d1 == 0.0 && on_segment(c, d, a))
+        || (d2 == 0.0 && on_segment(c, d, b))
+        || (d3 == 0.0 && on_segment(a, b, c))
+        || (d4 == 0.0 && on_segment(a, b, d))
+}
+
+// Whether segments a-b and c-d cross at a single point interior to both
+fn segments_cross(a: &Coordinates, b: &Coordinates, c: &Coordinates, d: &Coordinates) -> bool {
+    let d1 = cross(c, d, a);
+    let d2 = cross(c, d, b);
+    let d3 = cross(a, b, c);
+    let d4 = cross(a, b, d);
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+// Ray casting test of a point against a closed ring
+fn ring_contains(ring: &[Coordinates], point: &Coordinates) -> bool {
+    let mut inside = false;
+    for edge in ring.windows(2) {
+        let (a, b) = (&edge[0], &edge[1]);
+        if (a.lat > point.lat) != (b.lat > point.lat)
+            && point.lng < (b.lng - a.lng) * (point.lat - a.lat) / (b.lat - a.lat) + a.lng
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn ring_area(ring: &[Coordinates]) -> f64 {
+    ring.windows(2)
+        .map(|edge| edge[0].lng * edge[1].lat - edge[1].lng * edge[0].lat)
+        .sum::<f64>()
+        / 2.0
+}
+
+fn invalid_geometry(msg: String) -> Error {
+    Error::InvalidGeometry { msg }
+}
+
+fn validate_ring(ring: &[Coordinates], name: &str) -> Result<(), Error> {
+    if ring.len() < 4 {
+        return Err(invalid_geometry(format!(
+            "The {} has {} vertices; a closed ring needs at least 4",
+            name,
+            ring.len()
+        )));
+    }
+    if ring.first() != ring.last() {
+        return Err(invalid_geometry(format!(
+            "The {} is not closed; its first and last vertex must be equal",
+            name
+        )));
+    }
+    for coordinates in ring {
+        coordinates.validate()?;
+    }
+    // Non-adjacent edges must not touch; the first and last edge are adjacent
+    let edges = ring.len() - 1;
+    for i in 0..edges {
+        for j in i + 2..edges {
+            if i == 0 && j == edges - 1 {
+                continue;
+            }
+            if segments_intersect(&ring[i], &ring[i + 1], &ring[j], &ring[j + 1]) {
+                return Err(invalid_geometry(format!(
+                    "The {} intersects itself between vertices {} and {}",
+                    name,
+                    i + 1,
+                    j + 1
+                )));
+            }
+        }
+    }
+    if ring_area(ring) == 0.0 {
+        return Err(invalid_geometry(format!("The {} encloses no area", name)));
+    }
+    Ok(())
+}
+
+impl Polygon {
+    pub(crate) fn rings(&self) -> impl Iterator<Item = &Vec<Coordinates>> {
+        std::iter::once(&self.exterior).chain(self.holes.iter())
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let vertices: usize = self.rings().map(Vec::len).sum();
+        if vertices > MAX_BOUNDARY_VERTICES {
+            return Err(invalid_geometry(format!(
+                "Boundaries are limited to {} vertices, got {}",
+                MAX_BOUNDARY_VERTICES, vertices
+            )));
+        }
+        validate_ring(&self.exterior, "exterior ring")?;
+        for (i, hole) in self.holes.iter().enumerate() {
+            let name = format!("hole {}", i + 1);
+            validate_ring(hole, &name)?;
+            if !ring_contains(&self.exterior, &hole[0]) {
+                return Err(invalid_geometry(format!(
+                    "The {} lies outside the exterior ring",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Area in square metres, projecting the polygon onto a plane at its mean
+    // latitude, which is accurate enough at city scale
+    pub(crate) fn area_m2(&self) -> f64 {
+        let bounds = self.bounds();
+        let mean_lat = ((bounds.south + bounds.north) / 2.0).to_radians();
+        let metres_per_deg = EARTH_RADIUS_M.to_radians();
+        let holes: f64 = self.holes.iter().map(|h| ring_area(h).abs()).sum();
+        (ring_area(&self.exterior).abs() - holes) * metres_per_deg.powi(2) * mean_lat.cos()
+    }
+
+    pub(crate) fn contains(&self, point: &Coordinates) -> bool {
+        ring_contains(&self.exterior, point)
+            && !self.holes.iter().any(|hole| ring_contains(hole, point))
+    }
+
+    pub(crate) fn bounds(&self) -> BoundingBox {
+        let mut bounds = BoundingBox {
+            south: f64::MAX,
+            west: f64::MAX,
+            north: f64::MIN,
+            east: f64::MIN,
+        };
+        for c in &self.exterior {
+            bounds.south = bounds.south.min(c.lat);
+            bounds.west = bounds.west.min(c.lng);
+            bounds.north = bounds.north.max(c.lat);
+            bounds.east = bounds.east.max(c.lng);
+        }
+        bounds
+    }
+
+    // Whether the interiors of two polygons share some area; polygons that
+    // merely touch along an edge or at a vertex do not overlap
+    pub(crate) fn overlaps(&self, other: &Polygon) -> bool {
+        if !self.bounds().intersects(&other.bounds()) {
+            return false;
+        }
+        if self.exterior == other.exterior {
+            return true;
+        }
+        let edges_cross = self.rings().any(|ring| {
+            ring.windows(2).any(|a| {
+                other.rings().any(|other_ring| {
+                    other_ring
+                        .windows(2)
+                        .any(|b| segments_cross(&a[0], &a[1], &b[0], &b[1]))
+                })
+            })
+        });
+        edges_cross
+            || self.exterior.iter().any(|c| other.contains(c))
+            || other.exterior.iter().any(|c| self.contains(c))
+    }
+}
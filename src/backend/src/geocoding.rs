@@ -0,0 +1,280 @@
+use crate::geo::Coordinates;
+use crate::maintenance::ensure_writable;
+use crate::outcalls::{canonical_response, http_get, url_encode};
+use crate::quotas::{consume_quota, QuotaOp};
+use crate::{
+    _get_green_space, do_insert_green_space, ensure_space_admin, get_memory, Error, GreenSpace,
+    Memory, NANOS_PER_DAY,
+};
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use ic_cdk::api::time;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+const GEOCODE_CACHE_MEMORY_ID: u8 = 17;
+const REVERSE_GEOCODE_CACHE_MEMORY_ID: u8 = 18;
+
+const NOMINATIM_SEARCH_URL: &str = "https://nominatim.openstreetmap.org/search";
+const NOMINATIM_REVERSE_URL: &str = "https://nominatim.openstreetmap.org/reverse";
+const GEOCODE_MAX_RESPONSE_BYTES: u64 = 8 * 1024;
+// Cached lookups, including "no match" answers, are reused for this long
+const GEOCODE_CACHE_TTL_DAYS: u64 = 90;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct GeocodeResult {
+    coordinates: Coordinates,
+    display_name: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct GeocodeCacheEntry {
+    query: String,
+    result: Option<GeocodeResult>,
+    cached_at: u64,
+}
+
+impl_storable!(GeocodeCacheEntry, 2048);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReverseGeocodeResult {
+    coordinates: Coordinates,
+    pub(crate) address: String,
+    district: Option<String>,
+}
+
+impl_storable!(ReverseGeocodeResult, 2048);
+
+// The fields of a Nominatim search hit that we use; Nominatim returns coordinates as strings
+#[derive(Serialize, Deserialize)]
+struct NominatimPlace {
+    lat: String,
+    lon: String,
+    display_name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NominatimAddress {
+    city_district: Option<String>,
+    suburb: Option<String>,
+    borough: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NominatimReverse {
+    display_name: Option<String>,
+    address: Option<NominatimAddress>,
+}
+
+thread_local! {
+    // Cache keyed by the SHA-256 of the normalized location text
+    pub(crate) static GEOCODE_CACHE: RefCell<StableBTreeMap<Blob<32>, GeocodeCacheEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(GEOCODE_CACHE_MEMORY_ID)));
+
+    // Addresses keyed by the SHA-256 of the coordinates rounded to ~1 m; addresses
+    // practically never change, so these entries do not expire
+    pub(crate) static REVERSE_GEOCODE_CACHE: RefCell<StableBTreeMap<Blob<32>, ReverseGeocodeResult, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(REVERSE_GEOCODE_CACHE_MEMORY_ID)));
+}
+
+fn normalize_query(location: &str) -> String {
+    location
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn cache_key(query: &str) -> Blob<32> {
+    Blob::try_from(Sha256::digest(query.as_bytes()).as_slice()).unwrap()
+}
+
+fn cached_lookup(query: &str) -> Option<GeocodeCacheEntry> {
+    let entry = GEOCODE_CACHE.with(|c| c.borrow().get(&cache_key(query)))?;
+    let is_fresh = time().saturating_sub(entry.cached_at) < GEOCODE_CACHE_TTL_DAYS * NANOS_PER_DAY;
+    is_fresh.then_some(entry)
+}
+
+// Removes the lookups that are past their TTL; returns the number removed
+pub(crate) fn expire_geocode_cache() -> u64 {
+    let now = time();
+    let expired: Vec<_> = GEOCODE_CACHE.with(|c| {
+        c.borrow()
+            .iter()
+            .filter(|(_, entry)| {
+                now.saturating_sub(entry.cached_at) >= GEOCODE_CACHE_TTL_DAYS * NANOS_PER_DAY
+            })
+            .map(|(key, _)| key)
+            .collect()
+    });
+    GEOCODE_CACHE.with(|c| {
+        let mut cache = c.borrow_mut();
+        for key in &expired {
+            cache.remove(key);
+        }
+    });
+    expired.len() as u64
+}
+
+fn parse_place(body: &[u8]) -> Result<Option<GeocodeResult>, Error> {
+    let places: Vec<NominatimPlace> =
+        serde_json::from_slice(body).map_err(|e| Error::OutcallFailed {
+            msg: format!("Unexpected geocoder response: {}", e),
+        })?;
+    let Some(place) = places.into_iter().next() else {
+        return Ok(None);
+    };
+
+    let parse = |value: &str| {
+        value.parse::<f64>().map_err(|_| Error::OutcallFailed {
+            msg: format!("Geocoder returned an invalid coordinate '{}'", value),
+        })
+    };
+    let coordinates = Coordinates {
+        lat: parse(&place.lat)?,
+        lng: parse(&place.lon)?,
+    };
+    coordinates.validate()?;
+    Ok(Some(GeocodeResult {
+        coordinates,
+        display_name: place.display_name,
+    }))
+}
+
+// Looks a location up through the cache, falling back to an outcall to Nominatim
+async fn geocode(location: &str) -> Result<Option<GeocodeResult>, Error> {
+    let query = normalize_query(location);
+    if query.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "Cannot geocode an empty location".to_string(),
+        });
+    }
+    if let Some(entry) = cached_lookup(&query) {
+        return Ok(entry.result);
+    }
+
+    let url = format!(
+        "{}?format=jsonv2&limit=1&q={}",
+        NOMINATIM_SEARCH_URL,
+        url_encode(&query)
+    );
+    let body = http_get(
+        url,
+        GEOCODE_MAX_RESPONSE_BYTES,
+        "transform_geocode_response",
+    )
+    .await?;
+    let result = parse_place(&body)?;
+
+    let entry = GeocodeCacheEntry {
+        query: query.clone(),
+        result: result.clone(),
+        cached_at: time(),
+    };
+    GEOCODE_CACHE.with(|c| c.borrow_mut().insert(cache_key(&query), entry));
+    Ok(result)
+}
+
+fn parse_reverse(coordinates: Coordinates, body: &[u8]) -> Result<ReverseGeocodeResult, Error> {
+    let reverse: NominatimReverse =
+        serde_json::from_slice(body).map_err(|e| Error::OutcallFailed {
+            msg: format!("Unexpected reverse geocoder response: {}", e),
+        })?;
+    let address = reverse.display_name.ok_or_else(|| Error::NotFound {
+        msg: format!(
+            "No address found for coordinates ({}, {})",
+            coordinates.lat, coordinates.lng
+        ),
+    })?;
+    let district = reverse
+        .address
+        .and_then(|a| a.city_district.or(a.suburb).or(a.borough));
+    Ok(ReverseGeocodeResult {
+        coordinates,
+        address,
+        district,
+    })
+}
+
+// Resolves coordinates into an address through the cache, falling back to an
+// outcall to Nominatim
+pub(crate) async fn reverse_geocode_address(
+    coordinates: Coordinates,
+) -> Result<ReverseGeocodeResult, Error> {
+    coordinates.validate()?;
+    let rounded = format!("{:.5},{:.5}", coordinates.lat, coordinates.lng);
+    if let Some(result) = REVERSE_GEOCODE_CACHE.with(|c| c.borrow().get(&cache_key(&rounded))) {
+        return Ok(result);
+    }
+
+    let url = format!(
+        "{}?format=jsonv2&zoom=18&addressdetails=1&lat={:.5}&lon={:.5}",
+        NOMINATIM_REVERSE_URL, coordinates.lat, coordinates.lng
+    );
+    let body = http_get(
+        url,
+        GEOCODE_MAX_RESPONSE_BYTES,
+        "transform_reverse_geocode_response",
+    )
+    .await?;
+    let result = parse_reverse(coordinates, &body)?;
+
+    REVERSE_GEOCODE_CACHE.with(|c| c.borrow_mut().insert(cache_key(&rounded), result.clone()));
+    Ok(result)
+}
+
+// Function to look up the address and district at a map position
+#[ic_cdk::update]
+async fn reverse_geocode(lat: f64, lng: f64) -> Result<ReverseGeocodeResult, Error> {
+    ensure_writable()?;
+    consume_quota(QuotaOp::Geocode)?;
+    reverse_geocode_address(Coordinates { lat, lng }).await
+}
+
+// Function to resolve the free-text location of a green space into coordinates
+#[ic_cdk::update]
+async fn geocode_green_space(id: u64) -> Result<GreenSpace, Error> {
+    ensure_writable()?;
+    ensure_space_admin(id)?;
+    let space = _get_green_space(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("A green space with id={} not found", id),
+    })?;
+    consume_quota(QuotaOp::Geocode)?;
+
+    let result = geocode(&space.location)
+        .await?
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("No coordinates found for location '{}'", space.location),
+        })?;
+
+    // The space may have been edited or deleted while the outcall was in flight
+    let mut space = _get_green_space(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("A green space with id={} not found", id),
+    })?;
+    space.coordinates = Some(result.coordinates);
+    Ok(do_insert_green_space(&space))
+}
+
+// Reduces a Nominatim response to the fields we parse, in a fixed order, so
+// every replica produces byte-identical responses
+#[ic_cdk::query]
+fn transform_geocode_response(args: TransformArgs) -> HttpResponse {
+    let places: Vec<NominatimPlace> =
+        serde_json::from_slice(&args.response.body).unwrap_or_default();
+    let first: Vec<_> = places.into_iter().take(1).collect();
+    canonical_response(
+        &args.response,
+        serde_json::to_vec(&first).unwrap_or_default(),
+    )
+}
+
+// Reduces a Nominatim reverse response to the address fields we parse
+#[ic_cdk::query]
+fn transform_reverse_geocode_response(args: TransformArgs) -> HttpResponse {
+    let body = match serde_json::from_slice::<NominatimReverse>(&args.response.body) {
+        Ok(reverse) => serde_json::to_vec(&reverse).unwrap_or_default(),
+        Err(_) => vec![],
+    };
+    canonical_response(&args.response, body)
+}
@@ -0,0 +1,308 @@
+use crate::block_log::{log_config, log_upsert};
+use crate::geo::BoundingBox;
+use crate::maintenance::ensure_writable;
+use crate::osm_import::{fetch_osm_parks, import_osm_upload, ImportStrategy};
+use crate::paging::{keys_before_token, page_by_size, Page};
+use crate::snapshot::restore_uploaded_snapshot;
+use crate::{do_delete_green_space, ensure_controller, get_memory, next_id, Error, Memory};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use std::cell::RefCell;
+
+const GOVERNANCE_CONFIG_MEMORY_ID: u8 = 31;
+const PROPOSALS_MEMORY_ID: u8 = 32;
+
+const MAX_GOVERNORS: usize = 50;
+const PROPOSAL_TTL_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_SUMMARY_LEN: usize = 500;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct GovernanceConfig {
+    // While enabled, the governed operations only run through approved proposals
+    enabled: bool,
+    governors: Vec<Principal>,
+    // Number of approving votes a proposal needs
+    threshold: u32,
+}
+
+impl_storable!(GovernanceConfig, 4096);
+
+// An operation that needs an approved proposal while governance is enabled
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum GovernedAction {
+    DeleteGreenSpace {
+        id: u64,
+    },
+    ImportOsmParks {
+        bbox: BoundingBox,
+        city_id: Option<u64>,
+        strategy: Option<ImportStrategy>,
+    },
+    ImportOsmUpload {
+        upload_id: u64,
+        bbox: Option<BoundingBox>,
+        city_id: Option<u64>,
+        strategy: Option<ImportStrategy>,
+    },
+    RestoreSnapshot {
+        sha256: String,
+    },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) enum ProposalStatus {
+    Open,
+    // Approved, with an import still awaiting its outcall
+    Executing,
+    Executed { summary: String },
+    Failed { msg: String },
+    Rejected,
+    Expired,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Proposal {
+    id: u64,
+    action: GovernedAction,
+    summary: String,
+    proposer: Principal,
+    created_at: u64,
+    approvals: Vec<Principal>,
+    rejections: Vec<Principal>,
+    status: ProposalStatus,
+    decided_at: Option<u64>,
+}
+
+impl_storable!(Proposal, 8192);
+
+thread_local! {
+    static GOVERNANCE_CONFIG: RefCell<Cell<GovernanceConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(GOVERNANCE_CONFIG_MEMORY_ID), GovernanceConfig::default())
+            .expect("Cannot initialize the governance config")
+    );
+
+    static PROPOSALS: RefCell<StableBTreeMap<u64, Proposal, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(PROPOSALS_MEMORY_ID)));
+}
+
+fn config() -> GovernanceConfig {
+    GOVERNANCE_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+fn proposal_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A proposal with id={} not found", id),
+    }
+}
+
+fn _get_proposal(id: &u64) -> Option<Proposal> {
+    PROPOSALS.with(|s| s.borrow().get(id))
+}
+
+fn do_insert_proposal(proposal: &Proposal) {
+    PROPOSALS.with(|s| s.borrow_mut().insert(proposal.id, proposal.clone()));
+    log_upsert("proposal", &proposal.id, proposal);
+}
+
+// Fails while governance is enabled; guards the direct endpoints of the
+// governed operations
+pub(crate) fn ensure_not_governed(operation: &str) -> Result<(), Error> {
+    if config().enabled {
+        return Err(Error::Unauthorized {
+            msg: format!(
+                "{} requires an approved proposal while governance is enabled",
+                operation
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn ensure_governor() -> Result<(), Error> {
+    if config().governors.contains(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: "Only governance principals can perform this operation".to_string(),
+        })
+    }
+}
+
+async fn execute(action: GovernedAction) -> Result<String, Error> {
+    match action {
+        GovernedAction::DeleteGreenSpace { id } => {
+            do_delete_green_space(id).map(|space| format!("Green space id={} deleted", space.id))
+        }
+        GovernedAction::ImportOsmParks {
+            bbox,
+            city_id,
+            strategy,
+        } => fetch_osm_parks(bbox, city_id, strategy.unwrap_or_default())
+            .await
+            .map(|report| report.summary()),
+        GovernedAction::ImportOsmUpload {
+            upload_id,
+            bbox,
+            city_id,
+            strategy,
+        } => import_osm_upload(upload_id, bbox, city_id, strategy.unwrap_or_default())
+            .map(|report| report.summary()),
+        GovernedAction::RestoreSnapshot { sha256 } => restore_uploaded_snapshot(&sha256)
+            .map(|manifest| format!("{} entries restored", manifest.entry_count)),
+    }
+}
+
+// Open proposals past their lifetime can no longer be voted on
+fn expire_if_stale(proposal: &mut Proposal, now: u64) {
+    if proposal.status == ProposalStatus::Open
+        && now.saturating_sub(proposal.created_at) > PROPOSAL_TTL_NANOS
+    {
+        proposal.status = ProposalStatus::Expired;
+        proposal.decided_at = Some(now);
+        do_insert_proposal(proposal);
+    }
+}
+
+// Function to turn governance on or off and set who votes; only controllers
+// can change it, whether or not it is enabled
+#[ic_cdk::update]
+fn configure_governance(
+    enabled: bool,
+    governors: Vec<Principal>,
+    threshold: u32,
+) -> Result<GovernanceConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut governors = governors;
+    governors.sort();
+    governors.dedup();
+    if governors.len() > MAX_GOVERNORS {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} governors can be configured", MAX_GOVERNORS),
+        });
+    }
+    if enabled && (threshold == 0 || threshold as usize > governors.len()) {
+        return Err(Error::InvalidInput {
+            msg: "The threshold must be between 1 and the number of governors".to_string(),
+        });
+    }
+    let config = GovernanceConfig {
+        enabled,
+        governors,
+        threshold,
+    };
+    GOVERNANCE_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the governance config")
+    });
+    log_config("governance_config", &config);
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_governance_config() -> Result<GovernanceConfig, Error> {
+    Ok(config())
+}
+
+// Function for a governor to propose a governed operation
+#[ic_cdk::update]
+fn create_proposal(action: GovernedAction, summary: String) -> Result<Proposal, Error> {
+    ensure_writable()?;
+    ensure_governor()?;
+    if summary.len() > MAX_SUMMARY_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Proposal summaries must be at most {} characters",
+                MAX_SUMMARY_LEN
+            ),
+        });
+    }
+    let proposal = Proposal {
+        id: next_id(PROPOSALS_MEMORY_ID),
+        action,
+        summary,
+        proposer: ic_cdk::caller(),
+        created_at: time(),
+        approvals: vec![],
+        rejections: vec![],
+        status: ProposalStatus::Open,
+        decided_at: None,
+    };
+    do_insert_proposal(&proposal);
+    Ok(proposal)
+}
+
+// Function for a governor to vote; the proposal is executed as soon as it has
+// enough approvals and rejected once it can no longer get them
+#[ic_cdk::update]
+async fn vote_on_proposal(id: u64, approve: bool) -> Result<Proposal, Error> {
+    ensure_writable()?;
+    ensure_governor()?;
+    let config = config();
+    let caller = ic_cdk::caller();
+    let now = time();
+    let mut proposal = _get_proposal(&id).ok_or_else(|| proposal_not_found(id))?;
+    expire_if_stale(&mut proposal, now);
+    if proposal.status != ProposalStatus::Open {
+        return Err(Error::InvalidInput {
+            msg: format!("Proposal id={} is no longer open", id),
+        });
+    }
+    if proposal.approvals.contains(&caller) || proposal.rejections.contains(&caller) {
+        return Err(Error::InvalidInput {
+            msg: format!("You already voted on proposal id={}", id),
+        });
+    }
+    if approve {
+        proposal.approvals.push(caller);
+    } else {
+        proposal.rejections.push(caller);
+    }
+
+    let approvals = proposal.approvals.len() as u32;
+    let possible = (config.governors.len() as u32).saturating_sub(proposal.rejections.len() as u32);
+    if approvals >= config.threshold {
+        // Decided before executing, so votes arriving while an import awaits
+        // cannot execute it twice
+        proposal.status = ProposalStatus::Executing;
+        proposal.decided_at = Some(now);
+        do_insert_proposal(&proposal);
+        proposal.status = match execute(proposal.action.clone()).await {
+            Ok(summary) => ProposalStatus::Executed { summary },
+            Err(error) => ProposalStatus::Failed {
+                msg: error.message().to_string(),
+            },
+        };
+    } else if possible < config.threshold {
+        proposal.status = ProposalStatus::Rejected;
+        proposal.decided_at = Some(now);
+    }
+    do_insert_proposal(&proposal);
+    Ok(proposal)
+}
+
+#[ic_cdk::query]
+fn get_proposal(id: u64) -> Result<Proposal, Error> {
+    _get_proposal(&id).ok_or_else(|| proposal_not_found(id))
+}
+
+// Function to get all proposals, newest first, optionally only the open ones
+#[ic_cdk::query]
+fn get_proposals(open_only: bool, next_token: Option<String>) -> Result<Page<Proposal>, Error> {
+    let now = time();
+    let range = keys_before_token::<u64>(&next_token)?;
+    let mut proposals: Vec<_> = PROPOSALS.with(|s| {
+        s.borrow()
+            .range(range)
+            .filter(|(_, proposal)| {
+                !open_only
+                    || (proposal.status == ProposalStatus::Open
+                        && now.saturating_sub(proposal.created_at) <= PROPOSAL_TTL_NANOS)
+            })
+            .collect()
+    });
+    proposals.reverse();
+    Ok(page_by_size(proposals.into_iter()))
+}
@@ -0,0 +1,199 @@
+use crate::canopy::space_canopy;
+use crate::districts::DISTRICTS;
+use crate::metadata::metadata_value;
+use crate::name_index::normalize_name;
+use crate::water::WATER_FEATURES;
+use crate::{
+    ensure_green_space_exists, get_memory, Error, GreenSpace, Memory, GREEN_SPACE_STORAGE,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::thread::LocalKey;
+
+const HEAT_SCORES_MEMORY_ID: u8 = 95;
+const DISTRICT_HEAT_MEMORY_ID: u8 = 96;
+
+// Read from the metadata of a space when defined with set_metadata_keys
+pub(crate) const SURFACE_METADATA_KEY: &str = "surface";
+// The score each district's spaces should reach on average; the deficit is
+// what they fall short of it
+const TARGET_SCORE: f64 = 60.0;
+// A space this large counts fully towards the size part of the score
+const FULL_SIZE_M2: f64 = 100_000.0;
+const MAX_DISTRICTS_RANKED: u32 = 100;
+
+// Parts of the 0-100 score
+const CANOPY_POINTS: f64 = 50.0;
+const SURFACE_POINTS: f64 = 25.0;
+const WATER_POINTS: f64 = 15.0;
+const SIZE_POINTS: f64 = 10.0;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct HeatScore {
+    space_id: u64,
+    // 0-100: canopy coverage 50, surface 25, water 15 and size 10
+    score: f64,
+    canopy_percent: Option<f64>,
+    has_water: bool,
+    surface: Option<String>,
+    area_m2: Option<f64>,
+    computed_at: u64,
+}
+
+impl_storable!(HeatScore, 512);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DistrictHeatDeficit {
+    district_id: u64,
+    name: String,
+    city_id: Option<u64>,
+    space_count: u64,
+    // Of the district's spaces, weighted by area where known; 0 without spaces
+    mean_score: f64,
+    deficit: f64,
+    computed_at: u64,
+}
+
+impl_storable!(DistrictHeatDeficit, 512);
+
+thread_local! {
+    // Recomputed daily from the spaces, so not snapshotted
+    static HEAT_SCORES: RefCell<StableBTreeMap<u64, HeatScore, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(HEAT_SCORES_MEMORY_ID)));
+
+    static DISTRICT_HEAT: RefCell<StableBTreeMap<u64, DistrictHeatDeficit, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(DISTRICT_HEAT_MEMORY_ID)));
+}
+
+// How much a surface keeps cool, from 0 for sealed ground to 1 for vegetation;
+// unknown surfaces count halfway
+fn surface_factor(surface: Option<&str>) -> f64 {
+    match surface {
+        Some("grass" | "meadow" | "vegetation" | "forest" | "planted" | "water") => 1.0,
+        Some("soil" | "mulch" | "wood chips" | "bark" | "sand") => 0.7,
+        Some("gravel" | "fine gravel" | "compacted" | "unpaved") => 0.5,
+        Some("paving stones" | "sett" | "rubber") => 0.2,
+        Some("paved" | "asphalt" | "concrete") => 0.0,
+        _ => 0.5,
+    }
+}
+
+fn heat_score(space: &GreenSpace, water_ids: &BTreeSet<u64>, now: u64) -> HeatScore {
+    let (_, canopy_m2, area_m2) = space_canopy(space.id);
+    let canopy_percent = area_m2.map(|area| canopy_m2 / area * 100.0);
+    let has_water = space.has_water == Some(true) || water_ids.contains(&space.id);
+    let surface = metadata_value(space, SURFACE_METADATA_KEY).map(normalize_name);
+    // Without a boundary the canopy is unknown and counts as none
+    let score = CANOPY_POINTS * canopy_percent.unwrap_or(0.0) / 100.0
+        + SURFACE_POINTS * surface_factor(surface.as_deref())
+        + if has_water { WATER_POINTS } else { 0.0 }
+        + SIZE_POINTS * area_m2.map_or(0.0, |area| (area / FULL_SIZE_M2).sqrt().min(1.0));
+    HeatScore {
+        space_id: space.id,
+        score,
+        canopy_percent,
+        has_water,
+        surface,
+        area_m2,
+        computed_at: now,
+    }
+}
+
+fn clear<V: BoundedStorable>(store: &'static LocalKey<RefCell<StableBTreeMap<u64, V, Memory>>>) {
+    store.with(|s| {
+        let keys: Vec<u64> = s.borrow().iter().map(|(key, _)| key).collect();
+        let mut s = s.borrow_mut();
+        for key in keys {
+            s.remove(&key);
+        }
+    });
+}
+
+// Scores every space and every district's deficit; returns the number of
+// spaces scored
+pub(crate) fn compute_heat_scores() -> u64 {
+    let now = time();
+    let water_ids: BTreeSet<u64> = WATER_FEATURES.with(|w| {
+        w.borrow()
+            .iter()
+            .map(|(_, feature)| feature.space_id)
+            .collect()
+    });
+    let spaces: Vec<GreenSpace> =
+        GREEN_SPACE_STORAGE.with(|s| s.borrow().iter().map(|(_, space)| space).collect());
+    // Per district: spaces, weighted score sum and weight
+    let mut districts: BTreeMap<u64, (u64, f64, f64)> = BTreeMap::new();
+    clear(&HEAT_SCORES);
+    for space in &spaces {
+        let score = heat_score(space, &water_ids, now);
+        if let Some(district_id) = space.district_id {
+            let weight = score.area_m2.unwrap_or(1.0);
+            let entry = districts.entry(district_id).or_default();
+            entry.0 += 1;
+            entry.1 += score.score * weight;
+            entry.2 += weight;
+        }
+        HEAT_SCORES.with(|h| h.borrow_mut().insert(space.id, score));
+    }
+    clear(&DISTRICT_HEAT);
+    let all_districts: Vec<_> =
+        DISTRICTS.with(|d| d.borrow().iter().map(|(_, district)| district).collect());
+    for district in all_districts {
+        let (space_count, weighted, weight) =
+            districts.get(&district.id).copied().unwrap_or_default();
+        let mean_score = if weight > 0.0 { weighted / weight } else { 0.0 };
+        let deficit = DistrictHeatDeficit {
+            district_id: district.id,
+            name: district.name,
+            city_id: district.city_id,
+            space_count,
+            mean_score,
+            deficit: (TARGET_SCORE - mean_score).max(0.0),
+            computed_at: now,
+        };
+        DISTRICT_HEAT.with(|h| h.borrow_mut().insert(district.id, deficit));
+    }
+    spaces.len() as u64
+}
+
+// Function to get how much a space helps against urban heat, as of the last
+// daily computation
+#[ic_cdk::query]
+fn get_heat_mitigation_score(space_id: u64) -> Result<HeatScore, Error> {
+    ensure_green_space_exists(space_id)?;
+    HEAT_SCORES
+        .with(|h| h.borrow().get(&space_id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("No heat score has been computed for space {} yet", space_id),
+        })
+}
+
+// Function for planners to rank the districts of a city, or all, by how far
+// their spaces fall short of the target heat mitigation score, largest first
+#[ic_cdk::query]
+fn get_district_heat_deficits(
+    city_id: Option<u64>,
+    limit: u32,
+) -> Result<Vec<DistrictHeatDeficit>, Error> {
+    if limit == 0 || limit > MAX_DISTRICTS_RANKED {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_DISTRICTS_RANKED),
+        });
+    }
+    let mut ranked: Vec<DistrictHeatDeficit> = DISTRICT_HEAT.with(|h| {
+        h.borrow()
+            .iter()
+            .map(|(_, deficit)| deficit)
+            .filter(|deficit| city_id.is_none() || deficit.city_id == city_id)
+            .collect()
+    });
+    ranked.sort_by(|a, b| {
+        b.deficit
+            .total_cmp(&a.deficit)
+            .then(a.district_id.cmp(&b.district_id))
+    });
+    ranked.truncate(limit as usize);
+    Ok(ranked)
+}
@@ -0,0 +1,96 @@
+use crate::calendar::format_utc;
+use crate::events::Event;
+use crate::kml::escape_xml;
+use crate::slugs::current_slug;
+use crate::GreenSpace;
+
+const STYLE: &str = concat!(
+    "body{font-family:system-ui,sans-serif;max-width:42rem;margin:2rem auto;",
+    "padding:0 1rem;line-height:1.5;color:#1b2b1b}",
+    "a{color:#2e7d32}dt{font-weight:600}li{margin:.5rem 0}",
+);
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        concat!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n",
+            "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+            "<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        ),
+        escape_xml(title),
+        STYLE,
+        body
+    )
+}
+
+// A page of the green spaces, each linking to its own page
+pub(crate) fn index_page(spaces: &[GreenSpace], next_url: Option<&str>) -> String {
+    let mut body = String::from("<h1>Green spaces</h1>\n<ul>\n");
+    for space in spaces {
+        body.push_str(&format!(
+            "<li><a href=\"/spaces/{}\">{}</a>",
+            current_slug(space.id).unwrap_or_else(|| space.id.to_string()),
+            escape_xml(&space.name)
+        ));
+        if !space.location.is_empty() {
+            body.push_str(&format!(" &middot; {}", escape_xml(&space.location)));
+        }
+        body.push_str("</li>\n");
+    }
+    body.push_str("</ul>\n");
+    if let Some(next_url) = next_url {
+        body.push_str(&format!(
+            "<p><a href=\"{}\">More green spaces</a></p>\n",
+            escape_xml(next_url)
+        ));
+    }
+    page("Green spaces", &body)
+}
+
+// One space with its attributes and upcoming events; opening hours and the
+// like are metadata attributes, listed with the rest
+pub(crate) fn space_page(space: &GreenSpace, events: &[Event]) -> String {
+    let mut body = format!(
+        "<p><a href=\"/\">All green spaces</a></p>\n<h1>{}</h1>\n",
+        escape_xml(&space.name)
+    );
+    if !space.location.is_empty() {
+        body.push_str(&format!("<p>{}</p>\n", escape_xml(&space.location)));
+    }
+    for paragraph in space
+        .description
+        .split("\n\n")
+        .filter(|p| !p.trim().is_empty())
+    {
+        body.push_str(&format!("<p>{}</p>\n", escape_xml(paragraph.trim())));
+    }
+    let metadata = space.metadata.as_deref().unwrap_or_default();
+    if !metadata.is_empty() {
+        body.push_str("<h2>Details</h2>\n<dl>\n");
+        for (key, value) in metadata {
+            body.push_str(&format!(
+                "<dt>{}</dt><dd>{}</dd>\n",
+                escape_xml(&key.replace('_', " ")),
+                escape_xml(value)
+            ));
+        }
+        body.push_str("</dl>\n");
+    }
+    if !events.is_empty() {
+        body.push_str("<h2>Upcoming events</h2>\n<ul>\n");
+        for event in events {
+            body.push_str(&format!(
+                "<li><strong>{}</strong><br>{} &ndash; {}",
+                escape_xml(&event.title),
+                format_utc(event.starts_at),
+                format_utc(event.ends_at)
+            ));
+            if !event.description.is_empty() {
+                body.push_str(&format!("<br>{}", escape_xml(&event.description)));
+            }
+            body.push_str("</li>\n");
+        }
+        body.push_str("</ul>\n");
+    }
+    page(&space.name, &body)
+}
@@ -0,0 +1,572 @@
+use crate::api_keys::{check_api_key, use_api_key, ApiKeyRejection, ApiScope};
+use crate::events::upcoming_events;
+use crate::feed::atom_feed;
+use crate::formats::{csv_table, geojson_feature, geojson_feature_collection};
+use crate::geo::BoundingBox;
+use crate::html::{index_page, space_page};
+use crate::ical::ical_calendar;
+use crate::maintenance::in_maintenance;
+use crate::paging::{encode_token, keys_after_token};
+use crate::query_api::{filter_matches, validate_filter, QueryFilter};
+use crate::slugs::resolve_slug;
+use crate::snapshot::sha256_hex;
+use crate::tickets::{open_ticket, validate_ticket_text, TicketSource};
+use crate::{_get_green_space, GreenSpace, GreenSpaceCategory, GREEN_SPACE_STORAGE};
+use ic_cdk::api::time;
+
+// A request from the HTTP gateway
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct HttpGatewayResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    // Asks the gateway to repeat the request as an update call
+    upgrade: Option<bool>,
+}
+
+// Representations a route can answer with, picked from the Accept header
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    GeoJson,
+    Csv,
+    Html,
+}
+
+impl Format {
+    fn media_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::GeoJson => "application/geo+json",
+            Format::Csv => "text/csv",
+            Format::Html => "text/html",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::GeoJson => "application/geo+json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+// Browsers ask for HTML first and get the pages of the mini-site; other
+// clients get data, JSON unless they ask otherwise
+const LISTING_FORMATS: [Format; 4] = [Format::Json, Format::GeoJson, Format::Csv, Format::Html];
+const RECORD_FORMATS: [Format; 3] = [Format::Json, Format::GeoJson, Format::Html];
+const SITE_FORMATS: [Format; 1] = [Format::Html];
+
+const EVENTS_PER_PAGE: usize = 20;
+// Listings are cut into pages well below the 2MiB reply limit; the Link
+// header points at the next one
+const MAX_LISTING_BYTES: usize = 1_500_000;
+
+// The body of POST /spaces/{id}/issues
+#[derive(Deserialize)]
+struct IssueReport {
+    title: String,
+    description: Option<String>,
+}
+
+fn plain_response(status_code: u16, message: &str) -> HttpGatewayResponse {
+    HttpGatewayResponse {
+        status_code,
+        headers: vec![
+            (
+                "Content-Type".to_string(),
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            ("Cache-Control".to_string(), "no-store".to_string()),
+        ],
+        body: message.as_bytes().to_vec(),
+        upgrade: None,
+    }
+}
+
+fn header<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    request
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+// Whether an If-None-Match header lists the given entity tag
+fn matches_etag(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').any(|candidate| {
+        let candidate = candidate.trim();
+        candidate == "*" || candidate.trim_start_matches("W/") == etag
+    })
+}
+
+// The offered format the Accept header prefers, the first one without a
+// header; None when it accepts none of them
+fn negotiate(request: &HttpRequest, offered: &[Format]) -> Option<Format> {
+    let Some(accept) = header(request, "Accept").filter(|value| !value.trim().is_empty()) else {
+        return offered.first().copied();
+    };
+    let mut best: Option<(Format, f32)> = None;
+    for range in accept.split(',') {
+        let mut parts = range.split(';').map(str::trim);
+        let media_range = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = parts
+            .filter_map(|param| param.strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality <= 0.0 {
+            continue;
+        }
+        let matching = offered.iter().find(|format| {
+            let media_type = format.media_type();
+            media_range == "*/*"
+                || media_range == media_type
+                || media_range
+                    .strip_suffix("/*")
+                    .is_some_and(|kind| media_type.split('/').next() == Some(kind))
+        });
+        if let Some(&format) = matching {
+            if best.is_none_or(|(_, best_quality)| quality > best_quality) {
+                best = Some((format, quality));
+            }
+        }
+    }
+    best.map(|(format, _)| format)
+}
+
+fn not_acceptable(offered: &[Format]) -> HttpGatewayResponse {
+    let types: Vec<_> = offered.iter().map(|format| format.media_type()).collect();
+    plain_response(
+        406,
+        &format!("This route can answer with {}", types.join(", ")),
+    )
+}
+
+// Answers with the body, or with 304 when the client already has it; the
+// variants of a route differ by Accept, so caches key on it
+fn encoded_response(
+    request: &HttpRequest,
+    content_type: &str,
+    body: Vec<u8>,
+) -> HttpGatewayResponse {
+    let etag = format!("\"{}\"", sha256_hex(&body));
+    // Keyed requests are per app; anonymous reads may be shared by caches
+    let cache_control = if header(request, "X-API-Key").is_some() {
+        "private, no-cache"
+    } else {
+        "public, no-cache"
+    };
+    let mut headers = vec![
+        ("ETag".to_string(), etag.clone()),
+        ("Cache-Control".to_string(), cache_control.to_string()),
+        ("Vary".to_string(), "Accept".to_string()),
+    ];
+    if header(request, "If-None-Match").is_some_and(|value| matches_etag(value, &etag)) {
+        return HttpGatewayResponse {
+            status_code: 304,
+            headers,
+            body: vec![],
+            upgrade: None,
+        };
+    }
+    headers.push(("Content-Type".to_string(), content_type.to_string()));
+    HttpGatewayResponse {
+        status_code: 200,
+        headers,
+        body,
+        upgrade: None,
+    }
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// Undoes the percent-encoding of a query value, with `+` for a space
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn text_param(query: &str, name: &str) -> Result<Option<String>, HttpGatewayResponse> {
+    query_param(query, name)
+        .map(|value| {
+            percent_decode(value).ok_or_else(|| {
+                plain_response(
+                    400,
+                    &format!("{} is not a valid percent-encoded text", name),
+                )
+            })
+        })
+        .transpose()
+}
+
+fn parsed_param<T: std::str::FromStr>(
+    query: &str,
+    name: &str,
+    expected: &str,
+) -> Result<Option<T>, HttpGatewayResponse> {
+    query_param(query, name)
+        .map(|value| {
+            value
+                .parse::<T>()
+                .map_err(|_| plain_response(400, &format!("{} must be {}", name, expected)))
+        })
+        .transpose()
+}
+
+// The filter of the query API as query parameters: city_id, district_id,
+// name, category (as in the JSON records), has_coordinates (true or false),
+// bbox (south,west,north,east) and metadata (key:value)
+fn listing_filter(query: &str) -> Result<QueryFilter, HttpGatewayResponse> {
+    let category = match text_param(query, "category")? {
+        Some(category) => Some(
+            serde_json::from_value::<GreenSpaceCategory>(serde_json::Value::String(category))
+                .map_err(|_| plain_response(400, "category is not a known category"))?,
+        ),
+        None => None,
+    };
+    let bbox = match query_param(query, "bbox") {
+        Some(value) => {
+            let edges = value
+                .split(',')
+                .map(str::parse::<f64>)
+                .collect::<Result<Vec<f64>, _>>();
+            match edges.as_deref() {
+                Ok(&[south, west, north, east]) => Some(BoundingBox {
+                    south,
+                    west,
+                    north,
+                    east,
+                }),
+                _ => return Err(plain_response(400, "bbox must be south,west,north,east")),
+            }
+        }
+        None => None,
+    };
+    let metadata = match text_param(query, "metadata")? {
+        Some(pair) => match pair.split_once(':') {
+            Some((key, value)) => Some((key.trim().to_string(), value.trim().to_string())),
+            None => return Err(plain_response(400, "metadata must be key:value")),
+        },
+        None => None,
+    };
+    let filter = QueryFilter {
+        city_id: parsed_param(query, "city_id", "a number")?,
+        district_id: parsed_param(query, "district_id", "a number")?,
+        name_contains: text_param(query, "name")?,
+        bbox,
+        has_coordinates: parsed_param(query, "has_coordinates", "true or false")?,
+        category,
+        metadata,
+    };
+    validate_filter(&filter).map_err(|error| plain_response(400, error.message()))?;
+    Ok(filter)
+}
+
+fn list_spaces(request: &HttpRequest, query: &str, offered: &[Format]) -> HttpGatewayResponse {
+    let filter = match listing_filter(query) {
+        Ok(filter) => filter,
+        Err(response) => return response,
+    };
+    let Some(format) = negotiate(request, offered) else {
+        return not_acceptable(offered);
+    };
+    let next_token = match text_param(query, "next_token") {
+        Ok(next_token) => next_token,
+        Err(response) => return response,
+    };
+    let range = match keys_after_token::<u64>(&next_token) {
+        Ok(range) => range,
+        Err(error) => return plain_response(400, error.message()),
+    };
+    // Pages are cut by the size of the spaces in the format asked for, as
+    // GeoJSON boundaries can be far larger than the records
+    let mut spaces: Vec<GreenSpace> = vec![];
+    let mut size = 0;
+    let mut next_url = None;
+    GREEN_SPACE_STORAGE.with(|s| {
+        for (_, space) in s.borrow().range(range) {
+            if !filter_matches(&filter, &space) {
+                continue;
+            }
+            let space_size = match format {
+                Format::GeoJson => geojson_feature(&space).len(),
+                _ => serde_json::to_vec(&space).map_or(0, |bytes| bytes.len()),
+            };
+            if !spaces.is_empty() && size + space_size > MAX_LISTING_BYTES {
+                let last_id = spaces.last().unwrap().id;
+                next_url = Some(next_page_url(request, query, &encode_token(&last_id)));
+                break;
+            }
+            size += space_size;
+            spaces.push(space);
+        }
+    });
+    let body = match format {
+        Format::Json => serde_json::to_vec(&spaces).expect("Cannot encode green spaces"),
+        Format::GeoJson => geojson_feature_collection(&spaces),
+        Format::Csv => csv_table(&spaces),
+        Format::Html => index_page(&spaces, next_url.as_deref()).into_bytes(),
+    };
+    let mut response = encoded_response(request, format.content_type(), body);
+    if let Some(next_url) = next_url {
+        response
+            .headers
+            .push(("Link".to_string(), format!("<{}>; rel=\"next\"", next_url)));
+    }
+    response
+}
+
+// The same listing resumed after a page, with its parameters kept
+fn next_page_url(request: &HttpRequest, query: &str, next_token: &str) -> String {
+    let path = request.url.split('?').next().unwrap_or_default();
+    let mut params: Vec<&str> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("next_token="))
+        .collect();
+    let token = format!("next_token={}", next_token);
+    params.push(&token);
+    format!("{}?{}", path, params.join("&"))
+}
+
+// Sends clients on to the current link of a space renamed since
+fn redirect(location: String) -> HttpGatewayResponse {
+    let mut response = plain_response(301, "Moved permanently");
+    response.headers.push(("Location".to_string(), location));
+    response
+}
+
+// Spaces are addressed by id or by slug
+fn get_space(request: &HttpRequest, id_or_slug: &str) -> HttpGatewayResponse {
+    let found = match id_or_slug.parse::<u64>() {
+        Ok(id) => _get_green_space(&id),
+        Err(_) => match resolve_slug(&id_or_slug.to_lowercase()) {
+            Some((_, slug)) if slug != id_or_slug => {
+                return redirect(format!("/spaces/{}", slug));
+            }
+            Some((space, _)) => Some(space),
+            None => None,
+        },
+    };
+    let Some(space) = found else {
+        return plain_response(404, "Green space not found");
+    };
+    let Some(format) = negotiate(request, &RECORD_FORMATS) else {
+        return not_acceptable(&RECORD_FORMATS);
+    };
+    let body = match format {
+        Format::GeoJson => geojson_feature(&space),
+        Format::Html => {
+            let events = upcoming_events(Some(space.id), EVENTS_PER_PAGE);
+            space_page(&space, &events).into_bytes()
+        }
+        _ => serde_json::to_vec(&space).expect("Cannot encode a green space"),
+    };
+    encoded_response(request, format.content_type(), body)
+}
+
+fn report_issue(request: &HttpRequest, id: &str, key_id: u64) -> HttpGatewayResponse {
+    let Ok(space_id) = id.parse::<u64>() else {
+        return plain_response(400, "Green space ids are numbers");
+    };
+    if _get_green_space(&space_id).is_none() {
+        return plain_response(404, "Green space not found");
+    }
+    let Ok(report) = serde_json::from_slice::<IssueReport>(&request.body) else {
+        return plain_response(400, "The body must be a JSON object with a title");
+    };
+    let description = report.description.unwrap_or_default();
+    if let Err(error) = validate_ticket_text(&report.title, &description) {
+        return plain_response(400, error.message());
+    }
+    let ticket = open_ticket(
+        space_id,
+        report.title,
+        description,
+        TicketSource::ApiReport { key_id },
+    );
+    HttpGatewayResponse {
+        status_code: 201,
+        headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+        body: serde_json::to_vec(&ticket).expect("Cannot encode a maintenance ticket"),
+        upgrade: None,
+    }
+}
+
+// Readers differ in what they accept, so the feed is Atom whatever they ask for
+fn get_feed(request: &HttpRequest) -> HttpGatewayResponse {
+    let base_url = header(request, "Host")
+        .map(|host| format!("https://{}", host))
+        .unwrap_or_default();
+    encoded_response(
+        request,
+        "application/atom+xml; charset=utf-8",
+        atom_feed(&base_url, time()).into_bytes(),
+    )
+}
+
+fn get_calendar(request: &HttpRequest, query: &str) -> HttpGatewayResponse {
+    let space_id = match query_param(query, "space_id").map(str::parse::<u64>) {
+        Some(Ok(space_id)) => Some(space_id),
+        Some(Err(_)) => return plain_response(400, "space_id must be a number"),
+        None => None,
+    };
+    if space_id.is_some_and(|id| _get_green_space(&id).is_none()) {
+        return plain_response(404, "Green space not found");
+    }
+    encoded_response(
+        request,
+        "text/calendar; charset=utf-8",
+        ical_calendar(space_id).into_bytes(),
+    )
+}
+
+fn split_url(request: &HttpRequest) -> (Vec<&str>, &str) {
+    let (path, query) = request
+        .url
+        .split_once('?')
+        .unwrap_or((request.url.as_str(), ""));
+    (path.split('/').filter(|s| !s.is_empty()).collect(), query)
+}
+
+fn read(request: &HttpRequest) -> HttpGatewayResponse {
+    let (segments, query) = split_url(request);
+    match segments.as_slice() {
+        [] => list_spaces(request, query, &SITE_FORMATS),
+        ["spaces"] => list_spaces(request, query, &LISTING_FORMATS),
+        ["spaces", id] => get_space(request, id),
+        ["feed.xml"] => get_feed(request),
+        ["calendar.ics"] => get_calendar(request, query),
+        _ => plain_response(404, "Not found"),
+    }
+}
+
+// Checks the X-API-Key header against a scope and counts the request, unless
+// the canister is in maintenance and its state must not change
+fn authorize(request: &HttpRequest, scope: ApiScope) -> Result<u64, HttpGatewayResponse> {
+    let Some(secret) = header(request, "X-API-Key") else {
+        return Err(plain_response(401, "An X-API-Key header is required"));
+    };
+    let checked = if in_maintenance() {
+        check_api_key(secret, scope)
+    } else {
+        use_api_key(secret, scope)
+    };
+    checked.map_err(|rejection| match rejection {
+        ApiKeyRejection::Unknown => plain_response(401, "Unknown API key"),
+        ApiKeyRejection::Revoked => plain_response(401, "This API key was revoked"),
+        ApiKeyRejection::MissingScope => {
+            plain_response(403, "This API key does not allow the request")
+        }
+        ApiKeyRejection::Throttled => {
+            let mut response = plain_response(429, "Too many requests for this API key");
+            response
+                .headers
+                .push(("Retry-After".to_string(), "60".to_string()));
+            response
+        }
+    })
+}
+
+// Lets browser apps on any origin call the API, with their keys
+fn with_cors(mut response: HttpGatewayResponse) -> HttpGatewayResponse {
+    response.headers.extend(
+        [
+            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
+            (
+                "Access-Control-Allow-Headers",
+                "Accept, Content-Type, If-None-Match, X-API-Key",
+            ),
+            ("Access-Control-Expose-Headers", "ETag, Link, Retry-After"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string())),
+    );
+    response
+}
+
+fn preflight_response() -> HttpGatewayResponse {
+    HttpGatewayResponse {
+        status_code: 204,
+        headers: vec![("Access-Control-Max-Age".to_string(), "86400".to_string())],
+        body: vec![],
+        upgrade: None,
+    }
+}
+
+// JSON, GeoJSON and CSV API for map clients and third-party apps, and HTML
+// pages for browsers, served uncertified through the raw domain; every read
+// carries an ETag of its body. Anonymous reads are answered here, while requests with an API key
+// and writes are upgraded so their keys can be counted
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpGatewayResponse {
+    with_cors(match request.method.as_str() {
+        "OPTIONS" => preflight_response(),
+        "GET" if header(&request, "X-API-Key").is_none() => read(&request),
+        "GET" | "POST" => HttpGatewayResponse {
+            status_code: 200,
+            headers: vec![],
+            body: vec![],
+            upgrade: Some(true),
+        },
+        _ => plain_response(405, "Only GET and POST requests are supported"),
+    })
+}
+
+#[ic_cdk::update]
+fn http_request_update(request: HttpRequest) -> HttpGatewayResponse {
+    with_cors(match request.method.as_str() {
+        "GET" if header(&request, "X-API-Key").is_none() => read(&request),
+        "GET" => match authorize(&request, ApiScope::Read) {
+            Ok(_) => read(&request),
+            Err(response) => response,
+        },
+        // Reads keep working in maintenance; writes wait for it to end
+        "POST" if in_maintenance() => plain_response(503, "The canister is in maintenance"),
+        "POST" => {
+            let (segments, _) = split_url(&request);
+            match segments.as_slice() {
+                ["spaces", id, "issues"] => match authorize(&request, ApiScope::WriteIssues) {
+                    Ok(key_id) => report_issue(&request, id, key_id),
+                    Err(response) => response,
+                },
+                _ => plain_response(404, "Not found"),
+            }
+        }
+        _ => plain_response(405, "Only GET and POST requests are supported"),
+    })
+}
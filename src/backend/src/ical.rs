@@ -0,0 +1,102 @@
+use crate::calendar::format_ical_utc;
+use crate::events::{approved_events, Event};
+use crate::{_get_green_space, ensure_green_space_exists, Error, NANOS_PER_DAY};
+use ic_cdk::api::time;
+
+// Calendars keep events that ended recently, so subscribers see them drop
+// off gradually rather than at once
+const PAST_EVENT_WINDOW: u64 = 90 * NANOS_PER_DAY;
+// RFC 5545 lines are folded once they exceed 75 octets
+const MAX_LINE_OCTETS: usize = 75;
+
+// Escapes a TEXT value as RFC 5545 section 3.3.11 requires
+fn escape_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Appends a content line, folded with CRLF and a space at octet boundaries
+// that do not split a character
+fn push_line(ics: &mut String, line: &str) {
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            ics.push_str("\r\n ");
+            octets = 1;
+        }
+        ics.push(c);
+        octets += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+fn push_event(ics: &mut String, event: &Event, stamp: &str) {
+    let location = _get_green_space(&event.space_id).map(|space| {
+        if space.location.is_empty() {
+            space.name
+        } else {
+            format!("{}, {}", space.name, space.location)
+        }
+    });
+    push_line(ics, "BEGIN:VEVENT");
+    push_line(ics, &format!("UID:event-{}@green-spaces", event.id));
+    push_line(ics, &format!("DTSTAMP:{}", stamp));
+    push_line(
+        ics,
+        &format!("CREATED:{}", format_ical_utc(event.created_at)),
+    );
+    push_line(
+        ics,
+        &format!("DTSTART:{}", format_ical_utc(event.starts_at)),
+    );
+    push_line(ics, &format!("DTEND:{}", format_ical_utc(event.ends_at)));
+    push_line(ics, &format!("SUMMARY:{}", escape_text(&event.title)));
+    if !event.description.is_empty() {
+        push_line(
+            ics,
+            &format!("DESCRIPTION:{}", escape_text(&event.description)),
+        );
+    }
+    if let Some(location) = location {
+        push_line(ics, &format!("LOCATION:{}", escape_text(&location)));
+    }
+    push_line(ics, "END:VEVENT");
+}
+
+// An RFC 5545 calendar of the approved events of a space, or of all spaces
+pub(crate) fn ical_calendar(space_id: Option<u64>) -> String {
+    let now = time();
+    let stamp = format_ical_utc(now);
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//Green spaces//Events//EN");
+    push_line(&mut ics, "CALSCALE:GREGORIAN");
+    push_line(&mut ics, "METHOD:PUBLISH");
+    push_line(&mut ics, "X-WR-CALNAME:Green space events");
+    for event in approved_events(space_id, now.saturating_sub(PAST_EVENT_WINDOW)) {
+        push_event(&mut ics, &event, &stamp);
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+// Function to export the approved events of a space, or of all spaces, as
+// an iCalendar document
+#[ic_cdk::query]
+fn export_ical(space_id: Option<u64>) -> Result<String, Error> {
+    if let Some(space_id) = space_id {
+        ensure_green_space_exists(space_id)?;
+    }
+    Ok(ical_calendar(space_id))
+}
@@ -0,0 +1,196 @@
+use crate::block_log::log_config;
+use crate::maintenance::ensure_writable;
+use crate::{ensure_controller, get_memory, principal_key, Error, Memory, PrincipalKey};
+use candid::{CandidType, Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::future::Future;
+
+const IDEMPOTENCY_CACHE_MEMORY_ID: u8 = 67;
+const IDEMPOTENCY_CONFIG_MEMORY_ID: u8 = 68;
+
+const MAX_KEY_LEN: usize = 64;
+const MAX_WINDOW_SECS: u64 = 7 * 24 * 60 * 60;
+const MAX_EXPIRED_PER_RUN: usize = 1000;
+
+type KeyBytes = Blob<64>;
+type CacheKey = (PrincipalKey, KeyBytes);
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct IdempotencyConfig {
+    // How long a result is returned again for a retried call
+    window_secs: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig {
+            window_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl_storable!(IdempotencyConfig, 32);
+
+// The result of a call made with an idempotency key
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CachedResponse {
+    // SHA-256 of the endpoint and its arguments, so a key cannot be reused
+    // for a different request
+    request_hash: Vec<u8>,
+    // The candid-encoded value the call returned
+    response: Vec<u8>,
+    created_at: u64,
+}
+
+impl_storable!(CachedResponse, 70 * 1024);
+
+thread_local! {
+    static IDEMPOTENCY_CACHE: RefCell<StableBTreeMap<CacheKey, CachedResponse, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(IDEMPOTENCY_CACHE_MEMORY_ID)));
+
+    static IDEMPOTENCY_CONFIG: RefCell<Cell<IdempotencyConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(IDEMPOTENCY_CONFIG_MEMORY_ID), IdempotencyConfig::default())
+            .expect("Cannot initialize the idempotency config")
+    );
+
+    // Keys of calls still awaiting; a retry arriving meanwhile is turned away
+    static IN_FLIGHT: RefCell<BTreeSet<CacheKey>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+fn window_nanos() -> u64 {
+    IDEMPOTENCY_CONFIG.with(|c| c.borrow().get().window_secs) * 1_000_000_000
+}
+
+fn cache_key(key: &str) -> Result<CacheKey, Error> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Idempotency keys must be 1-{} bytes", MAX_KEY_LEN),
+        });
+    }
+    Ok((
+        principal_key(&ic_cdk::caller()),
+        KeyBytes::try_from(key.as_bytes()).unwrap(),
+    ))
+}
+
+// Runs a call at most once per idempotency key and caller within the
+// window: a retry gets the stored result instead. Failed calls are not
+// stored, since they change nothing and may succeed when retried
+pub(crate) async fn idempotent<T, F>(
+    endpoint: &str,
+    key: Option<String>,
+    arguments: Vec<u8>,
+    call: F,
+) -> Result<T, Error>
+where
+    T: CandidType + DeserializeOwned,
+    F: Future<Output = Result<T, Error>>,
+{
+    let Some(key) = key else {
+        return call.await;
+    };
+    let cache_key = cache_key(&key)?;
+    let mut hasher = Sha256::new();
+    hasher.update(endpoint.as_bytes());
+    hasher.update(&arguments);
+    let request_hash = hasher.finalize().to_vec();
+
+    let cached = IDEMPOTENCY_CACHE
+        .with(|c| c.borrow().get(&cache_key))
+        .filter(|cached| time().saturating_sub(cached.created_at) < window_nanos());
+    if let Some(cached) = cached {
+        if cached.request_hash != request_hash {
+            return Err(Error::InvalidInput {
+                msg: "This idempotency key was used for a different request".to_string(),
+            });
+        }
+        return Ok(Decode!(&cached.response, T).expect("Cannot decode a cached response"));
+    }
+    if !IN_FLIGHT.with(|f| f.borrow_mut().insert(cache_key)) {
+        return Err(Error::InvalidInput {
+            msg: "A call with this idempotency key is still in progress".to_string(),
+        });
+    }
+    let result = call.await;
+    IN_FLIGHT.with(|f| f.borrow_mut().remove(&cache_key));
+    if let Ok(value) = &result {
+        let cached = CachedResponse {
+            request_hash,
+            response: Encode!(value).expect("Cannot encode a response"),
+            created_at: time(),
+        };
+        IDEMPOTENCY_CACHE.with(|c| c.borrow_mut().insert(cache_key, cached));
+    }
+    result
+}
+
+// Drops the results stored for a user's calls; returns the number dropped
+pub(crate) fn forget_idempotency_keys(user: &PrincipalKey) -> u64 {
+    let keys: Vec<CacheKey> = IDEMPOTENCY_CACHE.with(|c| {
+        c.borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(owner, _)| owner == user)
+            .collect()
+    });
+    IDEMPOTENCY_CACHE.with(|c| {
+        let mut cache = c.borrow_mut();
+        for key in &keys {
+            cache.remove(key);
+        }
+    });
+    keys.len() as u64
+}
+
+// Drops results older than the window; returns the number dropped
+pub(crate) fn expire_idempotency_cache() -> u64 {
+    let cutoff = time().saturating_sub(window_nanos());
+    let expired: Vec<CacheKey> = IDEMPOTENCY_CACHE.with(|c| {
+        c.borrow()
+            .iter()
+            .filter(|(_, cached)| cached.created_at < cutoff)
+            .map(|(key, _)| key)
+            .take(MAX_EXPIRED_PER_RUN)
+            .collect()
+    });
+    IDEMPOTENCY_CACHE.with(|c| {
+        let mut cache = c.borrow_mut();
+        for key in &expired {
+            cache.remove(key);
+        }
+    });
+    expired.len() as u64
+}
+
+// Function to set how long retried calls get their first result back
+#[ic_cdk::update]
+fn set_idempotency_config(config: IdempotencyConfig) -> Result<IdempotencyConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if config.window_secs == 0 || config.window_secs > MAX_WINDOW_SECS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The window must be between 1 and {} seconds",
+                MAX_WINDOW_SECS
+            ),
+        });
+    }
+    IDEMPOTENCY_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config)
+            .expect("Cannot store the idempotency config")
+    });
+    log_config("idempotency_config", &config);
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_idempotency_config() -> Result<IdempotencyConfig, Error> {
+    Ok(IDEMPOTENCY_CONFIG.with(|c| *c.borrow().get()))
+}
@@ -0,0 +1,165 @@
+use crate::cities::in_city;
+use crate::geo::{Coordinates, Polygon};
+use crate::query_api::{filter_matches, validate_filter, QueryFilter};
+use crate::{_get_green_space_boundary, Error, GreenSpace, GREEN_SPACE_STORAGE};
+
+// Boundaries of up to 2,000 vertices make a placemark at most ~80KB, so a
+// chunk stays below the query response limit
+const KML_SPACES_PER_CHUNK: u64 = 25;
+
+const KML_HEADER: &str = concat!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+    "<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n",
+    "<Document>\n",
+    "<name>Green spaces</name>\n",
+);
+const KML_FOOTER: &str = "</Document>\n</kml>\n";
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct KmlChunk {
+    chunk: u64,
+    total_chunks: u64,
+    // Concatenating the data of all chunks in order yields the complete document
+    data: String,
+}
+
+pub(crate) fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// KML orders coordinates as longitude,latitude
+fn kml_ring(ring: &[Coordinates]) -> String {
+    let coordinates: Vec<_> = ring
+        .iter()
+        .map(|c| format!("{},{}", c.lng, c.lat))
+        .collect();
+    format!(
+        "<LinearRing><coordinates>{}</coordinates></LinearRing>",
+        coordinates.join(" ")
+    )
+}
+
+fn kml_polygon(polygon: &Polygon) -> String {
+    let mut kml = format!(
+        "<Polygon><outerBoundaryIs>{}</outerBoundaryIs>",
+        kml_ring(&polygon.exterior)
+    );
+    for hole in &polygon.holes {
+        kml.push_str(&format!(
+            "<innerBoundaryIs>{}</innerBoundaryIs>",
+            kml_ring(hole)
+        ));
+    }
+    kml.push_str("</Polygon>");
+    kml
+}
+
+fn placemark(space: &GreenSpace) -> String {
+    let mut kml = format!(
+        "<Placemark id=\"green-space-{}\">\n<name>{}</name>\n",
+        space.id,
+        escape_xml(&space.name)
+    );
+    if !space.description.is_empty() {
+        kml.push_str(&format!(
+            "<description>{}</description>\n",
+            escape_xml(&space.description)
+        ));
+    }
+    if !space.location.is_empty() {
+        kml.push_str(&format!(
+            "<address>{}</address>\n",
+            escape_xml(&space.location)
+        ));
+    }
+    let point = space.coordinates.map(|c| {
+        format!(
+            "<Point><coordinates>{},{}</coordinates></Point>",
+            c.lng, c.lat
+        )
+    });
+    let polygon = _get_green_space_boundary(&space.id).map(|b| kml_polygon(&b));
+    match (point, polygon) {
+        (Some(point), Some(polygon)) => kml.push_str(&format!(
+            "<MultiGeometry>{}{}</MultiGeometry>\n",
+            point, polygon
+        )),
+        (Some(geometry), None) | (None, Some(geometry)) => {
+            kml.push_str(&geometry);
+            kml.push('\n');
+        }
+        (None, None) => {}
+    }
+    kml.push_str("</Placemark>\n");
+    kml
+}
+
+// Function to export all green spaces, or those of one city or matching a
+// filter of the query API, as a KML document, one chunk per call
+#[ic_cdk::query]
+fn export_kml(
+    chunk: u64,
+    city_id: Option<u64>,
+    filter: Option<QueryFilter>,
+) -> Result<KmlChunk, Error> {
+    if let Some(filter) = &filter {
+        validate_filter(filter)?;
+    }
+    let included = |space: &GreenSpace| {
+        in_city(city_id, space.city_id)
+            && filter
+                .as_ref()
+                .is_none_or(|filter| filter_matches(filter, space))
+    };
+    let space_count = GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, space)| included(space))
+            .count() as u64
+    });
+    let total_chunks = space_count.div_ceil(KML_SPACES_PER_CHUNK).max(1);
+    if chunk >= total_chunks {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Chunk {} requested, but the export has {} chunks",
+                chunk, total_chunks
+            ),
+        });
+    }
+
+    let mut data = String::new();
+    if chunk == 0 {
+        data.push_str(KML_HEADER);
+    }
+    GREEN_SPACE_STORAGE.with(|s| {
+        for (_, space) in s
+            .borrow()
+            .iter()
+            .filter(|(_, space)| included(space))
+            .skip((chunk * KML_SPACES_PER_CHUNK) as usize)
+            .take(KML_SPACES_PER_CHUNK as usize)
+        {
+            data.push_str(&placemark(&space));
+        }
+    });
+    if chunk == total_chunks - 1 {
+        data.push_str(KML_FOOTER);
+    }
+
+    Ok(KmlChunk {
+        chunk,
+        total_chunks,
+        data,
+    })
+}
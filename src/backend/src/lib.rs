@@ -1,13 +1,246 @@
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode};
-use ic_cdk::api::time;
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+// Access checks ask these for the principal of the call; tests set it instead
+#[cfg(not(test))]
+use ic_cdk::{api::is_controller, caller};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{
+    storable::Blob, BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable,
+};
 use std::{borrow::Cow, cell::RefCell};
+#[cfg(test)]
+use test_caller::{caller, is_controller};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
+// Principals are at most 29 bytes, which makes their raw bytes a bounded map key
+type PrincipalKey = Blob<29>;
+type ExternalRefKey = Blob<64>;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_EXTERNAL_REF_LEN: usize = 64;
+
+// Implements Storable and BoundedStorable for a candid-encoded record
+macro_rules! impl_storable {
+    ($type:ty, $max_size:expr) => {
+        impl ic_stable_structures::Storable for $type {
+            fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+                use candid::Encode;
+                std::borrow::Cow::Owned(Encode!(self).unwrap())
+            }
+
+            fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                use candid::Decode;
+                Decode!(bytes.as_ref(), Self).unwrap()
+            }
+        }
+
+        impl ic_stable_structures::BoundedStorable for $type {
+            const MAX_SIZE: u32 = $max_size;
+            const IS_FIXED_SIZE: bool = false;
+        }
+    };
+}
+
+mod accessibility;
+mod api_keys;
+mod api_v1;
+mod apiary;
+mod bans;
+mod batch;
+mod block_log;
+mod calendar;
+mod canopy;
+mod cascade;
+mod certification;
+mod challenge;
+mod changelog;
+mod cities;
+mod compost;
+mod content_filter;
+mod contractors;
+mod cool_spaces;
+mod corridors;
+mod cost_forecast;
+mod cycles;
+mod deletion;
+mod digest;
+mod districts;
+mod erasure;
+mod events;
+mod expenses;
+mod export;
+mod external_ids;
+mod favorites;
+mod featured;
+mod federation;
+mod feed;
+mod formats;
+mod fulltext;
+mod geo;
+mod geocoding;
+mod governance;
+mod heat;
+mod html;
+mod http;
+mod ical;
+mod idempotency;
+mod kml;
+mod links;
+mod maintenance;
+mod metadata;
+mod metrics;
+mod moderation;
+mod name_index;
+mod noise;
+mod notifications;
+mod open_data;
+mod orphans;
+mod osm_import;
+mod outcalls;
+mod overlap;
+mod paging;
+mod playground;
+mod quality;
+mod query_api;
+mod quotas;
+mod recommendations;
+mod related_tags;
+mod renames;
+mod replication;
+mod reports;
+mod reset;
+mod retention;
+mod reviews;
+mod saved_searches;
+mod scheduler;
+mod search;
+mod search_alerts;
+mod search_analytics;
+mod shards;
+mod signing;
+mod similar;
+mod slugs;
+mod snapshot;
+mod soil;
+mod spam;
+mod spatial_index;
+mod split;
+mod stats;
+mod stormwater;
+mod subscribers;
+mod suggest;
+mod sync;
+mod synonyms;
+mod terms;
+#[cfg(test)]
+mod test_caller;
+mod tickets;
+mod trees;
+mod trending;
+mod ulid;
+mod validation;
+mod views;
+mod visits;
+mod water;
+mod webhooks;
+mod wkt;
+
+use accessibility::*;
+use api_keys::*;
+use api_v1::*;
+use apiary::*;
+use bans::*;
+use batch::*;
+use block_log::*;
+use canopy::*;
+use cascade::*;
+use certification::*;
+use challenge::*;
+use changelog::*;
+use cities::*;
+use compost::*;
+use content_filter::*;
+use contractors::*;
+use cool_spaces::*;
+use corridors::*;
+use cost_forecast::*;
+use cycles::*;
+use deletion::*;
+use digest::*;
+use districts::*;
+use erasure::*;
+use events::*;
+use expenses::*;
+use export::*;
+use external_ids::*;
+use featured::*;
+use federation::*;
+use fulltext::*;
+use geo::*;
+use geocoding::*;
+use governance::*;
+use heat::*;
+use http::*;
+use idempotency::*;
+use kml::*;
+use links::*;
+use maintenance::*;
+use metadata::*;
+use metrics::*;
+use moderation::*;
+use name_index::*;
+use noise::*;
+use notifications::*;
+use open_data::*;
+use orphans::*;
+use osm_import::*;
+use overlap::*;
+use paging::*;
+use playground::*;
+use quality::*;
+use query_api::*;
+use quotas::*;
+use recommendations::*;
+use related_tags::*;
+use renames::*;
+use replication::*;
+use reports::*;
+use reset::*;
+use retention::*;
+use reviews::*;
+use saved_searches::*;
+use scheduler::*;
+use search::*;
+use search_analytics::*;
+use shards::*;
+use signing::*;
+use similar::*;
+use slugs::*;
+use snapshot::*;
+use soil::*;
+use spam::*;
+use spatial_index::*;
+use split::*;
+use stats::*;
+use stormwater::*;
+use subscribers::*;
+use suggest::*;
+use sync::*;
+use terms::*;
+use tickets::*;
+use trees::*;
+use trending::*;
+use ulid::*;
+use validation::*;
+use views::*;
+use visits::*;
+use water::*;
+use webhooks::*;
+use wkt::*;
+
 // ... (existing imports and types)
 
 // Import necessary libraries and modules
@@ -18,11 +251,55 @@ struct GreenSpace {
     name: String,
     location: String,
     description: String,
+    coordinates: Option<Coordinates>,
+    // Identifier of the record in the dataset it was imported from, e.g. "osm:way/123"
+    external_ref: Option<String>,
+    district_id: Option<u64>,
+    city_id: Option<u64>,
+    category: Option<GreenSpaceCategory>,
+    status: Option<GreenSpaceStatus>,
+    // Unknown for spaces created before it was recorded
+    created_at: Option<u64>,
+    // Identifies the space across deployments, unlike the sequential id
+    ulid: Option<String>,
+    // Bumped on every write, for updates to detect they started from a stale
+    // copy; None for spaces not written since it was introduced
+    version: Option<u64>,
+    // Attributes under the keys defined with set_metadata_keys
+    metadata: Option<Vec<(String, String)>>,
+    // Share of the area in shade, 0-100
+    shade_coverage_percent: Option<u8>,
+    // Whether the space has a pond, fountain, stream or other water
+    has_water: Option<bool>,
+    // Filled in by get_green_space from the link store; never stored
+    links: Option<Vec<SpaceLink>>,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+enum GreenSpaceCategory {
+    Park,
+    Garden,
+    CommunityGarden,
+    Playground,
+    Forest,
+    Meadow,
+    Cemetery,
+    Other,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+enum GreenSpaceStatus {
+    Open,
+    TemporarilyClosed,
+    UnderRenovation,
+    Planned,
+    // Kept for reference after being split into other spaces
+    Archived,
 }
 
 impl Storable for GreenSpace {
     // Implement Storable trait methods for serialization and deserialization
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
@@ -50,66 +327,492 @@ thread_local! {
         RefCell::new(StableBTreeMap::init(
             GREEN_SPACE_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
     ));
+
+    // Id counters for every other record type, keyed by the memory id of its storage
+    static ID_COUNTERS: RefCell<StableBTreeMap<u8, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(2)));
+
+    // Green space ids keyed by their external reference
+    static EXTERNAL_REF_INDEX: RefCell<StableBTreeMap<ExternalRefKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(19)));
+
+    // Boundaries are kept apart from the spaces, which are capped at 1KB each
+    static GREEN_SPACE_BOUNDARIES: RefCell<StableBTreeMap<u64, Polygon, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(20)));
+}
+
+// Returns the virtual memory with the given id
+fn get_memory(id: u8) -> Memory {
+    GREEN_SPACE_MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(id)))
+}
+
+// Returns the next id for the records stored in the memory with the given id
+fn next_id(memory_id: u8) -> u64 {
+    ID_COUNTERS.with(|counters| {
+        let mut counters = counters.borrow_mut();
+        let id = counters.get(&memory_id).unwrap_or(0);
+        counters.insert(memory_id, id + 1);
+        id
+    })
+}
+
+fn principal_key(principal: &Principal) -> PrincipalKey {
+    PrincipalKey::try_from(principal.as_slice()).expect("Principal longer than 29 bytes")
+}
+
+// Fails with Unauthorized unless the caller controls this canister
+fn ensure_controller() -> Result<(), Error> {
+    if is_controller(&caller()) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: "Only canister controllers can perform this operation".to_string(),
+        })
+    }
+}
+
+// The caller, unless it is anonymous; per-user data needs a signed-in principal
+fn authenticated_caller() -> Result<Principal, Error> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err(Error::Unauthorized {
+            msg: "Sign in to keep data of your own".to_string(),
+        });
+    }
+    Ok(caller)
+}
+
+// Fails with NotFound unless a green space with the given id exists
+fn ensure_green_space_exists(id: u64) -> Result<(), Error> {
+    match _get_green_space(&id) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("A green space with id={} not found", id),
+        }),
+    }
+}
+
+// Fails unless the space exists and the caller administers the city it belongs to
+fn ensure_space_admin(id: u64) -> Result<(), Error> {
+    let space = _get_green_space(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("A green space with id={} not found", id),
+    })?;
+    ensure_city_admin(space.city_id)
+}
+
+fn external_ref_key(external_ref: &str) -> Result<ExternalRefKey, Error> {
+    if external_ref.is_empty() || external_ref.len() > MAX_EXTERNAL_REF_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "External references must be 1-{} bytes",
+                MAX_EXTERNAL_REF_LEN
+            ),
+        });
+    }
+    Ok(ExternalRefKey::try_from(external_ref.as_bytes()).unwrap())
+}
+
+// Returns the id of the green space imported under the given external reference
+fn find_by_external_ref(external_ref: &str) -> Option<u64> {
+    let key = external_ref_key(external_ref).ok()?;
+    EXTERNAL_REF_INDEX.with(|index| index.borrow().get(&key))
+}
+
+fn next_green_space_id() -> u64 {
+    GREEN_SPACE_ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("Cannot increment id counter for green spaces")
+}
+
+// Stores a new green space and indexes its external reference, if any
+fn insert_new_green_space(space: &GreenSpace) -> Result<GreenSpace, Error> {
+    if let Some(external_ref) = &space.external_ref {
+        let key = external_ref_key(external_ref)?;
+        EXTERNAL_REF_INDEX.with(|index| index.borrow_mut().insert(key, space.id));
+    }
+    Ok(do_insert_green_space(space))
 }
 
-// Helper method to perform insert for GreenSpace
-fn do_insert_green_space(space: &GreenSpace) {
-    GREEN_SPACE_STORAGE.with(|service| service.borrow_mut().insert(space.id, space.clone()));
+// Returns the stored boundary of a green space
+fn _get_green_space_boundary(id: &u64) -> Option<Polygon> {
+    GREEN_SPACE_BOUNDARIES.with(|b| b.borrow().get(id))
 }
 
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+// Stores or, given None, removes the boundary of a green space
+fn set_green_space_boundary(id: u64, boundary: Option<Polygon>) {
+    match boundary {
+        Some(boundary) => {
+            log_upsert("boundary", &id, &boundary);
+            GREEN_SPACE_BOUNDARIES.with(|b| b.borrow_mut().insert(id, boundary));
+        }
+        None => {
+            if GREEN_SPACE_BOUNDARIES
+                .with(|b| b.borrow_mut().remove(&id))
+                .is_some()
+            {
+                log_delete("boundary", &id);
+            }
+        }
+    }
+    reindex_green_space(id);
+}
+
+// Parses the optional WKT boundary of a payload
+fn parse_boundary(boundary_wkt: &Option<String>) -> Result<Option<Polygon>, Error> {
+    boundary_wkt.as_deref().map(parse_wkt_polygon).transpose()
+}
+
+// Checks of a payload that need nothing but the payload itself
+fn validate_payload_fields(payload: &GreenSpaceUpdatePayload) -> Result<(), Error> {
+    if let Some(coordinates) = &payload.coordinates {
+        coordinates.validate()?;
+    }
+    validate_against_config(payload)?;
+    // Spaces are written by editors without a moderation step, so only
+    // refused terms apply
+    ensure_no_rejected_terms(&[&payload.name, &payload.location, &payload.description])?;
+    if let Some(metadata) = &payload.metadata {
+        validate_metadata(metadata)?;
+    }
+    if payload
+        .shade_coverage_percent
+        .is_some_and(|percent| percent > 100)
+    {
+        return Err(Error::InvalidInput {
+            msg: "The shade coverage is a percentage, 0-100".to_string(),
+        });
+    }
+    let mut space = GreenSpace::default();
+    apply_payload(&mut space, payload.clone());
+    ensure_fits_in_storage(&space)
+}
+
+// The stored record is capped, whatever the individual lengths add up to;
+// checked with the widest values of the fields filled in when it is stored
+fn ensure_fits_in_storage(space: &GreenSpace) -> Result<(), Error> {
+    let record = GreenSpace {
+        id: u64::MAX,
+        created_at: Some(u64::MAX),
+        ulid: Some("0".repeat(26)),
+        version: Some(u64::MAX),
+        links: None,
+        ..space.clone()
+    };
+    if record.to_bytes().len() > GreenSpace::MAX_SIZE as usize {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "A green space must encode to at most {} bytes",
+                GreenSpace::MAX_SIZE
+            ),
+        });
+    }
+    Ok(())
+}
+
+// The fields of a stored space as a payload, for checks of edits made to it
+// field by field; the boundary is stored and checked apart
+fn payload_of(space: &GreenSpace) -> GreenSpaceUpdatePayload {
+    GreenSpaceUpdatePayload {
+        name: space.name.clone(),
+        location: space.location.clone(),
+        description: space.description.clone(),
+        coordinates: space.coordinates,
+        boundary_wkt: None,
+        district_id: space.district_id,
+        city_id: space.city_id,
+        category: space.category,
+        status: space.status,
+        metadata: space.metadata.clone(),
+        shade_coverage_percent: space.shade_coverage_percent,
+        has_water: space.has_water,
+    }
+}
+
+// Checks of a payload against the records of this canister; returns the parsed boundary
+fn validate_payload_references(
+    payload: &GreenSpaceUpdatePayload,
+) -> Result<Option<Polygon>, Error> {
+    let boundary = parse_boundary(&payload.boundary_wkt)?;
+    if let Some(district_id) = payload.district_id {
+        ensure_district_in_city(district_id, payload.city_id)?;
+    }
+    Ok(boundary)
+}
+
+// Helper method to perform insert for GreenSpace; bumps the version and
+// returns the stored record
+fn do_insert_green_space(space: &GreenSpace) -> GreenSpace {
+    let mut space = GreenSpace {
+        links: None,
+        ..space.clone()
+    };
+    if space.ulid.is_none() {
+        space.ulid = _get_green_space(&space.id)
+            .and_then(|previous| previous.ulid)
+            .or_else(|| Some(new_ulid()));
+    }
+    let stored_version = _get_green_space(&space.id).and_then(|previous| previous.version);
+    space.version = Some(stored_version.unwrap_or(0) + 1);
+    let previous =
+        GREEN_SPACE_STORAGE.with(|service| service.borrow_mut().insert(space.id, space.clone()));
+    certify_space(space.id, Some(&space));
+    index_ulid(previous.as_ref(), Some(&space));
+    adjust_space_counts(previous.as_ref(), Some(&space));
+    record_rename(previous.as_ref(), &space);
+    reindex_tags(previous.as_ref(), Some(&space));
+    reindex_terms(previous.as_ref(), Some(&space));
+    assign_slug(&space);
+    reindex_green_space(space.id);
+    reindex_green_space_name(space.id);
+    touch_green_space(space.id);
+    record_change(ChangeOp::Upsert, space.id, Some(space.clone()));
+    space
+}
+
+// Removes a green space together with its boundary and index entries
+fn remove_green_space(id: u64) -> Option<GreenSpace> {
+    let space = GREEN_SPACE_STORAGE.with(|service| service.borrow_mut().remove(&id))?;
+    certify_space(id, None);
+    adjust_space_counts(Some(&space), None);
+    reindex_tags(Some(&space), None);
+    reindex_terms(Some(&space), None);
+    index_ulid(Some(&space), None);
+    set_green_space_boundary(id, None);
+    if let Some(key) = space
+        .external_ref
+        .as_deref()
+        .and_then(|r| external_ref_key(r).ok())
+    {
+        EXTERNAL_REF_INDEX.with(|index| index.borrow_mut().remove(&key));
+    }
+    remove_external_ids(id);
+    remove_space_links(id);
+    remove_name_history(id);
+    remove_slugs(id);
+    queue_cascade(id);
+    reindex_green_space_name(id);
+    touch_green_space(id);
+    record_change(ChangeOp::Delete, id, None);
+    Some(space)
+}
+
+// Stores a space created elsewhere under its existing id, keeping the id
+// counter ahead of it so later spaces do not collide
+fn restore_green_space(space: &GreenSpace) {
+    if let Some(key) = space
+        .external_ref
+        .as_deref()
+        .and_then(|r| external_ref_key(r).ok())
+    {
+        EXTERNAL_REF_INDEX.with(|index| index.borrow_mut().insert(key, space.id));
+    }
+    GREEN_SPACE_ID_COUNTER.with(|counter| {
+        let next = *counter.borrow().get();
+        if next <= space.id {
+            counter
+                .borrow_mut()
+                .set(space.id + 1)
+                .expect("Cannot increment id counter for green spaces");
+        }
+    });
+    do_insert_green_space(space);
+}
+
+#[ic_cdk::init]
+fn init() {
+    seed_ulids_soon();
+    start_scheduler();
+    certify_data();
+}
+
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    // Pending views and searches live on the heap
+    flush_view_counts();
+    flush_search_stats();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    ensure_spatial_index();
+    ensure_name_index();
+    rebuild_space_counts();
+    rebuild_tag_index();
+    ensure_term_index();
+    ensure_modified_index();
+    ensure_slugs();
+    ensure_ulids();
+    ensure_certified_spaces();
+    seed_ulids_soon();
+    // Timers and certified data do not survive upgrades
+    start_scheduler();
+    certify_data();
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 struct GreenSpaceUpdatePayload {
     name: String,
     location: String,
     description: String,
+    coordinates: Option<Coordinates>,
+    // Boundary as a WKT `POLYGON((lng lat, ...))` string
+    boundary_wkt: Option<String>,
+    district_id: Option<u64>,
+    city_id: Option<u64>,
+    category: Option<GreenSpaceCategory>,
+    status: Option<GreenSpaceStatus>,
+    metadata: Option<Vec<(String, String)>>,
+    shade_coverage_percent: Option<u8>,
+    has_water: Option<bool>,
 }
 
-// Function to add a green space
+// Function to add a green space; a space dropped on the map with coordinates
+// but no location gets its address filled in by reverse geocoding, and spaces
+// of a city that has moved to a shard are created there. A retry with the
+// same idempotency key returns the space the first call created
 #[ic_cdk::update]
-fn add_green_space(space: GreenSpaceUpdatePayload) -> Option<GreenSpace> {
-    let id = GREEN_SPACE_ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("Cannot increment id counter for green spaces");
+async fn add_green_space(
+    space: GreenSpaceUpdatePayload,
+    idempotency_key: Option<String>,
+) -> Result<GreenSpace, Error> {
+    ensure_writable()?;
+    let arguments = Encode!(&space).unwrap();
+    idempotent(
+        "add_green_space",
+        idempotency_key,
+        arguments,
+        create_green_space(space),
+    )
+    .await
+}
+
+async fn create_green_space(mut space: GreenSpaceUpdatePayload) -> Result<GreenSpace, Error> {
+    validate_payload_fields(&space)?;
+    ensure_city_write(space.city_id)?;
+    if let Some(shard) = shard_for_city(space.city_id) {
+        return add_green_space_on_shard(shard, space).await;
+    }
+    let boundary = validate_payload_references(&space)?;
+    if let (true, Some(coordinates)) = (space.location.trim().is_empty(), space.coordinates) {
+        consume_quota(QuotaOp::Geocode)?;
+        space.location = reverse_geocode_address(coordinates).await?.address;
+        // The address is held to the same limits as one given by the caller
+        validate_payload_fields(&space)?;
+    }
 
     let green_space = GreenSpace {
-        id,
+        id: next_green_space_id(),
         name: space.name,
         location: space.location,
         description: space.description,
+        coordinates: space.coordinates,
+        external_ref: None,
+        district_id: space.district_id,
+        city_id: space.city_id,
+        category: space.category,
+        status: space.status,
+        created_at: Some(ic_cdk::api::time()),
+        ulid: Some(new_ulid()),
+        version: None,
+        metadata: space.metadata,
+        shade_coverage_percent: space.shade_coverage_percent,
+        has_water: space.has_water,
+        links: None,
     };
 
-    do_insert_green_space(&green_space);
-    Some(green_space)
+    let green_space = insert_new_green_space(&green_space)?;
+    set_green_space_boundary(green_space.id, boundary);
+    Ok(green_space)
 }
 
 // Function to get a green space by ID
 #[ic_cdk::query]
 fn get_green_space(id: u64) -> Result<GreenSpace, Error> {
     match _get_green_space(&id) {
-        Some(space) => Ok(space),
+        Some(space) => {
+            count_view(id);
+            Ok(GreenSpace {
+                links: Some(space_links(id)),
+                ..space
+            })
+        }
         None => Err(Error::NotFound {
             msg: format!("A green space with id={} not found", id),
         }),
     }
 }
 
+// Function to get a green space by the reference of the dataset it was imported from
+#[ic_cdk::query]
+fn get_green_space_by_external_ref(external_ref: String) -> Result<GreenSpace, Error> {
+    find_by_external_ref(&external_ref)
+        .and_then(|id| _get_green_space(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("A green space with external_ref={} not found", external_ref),
+        })
+}
+
+// Function to get the boundary of a green space
+#[ic_cdk::query]
+fn get_green_space_boundary(id: u64) -> Result<Polygon, Error> {
+    ensure_green_space_exists(id)?;
+    _get_green_space_boundary(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("The green space with id={} has no boundary", id),
+    })
+}
+
 // Internal function to get a green space by ID
 fn _get_green_space(id: &u64) -> Option<GreenSpace> {
     GREEN_SPACE_STORAGE.with(|s| s.borrow().get(id))
 }
 
-// Function to update a green space
+// Function to update a green space; spaces of a city that has moved to a shard
+// are updated there. A retry with the same idempotency key returns the first
+// result without writing again. Given the version the caller last read, the
+// update fails with Conflict when the space has been written since
 #[ic_cdk::update]
-fn update_green_space(id: u64, payload: GreenSpaceUpdatePayload) -> Result<GreenSpace, Error> {
+async fn update_green_space(
+    id: u64,
+    payload: GreenSpaceUpdatePayload,
+    idempotency_key: Option<String>,
+    expected_version: Option<u64>,
+) -> Result<GreenSpace, Error> {
+    ensure_writable()?;
+    let arguments = Encode!(&id, &payload, &expected_version).unwrap();
+    idempotent(
+        "update_green_space",
+        idempotency_key,
+        arguments,
+        write_green_space_update(id, payload, expected_version),
+    )
+    .await
+}
+
+async fn write_green_space_update(
+    id: u64,
+    payload: GreenSpaceUpdatePayload,
+    expected_version: Option<u64>,
+) -> Result<GreenSpace, Error> {
+    if _get_green_space(&id).is_none() {
+        if let Some(shard) = shard_for_city(payload.city_id) {
+            ensure_city_write(payload.city_id)?;
+            return update_green_space_on_shard(shard, id, payload, expected_version).await;
+        }
+    }
+    validate_payload_fields(&payload)?;
+    let boundary = validate_payload_references(&payload)?;
     match GREEN_SPACE_STORAGE.with(|service| service.borrow().get(&id)) {
         Some(mut space) => {
-            space.name = payload.name;
-            space.location = payload.location;
-            space.description = payload.description;
-            do_insert_green_space(&space);
+            // Moving a space to another city takes write access to both
+            ensure_city_write(space.city_id)?;
+            ensure_city_write(payload.city_id)?;
+            ensure_version(&space, expected_version)?;
+            apply_payload(&mut space, payload);
+            let space = do_insert_green_space(&space);
+            set_green_space_boundary(id, boundary);
             Ok(space)
         }
         None => Err(Error::NotFound {
@@ -121,10 +824,78 @@ fn update_green_space(id: u64, payload: GreenSpaceUpdatePayload) -> Result<Green
     }
 }
 
-// Function to delete a green space
+// Fails with Conflict when the space is not at the version the caller expects
+fn ensure_version(space: &GreenSpace, expected_version: Option<u64>) -> Result<(), Error> {
+    let version = space.version.unwrap_or(0);
+    match expected_version {
+        Some(expected) if expected != version => Err(Error::Conflict {
+            msg: format!(
+                "The green space with id={} is at version {}, not {}; reload it and retry",
+                space.id, version, expected
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Overwrites the editable fields of a space with those of a payload
+fn apply_payload(space: &mut GreenSpace, payload: GreenSpaceUpdatePayload) {
+    space.name = payload.name;
+    space.location = payload.location;
+    space.description = payload.description;
+    space.coordinates = payload.coordinates;
+    space.district_id = payload.district_id;
+    space.city_id = payload.city_id;
+    space.category = payload.category;
+    space.status = payload.status;
+    space.metadata = payload.metadata;
+    space.shade_coverage_percent = payload.shade_coverage_percent;
+    space.has_water = payload.has_water;
+}
+
+// Function to run the checks of add_green_space or, given an id, of
+// update_green_space without writing anything
+#[ic_cdk::query]
+fn validate_payload(payload: GreenSpaceUpdatePayload, id: Option<u64>) -> Result<(), Error> {
+    let existing = id.and_then(|id| _get_green_space(&id));
+    if let (Some(id), None) = (id, &existing) {
+        if shard_for_city(payload.city_id).is_none() {
+            return Err(Error::NotFound {
+                msg: format!("A green space with id={} not found", id),
+            });
+        }
+    }
+    validate_payload_fields(&payload)?;
+    if let Some(space) = &existing {
+        ensure_city_write(space.city_id)?;
+    }
+    ensure_city_write(payload.city_id)?;
+    // Spaces of a sharded city are checked against the shard's records when written
+    if existing.is_none() && shard_for_city(payload.city_id).is_some() {
+        return Ok(());
+    }
+    validate_payload_references(&payload)?;
+    Ok(())
+}
+
+// Function to delete a green space; a space with records attached is only
+// deleted when called again with the confirmation token of the first call,
+// and its records are removed in batches afterwards
 #[ic_cdk::update]
-fn delete_green_space(id: u64) -> Result<GreenSpace, Error> {
-    match GREEN_SPACE_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
+fn delete_green_space(id: u64, confirmation_token: Option<String>) -> Result<DeleteOutcome, Error> {
+    ensure_writable()?;
+    if let Some(space) = _get_green_space(&id) {
+        ensure_city_write(space.city_id)?;
+    }
+    ensure_not_governed("Deleting a green space")?;
+    if let Some(confirmation) = confirm_deletion(id, confirmation_token)? {
+        return Ok(confirmation);
+    }
+    do_delete_green_space(id).map(DeleteOutcome::Deleted)
+}
+
+fn do_delete_green_space(id: u64) -> Result<GreenSpace, Error> {
+    match remove_green_space(id) {
         Some(space) => Ok(space),
         None => Err(Error::NotFound {
             msg: format!(
@@ -135,59 +906,62 @@ fn delete_green_space(id: u64) -> Result<GreenSpace, Error> {
     }
 }
 
-// Function to get all green spaces
+// Function to get all green spaces, optionally only those of one city
 #[ic_cdk::query]
-fn get_all_green_spaces() -> Result<Vec<GreenSpace>, Error> {
-    GREEN_SPACE_STORAGE.with(|service| {
-        let storage = service.borrow_mut();
-        let result: Vec<_> = storage.iter().map(|(_, item)| item.clone()).collect();
-        Ok(result)
-    })
+fn get_all_green_spaces(
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<GreenSpace>, Error> {
+    list_green_spaces_matching(next_token, |space| in_city(city_id, space.city_id))
 }
 
 #[ic_cdk::query]
-fn search_green_spaces_by_name(name: String) -> Result<Vec<GreenSpace>, Error> {
-    GREEN_SPACE_STORAGE.with(|service| {
-        let borrow = service.borrow();
-        let result: Vec<_> = borrow
-            .iter()
-            .filter_map(|(_, space)| {
-                if space.name.contains(&name) {
-                    Some(space.clone())
-                } else {
-                    None
-                }
-            })
-            .collect();
-        Ok(result)
+fn search_green_spaces_by_name(
+    name: String,
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<GreenSpace>, Error> {
+    // Spaces are also found by the names they had before a rename
+    list_green_spaces_matching(next_token, |space| {
+        in_city(city_id, space.city_id)
+            && (space.name.contains(&name) || alias_contains(space.id, &name))
     })
 }
 
 #[ic_cdk::query]
-fn search_green_spaces_by_description(keyword: String) -> Result<Vec<GreenSpace>, Error> {
+fn search_green_spaces_by_description(
+    keyword: String,
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<GreenSpace>, Error> {
+    list_green_spaces_matching(next_token, |space| {
+        space.description.contains(&keyword) && in_city(city_id, space.city_id)
+    })
+}
+
+// Pages through the spaces, in id order, that pass the filter
+fn list_green_spaces_matching(
+    next_token: Option<String>,
+    filter: impl Fn(&GreenSpace) -> bool,
+) -> Result<Page<GreenSpace>, Error> {
+    let range = keys_after_token::<u64>(&next_token)?;
     GREEN_SPACE_STORAGE.with(|service| {
         let borrow = service.borrow();
-        let result: Vec<_> = borrow
-            .iter()
-            .filter_map(|(_, space)| {
-                if space.description.contains(&keyword) {
-                    Some(space.clone())
-                } else {
-                    None
-                }
-            })
-            .collect();
-        Ok(result)
+        Ok(page_by_size(
+            borrow.range(range).filter(|(_, space)| filter(space)),
+        ))
     })
 }
 
 #[ic_cdk::update]
 fn update_green_space_location(id: u64, new_location: String) -> Result<GreenSpace, Error> {
+    ensure_writable()?;
     match GREEN_SPACE_STORAGE.with(|service| service.borrow().get(&id)) {
         Some(mut space) => {
+            ensure_city_write(space.city_id)?;
             space.location = new_location;
-            do_insert_green_space(&space);
-            Ok(space)
+            validate_payload_fields(&payload_of(&space))?;
+            Ok(do_insert_green_space(&space))
         }
         None => Err(Error::NotFound {
             msg: format!(
@@ -199,25 +973,27 @@ fn update_green_space_location(id: u64, new_location: String) -> Result<GreenSpa
 }
 
 #[ic_cdk::query]
-fn get_green_space_count() -> Result<u64, Error> {
-    Ok(GREEN_SPACE_STORAGE.with(|service| service.borrow().len() as u64))
+fn get_green_space_count(city_id: Option<u64>) -> Result<u64, Error> {
+    GREEN_SPACE_STORAGE.with(|service| {
+        let storage = service.borrow();
+        Ok(match city_id {
+            None => storage.len(),
+            Some(_) => storage
+                .iter()
+                .filter(|(_, space)| in_city(city_id, space.city_id))
+                .count() as u64,
+        })
+    })
 }
 
 #[ic_cdk::query]
-fn search_green_spaces_by_location(location: String) -> Result<Vec<GreenSpace>, Error> {
-    GREEN_SPACE_STORAGE.with(|service| {
-        let borrow = service.borrow();
-        let result: Vec<_> = borrow
-            .iter()
-            .filter_map(|(_, space)| {
-                if space.location.contains(&location) {
-                    Some(space.clone())
-                } else {
-                    None
-                }
-            })
-            .collect();
-        Ok(result)
+fn search_green_spaces_by_location(
+    location: String,
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<GreenSpace>, Error> {
+    list_green_spaces_matching(next_token, |space| {
+        space.location.contains(&location) && in_city(city_id, space.city_id)
     })
 }
 
@@ -225,7 +1001,109 @@ fn search_green_spaces_by_location(location: String) -> Result<Vec<GreenSpace>,
 #[derive(candid::CandidType, Deserialize, Serialize)]
 enum Error {
     NotFound { msg: String },
+    InvalidInput { msg: String },
+    Unauthorized { msg: String },
+    OutcallFailed { msg: String },
+    InvalidGeometry { msg: String },
+    Maintenance { msg: String },
+    // The record changed since the caller read it
+    Conflict { msg: String },
+}
+
+impl Error {
+    fn message(&self) -> &str {
+        match self {
+            Error::NotFound { msg }
+            | Error::InvalidInput { msg }
+            | Error::Unauthorized { msg }
+            | Error::OutcallFailed { msg }
+            | Error::InvalidGeometry { msg }
+            | Error::Maintenance { msg }
+            | Error::Conflict { msg } => msg,
+        }
+    }
+
+    // The same error with its message prefixed, e.g. by where it occurred
+    fn prefixed(self, prefix: &str) -> Error {
+        let msg = |msg: String| format!("{}: {}", prefix, msg);
+        match self {
+            Error::NotFound { msg: m } => Error::NotFound { msg: msg(m) },
+            Error::InvalidInput { msg: m } => Error::InvalidInput { msg: msg(m) },
+            Error::Unauthorized { msg: m } => Error::Unauthorized { msg: msg(m) },
+            Error::OutcallFailed { msg: m } => Error::OutcallFailed { msg: msg(m) },
+            Error::InvalidGeometry { msg: m } => Error::InvalidGeometry { msg: msg(m) },
+            Error::Maintenance { msg: m } => Error::Maintenance { msg: msg(m) },
+            Error::Conflict { msg: m } => Error::Conflict { msg: msg(m) },
+        }
+    }
 }
 
 // Export Candid interface definitions for the canister
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload_with_description(description: String) -> GreenSpaceUpdatePayload {
+        GreenSpaceUpdatePayload {
+            name: "n".repeat(200),
+            location: "l".repeat(300),
+            description,
+            coordinates: Some(Coordinates { lat: 0.0, lng: 0.0 }),
+            boundary_wkt: None,
+            district_id: Some(1),
+            city_id: Some(1),
+            category: Some(GreenSpaceCategory::Park),
+            status: Some(GreenSpaceStatus::Open),
+            metadata: None,
+            shade_coverage_percent: Some(50),
+            has_water: Some(true),
+        }
+    }
+
+    // The size of the payload as do_insert_green_space stores it
+    fn stored_size(payload: &GreenSpaceUpdatePayload) -> usize {
+        GreenSpace {
+            id: 1,
+            name: payload.name.clone(),
+            location: payload.location.clone(),
+            description: payload.description.clone(),
+            coordinates: payload.coordinates,
+            external_ref: None,
+            district_id: payload.district_id,
+            city_id: payload.city_id,
+            category: payload.category,
+            status: payload.status,
+            created_at: Some(1),
+            ulid: Some("0".repeat(26)),
+            version: Some(1),
+            metadata: payload.metadata.clone(),
+            shade_coverage_percent: payload.shade_coverage_percent,
+            has_water: payload.has_water,
+            links: None,
+        }
+        .to_bytes()
+        .len()
+    }
+
+    #[test]
+    fn payloads_are_accepted_exactly_when_the_stored_record_fits() {
+        let max_size = GreenSpace::MAX_SIZE as usize;
+        let mut sizes = vec![];
+        for len in 0..=500 {
+            let payload = payload_with_description("d".repeat(len));
+            let size = stored_size(&payload);
+            assert_eq!(
+                validate_payload_fields(&payload).is_ok(),
+                size <= max_size,
+                "description of {} bytes, stored record of {} bytes",
+                len,
+                size
+            );
+            sizes.push(size);
+        }
+        assert!(sizes.contains(&max_size));
+        assert!(sizes.contains(&(max_size + 1)));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::maintenance::ensure_writable;
+use crate::{ensure_green_space_exists, ensure_space_admin, get_memory, Error, Memory};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const SPACE_LINKS_MEMORY_ID: u8 = 48;
+
+pub(crate) const MAX_LINKS_PER_SPACE: usize = 50;
+
+// How one space relates to another. Adjacency and trails hold both ways;
+// PartOf and HasPart, SplitFrom and SplitInto are each other's inverse
+#[derive(
+    candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+pub(crate) enum LinkKind {
+    AdjacentTo,
+    PartOf,
+    HasPart,
+    ConnectedByTrail,
+    // Recorded by split_green_space only
+    SplitFrom,
+    SplitInto,
+}
+
+impl LinkKind {
+    fn inverse(self) -> LinkKind {
+        match self {
+            LinkKind::PartOf => LinkKind::HasPart,
+            LinkKind::HasPart => LinkKind::PartOf,
+            LinkKind::SplitFrom => LinkKind::SplitInto,
+            LinkKind::SplitInto => LinkKind::SplitFrom,
+            kind => kind,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SpaceLink {
+    kind: LinkKind,
+    space_id: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct LinkKey {
+    from: u64,
+    kind: LinkKind,
+    to: u64,
+}
+
+impl_storable!(LinkKey, 128);
+
+thread_local! {
+    // Every link is stored from both ends, the far end under the inverse kind
+    pub(crate) static SPACE_LINKS: RefCell<StableBTreeMap<LinkKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SPACE_LINKS_MEMORY_ID)));
+}
+
+fn link_keys(id: u64) -> Vec<LinkKey> {
+    let start = LinkKey {
+        from: id,
+        kind: LinkKind::AdjacentTo,
+        to: 0,
+    };
+    SPACE_LINKS.with(|links| {
+        links
+            .borrow()
+            .range(start..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.from == id)
+            .collect()
+    })
+}
+
+// The links of a space, as returned with it by get_green_space
+pub(crate) fn space_links(id: u64) -> Vec<SpaceLink> {
+    link_keys(id)
+        .into_iter()
+        .map(|key| SpaceLink {
+            kind: key.kind,
+            space_id: key.to,
+        })
+        .collect()
+}
+
+fn parent_of(id: u64) -> Option<u64> {
+    link_keys(id)
+        .into_iter()
+        .find(|key| key.kind == LinkKind::PartOf)
+        .map(|key| key.to)
+}
+
+fn store_link(from: u64, kind: LinkKind, to: u64, linked: bool) {
+    // Logged once for both ends
+    let block_key = (from, (format!("{:?}", kind), to));
+    if linked {
+        log_upsert("space_link", &block_key, &LinkKey { from, kind, to });
+    } else {
+        log_delete("space_link", &block_key);
+    }
+    SPACE_LINKS.with(|links| {
+        let mut links = links.borrow_mut();
+        for key in [
+            LinkKey { from, kind, to },
+            LinkKey {
+                from: to,
+                kind: kind.inverse(),
+                to: from,
+            },
+        ] {
+            if linked {
+                links.insert(key, ());
+            } else {
+                links.remove(&key);
+            }
+        }
+    });
+}
+
+// Links a space created by a split to the space it was split from
+pub(crate) fn link_split_part(original: u64, part: u64) {
+    store_link(part, LinkKind::SplitFrom, original, true);
+}
+
+// Drops both ends of every link of a removed space
+pub(crate) fn remove_space_links(id: u64) {
+    for key in link_keys(id) {
+        store_link(key.from, key.kind, key.to, false);
+    }
+}
+
+// A space is part of at most one other, and never, through its parents, of itself
+fn validate_part_of(child: u64, parent: u64) -> Result<(), Error> {
+    if parent_of(child).is_some() {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The green space with id={} is already part of another",
+                child
+            ),
+        });
+    }
+    let mut ancestor = Some(parent);
+    while let Some(id) = ancestor {
+        if id == child {
+            return Err(Error::InvalidInput {
+                msg: "A green space cannot be part of itself".to_string(),
+            });
+        }
+        ancestor = parent_of(id);
+    }
+    Ok(())
+}
+
+// Function to link two green spaces; the inverse link is recorded on the
+// other space, and administering both spaces is required
+#[ic_cdk::update]
+fn link_green_spaces(from: u64, kind: LinkKind, to: u64) -> Result<Vec<SpaceLink>, Error> {
+    ensure_writable()?;
+    ensure_space_admin(from)?;
+    ensure_space_admin(to)?;
+    if from == to {
+        return Err(Error::InvalidInput {
+            msg: "A green space cannot be linked to itself".to_string(),
+        });
+    }
+    match kind {
+        LinkKind::PartOf => validate_part_of(from, to)?,
+        LinkKind::HasPart => validate_part_of(to, from)?,
+        LinkKind::AdjacentTo | LinkKind::ConnectedByTrail => {}
+        LinkKind::SplitFrom | LinkKind::SplitInto => {
+            return Err(Error::InvalidInput {
+                msg: "Split links are recorded by split_green_space".to_string(),
+            })
+        }
+    }
+    if [from, to]
+        .iter()
+        .any(|id| link_keys(*id).len() >= MAX_LINKS_PER_SPACE)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "A green space can have at most {} links",
+                MAX_LINKS_PER_SPACE
+            ),
+        });
+    }
+    store_link(from, kind, to, true);
+    Ok(space_links(from))
+}
+
+// Function to remove a link from both spaces
+#[ic_cdk::update]
+fn unlink_green_spaces(from: u64, kind: LinkKind, to: u64) -> Result<Vec<SpaceLink>, Error> {
+    ensure_writable()?;
+    ensure_space_admin(from)?;
+    ensure_space_admin(to)?;
+    if matches!(kind, LinkKind::SplitFrom | LinkKind::SplitInto) {
+        return Err(Error::InvalidInput {
+            msg: "Split links record history and cannot be removed".to_string(),
+        });
+    }
+    let key = LinkKey { from, kind, to };
+    if !SPACE_LINKS.with(|links| links.borrow().contains_key(&key)) {
+        return Err(Error::NotFound {
+            msg: format!(
+                "The green space with id={} has no {:?} link to id={}",
+                from, kind, to
+            ),
+        });
+    }
+    store_link(from, kind, to, false);
+    Ok(space_links(from))
+}
+
+#[ic_cdk::query]
+fn get_green_space_links(id: u64) -> Result<Vec<SpaceLink>, Error> {
+    ensure_green_space_exists(id)?;
+    Ok(space_links(id))
+}
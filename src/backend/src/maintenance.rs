@@ -0,0 +1,87 @@
+use crate::bans::ensure_not_banned;
+use crate::block_log::log_config;
+use crate::{caller, ensure_controller, get_memory, is_controller, Error, Memory};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::Cell;
+use std::cell::RefCell;
+
+const MAINTENANCE_MODE_MEMORY_ID: u8 = 43;
+
+const MAX_MAINTENANCE_MESSAGE_LEN: usize = 500;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct MaintenanceMode {
+    enabled: bool,
+    // Shown to callers whose writes are rejected
+    message: Option<String>,
+    changed_at: Option<u64>,
+    changed_by: Option<Principal>,
+}
+
+impl_storable!(MaintenanceMode, 1024);
+
+thread_local! {
+    static MAINTENANCE_MODE: RefCell<Cell<MaintenanceMode, Memory>> = RefCell::new(
+        Cell::init(get_memory(MAINTENANCE_MODE_MEMORY_ID), MaintenanceMode::default())
+            .expect("Cannot initialize the maintenance mode")
+    );
+}
+
+fn mode() -> MaintenanceMode {
+    MAINTENANCE_MODE.with(|m| m.borrow().get().clone())
+}
+
+pub(crate) fn in_maintenance() -> bool {
+    mode().enabled
+}
+
+// Fails while maintenance mode is on, except for controllers, who run the
+// migrations and imports the freeze is for, and for banned callers; every
+// update calls this first
+pub(crate) fn ensure_writable() -> Result<(), Error> {
+    let mode = mode();
+    if mode.enabled && !is_controller(&caller()) {
+        return Err(Error::Maintenance {
+            msg: mode.message.unwrap_or_else(|| {
+                "The canister is in maintenance mode, try again later".to_string()
+            }),
+        });
+    }
+    ensure_not_banned()
+}
+
+// Function to freeze or unfreeze writes; reads keep working either way
+#[ic_cdk::update]
+fn set_maintenance_mode(enabled: bool, message: Option<String>) -> Result<MaintenanceMode, Error> {
+    ensure_controller()?;
+    if message
+        .as_ref()
+        .is_some_and(|message| message.len() > MAX_MAINTENANCE_MESSAGE_LEN)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Maintenance messages must be at most {} characters",
+                MAX_MAINTENANCE_MESSAGE_LEN
+            ),
+        });
+    }
+    let mode = MaintenanceMode {
+        enabled,
+        message,
+        changed_at: Some(time()),
+        changed_by: Some(ic_cdk::caller()),
+    };
+    MAINTENANCE_MODE.with(|m| {
+        m.borrow_mut()
+            .set(mode.clone())
+            .expect("Cannot store the maintenance mode")
+    });
+    log_config("maintenance_mode", &mode);
+    Ok(mode)
+}
+
+#[ic_cdk::query]
+fn get_maintenance_mode() -> Result<MaintenanceMode, Error> {
+    Ok(mode())
+}
@@ -0,0 +1,163 @@
+use crate::block_log::log_config;
+use crate::cities::in_city;
+use crate::maintenance::ensure_writable;
+use crate::paging::Page;
+use crate::suggest::rebuild_tag_index;
+use crate::{ensure_controller, get_memory, list_green_spaces_matching, Error, GreenSpace, Memory};
+use ic_stable_structures::Cell;
+use std::cell::RefCell;
+
+const METADATA_KEYS_MEMORY_ID: u8 = 45;
+
+const MAX_METADATA_KEYS: usize = 20;
+const MAX_METADATA_KEY_LEN: usize = 32;
+const MAX_METADATA_VALUE_LEN: usize = 100;
+// Entries a single space may carry, whatever the number of defined keys
+const MAX_METADATA_ENTRIES: usize = 10;
+
+// A metadata key spaces may use, defined by the controllers
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MetadataKey {
+    key: String,
+    // Whether search_green_spaces_by_metadata accepts the key
+    searchable: bool,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct MetadataKeys(Vec<MetadataKey>);
+
+impl_storable!(MetadataKeys, 4096);
+
+thread_local! {
+    static METADATA_KEYS: RefCell<Cell<MetadataKeys, Memory>> = RefCell::new(
+        Cell::init(get_memory(METADATA_KEYS_MEMORY_ID), MetadataKeys::default())
+            .expect("Cannot initialize the metadata keys")
+    );
+}
+
+fn metadata_keys() -> Vec<MetadataKey> {
+    METADATA_KEYS.with(|k| k.borrow().get().0.clone())
+}
+
+fn find_key(key: &str) -> Option<MetadataKey> {
+    metadata_keys()
+        .into_iter()
+        .find(|definition| definition.key == key)
+}
+
+// Only defined keys, each at most once, with bounded values
+pub(crate) fn validate_metadata(metadata: &[(String, String)]) -> Result<(), Error> {
+    if metadata.len() > MAX_METADATA_ENTRIES {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "A green space can have at most {} metadata entries",
+                MAX_METADATA_ENTRIES
+            ),
+        });
+    }
+    for (i, (key, value)) in metadata.iter().enumerate() {
+        if find_key(key).is_none() {
+            return Err(Error::InvalidInput {
+                msg: format!("The metadata key {} is not defined", key),
+            });
+        }
+        if metadata[..i].iter().any(|(other, _)| other == key) {
+            return Err(Error::InvalidInput {
+                msg: format!("The metadata key {} is set more than once", key),
+            });
+        }
+        if value.len() > MAX_METADATA_VALUE_LEN {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "Metadata values must be at most {} bytes",
+                    MAX_METADATA_VALUE_LEN
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn metadata_value<'a>(space: &'a GreenSpace, key: &str) -> Option<&'a str> {
+    space
+        .metadata
+        .as_ref()?
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+}
+
+pub(crate) fn searchable_keys() -> Vec<String> {
+    metadata_keys()
+        .into_iter()
+        .filter(|definition| definition.searchable)
+        .map(|definition| definition.key)
+        .collect()
+}
+
+// Function to define the metadata keys spaces may carry; values stored under
+// keys that are no longer defined are kept until the space is next updated
+#[ic_cdk::update]
+fn set_metadata_keys(keys: Vec<MetadataKey>) -> Result<Vec<MetadataKey>, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if keys.len() > MAX_METADATA_KEYS {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} metadata keys can be defined", MAX_METADATA_KEYS),
+        });
+    }
+    for (i, definition) in keys.iter().enumerate() {
+        let valid = !definition.key.is_empty()
+            && definition.key.len() <= MAX_METADATA_KEY_LEN
+            && definition
+                .key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "Metadata keys must be 1-{} letters, digits, '_' or '-'",
+                    MAX_METADATA_KEY_LEN
+                ),
+            });
+        }
+        if keys[..i].iter().any(|other| other.key == definition.key) {
+            return Err(Error::InvalidInput {
+                msg: format!("The metadata key {} is defined twice", definition.key),
+            });
+        }
+    }
+    METADATA_KEYS.with(|k| {
+        k.borrow_mut()
+            .set(MetadataKeys(keys.clone()))
+            .expect("Cannot store the metadata keys")
+    });
+    log_config("metadata_keys", &keys);
+    rebuild_tag_index();
+    Ok(keys)
+}
+
+#[ic_cdk::query]
+fn get_metadata_keys() -> Result<Vec<MetadataKey>, Error> {
+    Ok(metadata_keys())
+}
+
+// Function to find the spaces whose value under a searchable key matches,
+// ignoring case
+#[ic_cdk::query]
+fn search_green_spaces_by_metadata(
+    key: String,
+    value: String,
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<GreenSpace>, Error> {
+    if !find_key(&key).is_some_and(|definition| definition.searchable) {
+        return Err(Error::InvalidInput {
+            msg: format!("The metadata key {} is not searchable", key),
+        });
+    }
+    list_green_spaces_matching(next_token, |space| {
+        in_city(city_id, space.city_id)
+            && metadata_value(space, &key).is_some_and(|v| v.eq_ignore_ascii_case(&value))
+    })
+}
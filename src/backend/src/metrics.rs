@@ -0,0 +1,279 @@
+use crate::block_log::log_config;
+use crate::calendar::{month_range, year_month_of};
+use crate::maintenance::ensure_writable;
+use crate::{
+    ensure_controller, ensure_green_space_exists, ensure_space_admin, get_memory, Error, Memory,
+    NANOS_PER_DAY,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use std::cell::RefCell;
+
+const METRIC_SERIES_MEMORY_ID: u8 = 40;
+const METRIC_RETENTION_MEMORY_ID: u8 = 41;
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const MAX_SERIES_POINTS: usize = 1_000;
+// Bounds the work of one pruning run; the rest is pruned on the next
+const MAX_PRUNED_PER_RUN: usize = 10_000;
+
+#[derive(
+    candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub(crate) enum Metric {
+    Visits,
+    AirQualityIndex,
+    NoiseDecibels,
+    WaterUsageLitres,
+}
+
+#[derive(
+    candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub(crate) enum Resolution {
+    Raw,
+    Hourly,
+    Daily,
+    Monthly,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct SeriesKey {
+    space_id: u64,
+    metric: Metric,
+    resolution: Resolution,
+    // Timestamp of a raw point, or start of a rollup bucket
+    start: u64,
+}
+
+impl_storable!(SeriesKey, 128);
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct Aggregate {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl_storable!(Aggregate, 64);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct MetricPoint {
+    start: u64,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    mean: f64,
+}
+
+// How long each resolution is kept, in days; monthly rollups are kept forever
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MetricRetention {
+    raw_days: u64,
+    hourly_days: u64,
+    daily_days: u64,
+}
+
+impl Default for MetricRetention {
+    fn default() -> Self {
+        MetricRetention {
+            raw_days: 7,
+            hourly_days: 90,
+            daily_days: 730,
+        }
+    }
+}
+
+impl_storable!(MetricRetention, 256);
+
+thread_local! {
+    // Raw points and their hourly, daily and monthly rollups, ordered by space,
+    // metric, resolution and time
+    pub(crate) static METRIC_SERIES: RefCell<StableBTreeMap<SeriesKey, Aggregate, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(METRIC_SERIES_MEMORY_ID)));
+
+    static METRIC_RETENTION: RefCell<Cell<MetricRetention, Memory>> = RefCell::new(
+        Cell::init(get_memory(METRIC_RETENTION_MEMORY_ID), MetricRetention::default())
+            .expect("Cannot initialize the metric retention")
+    );
+}
+
+fn retention() -> MetricRetention {
+    METRIC_RETENTION.with(|r| r.borrow().get().clone())
+}
+
+fn bucket_start(resolution: Resolution, timestamp: u64) -> u64 {
+    match resolution {
+        Resolution::Raw => timestamp,
+        Resolution::Hourly => timestamp / NANOS_PER_HOUR * NANOS_PER_HOUR,
+        Resolution::Daily => timestamp / NANOS_PER_DAY * NANOS_PER_DAY,
+        Resolution::Monthly => {
+            let (year, month) = year_month_of(timestamp);
+            month_range(year as u32, month).map_or(0, |(start, _)| start)
+        }
+    }
+}
+
+fn add_to_bucket(key: SeriesKey, value: f64) {
+    METRIC_SERIES.with(|series| {
+        let mut series = series.borrow_mut();
+        let aggregate = match series.get(&key) {
+            Some(a) => Aggregate {
+                count: a.count + 1,
+                sum: a.sum + value,
+                min: a.min.min(value),
+                max: a.max.max(value),
+            },
+            None => Aggregate {
+                count: 1,
+                sum: value,
+                min: value,
+                max: value,
+            },
+        };
+        series.insert(key, aggregate);
+    });
+}
+
+// Removes the raw points and rollups past their retention; returns the number
+// of entries removed
+pub(crate) fn prune_metric_series() -> u64 {
+    let retention = retention();
+    let now = time();
+    let cutoff = |days: u64| now.saturating_sub(days.saturating_mul(NANOS_PER_DAY));
+    let (raw_cutoff, hourly_cutoff, daily_cutoff) = (
+        cutoff(retention.raw_days),
+        cutoff(retention.hourly_days),
+        cutoff(retention.daily_days),
+    );
+    let expired: Vec<SeriesKey> = METRIC_SERIES.with(|series| {
+        series
+            .borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| match key.resolution {
+                Resolution::Raw => key.start < raw_cutoff,
+                Resolution::Hourly => key.start < hourly_cutoff,
+                Resolution::Daily => key.start < daily_cutoff,
+                Resolution::Monthly => false,
+            })
+            .take(MAX_PRUNED_PER_RUN)
+            .collect()
+    });
+    METRIC_SERIES.with(|series| {
+        let mut series = series.borrow_mut();
+        for key in &expired {
+            series.remove(key);
+        }
+    });
+    expired.len() as u64
+}
+
+// Function to record a measurement of a space, taken now unless a time is
+// given; the rollups are updated as the point is stored
+#[ic_cdk::update]
+fn record_metric(
+    space_id: u64,
+    metric: Metric,
+    value: f64,
+    timestamp: Option<u64>,
+) -> Result<(), Error> {
+    ensure_writable()?;
+    ensure_space_admin(space_id)?;
+    if !value.is_finite() {
+        return Err(Error::InvalidInput {
+            msg: "Metric values must be finite numbers".to_string(),
+        });
+    }
+    let now = time();
+    let timestamp = timestamp.unwrap_or(now);
+    if timestamp > now {
+        return Err(Error::InvalidInput {
+            msg: "Metric values cannot be recorded in the future".to_string(),
+        });
+    }
+    for resolution in [
+        Resolution::Raw,
+        Resolution::Hourly,
+        Resolution::Daily,
+        Resolution::Monthly,
+    ] {
+        add_to_bucket(
+            SeriesKey {
+                space_id,
+                metric,
+                resolution,
+                start: bucket_start(resolution, timestamp),
+            },
+            value,
+        );
+    }
+    Ok(())
+}
+
+// Function to read a series of a space between two times, oldest first
+#[ic_cdk::query]
+fn get_metric_series(
+    space_id: u64,
+    metric: Metric,
+    resolution: Resolution,
+    from: u64,
+    to: u64,
+) -> Result<Vec<MetricPoint>, Error> {
+    ensure_green_space_exists(space_id)?;
+    if from > to {
+        return Err(Error::InvalidInput {
+            msg: "The start of the range must not be after its end".to_string(),
+        });
+    }
+    let key = |start| SeriesKey {
+        space_id,
+        metric,
+        resolution,
+        start,
+    };
+    Ok(METRIC_SERIES.with(|series| {
+        series
+            .borrow()
+            .range(key(bucket_start(resolution, from))..=key(to))
+            .take(MAX_SERIES_POINTS)
+            .map(|(key, a)| MetricPoint {
+                start: key.start,
+                count: a.count,
+                sum: a.sum,
+                min: a.min,
+                max: a.max,
+                mean: a.sum / a.count as f64,
+            })
+            .collect()
+    }))
+}
+
+// Function to set how long the raw points and the hourly and daily rollups are kept
+#[ic_cdk::update]
+fn configure_metric_retention(retention: MetricRetention) -> Result<MetricRetention, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if retention.raw_days == 0
+        || retention.hourly_days < retention.raw_days
+        || retention.daily_days < retention.hourly_days
+    {
+        return Err(Error::InvalidInput {
+            msg: "Retention must be at least a day and grow with the resolution".to_string(),
+        });
+    }
+    METRIC_RETENTION.with(|r| {
+        r.borrow_mut()
+            .set(retention.clone())
+            .expect("Cannot store the metric retention")
+    });
+    log_config("metric_retention", &retention);
+    Ok(retention)
+}
+
+#[ic_cdk::query]
+fn get_metric_retention() -> Result<MetricRetention, Error> {
+    Ok(retention())
+}
@@ -0,0 +1,147 @@
+use crate::events::{decide_event, get_event, Event};
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::reviews::{decide_review, get_review, Review};
+use crate::{authenticated_caller, ensure_space_admin, get_memory, Error, Memory};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const MODERATION_QUEUE_MEMORY_ID: u8 = 73;
+
+const MAX_REASON_LEN: usize = 500;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum ModerationStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+// A piece of user-generated content that goes through moderation
+#[derive(
+    candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub(crate) enum ContentRef {
+    Review(u64),
+    Event(u64),
+}
+
+// Only needed to store the type in tuple keys
+impl Default for ContentRef {
+    fn default() -> Self {
+        ContentRef::Review(0)
+    }
+}
+
+impl_storable!(ContentRef, 32);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ModerationDecision {
+    moderator: Principal,
+    approved: bool,
+    // Given to the author along with a rejection
+    reason: Option<String>,
+    decided_at: u64,
+}
+
+impl ModerationDecision {
+    // A decision by the caller
+    pub(crate) fn new(approved: bool, reason: Option<String>) -> Self {
+        ModerationDecision {
+            moderator: ic_cdk::caller(),
+            approved,
+            reason,
+            decided_at: time(),
+        }
+    }
+
+    pub(crate) fn approved(&self) -> bool {
+        self.approved
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum ModerationItem {
+    Review(Review),
+    Event(Event),
+}
+
+thread_local! {
+    // Content awaiting a decision, oldest first, keyed by (submitted at, content)
+    pub(crate) static MODERATION_QUEUE: RefCell<StableBTreeMap<(u64, ContentRef), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(MODERATION_QUEUE_MEMORY_ID)));
+}
+
+// Keeps the queue in line with the status of stored or removed content
+pub(crate) fn track_moderation(content: ContentRef, submitted_at: u64, pending: bool) {
+    MODERATION_QUEUE.with(|q| {
+        let mut queue = q.borrow_mut();
+        if pending {
+            queue.insert((submitted_at, content), ());
+        } else {
+            queue.remove(&(submitted_at, content));
+        }
+    });
+}
+
+fn moderation_item(content: ContentRef) -> Result<(u64, ModerationItem), Error> {
+    Ok(match content {
+        ContentRef::Review(id) => {
+            let review = get_review(id)?;
+            (review.space_id, ModerationItem::Review(review))
+        }
+        ContentRef::Event(id) => {
+            let event = get_event(id)?;
+            (event.space_id, ModerationItem::Event(event))
+        }
+    })
+}
+
+// Function for moderators to list the content awaiting their decision, oldest
+// first; admins of a city moderate the content of its spaces
+#[ic_cdk::query]
+fn get_moderation_queue(next_token: Option<String>) -> Result<Page<ModerationItem>, Error> {
+    authenticated_caller()?;
+    let range = keys_after_token::<(u64, ContentRef)>(&next_token)?;
+    Ok(MODERATION_QUEUE.with(|q| {
+        page_by_size(q.borrow().range(range).filter_map(|(key, _)| {
+            let (space_id, item) = moderation_item(key.1).ok()?;
+            ensure_space_admin(space_id).ok()?;
+            Some((key, item))
+        }))
+    }))
+}
+
+// Function for moderators to publish or reject content; rejections need a
+// reason for the author
+#[ic_cdk::update]
+fn moderate_content(
+    content: ContentRef,
+    approve: bool,
+    reason: Option<String>,
+) -> Result<ModerationItem, Error> {
+    ensure_writable()?;
+    let (space_id, _) = moderation_item(content)?;
+    ensure_space_admin(space_id)?;
+    let reason = reason.filter(|reason| !reason.trim().is_empty());
+    if !approve && reason.is_none() {
+        return Err(Error::InvalidInput {
+            msg: "A rejection needs a reason".to_string(),
+        });
+    }
+    if reason
+        .as_ref()
+        .is_some_and(|reason| reason.len() > MAX_REASON_LEN)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!("Reasons must be at most {} bytes", MAX_REASON_LEN),
+        });
+    }
+    let decision = ModerationDecision::new(approve, reason);
+    Ok(match content {
+        ContentRef::Review(id) => ModerationItem::Review(decide_review(id, decision)?),
+        ContentRef::Event(id) => ModerationItem::Event(decide_event(id, decision)?),
+    })
+}
@@ -0,0 +1,154 @@
+use crate::paging::{decode_token, page_by_count, Page};
+use crate::{_get_green_space, get_memory, Error, GreenSpace, Memory, GREEN_SPACE_STORAGE};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::ops::Bound;
+
+const NAME_INDEX_MEMORY_ID: u8 = 35;
+const NAME_INDEX_KEYS_MEMORY_ID: u8 = 36;
+
+const MAX_NAME_PAGE: u32 = 500;
+// Longer names are indexed by their prefix so keys stay bounded
+const MAX_NORMALIZED_NAME_LEN: usize = 900;
+
+// Position of a space in alphabetical order; spaces sharing a name are
+// ordered by id
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct NameKey {
+    name: String,
+    id: u64,
+}
+
+impl_storable!(NameKey, 1024);
+
+thread_local! {
+    // Green space ids in order of their normalized name
+    static NAME_INDEX: RefCell<StableBTreeMap<NameKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(NAME_INDEX_MEMORY_ID)));
+
+    // The key each space is indexed under, so it can be removed on rename
+    static NAME_INDEX_KEYS: RefCell<StableBTreeMap<u64, NameKey, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(NAME_INDEX_KEYS_MEMORY_ID)));
+}
+
+// Case- and whitespace-insensitive form of a name
+pub(crate) fn normalize_name(name: &str) -> String {
+    let mut normalized = String::new();
+    for word in name.split_whitespace() {
+        if !normalized.is_empty() {
+            normalized.push(' ');
+        }
+        normalized.extend(word.chars().flat_map(char::to_lowercase));
+    }
+    if normalized.len() > MAX_NORMALIZED_NAME_LEN {
+        let mut end = MAX_NORMALIZED_NAME_LEN;
+        while !normalized.is_char_boundary(end) {
+            end -= 1;
+        }
+        normalized.truncate(end);
+    }
+    normalized
+}
+
+fn name_key(space: &GreenSpace) -> NameKey {
+    NameKey {
+        name: normalize_name(&space.name),
+        id: space.id,
+    }
+}
+
+// Brings the name index entry of a green space in line with its stored name;
+// spaces that no longer exist are removed from the index
+pub(crate) fn reindex_green_space_name(id: u64) {
+    let key = _get_green_space(&id).map(|space| name_key(&space));
+    let previous = NAME_INDEX_KEYS.with(|k| match &key {
+        Some(key) => k.borrow_mut().insert(id, key.clone()),
+        None => k.borrow_mut().remove(&id),
+    });
+    NAME_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous {
+            index.remove(&previous);
+        }
+        if let Some(key) = key {
+            index.insert(key, ());
+        }
+    });
+}
+
+// Rebuilds the name index unless it covers exactly the stored spaces, as after
+// an upgrade from a version without it or a snapshot restore
+pub(crate) fn ensure_name_index() {
+    let consistent = NAME_INDEX_KEYS.with(|k| k.borrow().len())
+        == GREEN_SPACE_STORAGE.with(|s| s.borrow().len())
+        && GREEN_SPACE_STORAGE.with(|s| {
+            s.borrow().iter().all(|(id, space)| {
+                NAME_INDEX_KEYS.with(|k| k.borrow().get(&id)) == Some(name_key(&space))
+            })
+        });
+    if consistent {
+        return;
+    }
+    NAME_INDEX.with(|index| {
+        let keys: Vec<NameKey> = index.borrow().iter().map(|(key, _)| key).collect();
+        let mut index = index.borrow_mut();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    NAME_INDEX_KEYS.with(|k| {
+        let ids: Vec<u64> = k.borrow().iter().map(|(id, _)| id).collect();
+        let mut k = k.borrow_mut();
+        for id in ids {
+            k.remove(&id);
+        }
+    });
+    let ids: Vec<u64> = GREEN_SPACE_STORAGE.with(|s| s.borrow().iter().map(|(id, _)| id).collect());
+    for id in ids {
+        reindex_green_space_name(id);
+    }
+}
+
+// Ids of the spaces whose normalized name starts with the given normalized
+// prefix, in alphabetical order
+pub(crate) fn ids_with_name_prefix(prefix: &str, limit: usize) -> Vec<(String, u64)> {
+    let start = NameKey {
+        name: prefix.to_string(),
+        id: 0,
+    };
+    NAME_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.name.starts_with(prefix))
+            .take(limit)
+            .map(|key| (key.name, key.id))
+            .collect()
+    })
+}
+
+// Function to browse green spaces alphabetically; start_after is the
+// next_token of the previous page
+#[ic_cdk::query]
+fn list_green_spaces_by_name(
+    start_after: Option<String>,
+    limit: u32,
+) -> Result<Page<GreenSpace>, Error> {
+    if limit == 0 || limit > MAX_NAME_PAGE {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_NAME_PAGE),
+        });
+    }
+    let start = match decode_token::<NameKey>(&start_after)? {
+        Some(key) => Bound::Excluded(key),
+        None => Bound::Unbounded,
+    };
+    NAME_INDEX.with(|index| {
+        let index = index.borrow();
+        let spaces = index
+            .range((start, Bound::Unbounded))
+            .filter_map(|(key, _)| _get_green_space(&key.id).map(|space| (key, space)));
+        Ok(page_by_count(spaces, limit as usize))
+    })
+}
@@ -0,0 +1,319 @@
+use crate::block_log::log_upsert;
+use crate::maintenance::ensure_writable;
+use crate::paging::{decode_token, keys_after_token, page_by_size, Page};
+use crate::{
+    ensure_green_space_exists, ensure_space_admin, get_memory, next_id, Error, Memory,
+    NANOS_PER_DAY,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+const NOISE_SENSORS_MEMORY_ID: u8 = 9;
+const NOISE_HOURLY_MEMORY_ID: u8 = 10;
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const MAX_LABEL_LEN: usize = 100;
+// Readings outside this range are sensor faults rather than real sound levels
+const MIN_DECIBELS: f64 = 0.0;
+const MAX_DECIBELS: f64 = 194.0;
+// Window over which profiles and rankings are computed
+const PROFILE_WINDOW_DAYS: u64 = 30;
+const RANKING_WINDOW_DAYS: u64 = 7;
+const MAX_RANKED_SPACES: u64 = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct NoiseSensor {
+    id: u64,
+    pub(crate) space_id: u64,
+    // Identity the sensor signs its readings with
+    principal: Principal,
+    label: String,
+    active: bool,
+    registered_at: u64,
+}
+
+impl_storable!(NoiseSensor, 512);
+
+// Readings for one space and one clock hour. Decibels are logarithmic, so the
+// aggregate keeps the sum of sound energy and averages that instead of dB values
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct NoiseHourlyAggregate {
+    space_id: u64,
+    hour_start: u64,
+    readings: u64,
+    energy_sum: f64,
+    min_db: f64,
+    max_db: f64,
+}
+
+impl_storable!(NoiseHourlyAggregate, 256);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct NoiseSensorPayload {
+    space_id: u64,
+    principal: Principal,
+    label: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct HourlyNoiseLevel {
+    // Hour of the day in UTC, 0-23
+    hour: u8,
+    average_db: Option<f64>,
+    readings: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct QuietHoursProfile {
+    space_id: u64,
+    window_days: u64,
+    hours: Vec<HourlyNoiseLevel>,
+    // Hours of the day with the lowest average level, quietest first
+    quietest_hours: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SpaceNoiseRanking {
+    space_id: u64,
+    average_db: f64,
+    readings: u64,
+}
+
+thread_local! {
+    pub(crate) static NOISE_SENSORS: RefCell<StableBTreeMap<u64, NoiseSensor, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(NOISE_SENSORS_MEMORY_ID)));
+
+    // Aggregates keyed by (space id, hour start)
+    pub(crate) static NOISE_HOURLY: RefCell<StableBTreeMap<(u64, u64), NoiseHourlyAggregate, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(NOISE_HOURLY_MEMORY_ID)));
+}
+
+fn db_to_energy(db: f64) -> f64 {
+    10f64.powf(db / 10.0)
+}
+
+fn energy_to_db(energy: f64) -> f64 {
+    10.0 * energy.log10()
+}
+
+fn sensor_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A noise sensor with id={} not found", id),
+    }
+}
+
+// Returns the aggregates of a space for every hour starting at or after `since`
+fn aggregates_since(space_id: u64, since: u64) -> Vec<NoiseHourlyAggregate> {
+    NOISE_HOURLY.with(|s| {
+        s.borrow()
+            .range((space_id, since)..=(space_id, u64::MAX))
+            .map(|(_, aggregate)| aggregate)
+            .collect()
+    })
+}
+
+// Function to register a sensor that may report readings for a space
+#[ic_cdk::update]
+fn register_noise_sensor(payload: NoiseSensorPayload) -> Result<NoiseSensor, Error> {
+    ensure_writable()?;
+    ensure_space_admin(payload.space_id)?;
+    if payload.label.trim().is_empty() || payload.label.len() > MAX_LABEL_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Sensor label must be 1-{} characters", MAX_LABEL_LEN),
+        });
+    }
+
+    let sensor = NoiseSensor {
+        id: next_id(NOISE_SENSORS_MEMORY_ID),
+        space_id: payload.space_id,
+        principal: payload.principal,
+        label: payload.label,
+        active: true,
+        registered_at: time(),
+    };
+
+    NOISE_SENSORS.with(|s| s.borrow_mut().insert(sensor.id, sensor.clone()));
+    log_upsert("noise_sensor", &sensor.id, &sensor);
+    Ok(sensor)
+}
+
+// Function to stop accepting readings from a sensor
+#[ic_cdk::update]
+fn deactivate_noise_sensor(id: u64) -> Result<NoiseSensor, Error> {
+    ensure_writable()?;
+    match NOISE_SENSORS.with(|s| s.borrow().get(&id)) {
+        Some(mut sensor) => {
+            ensure_space_admin(sensor.space_id)?;
+            sensor.active = false;
+            NOISE_SENSORS.with(|s| s.borrow_mut().insert(sensor.id, sensor.clone()));
+            log_upsert("noise_sensor", &sensor.id, &sensor);
+            Ok(sensor)
+        }
+        None => Err(sensor_not_found(id)),
+    }
+}
+
+#[ic_cdk::query]
+fn get_noise_sensors_for_space(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<NoiseSensor>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(NOISE_SENSORS.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, sensor)| sensor.space_id == space_id),
+        )
+    }))
+}
+
+// Function for a registered sensor to report a sound level; the reading is
+// folded into the hourly aggregate of the sensor's space
+#[ic_cdk::update]
+fn submit_noise_reading(sensor_id: u64, decibels: f64) -> Result<NoiseHourlyAggregate, Error> {
+    ensure_writable()?;
+    let sensor = NOISE_SENSORS
+        .with(|s| s.borrow().get(&sensor_id))
+        .ok_or_else(|| sensor_not_found(sensor_id))?;
+    if sensor.principal != ic_cdk::caller() || !sensor.active {
+        return Err(Error::Unauthorized {
+            msg: format!(
+                "Caller is not the active principal of sensor id={}",
+                sensor_id
+            ),
+        });
+    }
+    if !(MIN_DECIBELS..=MAX_DECIBELS).contains(&decibels) {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Reading must be between {} and {} dB, got {}",
+                MIN_DECIBELS, MAX_DECIBELS, decibels
+            ),
+        });
+    }
+
+    let hour_start = time() / NANOS_PER_HOUR * NANOS_PER_HOUR;
+    let key = (sensor.space_id, hour_start);
+    NOISE_HOURLY.with(|s| {
+        let mut hourly = s.borrow_mut();
+        let aggregate = match hourly.get(&key) {
+            Some(mut aggregate) => {
+                aggregate.readings += 1;
+                aggregate.energy_sum += db_to_energy(decibels);
+                aggregate.min_db = aggregate.min_db.min(decibels);
+                aggregate.max_db = aggregate.max_db.max(decibels);
+                aggregate
+            }
+            None => NoiseHourlyAggregate {
+                space_id: sensor.space_id,
+                hour_start,
+                readings: 1,
+                energy_sum: db_to_energy(decibels),
+                min_db: decibels,
+                max_db: decibels,
+            },
+        };
+        // Aggregates are counters over readings that are not kept; they are not logged
+        hourly.insert(key, aggregate.clone());
+        Ok(aggregate)
+    })
+}
+
+// Function to get the hourly aggregates of a space between two timestamps
+#[ic_cdk::query]
+fn get_noise_aggregates(
+    space_id: u64,
+    from: u64,
+    to: u64,
+    next_token: Option<String>,
+) -> Result<Page<NoiseHourlyAggregate>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let start = match decode_token::<u64>(&next_token)? {
+        Some(last) if last >= from => Bound::Excluded((space_id, last)),
+        _ => Bound::Included((space_id, from)),
+    };
+    Ok(NOISE_HOURLY.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range((start, Bound::Included((space_id, to))))
+                .map(|((_, hour_start), aggregate)| (hour_start, aggregate)),
+        )
+    }))
+}
+
+// Function to get the typical sound level of a space for each hour of the day
+#[ic_cdk::query]
+fn get_quiet_hours_profile(space_id: u64) -> Result<QuietHoursProfile, Error> {
+    ensure_green_space_exists(space_id)?;
+    let since = time().saturating_sub(PROFILE_WINDOW_DAYS * NANOS_PER_DAY);
+
+    let mut totals = [(0.0f64, 0u64); 24];
+    for aggregate in aggregates_since(space_id, since) {
+        let hour = ((aggregate.hour_start % NANOS_PER_DAY) / NANOS_PER_HOUR) as usize;
+        totals[hour].0 += aggregate.energy_sum;
+        totals[hour].1 += aggregate.readings;
+    }
+
+    let hours: Vec<_> = totals
+        .iter()
+        .enumerate()
+        .map(|(hour, (energy, readings))| HourlyNoiseLevel {
+            hour: hour as u8,
+            average_db: (*readings > 0).then(|| energy_to_db(energy / *readings as f64)),
+            readings: *readings,
+        })
+        .collect();
+
+    let mut measured: Vec<_> = hours
+        .iter()
+        .filter_map(|level| level.average_db.map(|db| (level.hour, db)))
+        .collect();
+    measured.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    Ok(QuietHoursProfile {
+        space_id,
+        window_days: PROFILE_WINDOW_DAYS,
+        hours,
+        quietest_hours: measured.into_iter().take(3).map(|(hour, _)| hour).collect(),
+    })
+}
+
+// Function to rank spaces by their average sound level over the last week
+#[ic_cdk::query]
+fn get_quietest_spaces(limit: u64) -> Result<Vec<SpaceNoiseRanking>, Error> {
+    if limit == 0 || limit > MAX_RANKED_SPACES {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_RANKED_SPACES),
+        });
+    }
+    let since = time().saturating_sub(RANKING_WINDOW_DAYS * NANOS_PER_DAY);
+
+    let mut totals: BTreeMap<u64, (f64, u64)> = BTreeMap::new();
+    NOISE_HOURLY.with(|s| {
+        for (_, aggregate) in s.borrow().iter() {
+            if aggregate.hour_start >= since {
+                let entry = totals.entry(aggregate.space_id).or_insert((0.0, 0));
+                entry.0 += aggregate.energy_sum;
+                entry.1 += aggregate.readings;
+            }
+        }
+    });
+
+    let mut ranking: Vec<_> = totals
+        .into_iter()
+        .map(|(space_id, (energy, readings))| SpaceNoiseRanking {
+            space_id,
+            average_db: energy_to_db(energy / readings as f64),
+            readings,
+        })
+        .collect();
+    ranking.sort_by(|a, b| a.average_db.total_cmp(&b.average_db));
+    ranking.truncate(limit as usize);
+    Ok(ranking)
+}
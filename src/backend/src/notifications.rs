@@ -0,0 +1,119 @@
+use crate::digest::WeeklyDigest;
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_before_token, page_by_size, Page};
+use crate::{get_memory, next_id, principal_key, Error, Memory, PrincipalKey};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::ops::RangeBounds;
+
+const NOTIFICATIONS_MEMORY_ID: u8 = 15;
+
+// What a notification is about, so clients can render and link it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum NotificationKind {
+    ApiaryInspectionDue { apiary_id: u64 },
+    // A space was added to, or changed into, the results of a saved search
+    SavedSearchMatch { search_id: u64 },
+    // What happened around the recipient's favorite spaces in the past week
+    WeeklyDigest(WeeklyDigest),
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Notification {
+    id: u64,
+    kind: NotificationKind,
+    space_id: Option<u64>,
+    message: String,
+    pub(crate) created_at: u64,
+    read: bool,
+}
+
+impl_storable!(Notification, 2048);
+
+thread_local! {
+    // Notifications keyed by (recipient, notification id)
+    pub(crate) static NOTIFICATIONS: RefCell<StableBTreeMap<(PrincipalKey, u64), Notification, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(NOTIFICATIONS_MEMORY_ID)));
+}
+
+// Queues a notification for a principal
+pub(crate) fn notify(
+    recipient: &Principal,
+    kind: NotificationKind,
+    space_id: Option<u64>,
+    message: String,
+) {
+    let notification = Notification {
+        id: next_id(NOTIFICATIONS_MEMORY_ID),
+        kind,
+        space_id,
+        message,
+        created_at: time(),
+        read: false,
+    };
+    NOTIFICATIONS.with(|s| {
+        s.borrow_mut()
+            .insert((principal_key(recipient), notification.id), notification)
+    });
+}
+
+fn notifications_of(recipient: &Principal) -> Vec<((PrincipalKey, u64), Notification)> {
+    let key = principal_key(recipient);
+    NOTIFICATIONS.with(|s| s.borrow().range((key, 0)..=(key, u64::MAX)).collect())
+}
+
+// Function to get the caller's notifications, newest first
+#[ic_cdk::query]
+fn get_my_notifications(
+    unread_only: bool,
+    next_token: Option<String>,
+) -> Result<Page<Notification>, Error> {
+    let range = keys_before_token::<u64>(&next_token)?;
+    let mut result: Vec<_> = notifications_of(&ic_cdk::caller())
+        .into_iter()
+        .map(|((_, id), notification)| (id, notification))
+        .filter(|(id, notification)| range.contains(id) && (!unread_only || !notification.read))
+        .collect();
+    result.reverse();
+    Ok(page_by_size(result.into_iter()))
+}
+
+#[ic_cdk::update]
+fn mark_notification_read(id: u64) -> Result<Notification, Error> {
+    ensure_writable()?;
+    let key = (principal_key(&ic_cdk::caller()), id);
+    NOTIFICATIONS.with(|s| {
+        let mut notifications = s.borrow_mut();
+        match notifications.get(&key) {
+            Some(mut notification) => {
+                notification.read = true;
+                notifications.insert(key, notification.clone());
+                Ok(notification)
+            }
+            None => Err(Error::NotFound {
+                msg: format!("A notification with id={} not found", id),
+            }),
+        }
+    })
+}
+
+// Function to mark every notification of the caller as read
+#[ic_cdk::update]
+fn mark_all_notifications_read() -> Result<u64, Error> {
+    ensure_writable()?;
+    let unread: Vec<_> = notifications_of(&ic_cdk::caller())
+        .into_iter()
+        .filter(|(_, notification)| !notification.read)
+        .collect();
+    let count = unread.len() as u64;
+    NOTIFICATIONS.with(|s| {
+        let mut notifications = s.borrow_mut();
+        for (key, mut notification) in unread {
+            notification.read = true;
+            notifications.insert(key, notification);
+        }
+    });
+    Ok(count)
+}
@@ -0,0 +1,119 @@
+use crate::calendar::format_rfc3339;
+use crate::events::{EventStatus, EVENTS};
+use crate::formats::feature;
+use crate::query_api::{filter_matches, validate_filter, QueryFilter};
+use crate::tickets::{TicketSource, MAINTENANCE_TICKETS};
+use crate::{Error, GREEN_SPACE_STORAGE};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+// Lines are appended to a chunk until it would grow past this, which keeps
+// responses below the 2MB message limit
+const OPEN_DATA_CHUNK_BYTES: usize = 1536 * 1024;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct OpenDataChunk {
+    chunk: u64,
+    total_chunks: u64,
+    // JSON lines; concatenating the data of all chunks in order yields the
+    // complete dataset
+    data: String,
+}
+
+// Every record fit for publication, one JSON object per line. Spaces leave
+// out their metadata, events their organizer and tickets their free text,
+// which reporters may have put contact details in; events not approved are
+// left out entirely. With a filter, only matching spaces and the events and
+// tickets of those spaces are included
+fn open_data_lines(filter: Option<&QueryFilter>) -> Vec<String> {
+    let line = |kind: &str, data: Value| json!({ "kind": kind, "data": data }).to_string();
+    let mut included = BTreeSet::new();
+    let mut lines: Vec<String> = GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, space)| filter.is_none_or(|filter| filter_matches(filter, space)))
+            .map(|(id, space)| {
+                included.insert(id);
+                line("green_space", feature(&space))
+            })
+            .collect()
+    });
+    let in_export = |space_id: u64| filter.is_none() || included.contains(&space_id);
+    EVENTS.with(|e| {
+        for (_, event) in e.borrow().iter() {
+            if event.status != EventStatus::Approved || !in_export(event.space_id) {
+                continue;
+            }
+            lines.push(line(
+                "event",
+                json!({
+                    "id": event.id,
+                    "space_id": event.space_id,
+                    "title": event.title,
+                    "description": event.description,
+                    "starts_at": format_rfc3339(event.starts_at),
+                    "ends_at": format_rfc3339(event.ends_at),
+                }),
+            ));
+        }
+    });
+    MAINTENANCE_TICKETS.with(|t| {
+        for (_, ticket) in t.borrow().iter() {
+            if !in_export(ticket.space_id) {
+                continue;
+            }
+            let source = match ticket.source {
+                TicketSource::Manual | TicketSource::ApiReport { .. } => "reported",
+                TicketSource::AccessibilityAudit { .. }
+                | TicketSource::InspectionOverdue { .. } => "generated",
+            };
+            lines.push(line(
+                "maintenance_ticket",
+                json!({
+                    "id": ticket.id,
+                    "space_id": ticket.space_id,
+                    "status": ticket.status,
+                    "source": source,
+                    "created_at": format_rfc3339(ticket.created_at),
+                    "resolved_at": ticket.resolved_at.map(format_rfc3339),
+                }),
+            ));
+        }
+    });
+    lines
+}
+
+// Function to export the dataset without principals, contact details or
+// unapproved submissions, for publication as open data, or only the part a
+// filter of the query API selects; one chunk per call
+#[ic_cdk::query]
+fn export_open_data(chunk: u64, filter: Option<QueryFilter>) -> Result<OpenDataChunk, Error> {
+    if let Some(filter) = &filter {
+        validate_filter(filter)?;
+    }
+    let mut chunks = vec![String::new()];
+    for line in open_data_lines(filter.as_ref()) {
+        let current = chunks.last_mut().unwrap();
+        if !current.is_empty() && current.len() + line.len() + 1 > OPEN_DATA_CHUNK_BYTES {
+            chunks.push(String::new());
+        }
+        let current = chunks.last_mut().unwrap();
+        current.push_str(&line);
+        current.push('\n');
+    }
+    let total_chunks = chunks.len() as u64;
+    let data = chunks
+        .into_iter()
+        .nth(chunk as usize)
+        .ok_or_else(|| Error::InvalidInput {
+            msg: format!(
+                "Chunk {} requested, but the export has {} chunks",
+                chunk, total_chunks
+            ),
+        })?;
+    Ok(OpenDataChunk {
+        chunk,
+        total_chunks,
+        data,
+    })
+}
@@ -0,0 +1,155 @@
+use crate::accessibility::ACCESSIBILITY_AUDITS;
+use crate::apiary::APIARIES;
+use crate::cascade::{queue_cascade, CASCADE_QUEUE};
+use crate::compost::COMPOST_SITES;
+use crate::contractors::WORK_ORDERS;
+use crate::events::EVENTS;
+use crate::expenses::EXPENSES;
+use crate::favorites::FAVORITES;
+use crate::maintenance::ensure_writable;
+use crate::noise::{NOISE_HOURLY, NOISE_SENSORS};
+use crate::playground::PLAYGROUND_EQUIPMENT;
+use crate::reviews::REVIEWS;
+use crate::soil::{SAMPLING_POINTS, SOIL_TESTS};
+use crate::tickets::MAINTENANCE_TICKETS;
+use crate::trees::TREES;
+use crate::visits::VISITS;
+use crate::water::WATER_FEATURES;
+use crate::{ensure_controller, Error, Memory, GREEN_SPACE_STORAGE};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::thread::LocalKey;
+
+type Store<K, V> = LocalKey<RefCell<StableBTreeMap<K, V, Memory>>>;
+
+// Records of one kind pointing at a space that does not exist
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct OrphanGroup {
+    kind: String,
+    space_id: u64,
+    records: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct OrphanReport {
+    groups: Vec<OrphanGroup>,
+    records: u64,
+    missing_spaces: u64,
+}
+
+// Adds the records of a store whose space is missing, and not already queued
+// for removal, to the report
+fn scan_store<K, V>(
+    report: &mut OrphanReport,
+    kind: &str,
+    store: &'static Store<K, V>,
+    record_space_id: fn(&K, &V) -> u64,
+) where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable,
+{
+    let mut counts: BTreeMap<u64, u64> = BTreeMap::new();
+    store.with(|s| {
+        for (key, value) in s.borrow().iter() {
+            *counts.entry(record_space_id(&key, &value)).or_default() += 1;
+        }
+    });
+    for (space_id, records) in counts {
+        let missing = !GREEN_SPACE_STORAGE.with(|s| s.borrow().contains_key(&space_id))
+            && !CASCADE_QUEUE.with(|q| q.borrow().contains_key(&space_id));
+        if missing {
+            report.records += records;
+            report.groups.push(OrphanGroup {
+                kind: kind.to_string(),
+                space_id,
+                records,
+            });
+        }
+    }
+}
+
+pub(crate) fn find_orphans() -> OrphanReport {
+    let mut report = OrphanReport::default();
+    scan_store(&mut report, "events", &EVENTS, |_, e| e.space_id);
+    scan_store(&mut report, "reviews", &REVIEWS, |_, r| r.space_id);
+    scan_store(
+        &mut report,
+        "maintenance_tickets",
+        &MAINTENANCE_TICKETS,
+        |_, t| t.space_id,
+    );
+    scan_store(
+        &mut report,
+        "playground_equipment",
+        &PLAYGROUND_EQUIPMENT,
+        |_, e| e.space_id,
+    );
+    scan_store(&mut report, "water_features", &WATER_FEATURES, |_, f| {
+        f.space_id
+    });
+    scan_store(&mut report, "apiaries", &APIARIES, |_, a| a.space_id);
+    scan_store(&mut report, "compost_sites", &COMPOST_SITES, |_, c| {
+        c.space_id
+    });
+    scan_store(&mut report, "noise_sensors", &NOISE_SENSORS, |_, n| {
+        n.space_id
+    });
+    scan_store(
+        &mut report,
+        "noise_aggregates",
+        &NOISE_HOURLY,
+        |(space, _), _| *space,
+    );
+    scan_store(&mut report, "sampling_points", &SAMPLING_POINTS, |_, p| {
+        p.space_id
+    });
+    scan_store(&mut report, "soil_tests", &SOIL_TESTS, |_, t| t.space_id);
+    scan_store(
+        &mut report,
+        "accessibility_audits",
+        &ACCESSIBILITY_AUDITS,
+        |_, a| a.space_id,
+    );
+    scan_store(&mut report, "favorites", &FAVORITES, |(_, space), _| *space);
+    scan_store(&mut report, "visits", &VISITS, |(_, space), _| *space);
+    scan_store(&mut report, "trees", &TREES, |_, t| t.space_id);
+    scan_store(&mut report, "work_orders", &WORK_ORDERS, |_, w| w.space_id);
+    scan_store(&mut report, "expenses", &EXPENSES, |_, e| e.space_id);
+    let mut missing: Vec<u64> = report.groups.iter().map(|g| g.space_id).collect();
+    missing.sort_unstable();
+    missing.dedup();
+    report.missing_spaces = missing.len() as u64;
+    report
+}
+
+impl OrphanReport {
+    pub(crate) fn summary(&self) -> String {
+        format!(
+            "{} orphaned record(s) of {} missing space(s)",
+            self.records, self.missing_spaces
+        )
+    }
+}
+
+// Function for controllers to list records pointing at spaces that do not
+// exist, e.g. left behind by deletions before records were removed with their
+// space
+#[ic_cdk::query]
+fn get_orphaned_records() -> Result<OrphanReport, Error> {
+    ensure_controller()?;
+    Ok(find_orphans())
+}
+
+// Function for controllers to remove the orphaned records, in the batches
+// deleted spaces are cleaned up in; returns what was queued for removal
+#[ic_cdk::update]
+fn remove_orphaned_records() -> Result<OrphanReport, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let report = find_orphans();
+    for group in &report.groups {
+        queue_cascade(group.space_id);
+    }
+    Ok(report)
+}
@@ -0,0 +1,512 @@
+use crate::cities::ensure_city_exists;
+use crate::geo::{BoundingBox, Coordinates};
+use crate::governance::ensure_not_governed;
+use crate::maintenance::ensure_writable;
+use crate::outcalls::{canonical_response, http_get, url_encode};
+use crate::quotas::{consume_quota, QuotaOp};
+use crate::slugs::slug_holder;
+use crate::ulid::new_ulid;
+use crate::{
+    _get_green_space, ensure_controller, ensure_fits_in_storage, find_by_external_ref,
+    insert_new_green_space, next_green_space_id, Error, GreenSpace, GreenSpaceCategory,
+};
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use ic_cdk::api::time;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const OVERPASS_URL: &str = "https://overpass-api.de/api/interpreter";
+// Outcall responses are capped at 2MB; larger areas have to be uploaded in chunks
+const OVERPASS_MAX_RESPONSE_BYTES: u64 = 2_000_000;
+const MAX_UPLOAD_BYTES: usize = 32 * 1024 * 1024;
+// Imported text is cut to these lengths; features whose record still exceeds
+// the storage bound are skipped
+const MAX_IMPORTED_NAME_CHARS: usize = 100;
+const MAX_IMPORTED_DESCRIPTION_CHARS: usize = 500;
+const MAX_IMPORTED_LOCATION_CHARS: usize = 200;
+// OSM tags that map onto GreenSpace fields; everything else is dropped
+const MAPPED_TAGS: [&str; 8] = [
+    "leisure",
+    "name",
+    "description",
+    "addr:full",
+    "addr:housenumber",
+    "addr:street",
+    "addr:postcode",
+    "addr:city",
+];
+
+#[derive(Serialize, Deserialize)]
+struct OverpassResponse {
+    elements: Vec<OsmElement>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OsmCenter {
+    lat: f64,
+    lon: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OsmElement {
+    #[serde(rename = "type")]
+    kind: String,
+    id: u64,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    center: Option<OsmCenter>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+
+// What an import does with a feature that matches an existing space, by
+// external reference or by slug within the city imported into
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub(crate) enum ImportStrategy {
+    // Leave the space as it is
+    #[default]
+    Skip,
+    // Replace the imported fields of the space with those of the feature
+    Overwrite,
+    // Replace only the fields the feature has a value for
+    MergeNonEmpty,
+    // Import nothing when any feature matches
+    Fail,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum ImportAction {
+    Created,
+    Skipped,
+    Overwritten,
+    Merged,
+    // The space with the feature applied would exceed the storage bound; it
+    // was left as it is
+    Failed,
+}
+
+// What became of one imported feature
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ImportRow {
+    external_ref: String,
+    space_id: u64,
+    action: ImportAction,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct OsmImportReport {
+    features_seen: u64,
+    created_ids: Vec<u64>,
+    skipped_existing: u64,
+    skipped_unnamed: u64,
+    skipped_outside_bbox: u64,
+    skipped_oversize: u64,
+    rows: Vec<ImportRow>,
+}
+
+impl OsmImportReport {
+    pub(crate) fn summary(&self) -> String {
+        format!(
+            "{} space(s) created and {} updated from {} feature(s)",
+            self.created_ids.len(),
+            self.rows
+                .iter()
+                .filter(|row| matches!(
+                    row.action,
+                    ImportAction::Overwritten | ImportAction::Merged
+                ))
+                .count(),
+            self.features_seen
+        )
+    }
+}
+
+thread_local! {
+    // In-progress chunked uploads; kept on the heap because they are only staging
+    // data and an upgrade simply means uploading again
+    static OSM_UPLOADS: RefCell<BTreeMap<u64, Vec<u8>>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_UPLOAD_ID: RefCell<u64> = const { RefCell::new(0) };
+}
+
+fn truncate_chars(value: &str, max_chars: usize) -> String {
+    value.chars().take(max_chars).collect()
+}
+
+impl OsmElement {
+    fn is_park(&self) -> bool {
+        self.tags.get("leisure").map(String::as_str) == Some("park")
+    }
+
+    fn coordinates(&self) -> Option<Coordinates> {
+        match (self.lat, self.lon, &self.center) {
+            (Some(lat), Some(lng), _) => Some(Coordinates { lat, lng }),
+            (_, _, Some(center)) => Some(Coordinates {
+                lat: center.lat,
+                lng: center.lon,
+            }),
+            _ => None,
+        }
+        .filter(|coordinates| coordinates.validate().is_ok())
+    }
+
+    fn external_ref(&self) -> String {
+        format!("osm:{}/{}", self.kind, self.id)
+    }
+
+    fn location(&self) -> String {
+        if let Some(full) = self.tags.get("addr:full") {
+            return full.clone();
+        }
+        let street = match (
+            self.tags.get("addr:street"),
+            self.tags.get("addr:housenumber"),
+        ) {
+            (Some(street), Some(number)) => Some(format!("{} {}", street, number)),
+            (Some(street), None) => Some(street.clone()),
+            _ => None,
+        };
+        let city = match (self.tags.get("addr:postcode"), self.tags.get("addr:city")) {
+            (Some(postcode), Some(city)) => Some(format!("{} {}", postcode, city)),
+            (None, Some(city)) => Some(city.clone()),
+            _ => None,
+        };
+        [street, city]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // The fields of a space the feature provides
+    fn to_green_space(&self, name: &str, city_id: Option<u64>) -> GreenSpace {
+        GreenSpace {
+            name: truncate_chars(name, MAX_IMPORTED_NAME_CHARS),
+            location: truncate_chars(&self.location(), MAX_IMPORTED_LOCATION_CHARS),
+            description: truncate_chars(
+                self.tags.get("description").map_or("", String::as_str),
+                MAX_IMPORTED_DESCRIPTION_CHARS,
+            ),
+            coordinates: self.coordinates(),
+            external_ref: Some(self.external_ref()),
+            city_id,
+            category: Some(GreenSpaceCategory::Park),
+            ..Default::default()
+        }
+    }
+}
+
+// The existing space a feature stands for: the one imported from it or,
+// failing that, the one of its city holding its slug
+fn find_match(feature: &GreenSpace) -> Option<GreenSpace> {
+    if let Some(space) = feature
+        .external_ref
+        .as_deref()
+        .and_then(find_by_external_ref)
+        .and_then(|id| _get_green_space(&id))
+    {
+        return Some(space);
+    }
+    slug_holder(&feature.name)
+        .and_then(|id| _get_green_space(&id))
+        .filter(|space| feature.city_id.is_none() || space.city_id == feature.city_id)
+}
+
+// The space with the fields of a feature applied as the strategy says
+fn apply_feature(space: GreenSpace, feature: GreenSpace, strategy: ImportStrategy) -> GreenSpace {
+    let merged = GreenSpace {
+        city_id: feature.city_id.or(space.city_id),
+        external_ref: space.external_ref.clone().or(feature.external_ref.clone()),
+        ..space.clone()
+    };
+    if strategy == ImportStrategy::Overwrite {
+        return GreenSpace {
+            name: feature.name,
+            location: feature.location,
+            description: feature.description,
+            coordinates: feature.coordinates,
+            category: feature.category,
+            ..merged
+        };
+    }
+    let or_existing = |value: String, existing: String| {
+        if value.trim().is_empty() {
+            existing
+        } else {
+            value
+        }
+    };
+    GreenSpace {
+        location: or_existing(feature.location, space.location),
+        description: or_existing(feature.description, space.description),
+        coordinates: feature.coordinates.or(space.coordinates),
+        category: feature.category.or(space.category),
+        name: feature.name,
+        ..merged
+    }
+}
+
+fn contains(bbox: &BoundingBox, coordinates: &Coordinates) -> bool {
+    (bbox.south..=bbox.north).contains(&coordinates.lat)
+        && (bbox.west..=bbox.east).contains(&coordinates.lng)
+}
+
+fn parse_overpass(body: &[u8]) -> Result<OverpassResponse, Error> {
+    serde_json::from_slice(body).map_err(|e| Error::InvalidInput {
+        msg: format!("Not a valid Overpass JSON extract: {}", e),
+    })
+}
+
+// Creates a green space for every named park in the extract that, when a
+// bounding box is given, lies inside it; parks matching an existing space are
+// handled as the strategy says
+fn import_elements(
+    response: OverpassResponse,
+    bbox: Option<BoundingBox>,
+    city_id: Option<u64>,
+    strategy: ImportStrategy,
+) -> Result<OsmImportReport, Error> {
+    let mut report = OsmImportReport::default();
+    let mut features = Vec::new();
+    for element in response.elements.iter().filter(|element| element.is_park()) {
+        report.features_seen += 1;
+
+        let Some(name) = element
+            .tags
+            .get("name")
+            .filter(|name| !name.trim().is_empty())
+        else {
+            report.skipped_unnamed += 1;
+            continue;
+        };
+        if let Some(bbox) = &bbox {
+            if !element.coordinates().is_some_and(|c| contains(bbox, &c)) {
+                report.skipped_outside_bbox += 1;
+                continue;
+            }
+        }
+        let feature = element.to_green_space(name, city_id);
+        if ensure_fits_in_storage(&feature).is_err() {
+            report.skipped_oversize += 1;
+            continue;
+        }
+        features.push(feature);
+    }
+
+    let matches: Vec<Option<GreenSpace>> = features.iter().map(find_match).collect();
+    if strategy == ImportStrategy::Fail {
+        if let Some((feature, space)) = features
+            .iter()
+            .zip(&matches)
+            .find_map(|(feature, space)| Some((feature, space.as_ref()?)))
+        {
+            return Err(Error::Conflict {
+                msg: format!(
+                    "{} matches the green space with id={}; nothing was imported",
+                    feature.external_ref.as_deref().unwrap_or_default(),
+                    space.id
+                ),
+            });
+        }
+    }
+    for (feature, space) in features.into_iter().zip(matches) {
+        let external_ref = feature.external_ref.clone().unwrap_or_default();
+        let (space_id, action) = match space {
+            None => {
+                let space = insert_new_green_space(&GreenSpace {
+                    id: next_green_space_id(),
+                    created_at: Some(time()),
+                    ulid: Some(new_ulid()),
+                    ..feature
+                })?;
+                report.created_ids.push(space.id);
+                (space.id, ImportAction::Created)
+            }
+            Some(space) if strategy == ImportStrategy::Skip => {
+                report.skipped_existing += 1;
+                (space.id, ImportAction::Skipped)
+            }
+            Some(space) => {
+                let action = match strategy {
+                    ImportStrategy::Overwrite => ImportAction::Overwritten,
+                    _ => ImportAction::Merged,
+                };
+                let applied = apply_feature(space, feature, strategy);
+                if ensure_fits_in_storage(&applied).is_err() {
+                    (applied.id, ImportAction::Failed)
+                } else {
+                    let space = insert_new_green_space(&applied)?;
+                    (space.id, action)
+                }
+            }
+        };
+        report.rows.push(ImportRow {
+            external_ref,
+            space_id,
+            action,
+        });
+    }
+    Ok(report)
+}
+
+// Function to fetch the parks in a bounding box from the Overpass API and
+// import them; parks already imported are skipped unless a strategy says otherwise
+#[ic_cdk::update]
+async fn import_osm_parks(
+    bbox: BoundingBox,
+    city_id: Option<u64>,
+    strategy: Option<ImportStrategy>,
+) -> Result<OsmImportReport, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    ensure_not_governed("Bulk imports")?;
+    consume_quota(QuotaOp::Import)?;
+    fetch_osm_parks(bbox, city_id, strategy.unwrap_or_default()).await
+}
+
+pub(crate) async fn fetch_osm_parks(
+    bbox: BoundingBox,
+    city_id: Option<u64>,
+    strategy: ImportStrategy,
+) -> Result<OsmImportReport, Error> {
+    bbox.validate()?;
+    if let Some(city_id) = city_id {
+        ensure_city_exists(city_id)?;
+    }
+
+    let area = format!("{},{},{},{}", bbox.south, bbox.west, bbox.north, bbox.east);
+    let query = format!(
+        "[out:json][timeout:25];(way[\"leisure\"=\"park\"]({area});relation[\"leisure\"=\"park\"]({area}););out center tags;"
+    );
+    let url = format!("{}?data={}", OVERPASS_URL, url_encode(&query));
+    let body = http_get(
+        url,
+        OVERPASS_MAX_RESPONSE_BYTES,
+        "transform_overpass_response",
+    )
+    .await?;
+    import_elements(parse_overpass(&body)?, Some(bbox), city_id, strategy)
+}
+
+// Function to begin a chunked upload of an Overpass extract; returns the upload id
+#[ic_cdk::update]
+fn start_osm_import() -> Result<u64, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let upload_id = NEXT_UPLOAD_ID.with(|id| {
+        let mut id = id.borrow_mut();
+        *id += 1;
+        *id
+    });
+    OSM_UPLOADS.with(|uploads| uploads.borrow_mut().insert(upload_id, Vec::new()));
+    Ok(upload_id)
+}
+
+// Function to append a chunk to an upload; returns the number of bytes received so far
+#[ic_cdk::update]
+fn upload_osm_import_chunk(upload_id: u64, chunk: Vec<u8>) -> Result<u64, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    OSM_UPLOADS.with(|uploads| {
+        let mut uploads = uploads.borrow_mut();
+        let buffer = uploads.get_mut(&upload_id).ok_or_else(|| Error::NotFound {
+            msg: format!("An OSM upload with id={} not found", upload_id),
+        })?;
+        if buffer.len() + chunk.len() > MAX_UPLOAD_BYTES {
+            return Err(Error::InvalidInput {
+                msg: format!("Uploads are limited to {} bytes", MAX_UPLOAD_BYTES),
+            });
+        }
+        buffer.extend_from_slice(&chunk);
+        Ok(buffer.len() as u64)
+    })
+}
+
+// Function to import a completed upload, optionally restricted to a bounding box
+#[ic_cdk::update]
+fn finish_osm_import(
+    upload_id: u64,
+    bbox: Option<BoundingBox>,
+    city_id: Option<u64>,
+    strategy: Option<ImportStrategy>,
+) -> Result<OsmImportReport, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    ensure_not_governed("Bulk imports")?;
+    consume_quota(QuotaOp::Import)?;
+    import_osm_upload(upload_id, bbox, city_id, strategy.unwrap_or_default())
+}
+
+pub(crate) fn import_osm_upload(
+    upload_id: u64,
+    bbox: Option<BoundingBox>,
+    city_id: Option<u64>,
+    strategy: ImportStrategy,
+) -> Result<OsmImportReport, Error> {
+    if let Some(city_id) = city_id {
+        ensure_city_exists(city_id)?;
+    }
+    if let Some(bbox) = &bbox {
+        bbox.validate()?;
+    }
+    let body = OSM_UPLOADS
+        .with(|uploads| uploads.borrow_mut().remove(&upload_id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("An OSM upload with id={} not found", upload_id),
+        })?;
+    import_elements(parse_overpass(&body)?, bbox, city_id, strategy)
+}
+
+// Keeps only park elements and the tags we map, serialized with sorted keys, so
+// every replica produces byte-identical responses
+#[ic_cdk::query]
+fn transform_overpass_response(args: TransformArgs) -> HttpResponse {
+    let body = match serde_json::from_slice::<OverpassResponse>(&args.response.body) {
+        Ok(mut response) => {
+            response.elements.retain(OsmElement::is_park);
+            for element in &mut response.elements {
+                element
+                    .tags
+                    .retain(|key, _| MAPPED_TAGS.contains(&key.as_str()));
+            }
+            serde_json::to_vec(&response).unwrap_or_default()
+        }
+        Err(_) => vec![],
+    };
+    canonical_response(&args.response, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_too_large_to_store_are_skipped() {
+        let element = OsmElement {
+            kind: "way".to_string(),
+            id: 1,
+            lat: Some(0.0),
+            lon: Some(0.0),
+            center: None,
+            tags: BTreeMap::from([
+                ("leisure".to_string(), "park".to_string()),
+                ("name".to_string(), "é".repeat(MAX_IMPORTED_NAME_CHARS)),
+                (
+                    "description".to_string(),
+                    "é".repeat(MAX_IMPORTED_DESCRIPTION_CHARS),
+                ),
+            ]),
+        };
+        let report = import_elements(
+            OverpassResponse {
+                elements: vec![element],
+            },
+            None,
+            None,
+            ImportStrategy::Skip,
+        )
+        .unwrap_or_else(|error| panic!("{}", error.message()));
+        assert_eq!(report.features_seen, 1);
+        assert_eq!(report.skipped_oversize, 1);
+        assert!(report.created_ids.is_empty());
+    }
+}
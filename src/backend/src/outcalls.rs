@@ -0,0 +1,154 @@
+use crate::cycles::conserving_cycles;
+use crate::Error;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse,
+    TransformContext,
+};
+
+// Nominatim's usage policy requires an identifying user agent
+const USER_AGENT: &str = "green-space-urban-canister/0.1";
+const SUBNET_SIZE: u128 = 13;
+
+// Cycles charged for an outcall on a 13-node subnet; unused cycles are refunded
+fn outcall_cycles(request_bytes: u64, max_response_bytes: u64) -> u128 {
+    (3_000_000 + 60_000 * SUBNET_SIZE) * SUBNET_SIZE
+        + 400 * SUBNET_SIZE * request_bytes as u128
+        + 800 * SUBNET_SIZE * max_response_bytes as u128
+}
+
+fn request_bytes(request: &CanisterHttpRequestArgument) -> u64 {
+    (request.url.len()
+        + request
+            .headers
+            .iter()
+            .map(|h| h.name.len() + h.value.len())
+            .sum::<usize>()
+        + request.body.as_ref().map_or(0, Vec::len)) as u64
+}
+
+async fn send(request: CanisterHttpRequestArgument) -> Result<HttpResponse, Error> {
+    let url = request.url.clone();
+    let cycles = outcall_cycles(
+        request_bytes(&request),
+        request.max_response_bytes.unwrap_or(0),
+    );
+    http_request(request, cycles)
+        .await
+        .map(|(response,)| response)
+        .map_err(|(code, msg)| Error::OutcallFailed {
+            msg: format!("Request to {} failed ({:?}): {}", url, code, msg),
+        })
+}
+
+// Sends a GET request whose response is normalized by the canister query
+// `transform_method`, so that all replicas agree on it, and returns the body.
+// Lookups like these pause while cycles are low
+pub(crate) async fn http_get(
+    url: String,
+    max_response_bytes: u64,
+    transform_method: &str,
+) -> Result<Vec<u8>, Error> {
+    if conserving_cycles() {
+        return Err(Error::OutcallFailed {
+            msg: "Outcalls are paused while the cycle balance is low".to_string(),
+        });
+    }
+    let headers = vec![
+        HttpHeader {
+            name: "User-Agent".to_string(),
+            value: USER_AGENT.to_string(),
+        },
+        HttpHeader {
+            name: "Accept".to_string(),
+            value: "application/json".to_string(),
+        },
+    ];
+    let request = CanisterHttpRequestArgument {
+        url: url.clone(),
+        max_response_bytes: Some(max_response_bytes),
+        method: HttpMethod::GET,
+        headers,
+        body: None,
+        transform: Some(TransformContext::from_name(
+            transform_method.to_string(),
+            vec![],
+        )),
+    };
+
+    let response = send(request).await?;
+    if response.status != 200u64 {
+        return Err(Error::OutcallFailed {
+            msg: format!("{} answered with HTTP status {}", url, response.status),
+        });
+    }
+    Ok(response.body)
+}
+
+// Sends a JSON body with POST and fails unless the response has a 2xx status.
+// Every replica of the subnet sends the request, so receivers should
+// deduplicate on the `idempotency_key`
+pub(crate) async fn http_post_json(
+    url: String,
+    body: Vec<u8>,
+    idempotency_key: String,
+    max_response_bytes: u64,
+    transform_method: &str,
+) -> Result<(), Error> {
+    let headers = vec![
+        HttpHeader {
+            name: "User-Agent".to_string(),
+            value: USER_AGENT.to_string(),
+        },
+        HttpHeader {
+            name: "Content-Type".to_string(),
+            value: "application/json".to_string(),
+        },
+        HttpHeader {
+            name: "Idempotency-Key".to_string(),
+            value: idempotency_key,
+        },
+    ];
+    let request = CanisterHttpRequestArgument {
+        url: url.clone(),
+        max_response_bytes: Some(max_response_bytes),
+        method: HttpMethod::POST,
+        headers,
+        body: Some(body),
+        transform: Some(TransformContext::from_name(
+            transform_method.to_string(),
+            vec![],
+        )),
+    };
+
+    let response = send(request).await?;
+    if response.status < 200u64 || response.status >= 300u64 {
+        return Err(Error::OutcallFailed {
+            msg: format!("{} answered with HTTP status {}", url, response.status),
+        });
+    }
+    Ok(())
+}
+
+// Drops everything from a response except status and the given body; replicas
+// see different headers (dates, request ids), which would prevent consensus
+pub(crate) fn canonical_response(response: &HttpResponse, body: Vec<u8>) -> HttpResponse {
+    HttpResponse {
+        status: response.status.clone(),
+        headers: vec![],
+        body,
+    }
+}
+
+// Percent-encodes a string for use as a URL query parameter value
+pub(crate) fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
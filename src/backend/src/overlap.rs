@@ -0,0 +1,72 @@
+use crate::cities::{ensure_city_admin, in_city};
+use crate::paging::{decode_token, keys_after_token, page_by_size, Page};
+use crate::spatial_index::candidate_ids;
+use crate::{
+    _get_green_space, _get_green_space_boundary, ensure_green_space_exists, Error,
+    GREEN_SPACE_BOUNDARIES,
+};
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BoundaryOverlap {
+    first_id: u64,
+    second_id: u64,
+}
+
+// Function to list the spaces whose boundaries overlap the boundary of the given space
+#[ic_cdk::query]
+fn check_boundary_overlap(id: u64, next_token: Option<String>) -> Result<Page<u64>, Error> {
+    ensure_green_space_exists(id)?;
+    let boundary = _get_green_space_boundary(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("The green space with id={} has no boundary", id),
+    })?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(page_by_size(
+        candidate_ids(&boundary.bounds())
+            .range(range)
+            .filter(|other_id| {
+                **other_id != id
+                    && _get_green_space_boundary(other_id)
+                        .is_some_and(|other| boundary.overlaps(&other))
+            })
+            .map(|other_id| (*other_id, *other_id)),
+    ))
+}
+
+// Function to list every pair of spaces whose boundaries overlap, optionally
+// only pairs involving a space of one city; candidates come from the spatial
+// index, so only nearby boundaries are compared
+#[ic_cdk::query]
+fn get_boundary_overlap_report(
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<BoundaryOverlap>, Error> {
+    ensure_city_admin(city_id)?;
+    // Pairs come in (first_id, second_id) order; the token holds the last one
+    let after = decode_token::<(u64, u64)>(&next_token)?;
+    let start = after.map_or(0, |(first_id, _)| first_id);
+    let boundaries: Vec<_> = GREEN_SPACE_BOUNDARIES.with(|b| b.borrow().range(start..).collect());
+    let in_scope = |id: &u64| _get_green_space(id).is_some_and(|s| in_city(city_id, s.city_id));
+    let overlaps = boundaries.into_iter().flat_map(|(first_id, first)| {
+        candidate_ids(&first.bounds())
+            .into_iter()
+            .filter(|second_id| {
+                *second_id > first_id && (in_scope(&first_id) || in_scope(second_id))
+            })
+            .filter(|second_id| {
+                _get_green_space_boundary(second_id).is_some_and(|second| first.overlaps(&second))
+            })
+            .map(|second_id| {
+                (
+                    (first_id, second_id),
+                    BoundaryOverlap {
+                        first_id,
+                        second_id,
+                    },
+                )
+            })
+            .collect::<Vec<_>>()
+    });
+    Ok(page_by_size(overlaps.filter(|(pair, _)| {
+        after.is_none_or(|after| *pair > after)
+    })))
+}
@@ -0,0 +1,148 @@
+use crate::Error;
+use candid::CandidType;
+use serde::de::DeserializeOwned;
+use std::ops::Bound;
+
+// Replies are capped at 2MiB; pages stop well short of it to leave room for
+// the envelope
+const MAX_PAGE_BYTES: usize = 1_500_000;
+
+const MAX_TOKEN_LEN: usize = 1024;
+
+// A page of a list endpoint; pass next_token back to get the rest
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Page<T> {
+    pub(crate) items: Vec<T>,
+    // None once the list is complete
+    pub(crate) next_token: Option<String>,
+}
+
+fn invalid_token() -> Error {
+    Error::InvalidInput {
+        msg: "Invalid continuation token".to_string(),
+    }
+}
+
+// Tokens hold the last key of the previous page, candid-encoded as hex, so a
+// list resumes after that key however many records were added or removed
+pub(crate) fn encode_token<K: CandidType>(key: &K) -> String {
+    candid::encode_one(key)
+        .expect("Cannot encode a page key")
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub(crate) fn decode_token<K: CandidType + DeserializeOwned>(
+    token: &Option<String>,
+) -> Result<Option<K>, Error> {
+    let Some(token) = token else {
+        return Ok(None);
+    };
+    if token.len() > MAX_TOKEN_LEN || token.len() % 2 != 0 || !token.is_ascii() {
+        return Err(invalid_token());
+    }
+    let bytes = (0..token.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&token[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| invalid_token())?;
+    candid::decode_one(&bytes)
+        .map(Some)
+        .map_err(|_| invalid_token())
+}
+
+// The keys left to list, in ascending order, after the page that returned the token
+pub(crate) fn keys_after_token<K: CandidType + DeserializeOwned>(
+    token: &Option<String>,
+) -> Result<(Bound<K>, Bound<K>), Error> {
+    Ok(match decode_token(token)? {
+        Some(key) => (Bound::Excluded(key), Bound::Unbounded),
+        None => (Bound::Unbounded, Bound::Unbounded),
+    })
+}
+
+type GroupRange = (Bound<(u64, u64)>, Bound<(u64, u64)>);
+
+// The keys of one group, e.g. the tests of one feature, left to list in
+// ascending order; the token holds the second part of the last key
+pub(crate) fn group_keys_after_token(
+    group: u64,
+    token: &Option<String>,
+) -> Result<GroupRange, Error> {
+    let start = match decode_token::<u64>(token)? {
+        Some(last) => Bound::Excluded((group, last)),
+        None => Bound::Included((group, 0)),
+    };
+    Ok((start, Bound::Included((group, u64::MAX))))
+}
+
+// The keys left to list, in descending order, after the page that returned the token
+pub(crate) fn keys_before_token<K: CandidType + DeserializeOwned>(
+    token: &Option<String>,
+) -> Result<(Bound<K>, Bound<K>), Error> {
+    Ok(match decode_token(token)? {
+        Some(key) => (Bound::Unbounded, Bound::Excluded(key)),
+        None => (Bound::Unbounded, Bound::Unbounded),
+    })
+}
+
+// Collects items in iteration order until the encoded page would outgrow the
+// reply limit; always returns at least one item when there is any
+pub(crate) fn page_by_size<K: CandidType, T: CandidType>(
+    items: impl Iterator<Item = (K, T)>,
+) -> Page<T> {
+    let mut page = Page {
+        items: vec![],
+        next_token: None,
+    };
+    let mut size = 0;
+    let mut last_key = None;
+    for (key, item) in items {
+        let item_size = candid::encode_one(&item).map_or(0, |bytes| bytes.len());
+        if !page.items.is_empty() && size + item_size > MAX_PAGE_BYTES {
+            page.next_token = last_key.as_ref().map(encode_token);
+            break;
+        }
+        size += item_size;
+        last_key = Some(key);
+        page.items.push(item);
+    }
+    page
+}
+
+// Takes at most limit items; the token resumes after the last one when more remain
+pub(crate) fn page_by_count<K: CandidType, T>(
+    items: impl Iterator<Item = (K, T)>,
+    limit: usize,
+) -> Page<T> {
+    let mut items = items.peekable();
+    let mut page = Page {
+        items: vec![],
+        next_token: None,
+    };
+    let mut last_key = None;
+    while page.items.len() < limit {
+        let Some((key, item)) = items.next() else {
+            break;
+        };
+        last_key = Some(key);
+        page.items.push(item);
+    }
+    if items.peek().is_some() {
+        page.next_token = last_key.as_ref().map(encode_token);
+    }
+    page
+}
+
+// Pages a list computed in full and sorted by key, resuming after the key in
+// the token
+pub(crate) fn page_sorted<K: CandidType + DeserializeOwned + Ord, T: CandidType>(
+    items: Vec<(K, T)>,
+    next_token: &Option<String>,
+) -> Result<Page<T>, Error> {
+    let after = decode_token::<K>(next_token)?;
+    Ok(page_by_size(items.into_iter().filter(|(key, _)| {
+        after.as_ref().is_none_or(|after| key > after)
+    })))
+}
@@ -0,0 +1,399 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::maintenance::ensure_writable;
+use crate::paging::{group_keys_after_token, keys_after_token, page_by_size, page_sorted, Page};
+use crate::tickets::{
+    open_ticket, resolve_tickets_matching, tickets_matching, TicketSource, TicketStatus,
+};
+use crate::{
+    ensure_green_space_exists, ensure_space_admin, get_memory, next_id, Error, Memory,
+    NANOS_PER_DAY,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const PLAYGROUND_EQUIPMENT_MEMORY_ID: u8 = 5;
+const EQUIPMENT_INSPECTIONS_MEMORY_ID: u8 = 6;
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_DEFECTS: usize = 20;
+const MAX_DEFECT_LEN: usize = 200;
+const MAX_NOTES_LEN: usize = 300;
+const MAX_INSPECTION_INTERVAL_DAYS: u32 = 3650;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum InspectionOutcome {
+    Pass,
+    PassWithDefects,
+    Fail,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PlaygroundEquipment {
+    id: u64,
+    pub(crate) space_id: u64,
+    name: String,
+    equipment_type: String,
+    // Mandated maximum number of days between two inspections
+    inspection_interval_days: u32,
+    last_inspected_at: Option<u64>,
+    last_outcome: Option<InspectionOutcome>,
+    created_at: u64,
+}
+
+impl_storable!(PlaygroundEquipment, 512);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct EquipmentInspection {
+    id: u64,
+    equipment_id: u64,
+    inspected_at: u64,
+    inspector: String,
+    outcome: InspectionOutcome,
+    defects: Vec<String>,
+    notes: String,
+}
+
+impl_storable!(EquipmentInspection, 8192);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct PlaygroundEquipmentPayload {
+    space_id: u64,
+    name: String,
+    equipment_type: String,
+    inspection_interval_days: u32,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct EquipmentInspectionPayload {
+    inspector: String,
+    outcome: InspectionOutcome,
+    defects: Vec<String>,
+    notes: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct OverdueInspection {
+    equipment: PlaygroundEquipment,
+    due_at: u64,
+    days_overdue: u64,
+}
+
+thread_local! {
+    pub(crate) static PLAYGROUND_EQUIPMENT: RefCell<StableBTreeMap<u64, PlaygroundEquipment, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(PLAYGROUND_EQUIPMENT_MEMORY_ID)));
+
+    // Inspections keyed by (equipment id, inspection id)
+    pub(crate) static EQUIPMENT_INSPECTIONS: RefCell<StableBTreeMap<(u64, u64), EquipmentInspection, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(EQUIPMENT_INSPECTIONS_MEMORY_ID)));
+}
+
+// How the equipment of a space fared at its last inspections
+pub(crate) struct EquipmentCondition {
+    pub(crate) total: u64,
+    pub(crate) failed: u64,
+    pub(crate) with_defects: u64,
+    pub(crate) overdue: u64,
+}
+
+impl PlaygroundEquipment {
+    // Equipment that was never inspected is due one interval after registration
+    fn next_inspection_due_at(&self) -> u64 {
+        let interval = (self.inspection_interval_days as u64).saturating_mul(NANOS_PER_DAY);
+        self.last_inspected_at
+            .unwrap_or(self.created_at)
+            .saturating_add(interval)
+    }
+}
+
+pub(crate) fn equipment_condition(space_id: u64) -> EquipmentCondition {
+    let now = time();
+    let mut condition = EquipmentCondition {
+        total: 0,
+        failed: 0,
+        with_defects: 0,
+        overdue: 0,
+    };
+    PLAYGROUND_EQUIPMENT.with(|s| {
+        for (_, equipment) in s.borrow().iter() {
+            if equipment.space_id != space_id {
+                continue;
+            }
+            condition.total += 1;
+            match equipment.last_outcome {
+                Some(InspectionOutcome::Fail) => condition.failed += 1,
+                Some(InspectionOutcome::PassWithDefects) => condition.with_defects += 1,
+                _ => {}
+            }
+            if equipment.next_inspection_due_at() < now {
+                condition.overdue += 1;
+            }
+        }
+    });
+    condition
+}
+
+fn equipment_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("Playground equipment with id={} not found", id),
+    }
+}
+
+fn _get_playground_equipment(id: &u64) -> Option<PlaygroundEquipment> {
+    PLAYGROUND_EQUIPMENT.with(|s| s.borrow().get(id))
+}
+
+fn do_insert_playground_equipment(equipment: &PlaygroundEquipment) {
+    PLAYGROUND_EQUIPMENT.with(|s| s.borrow_mut().insert(equipment.id, equipment.clone()));
+    log_upsert("playground_equipment", &equipment.id, equipment);
+}
+
+fn validate_equipment(payload: &PlaygroundEquipmentPayload) -> Result<(), Error> {
+    if payload.name.trim().is_empty() || payload.name.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Equipment name must be 1-{} characters", MAX_NAME_LEN),
+        });
+    }
+    if payload.equipment_type.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Equipment type must be at most {} characters", MAX_NAME_LEN),
+        });
+    }
+    if payload.inspection_interval_days == 0
+        || payload.inspection_interval_days > MAX_INSPECTION_INTERVAL_DAYS
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Inspection interval must be 1-{} days",
+                MAX_INSPECTION_INTERVAL_DAYS
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn validate_inspection(payload: &EquipmentInspectionPayload) -> Result<(), Error> {
+    if payload.inspector.trim().is_empty() || payload.inspector.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Inspector name must be 1-{} characters", MAX_NAME_LEN),
+        });
+    }
+    if payload.defects.len() > MAX_DEFECTS
+        || payload.defects.iter().any(|d| d.len() > MAX_DEFECT_LEN)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "At most {} defects of up to {} characters each can be recorded",
+                MAX_DEFECTS, MAX_DEFECT_LEN
+            ),
+        });
+    }
+    if payload.outcome == InspectionOutcome::PassWithDefects && payload.defects.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "A pass with defects must list at least one defect".to_string(),
+        });
+    }
+    if payload.notes.len() > MAX_NOTES_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Inspection notes must be at most {} characters",
+                MAX_NOTES_LEN
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn overdue_ticket_source(equipment_id: u64) -> TicketSource {
+    TicketSource::InspectionOverdue { equipment_id }
+}
+
+// Opens a maintenance ticket for every piece of equipment past its inspection
+// interval that has no unresolved one yet; returns the number of tickets opened
+pub(crate) fn open_overdue_inspection_tickets() -> u64 {
+    let now = time();
+    let overdue: Vec<_> = PLAYGROUND_EQUIPMENT.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, equipment)| equipment)
+            .filter(|equipment| equipment.next_inspection_due_at() < now)
+            .collect()
+    });
+    let mut opened = 0;
+    for equipment in overdue {
+        let source = overdue_ticket_source(equipment.id);
+        let has_ticket = !tickets_matching(|ticket| {
+            ticket.source == source && ticket.status != TicketStatus::Resolved
+        })
+        .is_empty();
+        if has_ticket {
+            continue;
+        }
+        open_ticket(
+            equipment.space_id,
+            format!("Inspect {}", equipment.name),
+            format!(
+                "Equipment id={} was due for its safety inspection {} day(s) ago",
+                equipment.id,
+                (now - equipment.next_inspection_due_at()) / NANOS_PER_DAY
+            ),
+            source,
+        );
+        opened += 1;
+    }
+    opened
+}
+
+// Function for admins of a green space to register a piece of playground equipment
+#[ic_cdk::update]
+fn add_playground_equipment(
+    payload: PlaygroundEquipmentPayload,
+) -> Result<PlaygroundEquipment, Error> {
+    ensure_writable()?;
+    ensure_space_admin(payload.space_id)?;
+    validate_equipment(&payload)?;
+
+    let equipment = PlaygroundEquipment {
+        id: next_id(PLAYGROUND_EQUIPMENT_MEMORY_ID),
+        space_id: payload.space_id,
+        name: payload.name,
+        equipment_type: payload.equipment_type,
+        inspection_interval_days: payload.inspection_interval_days,
+        last_inspected_at: None,
+        last_outcome: None,
+        created_at: time(),
+    };
+
+    do_insert_playground_equipment(&equipment);
+    Ok(equipment)
+}
+
+#[ic_cdk::query]
+fn get_playground_equipment(id: u64) -> Result<PlaygroundEquipment, Error> {
+    _get_playground_equipment(&id).ok_or_else(|| equipment_not_found(id))
+}
+
+#[ic_cdk::query]
+fn get_playground_equipment_for_space(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<PlaygroundEquipment>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(PLAYGROUND_EQUIPMENT.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, equipment)| equipment.space_id == space_id),
+        )
+    }))
+}
+
+// Function to delete playground equipment together with its inspection history
+#[ic_cdk::update]
+fn delete_playground_equipment(id: u64) -> Result<PlaygroundEquipment, Error> {
+    ensure_writable()?;
+    let equipment = _get_playground_equipment(&id).ok_or_else(|| equipment_not_found(id))?;
+    ensure_space_admin(equipment.space_id)?;
+    PLAYGROUND_EQUIPMENT.with(|s| s.borrow_mut().remove(&id));
+    log_delete("playground_equipment", &id);
+
+    EQUIPMENT_INSPECTIONS.with(|s| {
+        let mut inspections = s.borrow_mut();
+        let keys: Vec<_> = inspections
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            inspections.remove(&key);
+            log_delete("equipment_inspection", &key);
+        }
+    });
+    Ok(equipment)
+}
+
+// Function to record a safety inspection of playground equipment
+#[ic_cdk::update]
+fn record_equipment_inspection(
+    equipment_id: u64,
+    payload: EquipmentInspectionPayload,
+) -> Result<EquipmentInspection, Error> {
+    ensure_writable()?;
+    let mut equipment = _get_playground_equipment(&equipment_id)
+        .ok_or_else(|| equipment_not_found(equipment_id))?;
+    ensure_space_admin(equipment.space_id)?;
+    validate_inspection(&payload)?;
+
+    let inspection = EquipmentInspection {
+        id: next_id(EQUIPMENT_INSPECTIONS_MEMORY_ID),
+        equipment_id,
+        inspected_at: time(),
+        inspector: payload.inspector,
+        outcome: payload.outcome,
+        defects: payload.defects,
+        notes: payload.notes,
+    };
+
+    EQUIPMENT_INSPECTIONS.with(|s| {
+        s.borrow_mut()
+            .insert((equipment_id, inspection.id), inspection.clone())
+    });
+    log_upsert(
+        "equipment_inspection",
+        &(equipment_id, inspection.id),
+        &inspection,
+    );
+    equipment.last_inspected_at = Some(inspection.inspected_at);
+    equipment.last_outcome = Some(inspection.outcome);
+    do_insert_playground_equipment(&equipment);
+    // The inspection is the work the overdue ticket asked for
+    resolve_tickets_matching(|ticket| ticket.source == overdue_ticket_source(equipment_id));
+    Ok(inspection)
+}
+
+// Function to get the inspection history of playground equipment, oldest first
+#[ic_cdk::query]
+fn get_equipment_inspections(
+    equipment_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<EquipmentInspection>, Error> {
+    if _get_playground_equipment(&equipment_id).is_none() {
+        return Err(equipment_not_found(equipment_id));
+    }
+    let range = group_keys_after_token(equipment_id, &next_token)?;
+    Ok(EQUIPMENT_INSPECTIONS.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .map(|((_, id), inspection)| (id, inspection)),
+        )
+    }))
+}
+
+// Function to list equipment past its mandated inspection date, most overdue first
+#[ic_cdk::query]
+fn get_overdue_inspections(next_token: Option<String>) -> Result<Page<OverdueInspection>, Error> {
+    let now = time();
+    let mut result: Vec<_> = PLAYGROUND_EQUIPMENT.with(|s| {
+        s.borrow()
+            .iter()
+            .filter_map(|(id, equipment)| {
+                let due_at = equipment.next_inspection_due_at();
+                if due_at < now {
+                    Some((
+                        (due_at, id),
+                        OverdueInspection {
+                            equipment,
+                            due_at,
+                            days_overdue: (now - due_at) / NANOS_PER_DAY,
+                        },
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    });
+    result.sort_by_key(|(key, _)| *key);
+    page_sorted(result, &next_token)
+}
@@ -0,0 +1,79 @@
+use crate::cities::in_city;
+use crate::name_index::normalize_name;
+use crate::sync::MODIFIED_AT;
+use crate::{Error, GreenSpaceStatus, GREEN_SPACE_STORAGE, NANOS_PER_DAY};
+use ic_cdk::api::time;
+use std::collections::BTreeMap;
+
+const DEFAULT_MIN_DESCRIPTION_LEN: u32 = 50;
+const DEFAULT_STALE_AFTER_YEARS: u32 = 3;
+const MAX_STALE_AFTER_YEARS: u32 = 100;
+// Each list is cut off at this many spaces, so the report stays small
+const MAX_FLAGGED: usize = 1000;
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct DataQualityReport {
+    spaces_checked: u64,
+    missing_coordinates: Vec<u64>,
+    short_descriptions: Vec<u64>,
+    // Not written for longer than the given number of years
+    stale: Vec<u64>,
+    // Spaces of one city whose names only differ in case and whitespace
+    duplicate_names: Vec<Vec<u64>>,
+}
+
+fn push_flagged<T>(list: &mut Vec<T>, item: T) {
+    if list.len() < MAX_FLAGGED {
+        list.push(item);
+    }
+}
+
+// Function to flag the spaces, optionally of one city, that need curation;
+// archived spaces are left out
+#[ic_cdk::query]
+fn get_data_quality_report(
+    city_id: Option<u64>,
+    min_description_len: Option<u32>,
+    stale_after_years: Option<u32>,
+) -> Result<DataQualityReport, Error> {
+    let min_description_len = min_description_len.unwrap_or(DEFAULT_MIN_DESCRIPTION_LEN) as usize;
+    let stale_after_years = stale_after_years.unwrap_or(DEFAULT_STALE_AFTER_YEARS);
+    if stale_after_years == 0 || stale_after_years > MAX_STALE_AFTER_YEARS {
+        return Err(Error::InvalidInput {
+            msg: format!("Records go stale after 1-{} years", MAX_STALE_AFTER_YEARS),
+        });
+    }
+    let stale_before = time().saturating_sub(u64::from(stale_after_years) * 365 * NANOS_PER_DAY);
+
+    let mut report = DataQualityReport::default();
+    let mut names: BTreeMap<(Option<u64>, String), Vec<u64>> = BTreeMap::new();
+    GREEN_SPACE_STORAGE.with(|s| {
+        for (id, space) in s.borrow().iter() {
+            if !in_city(city_id, space.city_id) || space.status == Some(GreenSpaceStatus::Archived)
+            {
+                continue;
+            }
+            report.spaces_checked += 1;
+            if space.coordinates.is_none() {
+                push_flagged(&mut report.missing_coordinates, id);
+            }
+            if space.description.trim().chars().count() < min_description_len {
+                push_flagged(&mut report.short_descriptions, id);
+            }
+            if MODIFIED_AT
+                .with(|m| m.borrow().get(&id))
+                .is_some_and(|at| at < stale_before)
+            {
+                push_flagged(&mut report.stale, id);
+            }
+            names
+                .entry((space.city_id, normalize_name(&space.name)))
+                .or_default()
+                .push(id);
+        }
+    });
+    for ids in names.into_values().filter(|ids| ids.len() > 1) {
+        push_flagged(&mut report.duplicate_names, ids);
+    }
+    Ok(report)
+}
@@ -0,0 +1,243 @@
+use crate::cities::in_city;
+use crate::geo::{BoundingBox, Coordinates};
+use crate::paging::{decode_token, page_by_count};
+use crate::spatial_index::spaces_in_bbox;
+use crate::{
+    _get_green_space_boundary, Error, GreenSpace, GreenSpaceCategory, GREEN_SPACE_STORAGE,
+};
+
+const MAX_QUERY_LIMIT: u32 = 500;
+const MAX_TEXT_FILTER_LEN: usize = 200;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum GreenSpaceField {
+    Name,
+    Location,
+    Description,
+    Coordinates,
+    ExternalRef,
+    DistrictId,
+    CityId,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct QueryFilter {
+    pub(crate) city_id: Option<u64>,
+    pub(crate) district_id: Option<u64>,
+    // Case-insensitive substring of the name
+    pub(crate) name_contains: Option<String>,
+    pub(crate) bbox: Option<BoundingBox>,
+    pub(crate) has_coordinates: Option<bool>,
+    pub(crate) category: Option<GreenSpaceCategory>,
+    // A metadata key and the value the space has under it, ignoring case
+    pub(crate) metadata: Option<(String, String)>,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum SortField {
+    Id,
+    Name,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct QuerySort {
+    field: SortField,
+    descending: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct QueryRequest {
+    // Fields to return besides the id; empty returns only ids
+    fields: Vec<GreenSpaceField>,
+    pub(crate) filter: Option<QueryFilter>,
+    // Defaults to ascending ids
+    sort: Option<QuerySort>,
+    // The next_token of the previous page
+    page_token: Option<String>,
+    limit: u32,
+}
+
+// A green space with only the requested fields set
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct PartialGreenSpace {
+    id: u64,
+    name: Option<String>,
+    location: Option<String>,
+    description: Option<String>,
+    coordinates: Option<Coordinates>,
+    external_ref: Option<String>,
+    district_id: Option<u64>,
+    city_id: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct QueryResponse {
+    items: Vec<PartialGreenSpace>,
+    // Number of spaces matching the filter, across all pages
+    total: u64,
+    next_token: Option<String>,
+}
+
+// Position of a space in the requested order; the name is left empty when
+// sorting by id
+type SortKey = (String, u64);
+
+fn sort_key(space: &GreenSpace, field: SortField) -> SortKey {
+    match field {
+        SortField::Id => (String::new(), space.id),
+        SortField::Name => (space.name.to_lowercase(), space.id),
+    }
+}
+
+fn validate_query(request: &QueryRequest) -> Result<(), Error> {
+    if request.limit == 0 || request.limit > MAX_QUERY_LIMIT {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_QUERY_LIMIT),
+        });
+    }
+    if let Some(filter) = &request.filter {
+        validate_filter(filter)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_filter(filter: &QueryFilter) -> Result<(), Error> {
+    if let Some(bbox) = &filter.bbox {
+        bbox.validate()?;
+    }
+    let texts = [
+        filter.name_contains.as_ref(),
+        filter.metadata.as_ref().map(|(key, _)| key),
+        filter.metadata.as_ref().map(|(_, value)| value),
+    ];
+    if texts
+        .into_iter()
+        .flatten()
+        .any(|text| text.len() > MAX_TEXT_FILTER_LEN)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Text filters must be at most {} characters",
+                MAX_TEXT_FILTER_LEN
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn matches(filter: &QueryFilter, space: &GreenSpace, name_contains: &Option<String>) -> bool {
+    in_city(filter.city_id, space.city_id)
+        && filter
+            .district_id
+            .is_none_or(|district_id| space.district_id == Some(district_id))
+        && name_contains
+            .as_ref()
+            .is_none_or(|text| space.name.to_lowercase().contains(text))
+        && filter
+            .has_coordinates
+            .is_none_or(|has| space.coordinates.is_some() == has)
+        && filter
+            .category
+            .is_none_or(|category| space.category == Some(category))
+        && filter.metadata.as_ref().is_none_or(|(key, value)| {
+            space
+                .metadata
+                .iter()
+                .flatten()
+                .any(|(k, v)| k == key && v.eq_ignore_ascii_case(value))
+        })
+}
+
+// Whether a single space passes a filter, bounding box included
+pub(crate) fn filter_matches(filter: &QueryFilter, space: &GreenSpace) -> bool {
+    let name_contains = filter
+        .name_contains
+        .as_ref()
+        .map(|text| text.to_lowercase());
+    let in_bbox = filter.bbox.is_none_or(|bbox| {
+        space.coordinates.is_some_and(|c| bbox.contains(&c))
+            || _get_green_space_boundary(&space.id).is_some_and(|b| b.bounds().intersects(&bbox))
+    });
+    in_bbox && matches(filter, space, &name_contains)
+}
+
+fn project(space: GreenSpace, fields: &[GreenSpaceField]) -> PartialGreenSpace {
+    let wants = |field| fields.contains(&field);
+    PartialGreenSpace {
+        id: space.id,
+        name: wants(GreenSpaceField::Name).then_some(space.name),
+        location: wants(GreenSpaceField::Location).then_some(space.location),
+        description: wants(GreenSpaceField::Description).then_some(space.description),
+        coordinates: space
+            .coordinates
+            .filter(|_| wants(GreenSpaceField::Coordinates)),
+        external_ref: space
+            .external_ref
+            .filter(|_| wants(GreenSpaceField::ExternalRef)),
+        district_id: space
+            .district_id
+            .filter(|_| wants(GreenSpaceField::DistrictId)),
+        city_id: space.city_id.filter(|_| wants(GreenSpaceField::CityId)),
+    }
+}
+
+// Function to fetch spaces with only the fields a view needs, filtered, sorted
+// and paginated in a single call
+#[ic_cdk::query(name = "query")]
+fn query_green_spaces(request: QueryRequest) -> Result<QueryResponse, Error> {
+    run_query(request)
+}
+
+pub(crate) fn run_query(request: QueryRequest) -> Result<QueryResponse, Error> {
+    validate_query(&request)?;
+    let after: Option<SortKey> = decode_token(&request.page_token)?;
+    let filter = request.filter.unwrap_or_default();
+    let name_contains = filter
+        .name_contains
+        .as_ref()
+        .map(|text| text.to_lowercase());
+
+    // A bounding box narrows the candidates through the spatial index
+    let spaces: Vec<GreenSpace> = match &filter.bbox {
+        Some(bbox) => spaces_in_bbox(bbox, filter.city_id)
+            .into_iter()
+            .filter(|space| matches(&filter, space, &name_contains))
+            .collect(),
+        None => GREEN_SPACE_STORAGE.with(|s| {
+            s.borrow()
+                .iter()
+                .map(|(_, space)| space)
+                .filter(|space| matches(&filter, space, &name_contains))
+                .collect()
+        }),
+    };
+    let (field, descending) = request
+        .sort
+        .map_or((SortField::Id, false), |sort| (sort.field, sort.descending));
+    let mut keyed: Vec<(SortKey, GreenSpace)> = spaces
+        .into_iter()
+        .map(|space| (sort_key(&space, field), space))
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    if descending {
+        keyed.reverse();
+    }
+    let total = keyed.len() as u64;
+
+    // Resuming after the last key seen rather than at an offset keeps pages
+    // consistent while spaces are added or removed
+    let remaining = keyed.into_iter().filter(|(key, _)| match &after {
+        Some(after) if descending => key < after,
+        Some(after) => key > after,
+        None => true,
+    });
+    let page = page_by_count(
+        remaining.map(|(key, space)| (key, project(space, &request.fields))),
+        request.limit as usize,
+    );
+    Ok(QueryResponse {
+        items: page.items,
+        total,
+        next_token: page.next_token,
+    })
+}
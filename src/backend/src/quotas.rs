@@ -0,0 +1,276 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::calendar::year_month_of;
+use crate::maintenance::ensure_writable;
+use crate::views::today;
+use crate::{
+    authenticated_caller, ensure_controller, get_memory, principal_key, Error, Memory, PrincipalKey,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const QUOTA_LIMITS_MEMORY_ID: u8 = 81;
+const QUOTA_USAGE_MEMORY_ID: u8 = 82;
+const QUOTA_OVERRIDES_MEMORY_ID: u8 = 83;
+
+// Operations that cost enough cycles to be counted
+#[derive(
+    candid::CandidType,
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Default,
+    Debug,
+)]
+pub(crate) enum QuotaOp {
+    // Imports of OpenStreetMap parks, fetched or uploaded
+    #[default]
+    Import,
+    // Geocoding and reverse geocoding outcalls
+    Geocode,
+    // Batches of writes
+    Batch,
+}
+
+const QUOTA_OPS: [QuotaOp; 3] = [QuotaOp::Import, QuotaOp::Geocode, QuotaOp::Batch];
+
+impl_storable!(QuotaOp, 32);
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct QuotaLimits {
+    daily: u32,
+    monthly: u32,
+}
+
+impl_storable!(QuotaLimits, 32);
+
+impl QuotaOp {
+    fn label(self) -> &'static str {
+        match self {
+            QuotaOp::Import => "import",
+            QuotaOp::Geocode => "geocoding",
+            QuotaOp::Batch => "batch",
+        }
+    }
+
+    fn default_limits(self) -> QuotaLimits {
+        match self {
+            QuotaOp::Import => QuotaLimits {
+                daily: 10,
+                monthly: 100,
+            },
+            QuotaOp::Geocode => QuotaLimits {
+                daily: 100,
+                monthly: 1000,
+            },
+            QuotaOp::Batch => QuotaLimits {
+                daily: 50,
+                monthly: 500,
+            },
+        }
+    }
+}
+
+// Calls counted for the day and the month they were last made in
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default)]
+pub(crate) struct QuotaUsage {
+    day: u64,
+    daily: u32,
+    // Months counted from year 0
+    month: i64,
+    monthly: u32,
+}
+
+impl_storable!(QuotaUsage, 64);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct QuotaStatus {
+    op: QuotaOp,
+    limits: QuotaLimits,
+    // Whether the limits were set for this principal alone
+    overridden: bool,
+    used_today: u32,
+    used_this_month: u32,
+}
+
+thread_local! {
+    // Limits that differ from the defaults, for every principal
+    static QUOTA_LIMITS: RefCell<StableBTreeMap<QuotaOp, QuotaLimits, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(QUOTA_LIMITS_MEMORY_ID)));
+
+    static QUOTA_USAGE: RefCell<StableBTreeMap<(PrincipalKey, QuotaOp), QuotaUsage, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(QUOTA_USAGE_MEMORY_ID)));
+
+    // Limits set by controllers for individual principals
+    static QUOTA_OVERRIDES: RefCell<StableBTreeMap<(PrincipalKey, QuotaOp), QuotaLimits, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(QUOTA_OVERRIDES_MEMORY_ID)));
+}
+
+fn current_month() -> i64 {
+    let (year, month) = year_month_of(time());
+    year * 12 + i64::from(month) - 1
+}
+
+fn limits_for(user: &PrincipalKey, op: QuotaOp) -> (QuotaLimits, bool) {
+    match QUOTA_OVERRIDES.with(|o| o.borrow().get(&(*user, op))) {
+        Some(limits) => (limits, true),
+        None => (
+            QUOTA_LIMITS
+                .with(|l| l.borrow().get(&op))
+                .unwrap_or_else(|| op.default_limits()),
+            false,
+        ),
+    }
+}
+
+// Usage with the counts of elapsed days and months reset
+fn current_usage(user: &PrincipalKey, op: QuotaOp) -> QuotaUsage {
+    let mut usage = QUOTA_USAGE
+        .with(|u| u.borrow().get(&(*user, op)))
+        .unwrap_or_default();
+    let (day, month) = (today(), current_month());
+    if usage.day != day {
+        usage.day = day;
+        usage.daily = 0;
+    }
+    if usage.month != month {
+        usage.month = month;
+        usage.monthly = 0;
+    }
+    usage
+}
+
+// Fails when the caller's daily or monthly quota for an expensive operation
+// is used up
+pub(crate) fn ensure_quota_left(op: QuotaOp) -> Result<(), Error> {
+    let user = principal_key(&ic_cdk::caller());
+    let (limits, _) = limits_for(&user, op);
+    let usage = current_usage(&user, op);
+    if usage.daily >= limits.daily {
+        return Err(Error::Unauthorized {
+            msg: format!(
+                "The daily quota of {} {} calls is used up; it resets at midnight UTC",
+                limits.daily,
+                op.label()
+            ),
+        });
+    }
+    if usage.monthly >= limits.monthly {
+        return Err(Error::Unauthorized {
+            msg: format!(
+                "The monthly quota of {} {} calls is used up",
+                limits.monthly,
+                op.label()
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Counts a call of an expensive operation by the caller, whose quota was
+// checked before
+pub(crate) fn count_quota_use(op: QuotaOp) {
+    let user = principal_key(&ic_cdk::caller());
+    let mut usage = current_usage(&user, op);
+    usage.daily += 1;
+    usage.monthly += 1;
+    QUOTA_USAGE.with(|u| u.borrow_mut().insert((user, op), usage));
+}
+
+// Counts a call of an expensive operation by the caller, or fails when the
+// caller's daily or monthly quota for it is used up
+pub(crate) fn consume_quota(op: QuotaOp) -> Result<(), Error> {
+    ensure_quota_left(op)?;
+    count_quota_use(op);
+    Ok(())
+}
+
+fn quota_status(user: &PrincipalKey) -> Vec<QuotaStatus> {
+    QUOTA_OPS
+        .iter()
+        .map(|&op| {
+            let (limits, overridden) = limits_for(user, op);
+            let usage = current_usage(user, op);
+            QuotaStatus {
+                op,
+                limits,
+                overridden,
+                used_today: usage.daily,
+                used_this_month: usage.monthly,
+            }
+        })
+        .collect()
+}
+
+#[ic_cdk::query]
+fn get_my_quota_status() -> Result<Vec<QuotaStatus>, Error> {
+    let user = authenticated_caller()?;
+    Ok(quota_status(&principal_key(&user)))
+}
+
+#[ic_cdk::query]
+fn get_quota_status(user: Principal) -> Result<Vec<QuotaStatus>, Error> {
+    ensure_controller()?;
+    Ok(quota_status(&principal_key(&user)))
+}
+
+// Function for controllers to change the limits of an operation for everyone
+// without an override
+#[ic_cdk::update]
+fn set_quota_limits(op: QuotaOp, limits: QuotaLimits) -> Result<QuotaLimits, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if limits.daily > limits.monthly {
+        return Err(Error::InvalidInput {
+            msg: "The daily limit cannot exceed the monthly one".to_string(),
+        });
+    }
+    QUOTA_LIMITS.with(|l| l.borrow_mut().insert(op, limits));
+    log_upsert("quota_limits", &format!("{:?}", op), &limits);
+    Ok(limits)
+}
+
+// Function for controllers to give a principal its own limits for an
+// operation, or with None to return it to the shared ones
+#[ic_cdk::update]
+fn set_quota_override(
+    user: Principal,
+    op: QuotaOp,
+    limits: Option<QuotaLimits>,
+) -> Result<Vec<QuotaStatus>, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let key = (principal_key(&user), op);
+    match limits {
+        Some(limits) => {
+            QUOTA_OVERRIDES.with(|o| o.borrow_mut().insert(key, limits));
+            log_upsert("quota_override", &(user, format!("{:?}", op)), &limits);
+        }
+        None => {
+            QUOTA_OVERRIDES.with(|o| o.borrow_mut().remove(&key));
+            log_delete("quota_override", &(user, format!("{:?}", op)));
+        }
+    }
+    Ok(quota_status(&key.0))
+}
+
+// Function for controllers to clear what a principal used so far
+#[ic_cdk::update]
+fn reset_quota_usage(user: Principal) -> Result<Vec<QuotaStatus>, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let user = principal_key(&user);
+    QUOTA_USAGE.with(|u| {
+        let mut usage = u.borrow_mut();
+        for op in QUOTA_OPS {
+            usage.remove(&(user, op));
+        }
+    });
+    Ok(quota_status(&user))
+}
@@ -0,0 +1,131 @@
+use crate::favorites::favorite_ids;
+use crate::spatial_index::spaces_around;
+use crate::suggest::{space_tags, spaces_with_tag};
+use crate::visits::visits_of;
+use crate::{_get_green_space, authenticated_caller, Error, GreenSpace, GreenSpaceStatus};
+use std::collections::{BTreeMap, BTreeSet};
+
+const MAX_RECOMMENDATIONS: u32 = 50;
+// The most recent visits that make up the profile
+const MAX_PROFILE_VISITS: usize = 100;
+// Profile spaces whose surroundings are searched, favorites first
+const MAX_NEARBY_SOURCES: usize = 10;
+const NEARBY_RADIUS_M: f64 = 2_000.0;
+const MAX_NEARBY_PER_SOURCE: usize = 20;
+const MAX_PER_TAG: usize = 50;
+const MAX_CANDIDATES: usize = 1_000;
+// A favorite says more about a user's taste than a visit
+const FAVORITE_WEIGHT: u64 = 2;
+const VISIT_WEIGHT: u64 = 1;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Recommendation {
+    space: GreenSpace,
+    score: u64,
+    // The category and tags it shares with the caller's spaces
+    matched: Vec<String>,
+}
+
+// What a space is compared by: its category and its tags under searchable
+// keys, which is where amenities are recorded
+fn traits(space: &GreenSpace) -> Vec<String> {
+    space
+        .category
+        .map(|category| format!("category: {:?}", category))
+        .into_iter()
+        .chain(space_tags(space).iter().map(|tag| tag.label()))
+        .collect()
+}
+
+fn is_listed(space: &GreenSpace) -> bool {
+    space.status != Some(GreenSpaceStatus::Archived)
+}
+
+// Candidates are the spaces sharing a tag with the profile and those around
+// its first spaces, rather than every space, so a call does bounded work
+fn candidate_ids(profile: &[GreenSpace]) -> BTreeSet<u64> {
+    let mut candidates = BTreeSet::new();
+    for space in profile {
+        for tag in space_tags(space) {
+            candidates.extend(spaces_with_tag(&tag, MAX_PER_TAG));
+            if candidates.len() >= MAX_CANDIDATES {
+                return candidates;
+            }
+        }
+    }
+    for space in profile.iter().take(MAX_NEARBY_SOURCES) {
+        let Some(point) = &space.coordinates else {
+            continue;
+        };
+        candidates.extend(
+            spaces_around(point, NEARBY_RADIUS_M, None)
+                .into_iter()
+                .take(MAX_NEARBY_PER_SOURCE)
+                .map(|nearby| nearby.space.id),
+        );
+        if candidates.len() >= MAX_CANDIDATES {
+            break;
+        }
+    }
+    candidates
+}
+
+// Function to recommend spaces the caller has not visited, by how much their
+// category and tags overlap with those of the caller's favorites and visits;
+// empty for callers with neither
+#[ic_cdk::query]
+fn recommend_for_me(limit: u32) -> Result<Vec<Recommendation>, Error> {
+    let user = authenticated_caller()?;
+    if limit == 0 || limit > MAX_RECOMMENDATIONS {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_RECOMMENDATIONS),
+        });
+    }
+    let favorites = favorite_ids(&user);
+    let mut visits = visits_of(&user);
+    let known: BTreeSet<u64> = favorites
+        .iter()
+        .copied()
+        .chain(visits.iter().map(|(id, _)| *id))
+        .collect();
+    visits.sort_by_key(|(_, visit)| std::cmp::Reverse(visit.last_visited_at));
+    let weighted = favorites.iter().map(|id| (*id, FAVORITE_WEIGHT)).chain(
+        visits
+            .iter()
+            .take(MAX_PROFILE_VISITS)
+            .map(|(id, _)| (*id, VISIT_WEIGHT)),
+    );
+    let mut profile = vec![];
+    let mut weights: BTreeMap<String, u64> = BTreeMap::new();
+    for (id, weight) in weighted {
+        let Some(space) = _get_green_space(&id) else {
+            continue;
+        };
+        for name in traits(&space) {
+            *weights.entry(name).or_insert(0) += weight;
+        }
+        profile.push(space);
+    }
+
+    let mut recommendations: Vec<Recommendation> = candidate_ids(&profile)
+        .into_iter()
+        .filter(|id| !known.contains(id))
+        .filter_map(|id| _get_green_space(&id))
+        .filter(is_listed)
+        .filter_map(|space| {
+            let matched: Vec<String> = traits(&space)
+                .into_iter()
+                .filter(|name| weights.contains_key(name))
+                .collect();
+            let score = matched.iter().map(|name| weights[name]).sum();
+            (score > 0).then_some(Recommendation {
+                space,
+                score,
+                matched,
+            })
+        })
+        .collect();
+    recommendations.sort_by_key(|r| (std::cmp::Reverse(r.score), r.space.id));
+    recommendations.truncate(limit as usize);
+    Ok(recommendations)
+}
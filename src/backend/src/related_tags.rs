@@ -0,0 +1,106 @@
+use crate::name_index::normalize_name;
+use crate::{get_memory, Error, Memory};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const TAG_PAIRS_MEMORY_ID: u8 = 89;
+
+const MAX_RELATED_TAGS: u32 = 50;
+
+// Two tags, as "key: value" with the value normalized, found on the same
+// space; each pair is stored in both orders so either tag finds the other
+#[derive(
+    candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default,
+)]
+pub(crate) struct TagPair {
+    tag: String,
+    related: String,
+}
+
+impl_storable!(TagPair, 1024);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct RelatedTag {
+    tag: String,
+    // Spaces carrying both tags
+    spaces: u64,
+}
+
+thread_local! {
+    // Rebuilt along with the tag index, like it
+    static TAG_PAIRS: RefCell<StableBTreeMap<TagPair, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(TAG_PAIRS_MEMORY_ID)));
+}
+
+fn adjust_pairs(tags: &BTreeSet<String>, added: bool) {
+    TAG_PAIRS.with(|p| {
+        let mut pairs = p.borrow_mut();
+        for tag in tags {
+            for related in tags.iter().filter(|related| *related != tag) {
+                let pair = TagPair {
+                    tag: tag.clone(),
+                    related: related.clone(),
+                };
+                let count = pairs.get(&pair).unwrap_or(0);
+                match (added, count) {
+                    (true, _) => pairs.insert(pair, count + 1),
+                    (false, 0 | 1) => pairs.remove(&pair),
+                    (false, _) => pairs.insert(pair, count - 1),
+                };
+            }
+        }
+    });
+}
+
+// Moves the counts of a space from its previous tags to its current ones
+pub(crate) fn count_tag_pairs(previous: &BTreeSet<String>, current: &BTreeSet<String>) {
+    if previous != current {
+        adjust_pairs(previous, false);
+        adjust_pairs(current, true);
+    }
+}
+
+pub(crate) fn clear_tag_pairs() {
+    TAG_PAIRS.with(|p| {
+        let keys: Vec<TagPair> = p.borrow().iter().map(|(key, _)| key).collect();
+        let mut pairs = p.borrow_mut();
+        for key in keys {
+            pairs.remove(&key);
+        }
+    });
+}
+
+// Function to get the tags most often found on the same spaces as a tag,
+// given as "key: value", to suggest further filters
+#[ic_cdk::query]
+fn get_related_tags(tag: String, limit: u32) -> Result<Vec<RelatedTag>, Error> {
+    if limit == 0 || limit > MAX_RELATED_TAGS {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_RELATED_TAGS),
+        });
+    }
+    let Some((key, value)) = tag.split_once(':') else {
+        return Err(Error::InvalidInput {
+            msg: "Tags are given as \"key: value\"".to_string(),
+        });
+    };
+    let tag = format!("{}: {}", key.trim(), normalize_name(value));
+    let start = TagPair {
+        tag: tag.clone(),
+        related: String::new(),
+    };
+    let mut related: Vec<RelatedTag> = TAG_PAIRS.with(|p| {
+        p.borrow()
+            .range(start..)
+            .take_while(|(pair, _)| pair.tag == tag)
+            .map(|(pair, spaces)| RelatedTag {
+                tag: pair.related,
+                spaces,
+            })
+            .collect()
+    });
+    related.sort_by(|a, b| b.spaces.cmp(&a.spaces).then_with(|| a.tag.cmp(&b.tag)));
+    related.truncate(limit as usize);
+    Ok(related)
+}
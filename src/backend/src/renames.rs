@@ -0,0 +1,107 @@
+use crate::{ensure_green_space_exists, get_memory, Error, GreenSpace, Memory};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const NAME_HISTORY_MEMORY_ID: u8 = 49;
+
+// Older renames are dropped once a space has this many
+const MAX_RENAMES_PER_SPACE: usize = 20;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct NameChange {
+    previous_name: String,
+    new_name: String,
+    changed_at: u64,
+    changed_by: Principal,
+}
+
+impl_storable!(NameChange, 2048);
+
+thread_local! {
+    // Renames keyed by (space id, time of the rename)
+    pub(crate) static NAME_HISTORY: RefCell<StableBTreeMap<(u64, u64), NameChange, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(NAME_HISTORY_MEMORY_ID)));
+}
+
+fn history_of(id: u64) -> Vec<((u64, u64), NameChange)> {
+    NAME_HISTORY.with(|h| h.borrow().range((id, 0)..=(id, u64::MAX)).collect())
+}
+
+// Replaces a user as author of renames by the anonymous principal; returns
+// the number of renames changed
+pub(crate) fn anonymize_renames(user: &Principal) -> u64 {
+    let renames: Vec<((u64, u64), NameChange)> = NAME_HISTORY.with(|h| {
+        h.borrow()
+            .iter()
+            .filter(|(_, change)| change.changed_by == *user)
+            .collect()
+    });
+    NAME_HISTORY.with(|h| {
+        let mut h = h.borrow_mut();
+        for (key, change) in &renames {
+            h.insert(
+                *key,
+                NameChange {
+                    changed_by: Principal::anonymous(),
+                    ..change.clone()
+                },
+            );
+        }
+    });
+    renames.len() as u64
+}
+
+// Records a rename when a stored space is replaced by one with another name
+pub(crate) fn record_rename(previous: Option<&GreenSpace>, space: &GreenSpace) {
+    let Some(previous) = previous.filter(|previous| previous.name != space.name) else {
+        return;
+    };
+    let history = history_of(space.id);
+    let now = time();
+    NAME_HISTORY.with(|h| {
+        let mut h = h.borrow_mut();
+        let excess = (history.len() + 1).saturating_sub(MAX_RENAMES_PER_SPACE);
+        for (key, _) in history.iter().take(excess) {
+            h.remove(key);
+        }
+        h.insert(
+            (space.id, now),
+            NameChange {
+                previous_name: previous.name.clone(),
+                new_name: space.name.clone(),
+                changed_at: now,
+                changed_by: ic_cdk::caller(),
+            },
+        );
+    });
+}
+
+// Drops the rename history of a removed space
+pub(crate) fn remove_name_history(id: u64) {
+    let history = history_of(id);
+    NAME_HISTORY.with(|h| {
+        let mut h = h.borrow_mut();
+        for (key, _) in history {
+            h.remove(&key);
+        }
+    });
+}
+
+// Whether a former name of the space contains the given text
+pub(crate) fn alias_contains(id: u64, text: &str) -> bool {
+    history_of(id)
+        .iter()
+        .any(|(_, change)| change.previous_name.contains(text))
+}
+
+// Function to get the renames of a space, oldest first
+#[ic_cdk::query]
+fn get_green_space_name_history(id: u64) -> Result<Vec<NameChange>, Error> {
+    ensure_green_space_exists(id)?;
+    Ok(history_of(id)
+        .into_iter()
+        .map(|(_, change)| change)
+        .collect())
+}
@@ -0,0 +1,245 @@
+use crate::block_log::log_config;
+use crate::changelog::{changes_after, count_changes_after, ChangeLogEntry, ChangeOp};
+use crate::maintenance::ensure_writable;
+use crate::shards::call_failed;
+use crate::{
+    ensure_controller, get_memory, remove_green_space, restore_green_space, Error, Memory,
+    GREEN_SPACE_STORAGE,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::{Cell, Storable};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+const REPLICATION_CONFIG_MEMORY_ID: u8 = 28;
+
+// Entries pushed per call; upserts are at most ~1.5KB each
+const REPLICATION_BATCH_SIZE: usize = 200;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct ReplicationConfig {
+    // Canister this one pushes its changes to
+    backup_canister: Option<Principal>,
+    // Canister allowed to push changes into this one
+    source_canister: Option<Principal>,
+    last_replicated_seq: Option<u64>,
+    last_attempt_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+impl_storable!(ReplicationConfig, 512);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ReplicationStatus {
+    config: ReplicationConfig,
+    pending_entries: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct ReplicaDigest {
+    green_space_count: u64,
+    // Hex SHA-256 over the encoded spaces in id order
+    sha256: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ReplicaVerification {
+    local: ReplicaDigest,
+    replica: ReplicaDigest,
+    in_sync: bool,
+    pending_entries: u64,
+}
+
+thread_local! {
+    static REPLICATION_CONFIG: RefCell<Cell<ReplicationConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(REPLICATION_CONFIG_MEMORY_ID), ReplicationConfig::default())
+            .expect("Cannot initialize the replication config")
+    );
+}
+
+fn config() -> ReplicationConfig {
+    REPLICATION_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+// First change-log entry not yet pushed to the backup, if there is a backup
+pub(crate) fn first_unreplicated_seq() -> Option<u64> {
+    let config = config();
+    config
+        .backup_canister
+        .map(|_| config.last_replicated_seq.map_or(0, |seq| seq + 1))
+}
+
+fn update_config(f: impl FnOnce(&mut ReplicationConfig)) -> ReplicationConfig {
+    REPLICATION_CONFIG.with(|c| {
+        let mut config = c.borrow().get().clone();
+        f(&mut config);
+        c.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the replication config");
+        config
+    })
+}
+
+fn digest() -> ReplicaDigest {
+    let mut hasher = Sha256::new();
+    let mut green_space_count = 0;
+    GREEN_SPACE_STORAGE.with(|s| {
+        for (_, space) in s.borrow().iter() {
+            hasher.update(space.to_bytes());
+            green_space_count += 1;
+        }
+    });
+    ReplicaDigest {
+        green_space_count,
+        sha256: hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+    }
+}
+
+// Pushes one batch of unreplicated changes to the backup canister and returns
+// the number of entries it accepted
+pub(crate) async fn push_pending_changes() -> Result<u64, Error> {
+    let config = config();
+    let Some(backup) = config.backup_canister else {
+        return Ok(0);
+    };
+    let batch = changes_after(config.last_replicated_seq, REPLICATION_BATCH_SIZE);
+    let Some(last_seq) = batch.last().map(|entry| entry.seq) else {
+        return Ok(0);
+    };
+    let pushed = batch.len() as u64;
+
+    let result: Result<(Result<(), Error>,), _> =
+        ic_cdk::call(backup, "apply_replicated_changes", (batch,)).await;
+    let result = match result {
+        Ok((result,)) => result,
+        Err((code, msg)) => Err(call_failed(backup, "apply_replicated_changes", code, msg)),
+    };
+    update_config(|config| {
+        config.last_attempt_at = Some(time());
+        match &result {
+            // Only advance if the backup was not changed while we were awaiting
+            Ok(()) if config.backup_canister == Some(backup) => {
+                config.last_replicated_seq = Some(last_seq);
+                config.last_error = None;
+            }
+            Ok(()) => {}
+            Err(error) => config.last_error = Some(error.message().to_string()),
+        }
+    });
+    result.map(|()| pushed)
+}
+
+// Function to set the canister changes are replicated to; a new backup
+// receives the whole change log from the start
+#[ic_cdk::update]
+fn configure_replication(backup_canister: Option<Principal>) -> Result<ReplicationConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if backup_canister == Some(ic_cdk::id()) {
+        return Err(Error::InvalidInput {
+            msg: "A canister cannot replicate to itself".to_string(),
+        });
+    }
+    let config = update_config(|config| {
+        if config.backup_canister != backup_canister {
+            config.last_replicated_seq = None;
+            config.last_error = None;
+        }
+        config.backup_canister = backup_canister;
+    });
+    log_config("replication_config", &config);
+    Ok(config)
+}
+
+// Function to set, on a backup, the canister allowed to push changes into it
+#[ic_cdk::update]
+fn set_replication_source(source_canister: Option<Principal>) -> Result<ReplicationConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let config = update_config(|config| config.source_canister = source_canister);
+    log_config("replication_config", &config);
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_replication_status() -> Result<ReplicationStatus, Error> {
+    let config = config();
+    Ok(ReplicationStatus {
+        pending_entries: count_changes_after(config.last_replicated_seq),
+        config,
+    })
+}
+
+// Function to push a batch of pending changes right away instead of waiting for the timer
+#[ic_cdk::update]
+async fn replicate_now() -> Result<u64, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if config().backup_canister.is_none() {
+        return Err(Error::InvalidInput {
+            msg: "No backup canister is configured".to_string(),
+        });
+    }
+    push_pending_changes().await
+}
+
+// Function for the primary to apply its changes on a backup, in log order
+#[ic_cdk::update]
+fn apply_replicated_changes(entries: Vec<ChangeLogEntry>) -> Result<(), Error> {
+    ensure_writable()?;
+    let caller = ic_cdk::caller();
+    if config().source_canister != Some(caller) && ensure_controller().is_err() {
+        return Err(Error::Unauthorized {
+            msg: "Only the configured replication source can push changes".to_string(),
+        });
+    }
+    for entry in entries {
+        match (entry.op, entry.space) {
+            (ChangeOp::Upsert, Some(space)) => restore_green_space(&space),
+            (ChangeOp::Delete, _) => {
+                remove_green_space(entry.space_id);
+            }
+            (ChangeOp::Upsert, None) => {
+                return Err(Error::InvalidInput {
+                    msg: format!("Change {} is an upsert without a record", entry.seq),
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_replica_digest() -> Result<ReplicaDigest, Error> {
+    Ok(digest())
+}
+
+// Function to compare this canister's spaces with those on the backup
+#[ic_cdk::update]
+async fn verify_replica() -> Result<ReplicaVerification, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let config = config();
+    let backup = config.backup_canister.ok_or_else(|| Error::InvalidInput {
+        msg: "No backup canister is configured".to_string(),
+    })?;
+
+    let (result,): (Result<ReplicaDigest, Error>,) = ic_cdk::call(backup, "get_replica_digest", ())
+        .await
+        .map_err(|(code, msg)| call_failed(backup, "get_replica_digest", code, msg))?;
+    let replica = result?;
+    // Taken after the await so both sides reflect the same point in time as
+    // closely as possible
+    let local = digest();
+    Ok(ReplicaVerification {
+        in_sync: local == replica,
+        pending_entries: count_changes_after(self::config().last_replicated_seq),
+        local,
+        replica,
+    })
+}
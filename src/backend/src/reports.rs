@@ -0,0 +1,103 @@
+use crate::calendar::month_range;
+use crate::maintenance::ensure_writable;
+use crate::tickets::MAINTENANCE_TICKETS;
+use crate::{ensure_controller, get_memory, Error, Memory, GREEN_SPACE_STORAGE};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const MONTHLY_REPORTS_MEMORY_ID: u8 = 38;
+
+// What happened across the green spaces in one calendar month (UTC)
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MonthlyReport {
+    year: u32,
+    month: u32,
+    generated_at: u64,
+    // Spaces created in the month that still exist
+    new_space_ids: Vec<u64>,
+    opened_issues: u64,
+    resolved_issues: u64,
+    // Resolved issues, by the space they were reported for
+    resolved_issues_by_space: Vec<(u64, u64)>,
+}
+
+impl_storable!(MonthlyReport, 65_536);
+
+// Caps the stored record; larger months list the first spaces only
+const MAX_REPORTED_SPACES: usize = 2_000;
+
+thread_local! {
+    // Reports keyed by year * 12 + month - 1
+    pub(crate) static MONTHLY_REPORTS: RefCell<StableBTreeMap<u64, MonthlyReport, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(MONTHLY_REPORTS_MEMORY_ID)));
+}
+
+fn report_key(year: u32, month: u32) -> u64 {
+    u64::from(year) * 12 + u64::from(month) - 1
+}
+
+fn report_not_found(year: u32, month: u32) -> Error {
+    Error::NotFound {
+        msg: format!("No report has been generated for {}-{:02}", year, month),
+    }
+}
+
+// Function to assemble and store the report of a month, replacing any earlier
+// report of the same month
+#[ic_cdk::update]
+fn generate_monthly_report(year: u32, month: u32) -> Result<MonthlyReport, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let (start, end) = month_range(year, month)?;
+    let in_month = |at: u64| (start..end).contains(&at);
+
+    let new_space_ids: Vec<u64> = GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, space)| space.created_at.is_some_and(in_month))
+            .map(|(id, _)| id)
+            .take(MAX_REPORTED_SPACES)
+            .collect()
+    });
+    let mut report = MonthlyReport {
+        year,
+        month,
+        generated_at: time(),
+        new_space_ids,
+        opened_issues: 0,
+        resolved_issues: 0,
+        resolved_issues_by_space: vec![],
+    };
+    let mut resolved_by_space = BTreeMap::new();
+    MAINTENANCE_TICKETS.with(|s| {
+        for (_, ticket) in s.borrow().iter() {
+            if in_month(ticket.created_at) {
+                report.opened_issues += 1;
+            }
+            if ticket.resolved_at.is_some_and(in_month) {
+                report.resolved_issues += 1;
+                *resolved_by_space.entry(ticket.space_id).or_insert(0) += 1;
+            }
+        }
+    });
+    report.resolved_issues_by_space = resolved_by_space
+        .into_iter()
+        .take(MAX_REPORTED_SPACES)
+        .collect();
+
+    MONTHLY_REPORTS.with(|s| {
+        s.borrow_mut()
+            .insert(report_key(year, month), report.clone())
+    });
+    Ok(report)
+}
+
+#[ic_cdk::query]
+fn get_monthly_report(year: u32, month: u32) -> Result<MonthlyReport, Error> {
+    month_range(year, month)?;
+    MONTHLY_REPORTS
+        .with(|s| s.borrow().get(&report_key(year, month)))
+        .ok_or_else(|| report_not_found(year, month))
+}
@@ -0,0 +1,109 @@
+use crate::block_log::log_bulk_write;
+use crate::canopy::compute_canopy_stats;
+use crate::certification::{certify_data, rebuild_certified_spaces};
+use crate::fulltext::rebuild_term_index;
+use crate::governance::ensure_not_governed;
+use crate::heat::compute_heat_scores;
+use crate::maintenance::ensure_writable;
+use crate::name_index::ensure_name_index;
+use crate::search_analytics::discard_pending_searches;
+use crate::snapshot::{sha256_hex, snapshot_stores};
+use crate::spatial_index::ensure_spatial_index;
+use crate::stats::rebuild_space_counts;
+use crate::suggest::rebuild_tag_index;
+use crate::trending::clear_trends;
+use crate::ulid::rebuild_ulid_index;
+use crate::views::discard_pending_views;
+use crate::{ensure_controller, Error, GREEN_SPACE_ID_COUNTER};
+use candid::Principal;
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::time;
+use std::cell::RefCell;
+
+const RESET_TOKEN_TTL_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ResetToken {
+    token: String,
+    expires_at: u64,
+}
+
+thread_local! {
+    // The last token issued, with the controller it was issued to
+    static PENDING_RESET: RefCell<Option<(Principal, ResetToken)>> = const { RefCell::new(None) };
+}
+
+// Function for a controller to get the single-use token clear_all_data asks
+// for; it expires after five minutes and replaces any earlier token
+#[ic_cdk::update]
+async fn request_reset() -> Result<ResetToken, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let (random,) = raw_rand()
+        .await
+        .map_err(|(code, msg)| Error::OutcallFailed {
+            msg: format!("Cannot get randomness: {:?} {}", code, msg),
+        })?;
+    let token = ResetToken {
+        token: sha256_hex(&random),
+        expires_at: time() + RESET_TOKEN_TTL_NANOS,
+    };
+    PENDING_RESET.with(|p| *p.borrow_mut() = Some((ic_cdk::caller(), token.clone())));
+    Ok(token)
+}
+
+// Function to wipe every record and counter, for redeploying test and staging
+// canisters; the settings of the canister itself (replication, webhooks,
+// governance, retention, maintenance mode) are kept. It is refused
+// while governance is enabled
+#[ic_cdk::update]
+fn clear_all_data(confirmation_token: String) -> Result<u64, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    ensure_not_governed("Clearing all data")?;
+    let caller = ic_cdk::caller();
+    let now = time();
+    let confirmed = PENDING_RESET.with(|p| {
+        let mut pending = p.borrow_mut();
+        let valid = pending.as_ref().is_some_and(|(issued_to, token)| {
+            *issued_to == caller && token.token == confirmation_token && now < token.expires_at
+        });
+        if valid {
+            *pending = None;
+        }
+        valid
+    });
+    if !confirmed {
+        return Err(Error::Unauthorized {
+            msg: "Invalid or expired reset token, call request_reset first".to_string(),
+        });
+    }
+
+    let mut cleared = 0;
+    for (_, store) in snapshot_stores() {
+        cleared += store.clear();
+    }
+    // Cells keep their value; the only one holding data is the id counter
+    GREEN_SPACE_ID_COUNTER.with(|counter| {
+        counter
+            .borrow_mut()
+            .set(0)
+            .expect("Cannot reset the id counter for green spaces")
+    });
+    discard_pending_views();
+    discard_pending_searches();
+    ensure_spatial_index();
+    ensure_name_index();
+    rebuild_space_counts();
+    rebuild_tag_index();
+    rebuild_term_index();
+    rebuild_ulid_index();
+    rebuild_certified_spaces();
+    certify_data();
+    compute_canopy_stats();
+    compute_heat_scores();
+    clear_trends();
+    // The block log was cleared with the rest; this block starts the new one
+    log_bulk_write("reset", vec![]);
+    Ok(cleared)
+}
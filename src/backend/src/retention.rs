@@ -0,0 +1,186 @@
+use crate::block_log::log_config;
+use crate::changelog::CHANGE_LOG;
+use crate::maintenance::ensure_writable;
+use crate::noise::NOISE_HOURLY;
+use crate::notifications::NOTIFICATIONS;
+use crate::replication::first_unreplicated_seq;
+use crate::search_alerts::first_unalerted_seq;
+use crate::subscribers::first_unacknowledged_seq;
+use crate::webhooks::first_undelivered_seq;
+use crate::{ensure_controller, get_memory, Error, Memory, NANOS_PER_DAY};
+use ic_cdk::api::time;
+use ic_stable_structures::{Cell, Storable};
+use std::cell::RefCell;
+
+const RETENTION_POLICY_MEMORY_ID: u8 = 42;
+
+// Bounds the work of one run per structure; the rest is pruned on the next
+const MAX_PRUNED_PER_RUN: usize = 10_000;
+const MAX_RETENTION_DAYS: u32 = 36_500;
+
+// Days each kind of entry is kept; None keeps it forever
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct RetentionPolicy {
+    // Entries still awaiting replication or a webhook delivery are always kept
+    change_log_days: Option<u32>,
+    notification_days: Option<u32>,
+    noise_days: Option<u32>,
+}
+
+impl_storable!(RetentionPolicy, 256);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct PruneReport {
+    change_log_entries: u64,
+    notifications: u64,
+    noise_aggregates: u64,
+    // Encoded size of the removed keys and values, free for reuse by the
+    // structures they were removed from
+    reclaimed_bytes: u64,
+}
+
+impl PruneReport {
+    pub(crate) fn summary(&self) -> String {
+        format!(
+            "{} change(s), {} notification(s) and {} noise aggregate(s) pruned, {} bytes reclaimed",
+            self.change_log_entries,
+            self.notifications,
+            self.noise_aggregates,
+            self.reclaimed_bytes
+        )
+    }
+}
+
+thread_local! {
+    static RETENTION_POLICY: RefCell<Cell<RetentionPolicy, Memory>> = RefCell::new(
+        Cell::init(get_memory(RETENTION_POLICY_MEMORY_ID), RetentionPolicy::default())
+            .expect("Cannot initialize the retention policy")
+    );
+}
+
+fn policy() -> RetentionPolicy {
+    RETENTION_POLICY.with(|p| p.borrow().get().clone())
+}
+
+fn entry_size<K: Storable, V: Storable>(key: &K, value: &V) -> u64 {
+    (key.to_bytes().len() + value.to_bytes().len()) as u64
+}
+
+fn cutoff(now: u64, days: u32) -> u64 {
+    now.saturating_sub(u64::from(days) * NANOS_PER_DAY)
+}
+
+fn prune_change_log(cutoff: u64, report: &mut PruneReport) {
+    let needed_from = [
+        first_unreplicated_seq(),
+        first_undelivered_seq(),
+        first_unacknowledged_seq(),
+        first_unalerted_seq(),
+    ]
+    .into_iter()
+    .flatten()
+    .min()
+    .unwrap_or(u64::MAX);
+    CHANGE_LOG.with(|log| {
+        let expired: Vec<_> = log
+            .borrow()
+            .range(..needed_from)
+            .take_while(|(_, entry)| entry.recorded_at < cutoff)
+            .take(MAX_PRUNED_PER_RUN)
+            .collect();
+        let mut log = log.borrow_mut();
+        for (seq, entry) in expired {
+            report.reclaimed_bytes += entry_size(&seq, &entry);
+            report.change_log_entries += 1;
+            log.remove(&seq);
+        }
+    });
+}
+
+fn prune_notifications(cutoff: u64, report: &mut PruneReport) {
+    NOTIFICATIONS.with(|s| {
+        let expired: Vec<_> = s
+            .borrow()
+            .iter()
+            .filter(|(_, notification)| notification.created_at < cutoff)
+            .take(MAX_PRUNED_PER_RUN)
+            .collect();
+        let mut notifications = s.borrow_mut();
+        for (key, notification) in expired {
+            report.reclaimed_bytes += entry_size(&key, &notification);
+            report.notifications += 1;
+            notifications.remove(&key);
+        }
+    });
+}
+
+fn prune_noise(cutoff: u64, report: &mut PruneReport) {
+    NOISE_HOURLY.with(|s| {
+        let expired: Vec<_> = s
+            .borrow()
+            .iter()
+            .filter(|((_, hour_start), _)| *hour_start < cutoff)
+            .take(MAX_PRUNED_PER_RUN)
+            .collect();
+        let mut aggregates = s.borrow_mut();
+        for (key, aggregate) in expired {
+            report.reclaimed_bytes += entry_size(&key, &aggregate);
+            report.noise_aggregates += 1;
+            aggregates.remove(&key);
+        }
+    });
+}
+
+// Removes the entries older than the retention policy allows
+pub(crate) fn prune_expired_entries() -> PruneReport {
+    let policy = policy();
+    let now = time();
+    let mut report = PruneReport::default();
+    if let Some(days) = policy.change_log_days {
+        prune_change_log(cutoff(now, days), &mut report);
+    }
+    if let Some(days) = policy.notification_days {
+        prune_notifications(cutoff(now, days), &mut report);
+    }
+    if let Some(days) = policy.noise_days {
+        prune_noise(cutoff(now, days), &mut report);
+    }
+    report
+}
+
+// Function to set how long change-log entries, notifications and noise
+// aggregates are kept; the daily retention job prunes the older ones
+#[ic_cdk::update]
+fn configure_retention_policy(policy: RetentionPolicy) -> Result<RetentionPolicy, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let windows = [
+        policy.change_log_days,
+        policy.notification_days,
+        policy.noise_days,
+    ];
+    if windows
+        .into_iter()
+        .flatten()
+        .any(|days| days == 0 || days > MAX_RETENTION_DAYS)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Retention windows must be between 1 and {} days",
+                MAX_RETENTION_DAYS
+            ),
+        });
+    }
+    RETENTION_POLICY.with(|p| {
+        p.borrow_mut()
+            .set(policy.clone())
+            .expect("Cannot store the retention policy")
+    });
+    log_config("retention_policy", &policy);
+    Ok(policy)
+}
+
+#[ic_cdk::query]
+fn get_retention_policy() -> Result<RetentionPolicy, Error> {
+    Ok(policy())
+}
@@ -0,0 +1,215 @@
+use crate::bans::is_shadow_banned;
+use crate::block_log::{log_delete, log_upsert};
+use crate::content_filter::{ensure_no_rejected_terms, held_term_signals};
+use crate::maintenance::ensure_writable;
+use crate::moderation::{track_moderation, ContentRef, ModerationDecision, ModerationStatus};
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::spam::screen_submission;
+use crate::terms::ensure_terms_accepted;
+use crate::trending::{record_activity, Activity};
+use crate::{
+    authenticated_caller, ensure_green_space_exists, ensure_space_admin, get_memory, next_id,
+    Error, Memory,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const REVIEWS_MEMORY_ID: u8 = 72;
+
+const MAX_TEXT_LEN: usize = 2000;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Review {
+    pub(crate) id: u64,
+    pub(crate) space_id: u64,
+    pub(crate) author: Principal,
+    // From 1 to 5
+    rating: u8,
+    text: String,
+    // Only approved reviews are shown to the public
+    pub(crate) status: ModerationStatus,
+    pub(crate) created_at: u64,
+    moderation: Option<ModerationDecision>,
+    // Why the review was held for a moderator automatically, as likely spam or
+    // for a held term
+    spam_signals: Option<Vec<String>>,
+}
+
+impl_storable!(Review, 4096);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ReviewPayload {
+    space_id: u64,
+    rating: u8,
+    text: String,
+}
+
+thread_local! {
+    pub(crate) static REVIEWS: RefCell<StableBTreeMap<u64, Review, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(REVIEWS_MEMORY_ID)));
+}
+
+fn review_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A review with id={} not found", id),
+    }
+}
+
+pub(crate) fn get_review(id: u64) -> Result<Review, Error> {
+    REVIEWS
+        .with(|r| r.borrow().get(&id))
+        .ok_or_else(|| review_not_found(id))
+}
+
+fn do_insert_review(review: &Review) {
+    REVIEWS.with(|r| r.borrow_mut().insert(review.id, review.clone()));
+    log_upsert("review", &review.id, review);
+    track_moderation(
+        ContentRef::Review(review.id),
+        review.created_at,
+        review.status == ModerationStatus::Pending,
+    );
+}
+
+fn do_remove_review(review: &Review) {
+    REVIEWS.with(|r| r.borrow_mut().remove(&review.id));
+    log_delete("review", &review.id);
+    track_moderation(ContentRef::Review(review.id), review.created_at, false);
+}
+
+// Records a moderator's decision on a review
+pub(crate) fn decide_review(id: u64, decision: ModerationDecision) -> Result<Review, Error> {
+    let mut review = get_review(id)?;
+    if decision.approved() && review.status != ModerationStatus::Approved {
+        record_activity(review.space_id, Activity::Review);
+    }
+    review.status = if decision.approved() {
+        ModerationStatus::Approved
+    } else {
+        ModerationStatus::Rejected
+    };
+    review.moderation = Some(decision);
+    do_insert_review(&review);
+    Ok(review)
+}
+
+// Removes the reviews a user wrote; returns the number removed
+pub(crate) fn remove_reviews_by(user: &Principal) -> u64 {
+    let written: Vec<Review> = REVIEWS.with(|r| {
+        r.borrow()
+            .iter()
+            .map(|(_, review)| review)
+            .filter(|review| review.author == *user)
+            .collect()
+    });
+    for review in &written {
+        do_remove_review(review);
+    }
+    written.len() as u64
+}
+
+fn validate_review(payload: &ReviewPayload) -> Result<(), Error> {
+    if !(1..=5).contains(&payload.rating) {
+        return Err(Error::InvalidInput {
+            msg: "Ratings must be between 1 and 5".to_string(),
+        });
+    }
+    if payload.text.len() > MAX_TEXT_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Reviews must be at most {} characters", MAX_TEXT_LEN),
+        });
+    }
+    ensure_no_rejected_terms(&[&payload.text])
+}
+
+// Function to review a green space, once per user; reviews by admins of its
+// city are published directly unless they look like spam or contain a held
+// term, others wait for a moderator
+#[ic_cdk::update]
+fn submit_review(payload: ReviewPayload) -> Result<Review, Error> {
+    ensure_writable()?;
+    let author = authenticated_caller()?;
+    ensure_terms_accepted()?;
+    ensure_green_space_exists(payload.space_id)?;
+    validate_review(&payload)?;
+    let reviewed = REVIEWS.with(|r| {
+        r.borrow()
+            .iter()
+            .any(|(_, review)| review.space_id == payload.space_id && review.author == author)
+    });
+    if reviewed {
+        return Err(Error::Conflict {
+            msg: "You have already reviewed this green space".to_string(),
+        });
+    }
+    let mut signals = screen_submission(author, &payload.text);
+    signals.extend(held_term_signals(&[&payload.text]));
+    let published = signals.is_empty()
+        && !is_shadow_banned(&author)
+        && ensure_space_admin(payload.space_id).is_ok();
+    let (status, moderation) = if published {
+        (
+            ModerationStatus::Approved,
+            Some(ModerationDecision::new(true, None)),
+        )
+    } else {
+        (ModerationStatus::Pending, None)
+    };
+    let review = Review {
+        id: next_id(REVIEWS_MEMORY_ID),
+        space_id: payload.space_id,
+        author,
+        rating: payload.rating,
+        text: payload.text,
+        status,
+        created_at: time(),
+        moderation,
+        spam_signals: (!signals.is_empty()).then_some(signals),
+    };
+    do_insert_review(&review);
+    if published {
+        record_activity(review.space_id, Activity::Review);
+    }
+    Ok(review)
+}
+
+// Function for the author or an admin of the space's city to remove a review
+#[ic_cdk::update]
+fn delete_review(id: u64) -> Result<Review, Error> {
+    ensure_writable()?;
+    let review = get_review(id)?;
+    if review.author != ic_cdk::caller() {
+        ensure_space_admin(review.space_id)?;
+    }
+    do_remove_review(&review);
+    Ok(review)
+}
+
+// Function to list the approved reviews of a green space
+#[ic_cdk::query]
+fn get_reviews(space_id: u64, next_token: Option<String>) -> Result<Page<Review>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(REVIEWS.with(|r| {
+        page_by_size(r.borrow().range(range).filter(|(_, review)| {
+            review.space_id == space_id && review.status == ModerationStatus::Approved
+        }))
+    }))
+}
+
+// Function for a user to see their reviews, including those awaiting or
+// refused moderation
+#[ic_cdk::query]
+fn get_my_reviews(next_token: Option<String>) -> Result<Page<Review>, Error> {
+    let author = authenticated_caller()?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(REVIEWS.with(|r| {
+        page_by_size(
+            r.borrow()
+                .range(range)
+                .filter(|(_, review)| review.author == author),
+        )
+    }))
+}
@@ -0,0 +1,112 @@
+use crate::maintenance::ensure_writable;
+use crate::query_api::{run_query, validate_filter, QueryFilter, QueryRequest, QueryResponse};
+use crate::search_alerts::forget_alerted_spaces;
+use crate::{
+    authenticated_caller, get_memory, next_id, principal_key, Error, Memory, PrincipalKey,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const SAVED_SEARCHES_MEMORY_ID: u8 = 55;
+
+const MAX_SAVED_SEARCHES: usize = 50;
+const MAX_SEARCH_NAME_LEN: usize = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SavedSearch {
+    pub(crate) id: u64,
+    pub(crate) name: String,
+    pub(crate) filter: QueryFilter,
+    created_at: u64,
+    // Set while alerts are on: the first change-log entry they cover
+    pub(crate) alerts_from_seq: Option<u64>,
+}
+
+impl_storable!(SavedSearch, 2048);
+
+thread_local! {
+    // Saved searches keyed by (owner, id)
+    pub(crate) static SAVED_SEARCHES: RefCell<StableBTreeMap<(PrincipalKey, u64), SavedSearch, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SAVED_SEARCHES_MEMORY_ID)));
+}
+
+pub(crate) fn searches_of(owner: &Principal) -> Vec<SavedSearch> {
+    let key = principal_key(owner);
+    SAVED_SEARCHES.with(|s| {
+        s.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|(_, search)| search)
+            .collect()
+    })
+}
+
+pub(crate) fn saved_search(owner: &Principal, id: u64) -> Result<SavedSearch, Error> {
+    SAVED_SEARCHES
+        .with(|s| s.borrow().get(&(principal_key(owner), id)))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("You have no saved search with id={}", id),
+        })
+}
+
+// Function to save a named filter for the caller to run again by id
+#[ic_cdk::update]
+fn save_search(name: String, filter: QueryFilter) -> Result<SavedSearch, Error> {
+    ensure_writable()?;
+    let owner = authenticated_caller()?;
+    let name = name.trim().to_string();
+    if name.is_empty() || name.len() > MAX_SEARCH_NAME_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Search names must be 1-{} characters", MAX_SEARCH_NAME_LEN),
+        });
+    }
+    validate_filter(&filter)?;
+    if searches_of(&owner).len() >= MAX_SAVED_SEARCHES {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} searches can be saved", MAX_SAVED_SEARCHES),
+        });
+    }
+    let search = SavedSearch {
+        id: next_id(SAVED_SEARCHES_MEMORY_ID),
+        name,
+        filter,
+        created_at: time(),
+        alerts_from_seq: None,
+    };
+    SAVED_SEARCHES.with(|s| {
+        s.borrow_mut()
+            .insert((principal_key(&owner), search.id), search.clone())
+    });
+    Ok(search)
+}
+
+#[ic_cdk::query]
+fn get_my_saved_searches() -> Result<Vec<SavedSearch>, Error> {
+    Ok(searches_of(&authenticated_caller()?))
+}
+
+#[ic_cdk::update]
+fn delete_saved_search(id: u64) -> Result<SavedSearch, Error> {
+    ensure_writable()?;
+    let owner = authenticated_caller()?;
+    let search = saved_search(&owner, id)?;
+    SAVED_SEARCHES.with(|s| s.borrow_mut().remove(&(principal_key(&owner), id)));
+    forget_alerted_spaces(id);
+    Ok(search)
+}
+
+// Function to run a saved search like query; the request sets the fields,
+// sort and page, and the saved search supplies the filter
+#[ic_cdk::query]
+fn run_saved_search(id: u64, request: QueryRequest) -> Result<QueryResponse, Error> {
+    let owner = authenticated_caller()?;
+    if request.filter.is_some() {
+        return Err(Error::InvalidInput {
+            msg: "The filter of a saved search cannot be overridden".to_string(),
+        });
+    }
+    let mut request = request;
+    request.filter = Some(saved_search(&owner, id)?.filter);
+    run_query(request)
+}
@@ -0,0 +1,338 @@
+use crate::apiary::send_due_apiary_reminders;
+use crate::canopy::compute_canopy_stats;
+use crate::cascade::run_cascade_batch;
+use crate::cycles::check_cycle_balance;
+use crate::digest::send_weekly_digests;
+use crate::featured::rotate_featured_space;
+use crate::geocoding::expire_geocode_cache;
+use crate::heat::compute_heat_scores;
+use crate::idempotency::expire_idempotency_cache;
+use crate::maintenance::{ensure_writable, in_maintenance};
+use crate::metrics::prune_metric_series;
+use crate::orphans::find_orphans;
+use crate::playground::open_overdue_inspection_tickets;
+use crate::replication::push_pending_changes;
+use crate::retention::prune_expired_entries;
+use crate::search_alerts::send_saved_search_alerts;
+use crate::search_analytics::flush_search_stats;
+use crate::subscribers::push_to_subscribers;
+use crate::trending::expire_trends;
+use crate::ulid::refresh_ulid_seed;
+use crate::views::flush_view_counts;
+use crate::webhooks::deliver_due_webhooks;
+use crate::{ensure_controller, Error};
+use ic_cdk::api::time;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+type JobFuture = Pin<Box<dyn Future<Output = Result<String, Error>>>>;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+// A run still marked as running after this long is taken to be lost, e.g. to
+// a call that never returned, and no longer holds the job back
+const STALE_RUN_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+// A recurring job; `run` returns a short summary of what it did
+struct Job {
+    name: &'static str,
+    interval: Duration,
+    run: fn() -> JobFuture,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct JobStatus {
+    name: String,
+    interval_secs: u64,
+    running: bool,
+    run_count: u64,
+    failure_count: u64,
+    last_started_at: Option<u64>,
+    last_finished_at: Option<u64>,
+    last_summary: Option<String>,
+    last_error: Option<String>,
+}
+
+thread_local! {
+    // Kept on the heap; the counts restart when the canister is upgraded
+    static JOB_STATUS: RefCell<BTreeMap<&'static str, JobStatus>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+fn jobs() -> Vec<Job> {
+    vec![
+        Job {
+            name: "replication",
+            interval: Duration::from_secs(60),
+            run: || {
+                Box::pin(async {
+                    push_pending_changes()
+                        .await
+                        .map(|pushed| format!("{} change(s) replicated", pushed))
+                })
+            },
+        },
+        Job {
+            name: "webhooks",
+            interval: Duration::from_secs(30),
+            run: || {
+                Box::pin(async {
+                    let delivered = deliver_due_webhooks().await;
+                    Ok(format!("{} batch(es) delivered", delivered))
+                })
+            },
+        },
+        Job {
+            name: "subscribers",
+            interval: Duration::from_secs(30),
+            run: || Box::pin(async { Ok(format!("{} push(es) started", push_to_subscribers())) }),
+        },
+        Job {
+            name: "apiary_reminders",
+            interval: DAY,
+            run: || {
+                Box::pin(async { Ok(format!("{} reminder(s) sent", send_due_apiary_reminders())) })
+            },
+        },
+        Job {
+            name: "maintenance_reminders",
+            interval: DAY,
+            run: || {
+                Box::pin(async {
+                    let opened = open_overdue_inspection_tickets();
+                    Ok(format!("{} ticket(s) opened", opened))
+                })
+            },
+        },
+        Job {
+            name: "geocode_cache_expiry",
+            interval: DAY,
+            run: || Box::pin(async { Ok(format!("{} lookup(s) expired", expire_geocode_cache())) }),
+        },
+        Job {
+            name: "view_counts",
+            interval: Duration::from_secs(5 * 60),
+            run: || Box::pin(async { Ok(format!("{} space(s) flushed", flush_view_counts())) }),
+        },
+        Job {
+            name: "search_stats",
+            interval: Duration::from_secs(5 * 60),
+            run: || Box::pin(async { Ok(format!("{} search(es) flushed", flush_search_stats())) }),
+        },
+        Job {
+            name: "cascade_deletes",
+            interval: Duration::from_secs(60),
+            run: || Box::pin(async { Ok(format!("{} record(s) removed", run_cascade_batch())) }),
+        },
+        // Reports only; removal is left to remove_orphaned_records
+        Job {
+            name: "orphan_scan",
+            interval: DAY,
+            run: || Box::pin(async { Ok(find_orphans().summary()) }),
+        },
+        Job {
+            name: "saved_search_alerts",
+            interval: Duration::from_secs(60),
+            run: || Box::pin(async { Ok(format!("{} alert(s) sent", send_saved_search_alerts())) }),
+        },
+        // Daily, so digests stay weekly whatever the upgrade schedule
+        Job {
+            name: "weekly_digest",
+            interval: DAY,
+            run: || Box::pin(async { Ok(format!("{} digest(s) sent", send_weekly_digests())) }),
+        },
+        Job {
+            name: "idempotency_expiry",
+            interval: Duration::from_secs(60 * 60),
+            run: || {
+                Box::pin(async {
+                    Ok(format!(
+                        "{} cached response(s) expired",
+                        expire_idempotency_cache()
+                    ))
+                })
+            },
+        },
+        Job {
+            name: "ulid_seed",
+            interval: DAY,
+            run: || Box::pin(refresh_ulid_seed()),
+        },
+        Job {
+            name: "metric_retention",
+            interval: DAY,
+            run: || Box::pin(async { Ok(format!("{} point(s) pruned", prune_metric_series())) }),
+        },
+        // Hourly, so a new day gets its space soon after midnight UTC
+        Job {
+            name: "featured_space",
+            interval: Duration::from_secs(60 * 60),
+            run: || Box::pin(rotate_featured_space()),
+        },
+        Job {
+            name: "canopy_stats",
+            interval: DAY,
+            run: || Box::pin(async { Ok(format!("{} scope(s) computed", compute_canopy_stats())) }),
+        },
+        Job {
+            name: "heat_scores",
+            interval: DAY,
+            run: || Box::pin(async { Ok(format!("{} space(s) scored", compute_heat_scores())) }),
+        },
+        Job {
+            name: "trending_expiry",
+            interval: Duration::from_secs(60 * 60),
+            run: || Box::pin(async { Ok(format!("{} trend(s) expired", expire_trends())) }),
+        },
+        Job {
+            name: "cycle_balance",
+            interval: Duration::from_secs(10 * 60),
+            run: || Box::pin(check_cycle_balance()),
+        },
+        Job {
+            name: "retention",
+            interval: DAY,
+            run: || Box::pin(async { Ok(prune_expired_entries().summary()) }),
+        },
+    ]
+}
+
+fn job_not_found(name: &str) -> Error {
+    Error::NotFound {
+        msg: format!("A scheduled job named {} not found", name),
+    }
+}
+
+// Records the outcome of the run started at `started_at`, unless a later run
+// has taken over from it
+fn finish_run(name: &'static str, started_at: u64, outcome: Result<&str, &str>) {
+    JOB_STATUS.with(|s| {
+        let mut statuses = s.borrow_mut();
+        let Some(status) = statuses.get_mut(name) else {
+            return;
+        };
+        if status.last_started_at != Some(started_at) {
+            return;
+        }
+        status.running = false;
+        status.run_count += 1;
+        status.last_finished_at = Some(time());
+        match outcome {
+            Ok(summary) => {
+                status.last_summary = Some(summary.to_string());
+                status.last_error = None;
+            }
+            Err(error) => {
+                status.failure_count += 1;
+                status.last_error = Some(error.to_string());
+            }
+        }
+    });
+}
+
+// Fails the run when dropped before it finished. A job that traps after an
+// await has its future dropped by the cleanup callback, which keeps the
+// running flag from sticking
+struct RunGuard {
+    name: &'static str,
+    started_at: u64,
+    finished: bool,
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            finish_run(self.name, self.started_at, Err("The job trapped"));
+        }
+    }
+}
+
+// Runs a job unless its previous run is still awaiting, and records the outcome
+async fn run_job(name: &'static str, run: fn() -> JobFuture) -> Result<String, Error> {
+    let started_at = time();
+    let already_running = JOB_STATUS.with(|s| {
+        let mut statuses = s.borrow_mut();
+        let status = statuses.get_mut(name).expect("Job status missing");
+        let stale = status
+            .last_started_at
+            .is_some_and(|at| started_at.saturating_sub(at) > STALE_RUN_NANOS);
+        if status.running && !stale {
+            return true;
+        }
+        status.running = true;
+        status.last_started_at = Some(started_at);
+        false
+    });
+    if already_running {
+        return Err(Error::InvalidInput {
+            msg: format!("Job {} is already running", name),
+        });
+    }
+
+    let mut guard = RunGuard {
+        name,
+        started_at,
+        finished: false,
+    };
+    let result = run().await;
+    guard.finished = true;
+    finish_run(
+        name,
+        started_at,
+        result.as_deref().map_err(|error| error.message()),
+    );
+    result
+}
+
+// Starts a timer for every job; timers do not survive upgrades, so this runs
+// on install and after every upgrade
+pub(crate) fn start_scheduler() {
+    for job in jobs() {
+        JOB_STATUS.with(|s| {
+            s.borrow_mut().insert(
+                job.name,
+                JobStatus {
+                    name: job.name.to_string(),
+                    interval_secs: job.interval.as_secs(),
+                    running: false,
+                    run_count: 0,
+                    failure_count: 0,
+                    last_started_at: None,
+                    last_finished_at: None,
+                    last_summary: None,
+                    last_error: None,
+                },
+            )
+        });
+        let (name, run) = (job.name, job.run);
+        ic_cdk_timers::set_timer_interval(job.interval, move || {
+            // Scheduled writes pause with the rest during maintenance
+            if in_maintenance() {
+                return;
+            }
+            ic_cdk::spawn(async move {
+                // The outcome is kept in the job status
+                let _ = run_job(name, run).await;
+            })
+        });
+    }
+}
+
+#[ic_cdk::query]
+fn get_scheduler_status() -> Result<Vec<JobStatus>, Error> {
+    Ok(JOB_STATUS.with(|s| s.borrow().values().cloned().collect()))
+}
+
+// Function to run a scheduled job right away
+#[ic_cdk::update]
+async fn run_scheduled_job(name: String) -> Result<String, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let job = jobs()
+        .into_iter()
+        .find(|job| job.name == name)
+        .ok_or_else(|| job_not_found(&name))?;
+    run_job(job.name, job.run).await
+}
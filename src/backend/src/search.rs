@@ -0,0 +1,166 @@
+use crate::cities::in_city;
+use crate::fulltext::{candidate_ids_for_terms, tokenizer, TokenizerConfig};
+use crate::metadata::searchable_keys;
+use crate::paging::{decode_token, page_by_count, Page};
+use crate::search_analytics::log_search;
+use crate::synonyms::expand_synonyms;
+use crate::{_get_green_space, Error, GreenSpace};
+
+const MAX_SEARCH_PAGE: u32 = 100;
+const MAX_QUERY_TERMS: usize = 10;
+
+// Points per occurrence of a term, by the field it occurs in. Values under
+// searchable metadata keys are the tags of a space
+const NAME_WEIGHT: u32 = 3;
+const TAG_WEIGHT: u32 = 2;
+const DESCRIPTION_WEIGHT: u32 = 1;
+// Added when the name is the query itself or contains it as a phrase
+const EXACT_NAME_BOOST: u32 = 20;
+const PHRASE_BOOST: u32 = 5;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum SearchField {
+    Name,
+    Tag,
+    Description,
+}
+
+impl SearchField {
+    fn weight(self) -> u32 {
+        match self {
+            SearchField::Name => NAME_WEIGHT,
+            SearchField::Tag => TAG_WEIGHT,
+            SearchField::Description => DESCRIPTION_WEIGHT,
+        }
+    }
+}
+
+// Where the query terms occur in one field of a result, for highlighting
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SearchHit {
+    field: SearchField,
+    // The metadata key of a tag
+    key: Option<String>,
+    // Start and end of each occurrence, in characters, end exclusive
+    ranges: Vec<(u32, u32)>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ScoredGreenSpace {
+    space: GreenSpace,
+    score: u32,
+    hits: Vec<SearchHit>,
+}
+
+// Results are ordered by descending score, then ascending id
+type ScoreKey = (u32, u64);
+
+fn hit(
+    config: &TokenizerConfig,
+    field: SearchField,
+    key: Option<&String>,
+    query: &[String],
+    text: &str,
+) -> Option<SearchHit> {
+    let ranges: Vec<_> = config
+        .spans(text)
+        .into_iter()
+        .filter(|(_, term)| query.contains(term))
+        .map(|(range, _)| range)
+        .collect();
+    (!ranges.is_empty()).then(|| SearchHit {
+        field,
+        key: key.cloned(),
+        ranges,
+    })
+}
+
+// Terms match with their synonyms; the boosts only apply to the query as typed
+fn score(
+    config: &TokenizerConfig,
+    space: &GreenSpace,
+    query: &[String],
+    expanded: &[String],
+    tag_keys: &[String],
+) -> (u32, Vec<SearchHit>) {
+    let mut hits = Vec::new();
+    hits.extend(hit(config, SearchField::Name, None, expanded, &space.name));
+    for (key, value) in space.metadata.iter().flatten() {
+        if tag_keys.contains(key) {
+            hits.extend(hit(config, SearchField::Tag, Some(key), expanded, value));
+        }
+    }
+    hits.extend(hit(
+        config,
+        SearchField::Description,
+        None,
+        expanded,
+        &space.description,
+    ));
+    let matched: u32 = hits
+        .iter()
+        .map(|hit| hit.field.weight() * hit.ranges.len() as u32)
+        .sum();
+    if matched == 0 {
+        return (0, hits);
+    }
+    let phrase = query.join(" ");
+    let name = config.terms(&space.name).join(" ");
+    let boost = if name == phrase {
+        EXACT_NAME_BOOST
+    } else if query.len() > 1 && name.contains(&phrase) {
+        PHRASE_BOOST
+    } else {
+        0
+    };
+    (matched + boost, hits)
+}
+
+// Function to search names, tags and descriptions at once, best matches
+// first; a space matches when any term of the query occurs in it, and each
+// result lists where, so frontends can highlight the matches
+#[ic_cdk::query]
+fn search_green_spaces(
+    query: String,
+    city_id: Option<u64>,
+    next_token: Option<String>,
+    limit: u32,
+) -> Result<Page<ScoredGreenSpace>, Error> {
+    if limit == 0 || limit > MAX_SEARCH_PAGE {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_SEARCH_PAGE),
+        });
+    }
+    let config = tokenizer();
+    let terms = config.terms(&query);
+    if terms.is_empty() || terms.len() > MAX_QUERY_TERMS {
+        return Err(Error::InvalidInput {
+            msg: format!("A search needs between 1 and {} terms", MAX_QUERY_TERMS),
+        });
+    }
+    let after: Option<ScoreKey> = decode_token(&next_token)?;
+    let expanded = expand_synonyms(&config, &terms);
+    let tag_keys = searchable_keys();
+    // The term index narrows the spaces to those sharing a term with the query
+    let mut scored: Vec<(ScoreKey, ScoredGreenSpace)> = candidate_ids_for_terms(&expanded)
+        .into_iter()
+        .filter_map(|id| _get_green_space(&id))
+        .filter(|space| in_city(city_id, space.city_id))
+        .filter_map(|space| {
+            let (score, hits) = score(&config, &space, &terms, &expanded, &tag_keys);
+            (score > 0).then_some((
+                (u32::MAX - score, space.id),
+                ScoredGreenSpace { space, score, hits },
+            ))
+        })
+        .collect();
+    // Pages after the first are the same search, counted once
+    if next_token.is_none() {
+        log_search(&terms, !scored.is_empty());
+    }
+    scored.sort_by_key(|(key, _)| *key);
+    let remaining = scored
+        .into_iter()
+        .filter(|(key, _)| after.is_none_or(|after| *key > after));
+    Ok(page_by_count(remaining, limit as usize))
+}
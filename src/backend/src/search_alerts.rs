@@ -0,0 +1,143 @@
+use crate::changelog::{changes_after, last_change_seq, ChangeOp};
+use crate::maintenance::ensure_writable;
+use crate::notifications::{notify, NotificationKind};
+use crate::query_api::filter_matches;
+use crate::saved_searches::{saved_search, SavedSearch, SAVED_SEARCHES};
+use crate::{authenticated_caller, get_memory, principal_key, Error, Memory};
+use candid::Principal;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use std::cell::RefCell;
+
+const ALERT_CURSOR_MEMORY_ID: u8 = 56;
+const ALERTED_MEMORY_ID: u8 = 57;
+
+const MAX_CHANGES_PER_RUN: usize = 500;
+
+thread_local! {
+    // The next change-log entry to evaluate saved searches against
+    pub(crate) static ALERT_CURSOR: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(get_memory(ALERT_CURSOR_MEMORY_ID), 0)
+            .expect("Cannot initialize the alert cursor")
+    );
+
+    // (search id, space id) of the spaces a search has alerted about and that
+    // still match it; a space is alerted about again only after leaving the results
+    pub(crate) static ALERTED: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(ALERTED_MEMORY_ID)));
+}
+
+fn alerting_searches() -> Vec<(Principal, SavedSearch)> {
+    SAVED_SEARCHES.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, search)| search.alerts_from_seq.is_some())
+            .map(|((owner, _), search)| (Principal::from_slice(owner.as_slice()), search))
+            .collect()
+    })
+}
+
+pub(crate) fn forget_alerted_spaces(search_id: u64) {
+    ALERTED.with(|a| {
+        let keys: Vec<(u64, u64)> = a
+            .borrow()
+            .range((search_id, 0)..=(search_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        let mut alerted = a.borrow_mut();
+        for key in keys {
+            alerted.remove(&key);
+        }
+    });
+}
+
+// The first change-log entry saved search alerts still need, if any are on
+pub(crate) fn first_unalerted_seq() -> Option<u64> {
+    let any_on = SAVED_SEARCHES.with(|s| {
+        s.borrow()
+            .iter()
+            .any(|(_, search)| search.alerts_from_seq.is_some())
+    });
+    any_on.then(|| ALERT_CURSOR.with(|c| *c.borrow().get()))
+}
+
+// Evaluates the saved searches with alerts on against the spaces written
+// since the last run and notifies their owners of new matches; returns the
+// number of notifications queued
+pub(crate) fn send_saved_search_alerts() -> u64 {
+    let searches = alerting_searches();
+    let cursor = ALERT_CURSOR.with(|c| *c.borrow().get());
+    // With no alerts on there is nothing to evaluate; the cursor skips ahead
+    let changes = if searches.is_empty() {
+        vec![]
+    } else {
+        changes_after(cursor.checked_sub(1), MAX_CHANGES_PER_RUN)
+    };
+    let last = match changes.last() {
+        Some(entry) => entry.seq,
+        None => match last_change_seq() {
+            Some(seq) if seq >= cursor => seq,
+            _ => return 0,
+        },
+    };
+    let mut sent = 0;
+    for entry in &changes {
+        for (owner, search) in &searches {
+            if search.alerts_from_seq.is_some_and(|from| entry.seq < from) {
+                continue;
+            }
+            let key = (search.id, entry.space_id);
+            let space = match (entry.op, &entry.space) {
+                (ChangeOp::Upsert, Some(space)) if filter_matches(&search.filter, space) => space,
+                _ => {
+                    ALERTED.with(|a| a.borrow_mut().remove(&key));
+                    continue;
+                }
+            };
+            if ALERTED.with(|a| a.borrow().contains_key(&key)) {
+                continue;
+            }
+            ALERTED.with(|a| a.borrow_mut().insert(key, ()));
+            notify(
+                owner,
+                NotificationKind::SavedSearchMatch {
+                    search_id: search.id,
+                },
+                Some(space.id),
+                format!(
+                    "{} now matches your saved search {}",
+                    space.name, search.name
+                ),
+            );
+            sent += 1;
+        }
+    }
+    ALERT_CURSOR.with(|c| {
+        c.borrow_mut()
+            .set(last + 1)
+            .expect("Cannot store the alert cursor")
+    });
+    sent
+}
+
+// Function to turn alerts on or off for one of the caller's saved searches;
+// alerts cover spaces added or changed from then on and arrive as notifications
+#[ic_cdk::update]
+fn set_saved_search_alerts(id: u64, enabled: bool) -> Result<SavedSearch, Error> {
+    ensure_writable()?;
+    let owner = authenticated_caller()?;
+    let mut search = saved_search(&owner, id)?;
+    if enabled == search.alerts_from_seq.is_some() {
+        return Ok(search);
+    }
+    if enabled {
+        search.alerts_from_seq = Some(last_change_seq().map_or(0, |seq| seq + 1));
+    } else {
+        search.alerts_from_seq = None;
+        forget_alerted_spaces(id);
+    }
+    SAVED_SEARCHES.with(|s| {
+        s.borrow_mut()
+            .insert((principal_key(&owner), id), search.clone())
+    });
+    Ok(search)
+}
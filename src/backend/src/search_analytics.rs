@@ -0,0 +1,160 @@
+use crate::cycles::conserving_cycles;
+use crate::views::{today, ViewPeriod};
+use crate::{ensure_controller, get_memory, Error, Memory};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const SEARCH_STATS_MEMORY_ID: u8 = 54;
+
+// Queries are logged by their prefix so keys stay bounded
+const MAX_LOGGED_QUERY_LEN: usize = 200;
+const MAX_ANALYTICS_ROWS: u32 = 100;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default)]
+pub(crate) struct SearchCounts {
+    searches: u64,
+    zero_result_searches: u64,
+}
+
+impl_storable!(SearchCounts, 64);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct SearchStatsKey {
+    day: u64,
+    query: String,
+}
+
+impl_storable!(SearchStatsKey, 512);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct QueryStats {
+    query: String,
+    searches: u64,
+    zero_result_searches: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SearchAnalytics {
+    // Most searched first
+    popular: Vec<QueryStats>,
+    // Queries that found nothing, most frequent first
+    zero_results: Vec<QueryStats>,
+}
+
+thread_local! {
+    // Searches since the last flush, by normalized query; like views, those
+    // made through query calls are discarded
+    static PENDING_SEARCHES: RefCell<BTreeMap<String, SearchCounts>> =
+        const { RefCell::new(BTreeMap::new()) };
+
+    // Flushed counts keyed by day and normalized query; no caller is recorded
+    pub(crate) static SEARCH_STATS: RefCell<StableBTreeMap<SearchStatsKey, SearchCounts, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SEARCH_STATS_MEMORY_ID)));
+}
+
+fn truncated(query: String) -> String {
+    if query.len() <= MAX_LOGGED_QUERY_LEN {
+        return query;
+    }
+    let mut end = MAX_LOGGED_QUERY_LEN;
+    while !query.is_char_boundary(end) {
+        end -= 1;
+    }
+    query[..end].to_string()
+}
+
+impl SearchCounts {
+    fn add(&mut self, other: SearchCounts) {
+        self.searches = self.searches.saturating_add(other.searches);
+        self.zero_result_searches = self
+            .zero_result_searches
+            .saturating_add(other.zero_result_searches);
+    }
+}
+
+// Counts a search by its normalized terms, unless cycles are low
+pub(crate) fn log_search(terms: &[String], found_any: bool) {
+    if conserving_cycles() {
+        return;
+    }
+    let counts = SearchCounts {
+        searches: 1,
+        zero_result_searches: u64::from(!found_any),
+    };
+    PENDING_SEARCHES.with(|p| {
+        p.borrow_mut()
+            .entry(truncated(terms.join(" ")))
+            .or_default()
+            .add(counts)
+    });
+}
+
+pub(crate) fn discard_pending_searches() {
+    PENDING_SEARCHES.with(|p| p.borrow_mut().clear());
+}
+
+// Adds the pending searches to today's counts; returns the number of queries flushed
+pub(crate) fn flush_search_stats() -> u64 {
+    let pending = PENDING_SEARCHES.with(|p| std::mem::take(&mut *p.borrow_mut()));
+    let day = today();
+    SEARCH_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        for (query, counts) in &pending {
+            let key = SearchStatsKey {
+                day,
+                query: query.clone(),
+            };
+            let mut total = stats.get(&key).unwrap_or_default();
+            total.add(*counts);
+            stats.insert(key, total);
+        }
+    });
+    pending.len() as u64
+}
+
+fn ranked(
+    totals: &BTreeMap<String, SearchCounts>,
+    count: fn(&SearchCounts) -> u64,
+    limit: u32,
+) -> Vec<QueryStats> {
+    let mut rows: Vec<(&String, &SearchCounts)> = totals
+        .iter()
+        .filter(|(_, counts)| count(counts) > 0)
+        .collect();
+    rows.sort_by_key(|&(query, counts)| (std::cmp::Reverse(count(counts)), query));
+    rows.into_iter()
+        .take(limit as usize)
+        .map(|(query, counts)| QueryStats {
+            query: query.clone(),
+            searches: counts.searches,
+            zero_result_searches: counts.zero_result_searches,
+        })
+        .collect()
+}
+
+// Function for curators to see what people search for in a period and which
+// searches find nothing, including searches not flushed yet
+#[ic_cdk::query]
+fn get_search_analytics(period: ViewPeriod, limit: u32) -> Result<SearchAnalytics, Error> {
+    ensure_controller()?;
+    if limit == 0 || limit > MAX_ANALYTICS_ROWS {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_ANALYTICS_ROWS),
+        });
+    }
+    let start = SearchStatsKey {
+        day: period.first_day(today()),
+        query: String::new(),
+    };
+    let mut totals: BTreeMap<String, SearchCounts> = PENDING_SEARCHES.with(|p| p.borrow().clone());
+    SEARCH_STATS.with(|stats| {
+        for (key, counts) in stats.borrow().range(start..) {
+            totals.entry(key.query).or_default().add(counts);
+        }
+    });
+    Ok(SearchAnalytics {
+        popular: ranked(&totals, |counts| counts.searches, limit),
+        zero_results: ranked(&totals, |counts| counts.zero_result_searches, limit),
+    })
+}
@@ -0,0 +1,301 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::cities::ensure_city_exists;
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::{ensure_controller, get_memory, Error, GreenSpace, GreenSpaceUpdatePayload, Memory};
+use candid::Principal;
+use ic_cdk::api::management_canister::main::{
+    create_canister, install_code, CanisterInstallMode, CanisterSettings, CreateCanisterArgument,
+    InstallCodeArgument,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+const SHARDS_MEMORY_ID: u8 = 25;
+
+// Canisters are limited to 400GiB of stable memory; new cities should go to a
+// shard once this canister uses more than 80% of it
+const STABLE_MEMORY_LIMIT_BYTES: u64 = 400 * 1024 * 1024 * 1024;
+const NEAR_LIMIT_PERCENT: u64 = 80;
+const WASM_PAGE_BYTES: u64 = 64 * 1024;
+// install_code takes the module in a single message
+const MAX_SHARD_WASM_BYTES: usize = 2 * 1024 * 1024;
+const MAX_LABEL_LEN: usize = 100;
+
+// A child canister running this same code, serving every write for one city
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Shard {
+    city_id: u64,
+    canister_id: Principal,
+    label: String,
+    // Hex SHA-256 of the installed module, if this canister installed it
+    wasm_hash: Option<String>,
+    registered_at: u64,
+}
+
+impl_storable!(Shard, 512);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct StorageStatus {
+    stable_memory_bytes: u64,
+    limit_bytes: u64,
+    near_limit: bool,
+}
+
+thread_local! {
+    // Shards keyed by the city they serve
+    pub(crate) static SHARDS: RefCell<StableBTreeMap<u64, Shard, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SHARDS_MEMORY_ID)));
+
+    // Module installed into new shards; staged on the heap and uploaded again
+    // after an upgrade
+    static SHARD_WASM: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+fn validate_label(label: &str) -> Result<(), Error> {
+    if label.len() > MAX_LABEL_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Shard label must be at most {} characters", MAX_LABEL_LEN),
+        });
+    }
+    Ok(())
+}
+
+fn ensure_no_shard(city_id: u64) -> Result<(), Error> {
+    if SHARDS.with(|s| s.borrow().contains_key(&city_id)) {
+        return Err(Error::InvalidInput {
+            msg: format!("City id={} already has a shard", city_id),
+        });
+    }
+    Ok(())
+}
+
+// The shard that owns the records of a city, if the city has been moved to one
+pub(crate) fn shard_for_city(city_id: Option<u64>) -> Option<Principal> {
+    let city_id = city_id?;
+    SHARDS
+        .with(|s| s.borrow().get(&city_id))
+        .map(|shard| shard.canister_id)
+}
+
+pub(crate) fn shard_canisters() -> Vec<Principal> {
+    SHARDS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, shard)| shard.canister_id)
+            .collect()
+    })
+}
+
+pub(crate) fn call_failed(
+    canister_id: Principal,
+    method: &str,
+    code: impl std::fmt::Debug,
+    msg: String,
+) -> Error {
+    Error::OutcallFailed {
+        msg: format!(
+            "Call to {}.{} failed ({:?}): {}",
+            canister_id, method, code, msg
+        ),
+    }
+}
+
+// A shard holds a single city and knows neither the cities nor the districts of
+// this canister, so records are stored there unscoped and tagged on the way back
+fn to_shard_payload(mut payload: GreenSpaceUpdatePayload) -> GreenSpaceUpdatePayload {
+    payload.city_id = None;
+    payload.district_id = None;
+    payload
+}
+
+fn from_shard(
+    result: Result<GreenSpace, Error>,
+    city_id: Option<u64>,
+) -> Result<GreenSpace, Error> {
+    result.map(|mut space| {
+        space.city_id = city_id;
+        space
+    })
+}
+
+// Forwards a write to a shard; the shard sees this canister, one of its
+// controllers, as the caller, so authorization has to happen before forwarding
+pub(crate) async fn add_green_space_on_shard(
+    shard: Principal,
+    payload: GreenSpaceUpdatePayload,
+) -> Result<GreenSpace, Error> {
+    let city_id = payload.city_id;
+    let (result,): (Result<GreenSpace, Error>,) =
+        ic_cdk::call(shard, "add_green_space", (to_shard_payload(payload),))
+            .await
+            .map_err(|(code, msg)| call_failed(shard, "add_green_space", code, msg))?;
+    from_shard(result, city_id)
+}
+
+pub(crate) async fn update_green_space_on_shard(
+    shard: Principal,
+    id: u64,
+    payload: GreenSpaceUpdatePayload,
+    expected_version: Option<u64>,
+) -> Result<GreenSpace, Error> {
+    let city_id = payload.city_id;
+    let arguments = (
+        id,
+        to_shard_payload(payload),
+        None::<String>,
+        expected_version,
+    );
+    let (result,): (Result<GreenSpace, Error>,) =
+        ic_cdk::call(shard, "update_green_space", arguments)
+            .await
+            .map_err(|(code, msg)| call_failed(shard, "update_green_space", code, msg))?;
+    from_shard(result, city_id)
+}
+
+// Function to report how close this canister is to its stable memory limit
+#[ic_cdk::query]
+fn get_storage_status() -> Result<StorageStatus, Error> {
+    let stable_memory_bytes = ic_cdk::api::stable::stable64_size() * WASM_PAGE_BYTES;
+    Ok(StorageStatus {
+        stable_memory_bytes,
+        limit_bytes: STABLE_MEMORY_LIMIT_BYTES,
+        near_limit: stable_memory_bytes * 100 >= STABLE_MEMORY_LIMIT_BYTES * NEAR_LIMIT_PERCENT,
+    })
+}
+
+// Function to stage the module that new shards are created with; `reset`
+// discards a previous upload before appending the chunk
+#[ic_cdk::update]
+fn upload_shard_wasm_chunk(chunk: Vec<u8>, reset: bool) -> Result<u64, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    SHARD_WASM.with(|wasm| {
+        let mut wasm = wasm.borrow_mut();
+        if reset {
+            wasm.clear();
+        }
+        if wasm.len() + chunk.len() > MAX_SHARD_WASM_BYTES {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "Shard modules are limited to {} bytes",
+                    MAX_SHARD_WASM_BYTES
+                ),
+            });
+        }
+        wasm.extend_from_slice(&chunk);
+        Ok(wasm.len() as u64)
+    })
+}
+
+// Function to create a canister for a city, install the staged module in it and
+// route the city's writes there from now on
+#[ic_cdk::update]
+async fn spawn_city_shard(city_id: u64, label: String, cycles: u128) -> Result<Shard, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    ensure_city_exists(city_id)?;
+    ensure_no_shard(city_id)?;
+    validate_label(&label)?;
+    let wasm_module = SHARD_WASM.with(|wasm| wasm.borrow().clone());
+    if wasm_module.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "Upload the shard module with upload_shard_wasm_chunk first".to_string(),
+        });
+    }
+    let wasm_hash = Sha256::digest(&wasm_module)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    let management = Principal::management_canister();
+    let settings = CanisterSettings {
+        controllers: Some(vec![ic_cdk::id(), ic_cdk::caller()]),
+        ..Default::default()
+    };
+    let (record,) = create_canister(
+        CreateCanisterArgument {
+            settings: Some(settings),
+        },
+        cycles,
+    )
+    .await
+    .map_err(|(code, msg)| call_failed(management, "create_canister", code, msg))?;
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id: record.canister_id,
+        wasm_module,
+        arg: vec![],
+    })
+    .await
+    .map_err(|(code, msg)| call_failed(management, "install_code", code, msg))?;
+
+    // Another spawn for the city may have finished while this one was awaiting
+    ensure_no_shard(city_id)?;
+    let shard = Shard {
+        city_id,
+        canister_id: record.canister_id,
+        label,
+        wasm_hash: Some(wasm_hash),
+        registered_at: time(),
+    };
+    SHARDS.with(|s| s.borrow_mut().insert(city_id, shard.clone()));
+    log_upsert("shard", &city_id, &shard);
+    Ok(shard)
+}
+
+// Function to register an existing canister as the shard of a city
+#[ic_cdk::update]
+fn register_city_shard(
+    city_id: u64,
+    canister_id: Principal,
+    label: String,
+) -> Result<Shard, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    ensure_city_exists(city_id)?;
+    ensure_no_shard(city_id)?;
+    validate_label(&label)?;
+    let shard = Shard {
+        city_id,
+        canister_id,
+        label,
+        wasm_hash: None,
+        registered_at: time(),
+    };
+    SHARDS.with(|s| s.borrow_mut().insert(city_id, shard.clone()));
+    log_upsert("shard", &city_id, &shard);
+    Ok(shard)
+}
+
+// Function to stop routing a city's writes to its shard
+#[ic_cdk::update]
+fn unregister_city_shard(city_id: u64) -> Result<Shard, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let shard = SHARDS
+        .with(|s| s.borrow_mut().remove(&city_id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("City id={} has no shard", city_id),
+        })?;
+    log_delete("shard", &city_id);
+    Ok(shard)
+}
+
+// Registry interface: lets clients find the canister holding a city's records
+#[ic_cdk::query]
+fn get_city_shard(city_id: u64) -> Result<Shard, Error> {
+    SHARDS
+        .with(|s| s.borrow().get(&city_id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("City id={} has no shard", city_id),
+        })
+}
+
+#[ic_cdk::query]
+fn get_all_shards(next_token: Option<String>) -> Result<Page<Shard>, Error> {
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(SHARDS.with(|s| page_by_size(s.borrow().range(range))))
+}
@@ -0,0 +1,181 @@
+use crate::block_log::log_config;
+use crate::cycles::conserving_cycles;
+use crate::export::{export_manifest, ExportManifest};
+use crate::maintenance::ensure_writable;
+use crate::{ensure_controller, get_memory, Error, Memory};
+use candid::{Encode, Principal};
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument,
+    SignWithEcdsaArgument,
+};
+use ic_stable_structures::Cell;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+const SIGNING_CONFIG_MEMORY_ID: u8 = 86;
+
+// The threshold keys the IC offers: for local replicas, the test subnet and
+// production
+const KEY_NAMES: [&str; 3] = ["dfx_test_key", "test_key_1", "key_1"];
+// Export signatures come from a key of their own, derived from the
+// canister's with this path
+const DERIVATION_PATH: &[u8] = b"export_manifest";
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SigningConfig {
+    key_name: String,
+    // The SEC1-encoded public key, fetched with the first signature
+    public_key: Option<Vec<u8>>,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        SigningConfig {
+            key_name: "key_1".to_string(),
+            public_key: None,
+        }
+    }
+}
+
+impl_storable!(SigningConfig, 1024);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ExportSigningKey {
+    canister_id: Principal,
+    key_name: String,
+    derivation_path: Vec<Vec<u8>>,
+    public_key: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SignedExportManifest {
+    manifest: ExportManifest,
+    // The candid encoding of the manifest; the signature is over its SHA-256
+    encoded: Vec<u8>,
+    // secp256k1 ECDSA signature as 64 bytes of r and s
+    signature: Vec<u8>,
+    key: ExportSigningKey,
+}
+
+thread_local! {
+    static SIGNING_CONFIG: RefCell<Cell<SigningConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(SIGNING_CONFIG_MEMORY_ID), SigningConfig::default())
+            .expect("Cannot initialize the signing config")
+    );
+}
+
+fn signing_config() -> SigningConfig {
+    SIGNING_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+fn store_signing_config(config: SigningConfig) {
+    log_config("signing_config", &config);
+    SIGNING_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config)
+            .expect("Cannot store the signing config")
+    });
+}
+
+fn key_id(key_name: &str) -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: key_name.to_string(),
+    }
+}
+
+fn signing_key(key_name: String, public_key: Vec<u8>) -> ExportSigningKey {
+    ExportSigningKey {
+        canister_id: ic_cdk::id(),
+        key_name,
+        derivation_path: vec![DERIVATION_PATH.to_vec()],
+        public_key,
+    }
+}
+
+fn signing_failed(code: ic_cdk::api::call::RejectionCode, msg: String) -> Error {
+    Error::OutcallFailed {
+        msg: format!("Threshold signing failed: {:?} {}", code, msg),
+    }
+}
+
+// The public key of the configured key, fetched once and kept
+async fn public_key(key_name: &str) -> Result<Vec<u8>, Error> {
+    if let Some(public_key) = signing_config().public_key {
+        return Ok(public_key);
+    }
+    let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: vec![DERIVATION_PATH.to_vec()],
+        key_id: key_id(key_name),
+    })
+    .await
+    .map_err(|(code, msg)| signing_failed(code, msg))?;
+    let mut config = signing_config();
+    // The key name may have changed while the call awaited
+    if config.key_name == key_name {
+        config.public_key = Some(response.public_key.clone());
+        store_signing_config(config);
+    }
+    Ok(response.public_key)
+}
+
+// Function to get the export manifest signed by the canister's threshold
+// key, so anyone holding an export can check it came from this canister
+#[ic_cdk::update]
+async fn sign_export_manifest() -> Result<SignedExportManifest, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if conserving_cycles() {
+        return Err(Error::OutcallFailed {
+            msg: "Signing is paused while the cycle balance is low".to_string(),
+        });
+    }
+    let key_name = signing_config().key_name;
+    let manifest = export_manifest();
+    let encoded = Encode!(&manifest).expect("Cannot encode the export manifest");
+    let public_key = public_key(&key_name).await?;
+    let (response,) = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: Sha256::digest(&encoded).to_vec(),
+        derivation_path: vec![DERIVATION_PATH.to_vec()],
+        key_id: key_id(&key_name),
+    })
+    .await
+    .map_err(|(code, msg)| signing_failed(code, msg))?;
+    Ok(SignedExportManifest {
+        manifest,
+        encoded,
+        signature: response.signature,
+        key: signing_key(key_name, public_key),
+    })
+}
+
+// Function to get the public key export signatures verify against, once one
+// was made
+#[ic_cdk::query]
+fn get_export_signing_key() -> Result<ExportSigningKey, Error> {
+    let config = signing_config();
+    let public_key = config.public_key.ok_or_else(|| Error::NotFound {
+        msg: "No export has been signed with the current key yet".to_string(),
+    })?;
+    Ok(signing_key(config.key_name, public_key))
+}
+
+// Function to choose the threshold key exports are signed with
+#[ic_cdk::update]
+fn set_export_signing_key_name(key_name: String) -> Result<String, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if !KEY_NAMES.contains(&key_name.as_str()) {
+        return Err(Error::InvalidInput {
+            msg: format!("The key name must be one of {}", KEY_NAMES.join(", ")),
+        });
+    }
+    if signing_config().key_name != key_name {
+        store_signing_config(SigningConfig {
+            key_name: key_name.clone(),
+            public_key: None,
+        });
+    }
+    Ok(key_name)
+}
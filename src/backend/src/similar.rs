@@ -0,0 +1,118 @@
+use crate::name_index::{ids_with_name_prefix, normalize_name};
+use crate::spatial_index::spaces_around;
+use crate::suggest::{space_tags, spaces_with_tag};
+use crate::{_get_green_space, _get_green_space_boundary, Error, GreenSpace, GreenSpaceStatus};
+use std::collections::BTreeSet;
+
+const MAX_SIMILAR: u32 = 50;
+const MAX_PER_TAG: usize = 100;
+const MAX_SAME_NAME: usize = 50;
+const NEARBY_RADIUS_M: f64 = 5_000.0;
+const MAX_NEARBY: usize = 100;
+
+// How much each aspect counts; they add up to 1, so a similarity of 1 means
+// alike in every aspect
+const CATEGORY_WEIGHT: f64 = 0.3;
+const TAG_WEIGHT: f64 = 0.3;
+const SIZE_WEIGHT: f64 = 0.2;
+const DISTRICT_WEIGHT: f64 = 0.2;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SimilarGreenSpace {
+    space: GreenSpace,
+    // Between 0 and 1
+    similarity: f64,
+    // The aspects the spaces have in common, e.g. "category", "district" or a
+    // shared tag
+    shared: Vec<String>,
+}
+
+fn boundary_area(id: u64) -> Option<f64> {
+    _get_green_space_boundary(&id)
+        .map(|boundary| boundary.area_m2())
+        .filter(|area| *area > 0.0)
+}
+
+// The spaces sharing a tag or nearly the same name, or nearby, rather than
+// every space, so a call does bounded work
+fn candidate_ids(space: &GreenSpace) -> BTreeSet<u64> {
+    let mut candidates = BTreeSet::new();
+    for tag in space_tags(space) {
+        candidates.extend(spaces_with_tag(&tag, MAX_PER_TAG));
+    }
+    candidates.extend(
+        ids_with_name_prefix(&normalize_name(&space.name), MAX_SAME_NAME)
+            .into_iter()
+            .map(|(_, id)| id),
+    );
+    if let Some(point) = &space.coordinates {
+        candidates.extend(
+            spaces_around(point, NEARBY_RADIUS_M, None)
+                .into_iter()
+                .take(MAX_NEARBY)
+                .map(|nearby| nearby.space.id),
+        );
+    }
+    candidates.remove(&space.id);
+    candidates
+}
+
+fn compare(space: &GreenSpace, area: Option<f64>, other: GreenSpace) -> SimilarGreenSpace {
+    let mut similarity = 0.0;
+    let mut shared = vec![];
+    if space.category.is_some() && space.category == other.category {
+        similarity += CATEGORY_WEIGHT;
+        shared.push("category".to_string());
+    }
+    if space.district_id.is_some() && space.district_id == other.district_id {
+        similarity += DISTRICT_WEIGHT;
+        shared.push("district".to_string());
+    }
+    let tags: BTreeSet<String> = space_tags(space).iter().map(|tag| tag.label()).collect();
+    let other_tags: BTreeSet<String> = space_tags(&other).iter().map(|tag| tag.label()).collect();
+    let union = tags.union(&other_tags).count();
+    if union > 0 {
+        let common: Vec<String> = tags.intersection(&other_tags).cloned().collect();
+        similarity += TAG_WEIGHT * common.len() as f64 / union as f64;
+        shared.extend(common);
+    }
+    // Size counts by the ratio of the smaller boundary's area to the larger's
+    if let (Some(area), Some(other_area)) = (area, boundary_area(other.id)) {
+        similarity += SIZE_WEIGHT * area.min(other_area) / area.max(other_area);
+    }
+    SimilarGreenSpace {
+        space: other,
+        similarity,
+        shared,
+    }
+}
+
+// Function to get the spaces most like one by category, tags (amenities are
+// recorded as tags), size and district, for suggestions and for finding
+// duplicates; archived spaces are left out
+#[ic_cdk::query]
+fn get_similar_green_spaces(id: u64, limit: u32) -> Result<Vec<SimilarGreenSpace>, Error> {
+    if limit == 0 || limit > MAX_SIMILAR {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_SIMILAR),
+        });
+    }
+    let space = _get_green_space(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("A green space with id={} not found", id),
+    })?;
+    let area = boundary_area(id);
+    let mut similar: Vec<SimilarGreenSpace> = candidate_ids(&space)
+        .into_iter()
+        .filter_map(|id| _get_green_space(&id))
+        .filter(|other| other.status != Some(GreenSpaceStatus::Archived))
+        .map(|other| compare(&space, area, other))
+        .filter(|similar| similar.similarity > 0.0)
+        .collect();
+    similar.sort_by(|a, b| {
+        b.similarity
+            .total_cmp(&a.similarity)
+            .then(a.space.id.cmp(&b.space.id))
+    });
+    similar.truncate(limit as usize);
+    Ok(similar)
+}
@@ -0,0 +1,157 @@
+use crate::{_get_green_space, get_memory, Error, GreenSpace, Memory, GREEN_SPACE_STORAGE};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const SLUG_INDEX_MEMORY_ID: u8 = 63;
+const SPACE_SLUGS_MEMORY_ID: u8 = 64;
+
+const MAX_SLUG_LEN: usize = 60;
+// Earlier slugs of a renamed space keep resolving, up to this many
+const MAX_PREVIOUS_SLUGS: usize = 10;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Slug(String);
+
+impl_storable!(Slug, 80);
+
+// The slug a space is linked by, and those it had before renames
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SpaceSlugs {
+    current: String,
+    previous: Vec<String>,
+}
+
+impl_storable!(SpaceSlugs, 1024);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SlugResolution {
+    space: GreenSpace,
+    // Differs from the slug asked for when that one is from before a rename
+    slug: String,
+}
+
+thread_local! {
+    // Every current and previous slug, with the space it names
+    pub(crate) static SLUG_INDEX: RefCell<StableBTreeMap<Slug, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SLUG_INDEX_MEMORY_ID)));
+
+    pub(crate) static SPACE_SLUGS: RefCell<StableBTreeMap<u64, SpaceSlugs, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SPACE_SLUGS_MEMORY_ID)));
+}
+
+// Lowercase ASCII letters and digits separated by single hyphens
+fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            if slug.len() >= MAX_SLUG_LEN {
+                break;
+            }
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    // Numeric slugs would read as ids in URLs
+    if slug.is_empty() || slug.bytes().all(|b| b.is_ascii_digit()) {
+        format!("space-{}", slug).trim_end_matches('-').to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+fn slug_owner(slug: &str) -> Option<u64> {
+    SLUG_INDEX.with(|index| index.borrow().get(&Slug(slug.to_string())))
+}
+
+// The slug of the name, with the first free numeric suffix when another
+// space holds it
+fn unique_slug(name: &str, id: u64) -> String {
+    let base = slugify(name);
+    let mut candidate = base.clone();
+    let mut suffix = 2;
+    while slug_owner(&candidate).is_some_and(|owner| owner != id) {
+        candidate = format!("{}-{}", base, suffix);
+        suffix += 1;
+    }
+    candidate
+}
+
+// The space holding the slug a space of this name would get
+pub(crate) fn slug_holder(name: &str) -> Option<u64> {
+    slug_owner(&slugify(name))
+}
+
+pub(crate) fn current_slug(id: u64) -> Option<String> {
+    SPACE_SLUGS.with(|s| s.borrow().get(&id).map(|slugs| slugs.current))
+}
+
+// Gives a new space its slug, and a renamed one a new slug while its old one
+// keeps resolving
+pub(crate) fn assign_slug(space: &GreenSpace) {
+    let existing = SPACE_SLUGS.with(|s| s.borrow().get(&space.id));
+    if existing
+        .as_ref()
+        .is_some_and(|slugs| slugify(&space.name) == slugify(&slugs.current))
+    {
+        return;
+    }
+    let slug = unique_slug(&space.name, space.id);
+    let mut slugs = existing.unwrap_or(SpaceSlugs {
+        current: slug.clone(),
+        previous: vec![],
+    });
+    if slugs.current != slug {
+        let replaced = std::mem::replace(&mut slugs.current, slug.clone());
+        slugs.previous.retain(|previous| *previous != slug);
+        slugs.previous.push(replaced);
+        if slugs.previous.len() > MAX_PREVIOUS_SLUGS {
+            let dropped = slugs.previous.remove(0);
+            SLUG_INDEX.with(|index| index.borrow_mut().remove(&Slug(dropped)));
+        }
+    }
+    SLUG_INDEX.with(|index| index.borrow_mut().insert(Slug(slug), space.id));
+    SPACE_SLUGS.with(|s| s.borrow_mut().insert(space.id, slugs));
+}
+
+pub(crate) fn remove_slugs(id: u64) {
+    let Some(slugs) = SPACE_SLUGS.with(|s| s.borrow_mut().remove(&id)) else {
+        return;
+    };
+    SLUG_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for slug in std::iter::once(slugs.current).chain(slugs.previous) {
+            index.remove(&Slug(slug));
+        }
+    });
+}
+
+// Assigns slugs on the first upgrade to a version with them
+pub(crate) fn ensure_slugs() {
+    if !SPACE_SLUGS.with(|s| s.borrow().is_empty()) {
+        return;
+    }
+    let spaces: Vec<GreenSpace> =
+        GREEN_SPACE_STORAGE.with(|s| s.borrow().iter().map(|(_, space)| space).collect());
+    for space in &spaces {
+        assign_slug(space);
+    }
+}
+
+// The space a slug names, current or from before a rename, with its current slug
+pub(crate) fn resolve_slug(slug: &str) -> Option<(GreenSpace, String)> {
+    let id = slug_owner(slug)?;
+    let space = _get_green_space(&id)?;
+    Some((space, current_slug(id)?))
+}
+
+// Function to look a space up by the slug of its shareable links
+#[ic_cdk::query]
+fn get_green_space_by_slug(slug: String) -> Result<SlugResolution, Error> {
+    resolve_slug(&slug.to_lowercase())
+        .map(|(space, slug)| SlugResolution { space, slug })
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("No green space has the slug {}", slug),
+        })
+}
@@ -0,0 +1,401 @@
+use crate::accessibility::ACCESSIBILITY_AUDITS;
+use crate::apiary::APIARIES;
+use crate::bans::BANS;
+use crate::block_log::{log_bulk_write, Value, BLOCKS};
+use crate::canopy::compute_canopy_stats;
+use crate::cascade::CASCADE_QUEUE;
+use crate::certification::{certify_data, rebuild_certified_spaces};
+use crate::changelog::CHANGE_LOG;
+use crate::cities::CITIES;
+use crate::compost::COMPOST_SITES;
+use crate::contractors::{CONTRACTORS, WORK_ORDERS};
+use crate::digest::DIGEST_SUBSCRIPTIONS;
+use crate::districts::DISTRICTS;
+use crate::events::EVENTS;
+use crate::expenses::EXPENSES;
+use crate::external_ids::{EXTERNAL_IDS, EXTERNAL_ID_LOOKUP};
+use crate::favorites::FAVORITES;
+use crate::featured::FEATURED_SPACES;
+use crate::federation::PEERS;
+use crate::fulltext::rebuild_term_index;
+use crate::geocoding::{GEOCODE_CACHE, REVERSE_GEOCODE_CACHE};
+use crate::governance::ensure_not_governed;
+use crate::heat::compute_heat_scores;
+use crate::links::SPACE_LINKS;
+use crate::maintenance::ensure_writable;
+use crate::metrics::METRIC_SERIES;
+use crate::moderation::MODERATION_QUEUE;
+use crate::name_index::ensure_name_index;
+use crate::noise::{NOISE_HOURLY, NOISE_SENSORS};
+use crate::notifications::NOTIFICATIONS;
+use crate::playground::{EQUIPMENT_INSPECTIONS, PLAYGROUND_EQUIPMENT};
+use crate::renames::NAME_HISTORY;
+use crate::reports::MONTHLY_REPORTS;
+use crate::reviews::REVIEWS;
+use crate::saved_searches::SAVED_SEARCHES;
+use crate::search_alerts::{ALERTED, ALERT_CURSOR};
+use crate::search_analytics::SEARCH_STATS;
+use crate::shards::SHARDS;
+use crate::slugs::{SLUG_INDEX, SPACE_SLUGS};
+use crate::soil::{CONTAMINANT_THRESHOLDS, SAMPLING_POINTS, SOIL_TESTS};
+use crate::spatial_index::ensure_spatial_index;
+use crate::stats::rebuild_space_counts;
+use crate::suggest::rebuild_tag_index;
+use crate::sync::{MODIFIED_AT, MODIFIED_INDEX};
+use crate::terms::TERMS_ACCEPTANCES;
+use crate::tickets::MAINTENANCE_TICKETS;
+use crate::trees::{TREES, TREES_BY_SPACE};
+use crate::trending::clear_trends;
+use crate::ulid::rebuild_ulid_index;
+use crate::views::VIEW_COUNTS;
+use crate::visits::VISITS;
+use crate::water::{WATER_FEATURES, WATER_QUALITY_TESTS};
+use crate::{
+    ensure_controller, Error, Memory, EXTERNAL_REF_INDEX, GREEN_SPACE_BOUNDARIES,
+    GREEN_SPACE_ID_COUNTER, GREEN_SPACE_STORAGE, ID_COUNTERS,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+// Bumped whenever the layout of a snapshot or of a stored record changes
+const SNAPSHOT_VERSION: u32 = 1;
+// Keeps every chunk well below the 2MB message limit
+const SNAPSHOT_CHUNK_BYTES: usize = 1024 * 1024;
+
+// A stored key and value, in their stable memory encoding
+pub(crate) type SnapshotEntry = (Vec<u8>, Vec<u8>);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    created_at: u64,
+    sections: Vec<SnapshotSection>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct SnapshotSection {
+    memory_id: u8,
+    entries: Vec<SnapshotEntry>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SnapshotManifest {
+    version: u32,
+    created_at: u64,
+    total_bytes: u64,
+    total_chunks: u64,
+    // Hex SHA-256 of the whole serialized snapshot
+    sha256: String,
+    pub(crate) entry_count: u64,
+}
+
+thread_local! {
+    // The last snapshot created, served chunk by chunk
+    static SNAPSHOT: RefCell<Option<(SnapshotManifest, Vec<u8>)>> = const { RefCell::new(None) };
+
+    // Snapshot being uploaded for a restore
+    static RESTORE_UPLOAD: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// A stable structure that can be dumped into and rebuilt from a snapshot
+pub(crate) trait SnapshotStore {
+    fn dump(&'static self) -> Vec<SnapshotEntry>;
+    fn load(&'static self, entries: Vec<SnapshotEntry>);
+    // Removes every entry and returns how many there were; cells keep their value
+    fn clear(&'static self) -> u64;
+}
+
+impl<K, V> SnapshotStore for LocalKey<RefCell<StableBTreeMap<K, V, Memory>>>
+where
+    K: BoundedStorable + Ord + Clone + 'static,
+    V: BoundedStorable + 'static,
+{
+    fn dump(&'static self) -> Vec<SnapshotEntry> {
+        self.with(|map| {
+            map.borrow()
+                .iter()
+                .map(|(key, value)| (key.to_bytes().into_owned(), value.to_bytes().into_owned()))
+                .collect()
+        })
+    }
+
+    fn load(&'static self, entries: Vec<SnapshotEntry>) {
+        self.clear();
+        self.with(|map| {
+            let mut map = map.borrow_mut();
+            for (key, value) in entries {
+                map.insert(K::from_bytes(key.into()), V::from_bytes(value.into()));
+            }
+        })
+    }
+
+    fn clear(&'static self) -> u64 {
+        self.with(|map| {
+            let mut map = map.borrow_mut();
+            let stale: Vec<K> = map.iter().map(|(key, _)| key).collect();
+            for key in &stale {
+                map.remove(key);
+            }
+            stale.len() as u64
+        })
+    }
+}
+
+impl<T> SnapshotStore for LocalKey<RefCell<Cell<T, Memory>>>
+where
+    T: Storable + 'static,
+{
+    fn dump(&'static self) -> Vec<SnapshotEntry> {
+        self.with(|cell| vec![(vec![], cell.borrow().get().to_bytes().into_owned())])
+    }
+
+    fn load(&'static self, entries: Vec<SnapshotEntry>) {
+        if let Some((_, value)) = entries.into_iter().next() {
+            self.with(|cell| {
+                cell.borrow_mut()
+                    .set(T::from_bytes(value.into()))
+                    .expect("Cannot restore a cell")
+            });
+        }
+    }
+
+    fn clear(&'static self) -> u64 {
+        0
+    }
+}
+
+// Every stable structure a snapshot covers, by memory id. Derived from the
+// data and rebuilt after a restore: the spatial index (21, 22), the name index
+// (35, 36), the space counts (37), the tag index (50), the term index (53),
+// the ULID index (66), the certified space tree (85), the tag pairs (89), the
+// canopy stats (94) and the heat scores (95, 96). Cleared on restore, as the
+// activity they are built from is not kept: the trends (90, 91). Belonging to
+// the canister, not to its data: the replication config (28), the webhooks
+// (29), governance (31, 32), the retention, maintenance and validation
+// settings (41-44), the metadata keys (45), the synonyms (51), the tokenizer
+// config (52), the API keys (60, 61), the ULID seed (65), the idempotency
+// cache and window (67, 68), the terms (70), the spam settings and recent
+// submissions (74, 75), the blocked terms (76), the challenge config (77), the
+// cycle config and alerts (79, 80), the quota limits, usage and overrides
+// (81-83), the subscribers (84), the signing config (86) and the stormwater
+// config (97)
+pub(crate) fn snapshot_stores() -> Vec<(u8, &'static dyn SnapshotStore)> {
+    vec![
+        (0, &GREEN_SPACE_ID_COUNTER),
+        (1, &GREEN_SPACE_STORAGE),
+        (2, &ID_COUNTERS),
+        (3, &WATER_FEATURES),
+        (4, &WATER_QUALITY_TESTS),
+        (5, &PLAYGROUND_EQUIPMENT),
+        (6, &EQUIPMENT_INSPECTIONS),
+        (7, &MAINTENANCE_TICKETS),
+        (8, &ACCESSIBILITY_AUDITS),
+        (9, &NOISE_SENSORS),
+        (10, &NOISE_HOURLY),
+        (11, &SAMPLING_POINTS),
+        (12, &SOIL_TESTS),
+        (13, &CONTAMINANT_THRESHOLDS),
+        (14, &COMPOST_SITES),
+        (15, &NOTIFICATIONS),
+        (16, &APIARIES),
+        (17, &GEOCODE_CACHE),
+        (18, &REVERSE_GEOCODE_CACHE),
+        (19, &EXTERNAL_REF_INDEX),
+        (20, &GREEN_SPACE_BOUNDARIES),
+        (23, &DISTRICTS),
+        (24, &CITIES),
+        (25, &SHARDS),
+        (26, &PEERS),
+        (27, &CHANGE_LOG),
+        (30, &BLOCKS),
+        (33, &MODIFIED_INDEX),
+        (34, &MODIFIED_AT),
+        (38, &MONTHLY_REPORTS),
+        (39, &VIEW_COUNTS),
+        (40, &METRIC_SERIES),
+        (46, &EXTERNAL_IDS),
+        (47, &EXTERNAL_ID_LOOKUP),
+        (48, &SPACE_LINKS),
+        (49, &NAME_HISTORY),
+        (54, &SEARCH_STATS),
+        (55, &SAVED_SEARCHES),
+        (56, &ALERT_CURSOR),
+        (57, &ALERTED),
+        (58, &FAVORITES),
+        (59, &DIGEST_SUBSCRIPTIONS),
+        (62, &EVENTS),
+        (63, &SLUG_INDEX),
+        (64, &SPACE_SLUGS),
+        (69, &CASCADE_QUEUE),
+        (71, &TERMS_ACCEPTANCES),
+        (72, &REVIEWS),
+        (73, &MODERATION_QUEUE),
+        (78, &BANS),
+        (87, &FEATURED_SPACES),
+        (88, &VISITS),
+        (92, &TREES),
+        (93, &TREES_BY_SPACE),
+        (98, &EXPENSES),
+        (99, &CONTRACTORS),
+        (100, &WORK_ORDERS),
+    ]
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn manifest_of(snapshot: &Snapshot, bytes: &[u8]) -> SnapshotManifest {
+    SnapshotManifest {
+        version: snapshot.version,
+        created_at: snapshot.created_at,
+        total_bytes: bytes.len() as u64,
+        total_chunks: bytes.len().div_ceil(SNAPSHOT_CHUNK_BYTES) as u64,
+        sha256: sha256_hex(bytes),
+        entry_count: snapshot
+            .sections
+            .iter()
+            .map(|section| section.entries.len() as u64)
+            .sum(),
+    }
+}
+
+// Function to serialize every stable structure into a new snapshot, replacing
+// the previous one; the chunks are then read with get_snapshot_chunk
+#[ic_cdk::update]
+fn create_snapshot() -> Result<SnapshotManifest, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        created_at: time(),
+        sections: snapshot_stores()
+            .into_iter()
+            .map(|(memory_id, store)| SnapshotSection {
+                memory_id,
+                entries: store.dump(),
+            })
+            .collect(),
+    };
+    let bytes = Encode!(&snapshot).map_err(|e| Error::InvalidInput {
+        msg: format!("Cannot encode the snapshot: {}", e),
+    })?;
+    let manifest = manifest_of(&snapshot, &bytes);
+    SNAPSHOT.with(|s| *s.borrow_mut() = Some((manifest.clone(), bytes)));
+    Ok(manifest)
+}
+
+#[ic_cdk::query]
+fn get_snapshot_chunk(chunk: u64) -> Result<Vec<u8>, Error> {
+    ensure_controller()?;
+    SNAPSHOT.with(|s| {
+        let snapshot = s.borrow();
+        let (manifest, bytes) = snapshot.as_ref().ok_or_else(|| Error::NotFound {
+            msg: "No snapshot has been created".to_string(),
+        })?;
+        if chunk >= manifest.total_chunks {
+            return Err(Error::NotFound {
+                msg: format!(
+                    "Chunk {} not found, the snapshot has {} chunks",
+                    chunk, manifest.total_chunks
+                ),
+            });
+        }
+        let start = chunk as usize * SNAPSHOT_CHUNK_BYTES;
+        let end = (start + SNAPSHOT_CHUNK_BYTES).min(bytes.len());
+        Ok(bytes[start..end].to_vec())
+    })
+}
+
+// Function to stage a snapshot for restore_snapshot, chunk by chunk in order;
+// `reset` discards a previous upload before appending the chunk
+#[ic_cdk::update]
+fn upload_snapshot_chunk(chunk: Vec<u8>, reset: bool) -> Result<u64, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    RESTORE_UPLOAD.with(|upload| {
+        let mut upload = upload.borrow_mut();
+        if reset {
+            upload.clear();
+        }
+        upload.extend_from_slice(&chunk);
+        Ok(upload.len() as u64)
+    })
+}
+
+// Function to replace every stable structure with the contents of the uploaded
+// snapshot, after checking it against the hash of its manifest
+#[ic_cdk::update]
+fn restore_snapshot(sha256: String) -> Result<SnapshotManifest, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    ensure_not_governed("Restoring a snapshot")?;
+    restore_uploaded_snapshot(&sha256)
+}
+
+pub(crate) fn restore_uploaded_snapshot(sha256: &str) -> Result<SnapshotManifest, Error> {
+    let bytes = RESTORE_UPLOAD.with(|upload| upload.borrow().clone());
+    if sha256_hex(&bytes) != sha256.to_lowercase() {
+        return Err(Error::InvalidInput {
+            msg: "The uploaded snapshot does not match the given hash".to_string(),
+        });
+    }
+    let snapshot = Decode!(&bytes, Snapshot).map_err(|e| Error::InvalidInput {
+        msg: format!("Cannot decode the snapshot: {}", e),
+    })?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Snapshot version {} cannot be restored, expected version {}",
+                snapshot.version, SNAPSHOT_VERSION
+            ),
+        });
+    }
+    let manifest = manifest_of(&snapshot, &bytes);
+
+    let stores = snapshot_stores();
+    if let Some(section) = snapshot
+        .sections
+        .iter()
+        .find(|section| !stores.iter().any(|(id, _)| *id == section.memory_id))
+    {
+        return Err(Error::InvalidInput {
+            msg: format!("Snapshot has an unknown memory id={}", section.memory_id),
+        });
+    }
+    let mut sections = snapshot.sections;
+    // Structures missing from the snapshot are restored empty
+    for (memory_id, store) in stores {
+        let entries = sections
+            .iter_mut()
+            .find(|section| section.memory_id == memory_id)
+            .map(|section| std::mem::take(&mut section.entries))
+            .unwrap_or_default();
+        store.load(entries);
+    }
+    ensure_spatial_index();
+    ensure_name_index();
+    rebuild_space_counts();
+    rebuild_tag_index();
+    rebuild_term_index();
+    rebuild_ulid_index();
+    rebuild_certified_spaces();
+    certify_data();
+    compute_canopy_stats();
+    compute_heat_scores();
+    clear_trends();
+    // The block log is restored too; the restore itself goes on top of it
+    log_bulk_write(
+        "snapshot_restore",
+        vec![("sha256".to_string(), Value::Text(sha256.to_lowercase()))],
+    );
+    RESTORE_UPLOAD.with(|upload| upload.borrow_mut().clear());
+    Ok(manifest)
+}
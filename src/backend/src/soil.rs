@@ -0,0 +1,444 @@
+use crate::block_log::{log_config, log_upsert};
+use crate::geo::Coordinates;
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::{
+    ensure_controller, ensure_green_space_exists, ensure_space_admin, get_memory, next_id, Error,
+    Memory, NANOS_PER_DAY,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use std::cell::RefCell;
+
+const SAMPLING_POINTS_MEMORY_ID: u8 = 11;
+const SOIL_TESTS_MEMORY_ID: u8 = 12;
+const CONTAMINANT_THRESHOLDS_MEMORY_ID: u8 = 13;
+
+const MAX_LABEL_LEN: usize = 100;
+const MAX_CONTAMINANTS: usize = 30;
+const MAX_THRESHOLDS: usize = 50;
+const NANOS_PER_YEAR: f64 = 365.25 * NANOS_PER_DAY as f64;
+
+// Residential soil screening levels in mg/kg used until admins configure their own
+const DEFAULT_THRESHOLDS: [(&str, f64); 6] = [
+    ("arsenic", 20.0),
+    ("cadmium", 3.0),
+    ("chromium", 100.0),
+    ("lead", 400.0),
+    ("mercury", 1.0),
+    ("zinc", 300.0),
+];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SamplingPoint {
+    id: u64,
+    pub(crate) space_id: u64,
+    label: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    created_at: u64,
+}
+
+impl_storable!(SamplingPoint, 512);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ContaminantReading {
+    substance: String,
+    mg_per_kg: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SoilTest {
+    id: u64,
+    point_id: u64,
+    pub(crate) space_id: u64,
+    sampled_at: u64,
+    ph: Option<f64>,
+    organic_matter_percent: Option<f64>,
+    contaminants: Vec<ContaminantReading>,
+    lab: String,
+}
+
+impl_storable!(SoilTest, 4096);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ContaminantThreshold {
+    substance: String,
+    max_mg_per_kg: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ContaminantThresholds {
+    thresholds: Vec<ContaminantThreshold>,
+}
+
+impl_storable!(ContaminantThresholds, 4096);
+
+impl Default for ContaminantThresholds {
+    fn default() -> Self {
+        ContaminantThresholds {
+            thresholds: DEFAULT_THRESHOLDS
+                .iter()
+                .map(|(substance, max)| ContaminantThreshold {
+                    substance: substance.to_string(),
+                    max_mg_per_kg: *max,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SamplingPointPayload {
+    space_id: u64,
+    label: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SoilTestPayload {
+    // Date the sample was taken, in nanoseconds since the epoch
+    sampled_at: u64,
+    ph: Option<f64>,
+    organic_matter_percent: Option<f64>,
+    contaminants: Vec<ContaminantReading>,
+    lab: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ThresholdExceedance {
+    substance: String,
+    mg_per_kg: f64,
+    max_mg_per_kg: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ContaminatedSample {
+    test: SoilTest,
+    exceedances: Vec<ThresholdExceedance>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SoilTrend {
+    point_id: u64,
+    history: Vec<SoilTest>,
+    // Least-squares slopes over the whole history; None with fewer than two values
+    ph_change_per_year: Option<f64>,
+    organic_matter_change_per_year: Option<f64>,
+}
+
+thread_local! {
+    pub(crate) static SAMPLING_POINTS: RefCell<StableBTreeMap<u64, SamplingPoint, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SAMPLING_POINTS_MEMORY_ID)));
+
+    // Tests keyed by (sampling point id, test id)
+    pub(crate) static SOIL_TESTS: RefCell<StableBTreeMap<(u64, u64), SoilTest, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SOIL_TESTS_MEMORY_ID)));
+
+    pub(crate) static CONTAMINANT_THRESHOLDS: RefCell<Cell<ContaminantThresholds, Memory>> = RefCell::new(
+        Cell::init(get_memory(CONTAMINANT_THRESHOLDS_MEMORY_ID), ContaminantThresholds::default())
+            .expect("Cannot initialize soil contaminant thresholds")
+    );
+}
+
+fn sampling_point_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A soil sampling point with id={} not found", id),
+    }
+}
+
+fn normalize_substance(substance: &str) -> String {
+    substance.trim().to_lowercase()
+}
+
+fn validate_measurements(payload: &SoilTestPayload) -> Result<(), Error> {
+    if let Some(ph) = payload.ph {
+        if !(0.0..=14.0).contains(&ph) {
+            return Err(Error::InvalidInput {
+                msg: format!("pH must be between 0 and 14, got {}", ph),
+            });
+        }
+    }
+    if let Some(percent) = payload.organic_matter_percent {
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(Error::InvalidInput {
+                msg: format!("Organic matter must be between 0 and 100%, got {}", percent),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn validate_soil_test(payload: &SoilTestPayload) -> Result<(), Error> {
+    validate_measurements(payload)?;
+    if payload.lab.len() > MAX_LABEL_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Lab name must be at most {} characters", MAX_LABEL_LEN),
+        });
+    }
+    if payload.contaminants.len() > MAX_CONTAMINANTS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "A test can report at most {} contaminants",
+                MAX_CONTAMINANTS
+            ),
+        });
+    }
+    for reading in &payload.contaminants {
+        if reading.substance.trim().is_empty() || reading.substance.len() > MAX_LABEL_LEN {
+            return Err(Error::InvalidInput {
+                msg: format!("Substance names must be 1-{} characters", MAX_LABEL_LEN),
+            });
+        }
+        if !reading.mg_per_kg.is_finite() || reading.mg_per_kg < 0.0 {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "Concentration of {} must be a non-negative number",
+                    reading.substance
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn exceedances(test: &SoilTest, thresholds: &[ContaminantThreshold]) -> Vec<ThresholdExceedance> {
+    test.contaminants
+        .iter()
+        .filter_map(|reading| {
+            let threshold = thresholds
+                .iter()
+                .find(|threshold| threshold.substance == reading.substance)?;
+            (reading.mg_per_kg > threshold.max_mg_per_kg).then(|| ThresholdExceedance {
+                substance: reading.substance.clone(),
+                mg_per_kg: reading.mg_per_kg,
+                max_mg_per_kg: threshold.max_mg_per_kg,
+            })
+        })
+        .collect()
+}
+
+// Least-squares slope of (timestamp, value) points, in units per year
+fn slope_per_year(points: &[(u64, f64)]) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let origin = points[0].0;
+    let xs: Vec<f64> = points
+        .iter()
+        .map(|(t, _)| (t.saturating_sub(origin)) as f64 / NANOS_PER_YEAR)
+        .collect();
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = xs
+        .iter()
+        .zip(points)
+        .map(|(x, (_, y))| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+    (variance > 0.0).then(|| covariance / variance)
+}
+
+fn tests_for_point(point_id: u64) -> Vec<SoilTest> {
+    let mut tests: Vec<_> = SOIL_TESTS.with(|s| {
+        s.borrow()
+            .range((point_id, 0)..=(point_id, u64::MAX))
+            .map(|(_, test)| test)
+            .collect()
+    });
+    tests.sort_by_key(|test| test.sampled_at);
+    tests
+}
+
+// Function for admins of a green space to add a soil sampling point to it
+#[ic_cdk::update]
+fn add_sampling_point(payload: SamplingPointPayload) -> Result<SamplingPoint, Error> {
+    ensure_writable()?;
+    ensure_space_admin(payload.space_id)?;
+    if payload.label.trim().is_empty() || payload.label.len() > MAX_LABEL_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Sampling point label must be 1-{} characters",
+                MAX_LABEL_LEN
+            ),
+        });
+    }
+    match (payload.latitude, payload.longitude) {
+        (Some(lat), Some(lng)) => Coordinates { lat, lng }.validate()?,
+        (None, None) => {}
+        _ => {
+            return Err(Error::InvalidInput {
+                msg: "A sampling point needs both a latitude and a longitude, or neither"
+                    .to_string(),
+            })
+        }
+    }
+
+    let point = SamplingPoint {
+        id: next_id(SAMPLING_POINTS_MEMORY_ID),
+        space_id: payload.space_id,
+        label: payload.label,
+        latitude: payload.latitude,
+        longitude: payload.longitude,
+        created_at: time(),
+    };
+
+    SAMPLING_POINTS.with(|s| s.borrow_mut().insert(point.id, point.clone()));
+    log_upsert("sampling_point", &point.id, &point);
+    Ok(point)
+}
+
+#[ic_cdk::query]
+fn get_sampling_points_for_space(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<SamplingPoint>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(SAMPLING_POINTS.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, point)| point.space_id == space_id),
+        )
+    }))
+}
+
+// Function to record lab results for a soil sample
+#[ic_cdk::update]
+fn record_soil_test(point_id: u64, payload: SoilTestPayload) -> Result<SoilTest, Error> {
+    ensure_writable()?;
+    let point = SAMPLING_POINTS
+        .with(|s| s.borrow().get(&point_id))
+        .ok_or_else(|| sampling_point_not_found(point_id))?;
+    ensure_space_admin(point.space_id)?;
+    validate_soil_test(&payload)?;
+
+    let test = SoilTest {
+        id: next_id(SOIL_TESTS_MEMORY_ID),
+        point_id,
+        space_id: point.space_id,
+        sampled_at: payload.sampled_at,
+        ph: payload.ph,
+        organic_matter_percent: payload.organic_matter_percent,
+        contaminants: payload
+            .contaminants
+            .into_iter()
+            .map(|reading| ContaminantReading {
+                substance: normalize_substance(&reading.substance),
+                mg_per_kg: reading.mg_per_kg,
+            })
+            .collect(),
+        lab: payload.lab,
+    };
+
+    SOIL_TESTS.with(|s| s.borrow_mut().insert((point_id, test.id), test.clone()));
+    log_upsert("soil_test", &(point_id, test.id), &test);
+    Ok(test)
+}
+
+// Function to get the test history of a sampling point with pH and organic matter trends
+#[ic_cdk::query]
+fn get_soil_trend(point_id: u64) -> Result<SoilTrend, Error> {
+    if SAMPLING_POINTS
+        .with(|s| s.borrow().get(&point_id))
+        .is_none()
+    {
+        return Err(sampling_point_not_found(point_id));
+    }
+
+    let history = tests_for_point(point_id);
+    let ph: Vec<_> = history
+        .iter()
+        .filter_map(|test| test.ph.map(|ph| (test.sampled_at, ph)))
+        .collect();
+    let organic_matter: Vec<_> = history
+        .iter()
+        .filter_map(|test| {
+            test.organic_matter_percent
+                .map(|percent| (test.sampled_at, percent))
+        })
+        .collect();
+
+    Ok(SoilTrend {
+        point_id,
+        ph_change_per_year: slope_per_year(&ph),
+        organic_matter_change_per_year: slope_per_year(&organic_matter),
+        history,
+    })
+}
+
+// Function to list samples with a contaminant above its threshold, optionally for one space
+#[ic_cdk::query]
+fn get_contaminated_samples(
+    space_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<ContaminatedSample>, Error> {
+    if let Some(space_id) = space_id {
+        ensure_green_space_exists(space_id)?;
+    }
+    let thresholds = CONTAMINANT_THRESHOLDS.with(|c| c.borrow().get().thresholds.clone());
+    let range = keys_after_token::<(u64, u64)>(&next_token)?;
+
+    Ok(SOIL_TESTS.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, test)| space_id.is_none_or(|id| test.space_id == id))
+                .filter_map(|(key, test)| {
+                    let exceedances = exceedances(&test, &thresholds);
+                    (!exceedances.is_empty())
+                        .then_some((key, ContaminatedSample { test, exceedances }))
+                }),
+        )
+    }))
+}
+
+#[ic_cdk::query]
+fn get_contaminant_thresholds() -> Result<Vec<ContaminantThreshold>, Error> {
+    Ok(CONTAMINANT_THRESHOLDS.with(|c| c.borrow().get().thresholds.clone()))
+}
+
+// Function to replace the contamination thresholds samples are flagged against
+#[ic_cdk::update]
+fn set_contaminant_thresholds(
+    thresholds: Vec<ContaminantThreshold>,
+) -> Result<Vec<ContaminantThreshold>, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if thresholds.len() > MAX_THRESHOLDS {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} thresholds can be configured", MAX_THRESHOLDS),
+        });
+    }
+    let mut normalized = Vec::with_capacity(thresholds.len());
+    for threshold in thresholds {
+        let substance = normalize_substance(&threshold.substance);
+        if substance.is_empty() || substance.len() > MAX_LABEL_LEN {
+            return Err(Error::InvalidInput {
+                msg: format!("Substance names must be 1-{} characters", MAX_LABEL_LEN),
+            });
+        }
+        if !threshold.max_mg_per_kg.is_finite() || threshold.max_mg_per_kg < 0.0 {
+            return Err(Error::InvalidInput {
+                msg: format!("Threshold for {} must be a non-negative number", substance),
+            });
+        }
+        normalized.push(ContaminantThreshold {
+            substance,
+            max_mg_per_kg: threshold.max_mg_per_kg,
+        });
+    }
+
+    CONTAMINANT_THRESHOLDS.with(|c| {
+        c.borrow_mut()
+            .set(ContaminantThresholds {
+                thresholds: normalized.clone(),
+            })
+            .expect("Cannot store soil contaminant thresholds")
+    });
+    log_config("contaminant_thresholds", &normalized);
+    Ok(normalized)
+}
@@ -0,0 +1,176 @@
+use crate::block_log::log_config;
+use crate::maintenance::ensure_writable;
+use crate::{ensure_controller, get_memory, next_id, Error, Memory};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+const SPAM_CONFIG_MEMORY_ID: u8 = 74;
+const RECENT_SUBMISSIONS_MEMORY_ID: u8 = 75;
+
+const MAX_WINDOW_SECS: u64 = 7 * 24 * 60 * 60;
+// Shorter texts, like "Lovely park", are repeated by honest users too
+const MIN_DUPLICATE_LEN: usize = 20;
+const MAX_EXPIRED_PER_CALL: usize = 100;
+
+// Thresholds of the heuristics that hold content for a moderator rather
+// than publishing it
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct SpamConfig {
+    // Links a submission may contain
+    max_links: u32,
+    // Submissions a principal may make within the burst window
+    burst_limit: u32,
+    burst_window_secs: u64,
+    // How long a text counts as a repeat when submitted again, by anyone
+    duplicate_window_secs: u64,
+}
+
+impl Default for SpamConfig {
+    fn default() -> Self {
+        SpamConfig {
+            max_links: 2,
+            burst_limit: 5,
+            burst_window_secs: 10 * 60,
+            duplicate_window_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl_storable!(SpamConfig, 64);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RecentSubmission {
+    author: Principal,
+    // SHA-256 of the normalized text
+    text_hash: Vec<u8>,
+}
+
+impl_storable!(RecentSubmission, 128);
+
+thread_local! {
+    static SPAM_CONFIG: RefCell<Cell<SpamConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(SPAM_CONFIG_MEMORY_ID), SpamConfig::default())
+            .expect("Cannot initialize the spam config")
+    );
+
+    // Submissions within the longer window, keyed by (submitted at, sequence)
+    static RECENT_SUBMISSIONS: RefCell<StableBTreeMap<(u64, u64), RecentSubmission, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(RECENT_SUBMISSIONS_MEMORY_ID)));
+}
+
+fn spam_config() -> SpamConfig {
+    SPAM_CONFIG.with(|c| *c.borrow().get())
+}
+
+// Lowercase with runs of whitespace collapsed, so trivial variations repeat
+fn normalize_text(text: &str) -> String {
+    text.to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn count_links(text: &str) -> usize {
+    let text = text.to_lowercase();
+    ["http://", "https://", "www."]
+        .iter()
+        .map(|marker| text.matches(marker).count())
+        .sum::<usize>()
+        - text.matches("://www.").count()
+}
+
+fn expire_submissions(config: &SpamConfig, now: u64) {
+    let window = config.burst_window_secs.max(config.duplicate_window_secs) * 1_000_000_000;
+    let cutoff = now.saturating_sub(window);
+    let expired: Vec<(u64, u64)> = RECENT_SUBMISSIONS.with(|s| {
+        s.borrow()
+            .range(..(cutoff, 0))
+            .map(|(key, _)| key)
+            .take(MAX_EXPIRED_PER_CALL)
+            .collect()
+    });
+    RECENT_SUBMISSIONS.with(|s| {
+        let mut submissions = s.borrow_mut();
+        for key in &expired {
+            submissions.remove(key);
+        }
+    });
+}
+
+// Records a submission and returns why it looks like spam, if it does:
+// repeated text, a burst of submissions by its author, or many links
+pub(crate) fn screen_submission(author: Principal, text: &str) -> Vec<String> {
+    let config = spam_config();
+    let now = time();
+    expire_submissions(&config, now);
+    let normalized = normalize_text(text);
+    let text_hash = Sha256::digest(normalized.as_bytes()).to_vec();
+    let burst_from = now.saturating_sub(config.burst_window_secs * 1_000_000_000);
+    let duplicate_from = now.saturating_sub(config.duplicate_window_secs * 1_000_000_000);
+    let (burst, repeated) = RECENT_SUBMISSIONS.with(|s| {
+        let submissions = s.borrow();
+        let burst = submissions
+            .range((burst_from, 0)..)
+            .filter(|(_, submission)| submission.author == author)
+            .count();
+        let repeated = normalized.len() >= MIN_DUPLICATE_LEN
+            && submissions
+                .range((duplicate_from, 0)..)
+                .any(|(_, submission)| submission.text_hash == text_hash);
+        (burst, repeated)
+    });
+    let mut signals = vec![];
+    if repeated {
+        signals.push("Repeats the text of a recent submission".to_string());
+    }
+    if burst >= config.burst_limit as usize {
+        signals.push(format!(
+            "{} earlier submissions by the author within {} seconds",
+            burst, config.burst_window_secs
+        ));
+    }
+    let links = count_links(text);
+    if links > config.max_links as usize {
+        signals.push(format!("Contains {} links", links));
+    }
+    RECENT_SUBMISSIONS.with(|s| {
+        s.borrow_mut().insert(
+            (now, next_id(RECENT_SUBMISSIONS_MEMORY_ID)),
+            RecentSubmission { author, text_hash },
+        )
+    });
+    signals
+}
+
+// Function for controllers to tune the spam heuristics
+#[ic_cdk::update]
+fn set_spam_config(config: SpamConfig) -> Result<SpamConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let windows = [config.burst_window_secs, config.duplicate_window_secs];
+    if windows.iter().any(|w| *w == 0 || *w > MAX_WINDOW_SECS) {
+        return Err(Error::InvalidInput {
+            msg: format!("Windows must be between 1 and {} seconds", MAX_WINDOW_SECS),
+        });
+    }
+    if config.burst_limit == 0 {
+        return Err(Error::InvalidInput {
+            msg: "The burst limit must be at least 1".to_string(),
+        });
+    }
+    SPAM_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config)
+            .expect("Cannot store the spam config")
+    });
+    log_config("spam_config", &config);
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_spam_config() -> Result<SpamConfig, Error> {
+    Ok(spam_config())
+}
@@ -0,0 +1,369 @@
+use crate::cities::in_city;
+use crate::geo::{distance_m, BoundingBox, Coordinates, Polygon};
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::{
+    _get_green_space, _get_green_space_boundary, ensure_controller, get_memory, Error, GreenSpace,
+    Memory, GREEN_SPACE_STORAGE,
+};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const SPATIAL_INDEX_MEMORY_ID: u8 = 21;
+const SPATIAL_INDEX_CELLS_MEMORY_ID: u8 = 22;
+
+// Grid cells are 0.01° (~1.1 km north-south) on each side
+const CELL_SIZE_DEG: f64 = 0.01;
+// Geometry spanning more cells than this goes into a single bucket that every
+// lookup scans, and lookups over larger areas scan the whole index
+const MAX_CELLS: u64 = 4_096;
+const OVERSIZED_CELL: u64 = u64::MAX;
+const METRES_PER_DEG_LAT: f64 = 111_320.0;
+const MAX_NEAREST: u32 = 100;
+const NEAREST_START_RADIUS_M: f64 = 1_000.0;
+// Half the Earth's circumference; no two points are farther apart
+const MAX_EARTH_DISTANCE_M: f64 = 20_037_508.0;
+
+type CellKey = u64;
+
+thread_local! {
+    // Green space ids keyed by (grid cell, space id)
+    static SPATIAL_INDEX: RefCell<StableBTreeMap<(CellKey, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SPATIAL_INDEX_MEMORY_ID)));
+
+    // The cells each space is indexed under, keyed by (space id, grid cell), so
+    // entries can be removed when the space moves
+    static SPATIAL_INDEX_CELLS: RefCell<StableBTreeMap<(u64, CellKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SPATIAL_INDEX_CELLS_MEMORY_ID)));
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct NearbyGreenSpace {
+    pub(crate) space: GreenSpace,
+    pub(crate) distance_m: f64,
+}
+
+fn cell_row(lat: f64) -> u64 {
+    ((lat + 90.0) / CELL_SIZE_DEG).floor() as u64
+}
+
+fn cell_col(lng: f64) -> u64 {
+    ((lng + 180.0) / CELL_SIZE_DEG).floor() as u64
+}
+
+fn cell_key(row: u64, col: u64) -> CellKey {
+    (row << 32) | col
+}
+
+// The grid cells covering a bounding box, or None if there are more than MAX_CELLS
+fn cells_covering(bbox: &BoundingBox) -> Option<Vec<CellKey>> {
+    let (rows, cols) = (
+        cell_row(bbox.south)..=cell_row(bbox.north),
+        cell_col(bbox.west)..=cell_col(bbox.east),
+    );
+    let count = (rows.end() - rows.start() + 1) * (cols.end() - cols.start() + 1);
+    if count > MAX_CELLS {
+        return None;
+    }
+    Some(
+        rows.flat_map(|row| cols.clone().map(move |col| cell_key(row, col)))
+            .collect(),
+    )
+}
+
+// Grows a box by at least `margin_m` in every direction
+pub(crate) fn expand_bbox(bbox: &BoundingBox, margin_m: f64) -> BoundingBox {
+    let widest_lat = bbox.south.abs().max(bbox.north.abs());
+    let d_lat = margin_m / METRES_PER_DEG_LAT;
+    let d_lng = margin_m / (METRES_PER_DEG_LAT * widest_lat.to_radians().cos().max(0.01));
+    BoundingBox {
+        south: (bbox.south - d_lat).max(-90.0),
+        west: (bbox.west - d_lng).max(-180.0),
+        north: (bbox.north + d_lat).min(90.0),
+        east: (bbox.east + d_lng).min(180.0),
+    }
+}
+
+// A box around a point reaching at least `radius_m` in every direction
+pub(crate) fn bbox_around(center: &Coordinates, radius_m: f64) -> BoundingBox {
+    let point = BoundingBox {
+        south: center.lat,
+        west: center.lng,
+        north: center.lat,
+        east: center.lng,
+    };
+    expand_bbox(&point, radius_m)
+}
+
+fn cells_of(space: &GreenSpace, boundary: Option<&Polygon>) -> BTreeSet<CellKey> {
+    let mut cells = BTreeSet::new();
+    if let Some(c) = &space.coordinates {
+        cells.insert(cell_key(cell_row(c.lat), cell_col(c.lng)));
+    }
+    if let Some(boundary) = boundary {
+        match cells_covering(&boundary.bounds()) {
+            Some(covering) => cells.extend(covering),
+            None => {
+                cells.insert(OVERSIZED_CELL);
+            }
+        }
+    }
+    cells
+}
+
+fn unindex(id: u64) {
+    let cells: Vec<CellKey> = SPATIAL_INDEX_CELLS.with(|c| {
+        c.borrow()
+            .range((id, 0)..=(id, CellKey::MAX))
+            .map(|((_, cell), _)| cell)
+            .collect()
+    });
+    for cell in cells {
+        SPATIAL_INDEX.with(|index| index.borrow_mut().remove(&(cell, id)));
+        SPATIAL_INDEX_CELLS.with(|c| c.borrow_mut().remove(&(id, cell)));
+    }
+}
+
+// Brings the index entries of a green space in line with its stored geometry;
+// spaces that no longer exist are removed from the index
+pub(crate) fn reindex_green_space(id: u64) {
+    unindex(id);
+    let Some(space) = _get_green_space(&id) else {
+        return;
+    };
+    for cell in cells_of(&space, _get_green_space_boundary(&id).as_ref()) {
+        SPATIAL_INDEX.with(|index| index.borrow_mut().insert((cell, id), ()));
+        SPATIAL_INDEX_CELLS.with(|c| c.borrow_mut().insert((id, cell), ()));
+    }
+}
+
+// Ids of the spaces whose geometry may lie inside the bounding box
+pub(crate) fn candidate_ids(bbox: &BoundingBox) -> BTreeSet<u64> {
+    let Some(cells) = cells_covering(bbox) else {
+        return SPATIAL_INDEX.with(|index| index.borrow().iter().map(|((_, id), _)| id).collect());
+    };
+    SPATIAL_INDEX.with(|index| {
+        let index = index.borrow();
+        cells
+            .into_iter()
+            .chain(std::iter::once(OVERSIZED_CELL))
+            .flat_map(|cell| {
+                index
+                    .range((cell, 0)..=(cell, u64::MAX))
+                    .map(|((_, id), _)| id)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    })
+}
+
+// Distance from a point to a space: zero inside its boundary, otherwise the
+// distance to its marker or the nearest boundary vertex
+pub(crate) fn distance_to_space(
+    point: &Coordinates,
+    space: &GreenSpace,
+    boundary: Option<&Polygon>,
+) -> Option<f64> {
+    if boundary.is_some_and(|b| b.contains(point)) {
+        return Some(0.0);
+    }
+    space
+        .coordinates
+        .iter()
+        .chain(boundary.into_iter().flat_map(|b| b.exterior.iter()))
+        .map(|c| distance_m(point, c))
+        .min_by(f64::total_cmp)
+}
+
+fn in_bbox(bbox: &BoundingBox, c: &Coordinates) -> bool {
+    (bbox.south..=bbox.north).contains(&c.lat) && (bbox.west..=bbox.east).contains(&c.lng)
+}
+
+// Rebuilds the index from scratch when it does not match the stored spaces
+pub(crate) fn ensure_spatial_index() {
+    let indexed: BTreeSet<u64> =
+        SPATIAL_INDEX_CELLS.with(|c| c.borrow().iter().map(|((id, _), _)| id).collect());
+    let expected: BTreeSet<u64> = GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(id, space)| {
+                space.coordinates.is_some() || _get_green_space_boundary(id).is_some()
+            })
+            .map(|(id, _)| id)
+            .collect()
+    });
+    let consistent = indexed == expected
+        && SPATIAL_INDEX.with(|index| {
+            index.borrow().iter().all(|((cell, id), _)| {
+                SPATIAL_INDEX_CELLS.with(|c| c.borrow().contains_key(&(id, cell)))
+            })
+        });
+    if !consistent {
+        rebuild_index();
+    }
+}
+
+fn rebuild_index() {
+    let stale: Vec<_> = SPATIAL_INDEX.with(|index| index.borrow().iter().map(|(k, _)| k).collect());
+    SPATIAL_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in stale {
+            index.remove(&key);
+        }
+    });
+    let stale: Vec<_> = SPATIAL_INDEX_CELLS.with(|c| c.borrow().iter().map(|(k, _)| k).collect());
+    SPATIAL_INDEX_CELLS.with(|c| {
+        let mut cells = c.borrow_mut();
+        for key in stale {
+            cells.remove(&key);
+        }
+    });
+    let ids: Vec<u64> = GREEN_SPACE_STORAGE.with(|s| s.borrow().iter().map(|(id, _)| id).collect());
+    for id in ids {
+        reindex_green_space(id);
+    }
+}
+
+// Function to rebuild the spatial index from the stored spaces
+#[ic_cdk::update]
+fn rebuild_spatial_index() -> Result<u64, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    rebuild_index();
+    Ok(SPATIAL_INDEX.with(|index| index.borrow().len()))
+}
+
+// The indexed spaces of a city around a point with their distances, nearest first;
+// spaces beyond `radius_m` may be included but the list is only complete up to it
+pub(crate) fn spaces_around(
+    point: &Coordinates,
+    radius_m: f64,
+    city_id: Option<u64>,
+) -> Vec<NearbyGreenSpace> {
+    let mut found: Vec<_> = candidate_ids(&bbox_around(point, radius_m))
+        .into_iter()
+        .filter_map(|id| {
+            let space = _get_green_space(&id).filter(|s| in_city(city_id, s.city_id))?;
+            let boundary = _get_green_space_boundary(&id);
+            let distance_m = distance_to_space(point, &space, boundary.as_ref())?;
+            Some(NearbyGreenSpace { space, distance_m })
+        })
+        .collect();
+    found.sort_by(|a, b| a.distance_m.total_cmp(&b.distance_m));
+    found
+}
+
+// Function to get the spaces within a radius of a point, nearest first
+#[ic_cdk::query]
+fn get_green_spaces_nearby(
+    lat: f64,
+    lng: f64,
+    radius_m: f64,
+    city_id: Option<u64>,
+) -> Result<Vec<NearbyGreenSpace>, Error> {
+    let point = Coordinates { lat, lng };
+    point.validate()?;
+    if !radius_m.is_finite() || radius_m <= 0.0 {
+        return Err(Error::InvalidInput {
+            msg: "The radius must be positive".to_string(),
+        });
+    }
+
+    let mut result = spaces_around(&point, radius_m, city_id);
+    result.retain(|s| s.distance_m <= radius_m);
+    Ok(result)
+}
+
+// The spaces of a city whose marker or boundary lies in a bounding box
+// The space, if its marker or boundary lies in the bounding box
+fn space_in_bbox(id: u64, bbox: &BoundingBox, city_id: Option<u64>) -> Option<GreenSpace> {
+    let space = _get_green_space(&id).filter(|s| in_city(city_id, s.city_id))?;
+    let marker_inside = space.coordinates.is_some_and(|c| in_bbox(bbox, &c));
+    let boundary_inside =
+        _get_green_space_boundary(&id).is_some_and(|b| b.bounds().intersects(bbox));
+    (marker_inside || boundary_inside).then_some(space)
+}
+
+pub(crate) fn spaces_in_bbox(bbox: &BoundingBox, city_id: Option<u64>) -> Vec<GreenSpace> {
+    candidate_ids(bbox)
+        .into_iter()
+        .filter_map(|id| space_in_bbox(id, bbox, city_id))
+        .collect()
+}
+
+// A page of the spaces in a bounding box, in id order
+pub(crate) fn spaces_in_bbox_page(
+    bbox: &BoundingBox,
+    city_id: Option<u64>,
+    next_token: &Option<String>,
+) -> Result<Page<GreenSpace>, Error> {
+    let range = keys_after_token::<u64>(next_token)?;
+    Ok(page_by_size(candidate_ids(bbox).range(range).filter_map(
+        |id| space_in_bbox(*id, bbox, city_id).map(|space| (*id, space)),
+    )))
+}
+
+// Function to get the spaces whose marker or boundary lies in a bounding box
+#[ic_cdk::query]
+fn get_green_spaces_in_bbox(
+    bbox: BoundingBox,
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<GreenSpace>, Error> {
+    bbox.validate()?;
+    spaces_in_bbox_page(&bbox, city_id, &next_token)
+}
+
+// Function to get the spaces whose boundary contains a point
+#[ic_cdk::query]
+fn get_green_spaces_containing(
+    lat: f64,
+    lng: f64,
+    city_id: Option<u64>,
+    next_token: Option<String>,
+) -> Result<Page<GreenSpace>, Error> {
+    let point = Coordinates { lat, lng };
+    point.validate()?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(page_by_size(
+        candidate_ids(&bbox_around(&point, 0.0))
+            .range(range)
+            .filter(|id| _get_green_space_boundary(id).is_some_and(|b| b.contains(&point)))
+            .filter_map(_get_green_space)
+            .filter(|space| in_city(city_id, space.city_id))
+            .map(|space| (space.id, space)),
+    ))
+}
+
+// Function to get the k spaces closest to a point, nearest first
+#[ic_cdk::query]
+fn nearest_green_spaces(
+    lat: f64,
+    lng: f64,
+    k: u32,
+    city_id: Option<u64>,
+) -> Result<Vec<NearbyGreenSpace>, Error> {
+    let point = Coordinates { lat, lng };
+    point.validate()?;
+    if k == 0 || k > MAX_NEAREST {
+        return Err(Error::InvalidInput {
+            msg: format!("k must be between 1 and {}", MAX_NEAREST),
+        });
+    }
+    let k = k as usize;
+
+    // Widen the search until k spaces lie within the radius; a space outside the
+    // radius may be farther than an unscanned one, so only those inside count
+    let mut radius_m = NEAREST_START_RADIUS_M;
+    loop {
+        let mut found = spaces_around(&point, radius_m, city_id);
+
+        let within = found.iter().filter(|s| s.distance_m <= radius_m).count();
+        if within >= k || radius_m >= MAX_EARTH_DISTANCE_M {
+            found.truncate(k);
+            return Ok(found);
+        }
+        radius_m *= 4.0;
+    }
+}
@@ -0,0 +1,229 @@
+use crate::apiary::APIARIES;
+use crate::block_log::log_upsert;
+use crate::cities::ensure_city_write;
+use crate::governance::ensure_not_governed;
+use crate::links::{link_split_part, space_links, MAX_LINKS_PER_SPACE};
+use crate::maintenance::ensure_writable;
+use crate::playground::PLAYGROUND_EQUIPMENT;
+use crate::shards::shard_for_city;
+use crate::tickets::MAINTENANCE_TICKETS;
+use crate::ulid::new_ulid;
+use crate::water::WATER_FEATURES;
+use crate::{
+    _get_green_space, do_insert_green_space, insert_new_green_space, next_green_space_id,
+    set_green_space_boundary, validate_payload_fields, validate_payload_references, Error,
+    GreenSpace, GreenSpaceStatus, GreenSpaceUpdatePayload, Memory,
+};
+use candid::CandidType;
+use ic_cdk::api::time;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+const MAX_SPLIT_PARTS: usize = 20;
+
+// A record attached to a space that moves to one of its parts on a split
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) enum SplitRecord {
+    MaintenanceTicket(u64),
+    PlaygroundEquipment(u64),
+    WaterFeature(u64),
+    Apiary(u64),
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SplitAssignment {
+    record: SplitRecord,
+    // Index into the parts of the split
+    part: u32,
+}
+
+type RecordStore<V> = LocalKey<RefCell<StableBTreeMap<u64, V, Memory>>>;
+
+fn space_of<V: BoundedStorable>(
+    store: &'static RecordStore<V>,
+    id: u64,
+    space_id: fn(&V) -> u64,
+) -> Option<u64> {
+    store
+        .with(|s| s.borrow().get(&id))
+        .map(|record| space_id(&record))
+}
+
+fn move_record<V: BoundedStorable + CandidType>(
+    store: &'static RecordStore<V>,
+    kind: &str,
+    id: u64,
+    set_space_id: fn(&mut V, u64),
+    space_id: u64,
+) {
+    store.with(|s| {
+        let mut records = s.borrow_mut();
+        if let Some(mut record) = records.get(&id) {
+            set_space_id(&mut record, space_id);
+            log_upsert(kind, &id, &record);
+            records.insert(id, record);
+        }
+    });
+}
+
+impl SplitRecord {
+    fn space_id(self) -> Option<u64> {
+        match self {
+            SplitRecord::MaintenanceTicket(id) => {
+                space_of(&MAINTENANCE_TICKETS, id, |t| t.space_id)
+            }
+            SplitRecord::PlaygroundEquipment(id) => {
+                space_of(&PLAYGROUND_EQUIPMENT, id, |e| e.space_id)
+            }
+            SplitRecord::WaterFeature(id) => space_of(&WATER_FEATURES, id, |f| f.space_id),
+            SplitRecord::Apiary(id) => space_of(&APIARIES, id, |a| a.space_id),
+        }
+    }
+
+    fn move_to(self, space_id: u64) {
+        match self {
+            SplitRecord::MaintenanceTicket(id) => move_record(
+                &MAINTENANCE_TICKETS,
+                "maintenance_ticket",
+                id,
+                |t, s| t.space_id = s,
+                space_id,
+            ),
+            SplitRecord::PlaygroundEquipment(id) => move_record(
+                &PLAYGROUND_EQUIPMENT,
+                "playground_equipment",
+                id,
+                |e, s| e.space_id = s,
+                space_id,
+            ),
+            SplitRecord::WaterFeature(id) => move_record(
+                &WATER_FEATURES,
+                "water_feature",
+                id,
+                |f, s| f.space_id = s,
+                space_id,
+            ),
+            SplitRecord::Apiary(id) => {
+                move_record(&APIARIES, "apiary", id, |a, s| a.space_id = s, space_id)
+            }
+        }
+    }
+}
+
+fn validate_split(
+    original: &GreenSpace,
+    parts: &[GreenSpaceUpdatePayload],
+    assignments: &[SplitAssignment],
+) -> Result<(), Error> {
+    if original.status == Some(GreenSpaceStatus::Archived) {
+        return Err(Error::InvalidInput {
+            msg: format!("The green space with id={} is archived", original.id),
+        });
+    }
+    if parts.len() < 2 || parts.len() > MAX_SPLIT_PARTS {
+        return Err(Error::InvalidInput {
+            msg: format!("A split needs between 2 and {} parts", MAX_SPLIT_PARTS),
+        });
+    }
+    if space_links(original.id).len() + parts.len() > MAX_LINKS_PER_SPACE {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The green space with id={} has too many links to record the split",
+                original.id
+            ),
+        });
+    }
+    for part in parts {
+        validate_payload_fields(part)?;
+        ensure_city_write(part.city_id)?;
+        if shard_for_city(part.city_id).is_some() {
+            return Err(Error::InvalidInput {
+                msg: "The parts of a split must stay on this canister".to_string(),
+            });
+        }
+    }
+    for (i, assignment) in assignments.iter().enumerate() {
+        if assignment.part as usize >= parts.len() {
+            return Err(Error::InvalidInput {
+                msg: format!("There is no part {}", assignment.part),
+            });
+        }
+        if assignment.record.space_id() != Some(original.id) {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "{:?} does not belong to the green space with id={}",
+                    assignment.record, original.id
+                ),
+            });
+        }
+        if assignments[..i]
+            .iter()
+            .any(|other| other.record == assignment.record)
+        {
+            return Err(Error::InvalidInput {
+                msg: format!("{:?} is assigned more than once", assignment.record),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Function to divide a space into parts: the parts are created and linked to
+// the original, the given records move to their part and the original is
+// archived; records not assigned stay with the original
+#[ic_cdk::update]
+fn split_green_space(
+    id: u64,
+    parts: Vec<GreenSpaceUpdatePayload>,
+    assignments: Vec<SplitAssignment>,
+) -> Result<Vec<GreenSpace>, Error> {
+    ensure_writable()?;
+    let mut original = _get_green_space(&id).ok_or_else(|| Error::NotFound {
+        msg: format!("A green space with id={} not found", id),
+    })?;
+    ensure_city_write(original.city_id)?;
+    // Archiving the original takes it out of use like a deletion would
+    ensure_not_governed("Splitting a green space")?;
+    validate_split(&original, &parts, &assignments)?;
+    let boundaries = parts
+        .iter()
+        .map(validate_payload_references)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let now = time();
+    let mut created = Vec::with_capacity(parts.len());
+    for (part, boundary) in parts.into_iter().zip(boundaries) {
+        let space = GreenSpace {
+            id: next_green_space_id(),
+            name: part.name,
+            location: part.location,
+            description: part.description,
+            coordinates: part.coordinates,
+            external_ref: None,
+            district_id: part.district_id,
+            city_id: part.city_id,
+            category: part.category,
+            status: part.status,
+            created_at: Some(now),
+            ulid: Some(new_ulid()),
+            version: None,
+            metadata: part.metadata,
+            shade_coverage_percent: part.shade_coverage_percent,
+            has_water: part.has_water,
+            links: None,
+        };
+        let space = insert_new_green_space(&space)?;
+        set_green_space_boundary(space.id, boundary);
+        link_split_part(id, space.id);
+        created.push(space);
+    }
+    for assignment in assignments {
+        assignment
+            .record
+            .move_to(created[assignment.part as usize].id);
+    }
+    original.status = Some(GreenSpaceStatus::Archived);
+    do_insert_green_space(&original);
+    Ok(created)
+}
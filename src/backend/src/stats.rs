@@ -0,0 +1,166 @@
+use crate::calendar::year_month_of;
+use crate::{
+    get_memory, Error, GreenSpace, GreenSpaceCategory, GreenSpaceStatus, Memory,
+    GREEN_SPACE_STORAGE,
+};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const SPACE_COUNTS_MEMORY_ID: u8 = 37;
+
+// Bucket of the spaces for which a dimension is not set
+const UNSPECIFIED_BUCKET: u64 = 0;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum CountDimension {
+    Category,
+    District,
+    Status,
+    CreationMonth,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct DimensionCount {
+    // Category or status name, district id or "YYYY-MM"; "unspecified" when unset
+    bucket: String,
+    count: u64,
+}
+
+const DIMENSIONS: [CountDimension; 4] = [
+    CountDimension::Category,
+    CountDimension::District,
+    CountDimension::Status,
+    CountDimension::CreationMonth,
+];
+
+const CATEGORIES: [GreenSpaceCategory; 8] = [
+    GreenSpaceCategory::Park,
+    GreenSpaceCategory::Garden,
+    GreenSpaceCategory::CommunityGarden,
+    GreenSpaceCategory::Playground,
+    GreenSpaceCategory::Forest,
+    GreenSpaceCategory::Meadow,
+    GreenSpaceCategory::Cemetery,
+    GreenSpaceCategory::Other,
+];
+
+const STATUSES: [GreenSpaceStatus; 5] = [
+    GreenSpaceStatus::Open,
+    GreenSpaceStatus::TemporarilyClosed,
+    GreenSpaceStatus::UnderRenovation,
+    GreenSpaceStatus::Planned,
+    GreenSpaceStatus::Archived,
+];
+
+thread_local! {
+    // Number of spaces keyed by (dimension, bucket), kept up to date on every
+    // write so dashboards never scan the spaces
+    static SPACE_COUNTS: RefCell<StableBTreeMap<(u8, u64), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SPACE_COUNTS_MEMORY_ID)));
+}
+
+// Buckets are shifted by one so that 0 stays free for unset values
+fn bucket_of(space: &GreenSpace, dimension: CountDimension) -> u64 {
+    let position = match dimension {
+        CountDimension::Category => space
+            .category
+            .and_then(|category| CATEGORIES.iter().position(|c| *c == category))
+            .map(|i| i as u64),
+        CountDimension::District => space.district_id,
+        CountDimension::Status => space
+            .status
+            .and_then(|status| STATUSES.iter().position(|s| *s == status))
+            .map(|i| i as u64),
+        CountDimension::CreationMonth => space.created_at.map(|created_at| {
+            let (year, month) = year_month_of(created_at);
+            year as u64 * 12 + u64::from(month - 1)
+        }),
+    };
+    position.map_or(UNSPECIFIED_BUCKET, |position| position.saturating_add(1))
+}
+
+fn bucket_label(dimension: CountDimension, bucket: u64) -> String {
+    if bucket == UNSPECIFIED_BUCKET {
+        return "unspecified".to_string();
+    }
+    let position = bucket - 1;
+    match dimension {
+        CountDimension::Category => format!("{:?}", CATEGORIES[position as usize]),
+        CountDimension::District => position.to_string(),
+        CountDimension::Status => format!("{:?}", STATUSES[position as usize]),
+        CountDimension::CreationMonth => format!("{}-{:02}", position / 12, position % 12 + 1),
+    }
+}
+
+fn add_to_counts(space: &GreenSpace, delta: i64) {
+    SPACE_COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        for dimension in DIMENSIONS {
+            let key = (dimension as u8, bucket_of(space, dimension));
+            let count = counts.get(&key).unwrap_or(0).saturating_add_signed(delta);
+            if count == 0 {
+                counts.remove(&key);
+            } else {
+                counts.insert(key, count);
+            }
+        }
+    });
+}
+
+// Moves a space between buckets as it is created, updated or deleted
+pub(crate) fn adjust_space_counts(previous: Option<&GreenSpace>, current: Option<&GreenSpace>) {
+    if let Some(previous) = previous {
+        add_to_counts(previous, -1);
+    }
+    if let Some(current) = current {
+        add_to_counts(current, 1);
+    }
+}
+
+// Recounts every space, as after an upgrade or a snapshot restore
+pub(crate) fn rebuild_space_counts() {
+    SPACE_COUNTS.with(|counts| {
+        let keys: Vec<(u8, u64)> = counts.borrow().iter().map(|(key, _)| key).collect();
+        let mut counts = counts.borrow_mut();
+        for key in keys {
+            counts.remove(&key);
+        }
+    });
+    GREEN_SPACE_STORAGE.with(|s| {
+        for (_, space) in s.borrow().iter() {
+            add_to_counts(&space, 1);
+        }
+    });
+}
+
+// Every category, in declaration order, with its number of spaces
+pub(crate) fn category_counts() -> Vec<(String, u64)> {
+    let dimension_key = CountDimension::Category as u8;
+    SPACE_COUNTS.with(|counts| {
+        let counts = counts.borrow();
+        CATEGORIES
+            .iter()
+            .enumerate()
+            .map(|(i, category)| {
+                let count = counts.get(&(dimension_key, i as u64 + 1)).unwrap_or(0);
+                (format!("{:?}", category), count)
+            })
+            .collect()
+    })
+}
+
+// Function to get how many spaces fall into each bucket of a dimension
+#[ic_cdk::query]
+fn count_by(dimension: CountDimension) -> Result<Vec<DimensionCount>, Error> {
+    let dimension_key = dimension as u8;
+    Ok(SPACE_COUNTS.with(|counts| {
+        counts
+            .borrow()
+            .range((dimension_key, 0)..=(dimension_key, u64::MAX))
+            .map(|((_, bucket), count)| DimensionCount {
+                bucket: bucket_label(dimension, bucket),
+                count,
+            })
+            .collect()
+    }))
+}
@@ -0,0 +1,220 @@
+use crate::block_log::log_config;
+use crate::cities::in_city;
+use crate::heat::SURFACE_METADATA_KEY;
+use crate::maintenance::ensure_writable;
+use crate::metadata::metadata_value;
+use crate::name_index::normalize_name;
+use crate::{
+    _get_green_space, _get_green_space_boundary, ensure_controller, get_memory, Error, GreenSpace,
+    Memory, GREEN_SPACE_STORAGE,
+};
+use ic_stable_structures::Cell;
+use std::cell::RefCell;
+
+const STORMWATER_CONFIG_MEMORY_ID: u8 = 97;
+
+// Read from the metadata of a space when defined with set_metadata_keys
+const VEGETATION_METADATA_KEY: &str = "vegetation";
+
+// Share of the rain on a surface that runs off, as in the rational method
+const SURFACE_RUNOFF: [(&str, f64); 20] = [
+    ("forest", 0.10),
+    ("meadow", 0.12),
+    ("grass", 0.15),
+    ("vegetation", 0.15),
+    ("planted", 0.15),
+    ("sand", 0.20),
+    ("mulch", 0.25),
+    ("wood chips", 0.25),
+    ("bark", 0.25),
+    ("soil", 0.30),
+    ("gravel", 0.40),
+    ("fine gravel", 0.45),
+    ("unpaved", 0.50),
+    ("compacted", 0.60),
+    ("paving stones", 0.70),
+    ("sett", 0.75),
+    ("rubber", 0.80),
+    ("paved", 0.90),
+    ("asphalt", 0.95),
+    ("concrete", 0.95),
+];
+const DEFAULT_RUNOFF: f64 = 0.35;
+
+// Share of the rain that plants hold back on top of what the surface soaks up
+const VEGETATION_RETENTION: [(&str, f64); 5] = [
+    ("none", 0.0),
+    ("grass", 0.02),
+    ("shrubs", 0.06),
+    ("mixed", 0.08),
+    ("trees", 0.10),
+];
+
+const FORMULA: &str = "annual_capture_m3 = area_m2 * annual_rainfall_mm / 1000 * \
+    min(1, 1 - runoff_coefficient + vegetation_retention)";
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct StormwaterConfig {
+    annual_rainfall_mm: f64,
+}
+
+impl Default for StormwaterConfig {
+    fn default() -> Self {
+        StormwaterConfig {
+            annual_rainfall_mm: 600.0,
+        }
+    }
+}
+
+impl_storable!(StormwaterConfig, 32);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct StormwaterEstimate {
+    space_id: u64,
+    // Unknown without a boundary, and so is the capture
+    area_m2: Option<f64>,
+    surface: Option<String>,
+    vegetation: Option<String>,
+    runoff_coefficient: f64,
+    vegetation_retention: f64,
+    annual_capture_m3: Option<f64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CityStormwaterEstimate {
+    city_id: Option<u64>,
+    space_count: u64,
+    // Spaces left out of the totals for lack of a boundary
+    spaces_without_area: u64,
+    area_m2: f64,
+    annual_capture_m3: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct StormwaterCoefficients {
+    formula: String,
+    annual_rainfall_mm: f64,
+    surface_runoff: Vec<(String, f64)>,
+    default_runoff: f64,
+    vegetation_retention: Vec<(String, f64)>,
+    surface_metadata_key: String,
+    vegetation_metadata_key: String,
+}
+
+thread_local! {
+    static STORMWATER_CONFIG: RefCell<Cell<StormwaterConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(STORMWATER_CONFIG_MEMORY_ID), StormwaterConfig::default())
+            .expect("Cannot initialize the stormwater config")
+    );
+}
+
+fn stormwater_config() -> StormwaterConfig {
+    STORMWATER_CONFIG.with(|c| *c.borrow().get())
+}
+
+fn lookup(table: &[(&str, f64)], value: Option<&str>) -> Option<f64> {
+    let value = value?;
+    table
+        .iter()
+        .find(|(name, _)| *name == value)
+        .map(|(_, coefficient)| *coefficient)
+}
+
+fn estimate(space: &GreenSpace, rainfall_mm: f64) -> StormwaterEstimate {
+    let area_m2 = _get_green_space_boundary(&space.id)
+        .map(|boundary| boundary.area_m2())
+        .filter(|area| *area > 0.0);
+    let surface = metadata_value(space, SURFACE_METADATA_KEY).map(normalize_name);
+    let vegetation = metadata_value(space, VEGETATION_METADATA_KEY).map(normalize_name);
+    let runoff_coefficient = lookup(&SURFACE_RUNOFF, surface.as_deref()).unwrap_or(DEFAULT_RUNOFF);
+    let vegetation_retention = lookup(&VEGETATION_RETENTION, vegetation.as_deref()).unwrap_or(0.0);
+    let captured = (1.0 - runoff_coefficient + vegetation_retention).min(1.0);
+    StormwaterEstimate {
+        space_id: space.id,
+        area_m2,
+        surface,
+        vegetation,
+        runoff_coefficient,
+        vegetation_retention,
+        annual_capture_m3: area_m2.map(|area| area * rainfall_mm / 1000.0 * captured),
+    }
+}
+
+// Function to estimate how much rain a space soaks up or holds back in a year
+#[ic_cdk::query]
+fn get_stormwater_capture(space_id: u64) -> Result<StormwaterEstimate, Error> {
+    let space = _get_green_space(&space_id).ok_or_else(|| Error::NotFound {
+        msg: format!("A green space with id={} not found", space_id),
+    })?;
+    Ok(estimate(&space, stormwater_config().annual_rainfall_mm))
+}
+
+// Function to total the yearly stormwater capture of the spaces of a city,
+// or of all spaces
+#[ic_cdk::query]
+fn get_city_stormwater_capture(city_id: Option<u64>) -> Result<CityStormwaterEstimate, Error> {
+    let rainfall_mm = stormwater_config().annual_rainfall_mm;
+    let mut total = CityStormwaterEstimate {
+        city_id,
+        space_count: 0,
+        spaces_without_area: 0,
+        area_m2: 0.0,
+        annual_capture_m3: 0.0,
+    };
+    GREEN_SPACE_STORAGE.with(|s| {
+        for (_, space) in s.borrow().iter() {
+            if !in_city(city_id, space.city_id) {
+                continue;
+            }
+            let estimate = estimate(&space, rainfall_mm);
+            total.space_count += 1;
+            match (estimate.area_m2, estimate.annual_capture_m3) {
+                (Some(area), Some(capture)) => {
+                    total.area_m2 += area;
+                    total.annual_capture_m3 += capture;
+                }
+                _ => total.spaces_without_area += 1,
+            }
+        }
+    });
+    Ok(total)
+}
+
+// Function to document how stormwater capture is estimated
+#[ic_cdk::query]
+fn get_stormwater_coefficients() -> Result<StormwaterCoefficients, Error> {
+    let table = |entries: &[(&str, f64)]| -> Vec<(String, f64)> {
+        entries
+            .iter()
+            .map(|(name, coefficient)| (name.to_string(), *coefficient))
+            .collect()
+    };
+    Ok(StormwaterCoefficients {
+        formula: FORMULA.to_string(),
+        annual_rainfall_mm: stormwater_config().annual_rainfall_mm,
+        surface_runoff: table(&SURFACE_RUNOFF),
+        default_runoff: DEFAULT_RUNOFF,
+        vegetation_retention: table(&VEGETATION_RETENTION),
+        surface_metadata_key: SURFACE_METADATA_KEY.to_string(),
+        vegetation_metadata_key: VEGETATION_METADATA_KEY.to_string(),
+    })
+}
+
+// Function to set the yearly rainfall estimates are based on
+#[ic_cdk::update]
+fn set_stormwater_config(config: StormwaterConfig) -> Result<StormwaterConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if !(config.annual_rainfall_mm > 0.0 && config.annual_rainfall_mm <= 12_000.0) {
+        return Err(Error::InvalidInput {
+            msg: "The annual rainfall must be above 0 and at most 12000 mm".to_string(),
+        });
+    }
+    STORMWATER_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config)
+            .expect("Cannot store the stormwater config")
+    });
+    log_config("stormwater_config", &config);
+    Ok(config)
+}
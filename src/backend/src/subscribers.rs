@@ -0,0 +1,287 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::changelog::{changes_after, count_changes_after, last_change_seq, ChangeLogEntry};
+use crate::maintenance::ensure_writable;
+use crate::shards::call_failed;
+use crate::webhooks::retry_delay_nanos;
+use crate::{ensure_controller, get_memory, next_id, Error, Memory};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const SUBSCRIBERS_MEMORY_ID: u8 = 84;
+
+// Entries pushed per call; upserts are at most ~1.5KB each
+const SUBSCRIBER_BATCH_SIZE: usize = 200;
+const MAX_SUBSCRIBERS: u64 = 20;
+const MAX_METHOD_LEN: usize = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Subscriber {
+    id: u64,
+    canister: Principal,
+    // Called with a SubscriberDelivery; returns the sequence number of the
+    // last entry it applied
+    method: String,
+    created_at: u64,
+    active: bool,
+    // Sequence number of the last change-log entry the subscriber acknowledged
+    last_acknowledged_seq: Option<u64>,
+    last_delivered_at: Option<u64>,
+    consecutive_failures: u32,
+    next_attempt_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+impl_storable!(Subscriber, 1024);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SubscriberDelivery {
+    subscriber_id: u64,
+    entries: Vec<ChangeLogEntry>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SubscriberStatus {
+    subscriber: Subscriber,
+    pending_entries: u64,
+}
+
+thread_local! {
+    static SUBSCRIBERS: RefCell<StableBTreeMap<u64, Subscriber, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(SUBSCRIBERS_MEMORY_ID)));
+
+    // Subscribers with a call awaiting their answer
+    static IN_FLIGHT: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+fn subscriber_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A subscriber with id={} not found", id),
+    }
+}
+
+fn _get_subscriber(id: &u64) -> Option<Subscriber> {
+    SUBSCRIBERS.with(|s| s.borrow().get(id))
+}
+
+fn do_insert_subscriber(subscriber: &Subscriber) {
+    SUBSCRIBERS.with(|s| s.borrow_mut().insert(subscriber.id, subscriber.clone()));
+    log_upsert("subscriber", &subscriber.id, subscriber);
+}
+
+// First change-log entry some subscriber, paused or not, has yet to acknowledge
+pub(crate) fn first_unacknowledged_seq() -> Option<u64> {
+    SUBSCRIBERS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, subscriber)| subscriber.last_acknowledged_seq.map_or(0, |seq| seq + 1))
+            .min()
+    })
+}
+
+// Canister ids are 10 bytes ending in 0x01, unlike the principals of users
+fn is_canister(principal: &Principal) -> bool {
+    let bytes = principal.as_slice();
+    bytes.len() == 10 && bytes[9] == 0x01
+}
+
+// Pushes the next batch of changes to one subscriber and records what it
+// acknowledged; an acknowledgment short of the batch resends the rest
+async fn push_to(subscriber: Subscriber) {
+    let entries = changes_after(subscriber.last_acknowledged_seq, SUBSCRIBER_BATCH_SIZE);
+    let (Some(first), Some(last)) = (
+        entries.first().map(|e| e.seq),
+        entries.last().map(|e| e.seq),
+    ) else {
+        return;
+    };
+    let delivery = SubscriberDelivery {
+        subscriber_id: subscriber.id,
+        entries,
+    };
+    let result: Result<(u64,), _> =
+        ic_cdk::call(subscriber.canister, &subscriber.method, (delivery,)).await;
+    let result = match result {
+        Ok((acknowledged,)) if (first..=last).contains(&acknowledged) => Ok(acknowledged),
+        Ok((acknowledged,)) => Err(Error::InvalidInput {
+            msg: format!(
+                "Acknowledged entry {} is outside the batch {}-{}",
+                acknowledged, first, last
+            ),
+        }),
+        Err((code, msg)) => Err(call_failed(
+            subscriber.canister,
+            &subscriber.method,
+            code,
+            msg,
+        )),
+    };
+
+    // The subscriber may have been changed or removed while awaiting
+    let Some(mut current) = _get_subscriber(&subscriber.id) else {
+        return;
+    };
+    if current.last_acknowledged_seq != subscriber.last_acknowledged_seq {
+        return;
+    }
+    let now = time();
+    match result {
+        Ok(acknowledged) => {
+            current.last_acknowledged_seq = Some(acknowledged);
+            current.last_delivered_at = Some(now);
+            current.consecutive_failures = 0;
+            current.next_attempt_at = None;
+            current.last_error = None;
+        }
+        Err(error) => {
+            current.consecutive_failures += 1;
+            current.next_attempt_at = Some(now + retry_delay_nanos(current.consecutive_failures));
+            current.last_error = Some(error.message().to_string());
+        }
+    }
+    // Delivery progress is bookkeeping, not a change to the subscription; it is not logged
+    SUBSCRIBERS.with(|s| s.borrow_mut().insert(current.id, current));
+}
+
+// Starts a push to every active subscriber with pending changes that is not
+// waiting for a retry; returns the number of pushes started. Each push runs
+// on its own, so a subscriber slow to answer holds up no other
+pub(crate) fn push_to_subscribers() -> u64 {
+    let now = time();
+    let due: Vec<Subscriber> = SUBSCRIBERS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, subscriber)| subscriber)
+            .filter(|subscriber| {
+                subscriber.active
+                    && subscriber.next_attempt_at.is_none_or(|at| at <= now)
+                    && count_changes_after(subscriber.last_acknowledged_seq) > 0
+            })
+            .collect()
+    });
+    let mut started = 0;
+    for subscriber in due {
+        if !IN_FLIGHT.with(|f| f.borrow_mut().insert(subscriber.id)) {
+            continue;
+        }
+        started += 1;
+        ic_cdk::spawn(async move {
+            let id = subscriber.id;
+            push_to(subscriber).await;
+            IN_FLIGHT.with(|f| f.borrow_mut().remove(&id));
+        });
+    }
+    started
+}
+
+// Function for a canister to subscribe to every change from now on; the
+// method is called with batches of change-log entries
+#[ic_cdk::update]
+fn subscribe(method: String) -> Result<Subscriber, Error> {
+    ensure_writable()?;
+    let canister = ic_cdk::caller();
+    if !is_canister(&canister) || canister == ic_cdk::id() {
+        return Err(Error::Unauthorized {
+            msg: "Only other canisters can subscribe".to_string(),
+        });
+    }
+    if method.is_empty() || method.len() > MAX_METHOD_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Method names must be 1-{} bytes", MAX_METHOD_LEN),
+        });
+    }
+    let existing = SUBSCRIBERS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, subscriber)| subscriber)
+            .find(|subscriber| subscriber.canister == canister)
+    });
+    if let Some(mut subscriber) = existing {
+        subscriber.method = method;
+        do_insert_subscriber(&subscriber);
+        return Ok(subscriber);
+    }
+    if SUBSCRIBERS.with(|s| s.borrow().len()) >= MAX_SUBSCRIBERS {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} canisters can subscribe", MAX_SUBSCRIBERS),
+        });
+    }
+    let subscriber = Subscriber {
+        id: next_id(SUBSCRIBERS_MEMORY_ID),
+        canister,
+        method,
+        created_at: time(),
+        active: true,
+        // Past changes are not pushed; a new subscriber is expected to start
+        // from an export
+        last_acknowledged_seq: last_change_seq(),
+        last_delivered_at: None,
+        consecutive_failures: 0,
+        next_attempt_at: None,
+        last_error: None,
+    };
+    do_insert_subscriber(&subscriber);
+    Ok(subscriber)
+}
+
+// Function for a subscribed canister to stop receiving changes
+#[ic_cdk::update]
+fn unsubscribe() -> Result<Subscriber, Error> {
+    ensure_writable()?;
+    let canister = ic_cdk::caller();
+    let id = SUBSCRIBERS
+        .with(|s| {
+            s.borrow()
+                .iter()
+                .find(|(_, subscriber)| subscriber.canister == canister)
+                .map(|(id, _)| id)
+        })
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("{} is not subscribed", canister),
+        })?;
+    remove_subscription(id)
+}
+
+fn remove_subscription(id: u64) -> Result<Subscriber, Error> {
+    let subscriber = SUBSCRIBERS
+        .with(|s| s.borrow_mut().remove(&id))
+        .ok_or_else(|| subscriber_not_found(id))?;
+    log_delete("subscriber", &id);
+    Ok(subscriber)
+}
+
+// Function for controllers to pause or resume pushes; resuming retries right away
+#[ic_cdk::update]
+fn set_subscriber_active(id: u64, active: bool) -> Result<Subscriber, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut subscriber = _get_subscriber(&id).ok_or_else(|| subscriber_not_found(id))?;
+    subscriber.active = active;
+    subscriber.next_attempt_at = None;
+    do_insert_subscriber(&subscriber);
+    Ok(subscriber)
+}
+
+#[ic_cdk::update]
+fn remove_subscriber(id: u64) -> Result<Subscriber, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    remove_subscription(id)
+}
+
+// Function to get every subscriber with the number of changes it has yet to acknowledge
+#[ic_cdk::query]
+fn get_subscriber_status() -> Result<Vec<SubscriberStatus>, Error> {
+    ensure_controller()?;
+    Ok(SUBSCRIBERS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, subscriber)| SubscriberStatus {
+                pending_entries: count_changes_after(subscriber.last_acknowledged_seq),
+                subscriber,
+            })
+            .collect()
+    }))
+}
@@ -0,0 +1,224 @@
+use crate::metadata::searchable_keys;
+use crate::name_index::{ids_with_name_prefix, normalize_name};
+use crate::related_tags::{clear_tag_pairs, count_tag_pairs};
+use crate::{_get_green_space, get_memory, Error, GreenSpace, Memory, GREEN_SPACE_STORAGE};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const TAG_INDEX_MEMORY_ID: u8 = 50;
+
+const MAX_SUGGESTIONS: u32 = 20;
+
+// A value under a searchable metadata key, in order of its normalized form
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct TagKey {
+    value: String,
+    key: String,
+    id: u64,
+}
+
+impl_storable!(TagKey, 512);
+
+impl TagKey {
+    // The tag regardless of the space carrying it
+    pub(crate) fn label(&self) -> String {
+        format!("{}: {}", self.key, self.value)
+    }
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum SuggestionKind {
+    Name,
+    Tag,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Suggestion {
+    kind: SuggestionKind,
+    text: String,
+    // The space a name belongs to; tags are shared by many
+    space_id: Option<u64>,
+}
+
+thread_local! {
+    // Rebuilt rather than restored, and whenever the searchable keys change
+    static TAG_INDEX: RefCell<StableBTreeMap<TagKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(TAG_INDEX_MEMORY_ID)));
+}
+
+fn tag_keys(space: &GreenSpace, searchable: &[String]) -> Vec<TagKey> {
+    space
+        .metadata
+        .iter()
+        .flatten()
+        .filter(|(key, _)| searchable.contains(key))
+        .map(|(key, value)| TagKey {
+            value: normalize_name(value),
+            key: key.clone(),
+            id: space.id,
+        })
+        .filter(|tag| !tag.value.is_empty())
+        .collect()
+}
+
+// The tags of a space under the keys currently searchable
+pub(crate) fn space_tags(space: &GreenSpace) -> Vec<TagKey> {
+    tag_keys(space, &searchable_keys())
+}
+
+// Up to `limit` spaces carrying a tag, in id order
+pub(crate) fn spaces_with_tag(tag: &TagKey, limit: usize) -> Vec<u64> {
+    let start = TagKey {
+        value: tag.value.clone(),
+        key: tag.key.clone(),
+        id: 0,
+    };
+    TAG_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.value == tag.value && key.key == tag.key)
+            .take(limit)
+            .map(|key| key.id)
+            .collect()
+    })
+}
+
+fn tag_labels(tags: &[TagKey]) -> BTreeSet<String> {
+    tags.iter().map(TagKey::label).collect()
+}
+
+// Brings the tag index, and the counts of tags found together, in line with
+// a stored, replaced or removed space
+pub(crate) fn reindex_tags(previous: Option<&GreenSpace>, current: Option<&GreenSpace>) {
+    let searchable = searchable_keys();
+    let previous_tags = previous
+        .map(|space| tag_keys(space, &searchable))
+        .unwrap_or_default();
+    let current_tags = current
+        .map(|space| tag_keys(space, &searchable))
+        .unwrap_or_default();
+    count_tag_pairs(&tag_labels(&previous_tags), &tag_labels(&current_tags));
+    TAG_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in &previous_tags {
+            index.remove(tag);
+        }
+        for tag in current_tags {
+            index.insert(tag, ());
+        }
+    });
+}
+
+pub(crate) fn rebuild_tag_index() {
+    TAG_INDEX.with(|index| {
+        let keys: Vec<TagKey> = index.borrow().iter().map(|(key, _)| key).collect();
+        let mut index = index.borrow_mut();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    clear_tag_pairs();
+    let searchable = searchable_keys();
+    GREEN_SPACE_STORAGE.with(|s| {
+        for (_, space) in s.borrow().iter() {
+            let tags = tag_keys(&space, &searchable);
+            count_tag_pairs(&BTreeSet::new(), &tag_labels(&tags));
+            TAG_INDEX.with(|index| {
+                let mut index = index.borrow_mut();
+                for tag in tags {
+                    index.insert(tag, ());
+                }
+            });
+        }
+    });
+}
+
+// The first entry of each distinct tag value starting with the prefix
+fn tags_with_prefix(prefix: &str, limit: usize) -> Vec<TagKey> {
+    let mut tags: Vec<TagKey> = Vec::new();
+    let mut start = TagKey {
+        value: prefix.to_string(),
+        key: String::new(),
+        id: 0,
+    };
+    while tags.len() < limit {
+        let Some(tag) = TAG_INDEX.with(|index| {
+            index
+                .borrow()
+                .range(start.clone()..)
+                .next()
+                .map(|(key, _)| key)
+        }) else {
+            break;
+        };
+        if !tag.value.starts_with(prefix) {
+            break;
+        }
+        // Skips the other spaces sharing the value
+        start.value = format!("{}\0", tag.value);
+        tags.push(tag);
+    }
+    tags
+}
+
+fn tag_text(tag: &TagKey) -> String {
+    _get_green_space(&tag.id)
+        .and_then(|space| {
+            space
+                .metadata?
+                .into_iter()
+                .find(|(key, _)| *key == tag.key)
+                .map(|(_, value)| value)
+        })
+        .unwrap_or_else(|| tag.value.clone())
+}
+
+// Function to complete what a user is typing from the names and tags of the
+// spaces, as alphabetical suggestions
+#[ic_cdk::query]
+fn suggest(prefix: String, limit: u32) -> Result<Vec<Suggestion>, Error> {
+    if limit == 0 || limit > MAX_SUGGESTIONS {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_SUGGESTIONS),
+        });
+    }
+    let prefix = normalize_name(&prefix);
+    if prefix.is_empty() {
+        return Ok(vec![]);
+    }
+    let limit = limit as usize;
+    let names = ids_with_name_prefix(&prefix, limit)
+        .into_iter()
+        .filter_map(|(normalized, id)| {
+            let space = _get_green_space(&id)?;
+            Some((
+                normalized,
+                Suggestion {
+                    kind: SuggestionKind::Name,
+                    text: space.name,
+                    space_id: Some(id),
+                },
+            ))
+        });
+    let tags = tags_with_prefix(&prefix, limit).into_iter().map(|tag| {
+        let text = tag_text(&tag);
+        (
+            tag.value,
+            Suggestion {
+                kind: SuggestionKind::Tag,
+                text,
+                space_id: None,
+            },
+        )
+    });
+    let mut suggestions: Vec<(String, Suggestion)> = names.chain(tags).collect();
+    suggestions.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(suggestions
+        .into_iter()
+        .take(limit)
+        .map(|(_, suggestion)| suggestion)
+        .collect())
+}
@@ -0,0 +1,110 @@
+use crate::{_get_green_space, get_memory, Error, GreenSpace, Memory, GREEN_SPACE_STORAGE};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const MODIFIED_INDEX_MEMORY_ID: u8 = 33;
+const MODIFIED_AT_MEMORY_ID: u8 = 34;
+
+const MAX_MODIFIED_PAGE: u32 = 500;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ModifiedCursor {
+    modified_at: u64,
+    id: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ModifiedGreenSpaces {
+    spaces: Vec<GreenSpace>,
+    // Spaces deleted since the given time
+    deleted_ids: Vec<u64>,
+    // Where the next page starts; None once the client is up to date
+    next: Option<ModifiedCursor>,
+}
+
+thread_local! {
+    // Every space ever written, deleted ones included, keyed by (last write, id)
+    pub(crate) static MODIFIED_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(MODIFIED_INDEX_MEMORY_ID)));
+
+    // Time of the last write of every space, to find its entry in MODIFIED_INDEX
+    pub(crate) static MODIFIED_AT: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(MODIFIED_AT_MEMORY_ID)));
+}
+
+// Records a write to a space, including its deletion
+pub(crate) fn touch_green_space(id: u64) {
+    let now = time();
+    let previous = MODIFIED_AT.with(|m| m.borrow_mut().insert(id, now));
+    MODIFIED_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous {
+            index.remove(&(previous, id));
+        }
+        index.insert((now, id), ());
+    });
+}
+
+// Indexes the spaces written before the index existed, as modified now
+pub(crate) fn ensure_modified_index() {
+    let missing: Vec<u64> = GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(id, _)| id)
+            .filter(|id| MODIFIED_AT.with(|m| !m.borrow().contains_key(id)))
+            .collect()
+    });
+    for id in missing {
+        touch_green_space(id);
+    }
+}
+
+// Function for offline clients to fetch what changed since their last sync;
+// pages continue after the cursor of the previous page
+#[ic_cdk::query]
+fn get_green_spaces_modified_since(
+    timestamp: u64,
+    after: Option<ModifiedCursor>,
+    limit: u32,
+) -> Result<ModifiedGreenSpaces, Error> {
+    if limit == 0 || limit > MAX_MODIFIED_PAGE {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_MODIFIED_PAGE),
+        });
+    }
+    let start = match &after {
+        Some(cursor) if cursor.modified_at >= timestamp => match cursor.id.checked_add(1) {
+            Some(id) => (cursor.modified_at, id),
+            None => (cursor.modified_at.saturating_add(1), 0),
+        },
+        _ => (timestamp, 0),
+    };
+    let keys: Vec<(u64, u64)> = MODIFIED_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take(limit as usize)
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    let mut result = ModifiedGreenSpaces {
+        spaces: vec![],
+        deleted_ids: vec![],
+        next: None,
+    };
+    // A full page may be followed by more
+    if keys.len() == limit as usize {
+        result.next = keys
+            .last()
+            .map(|&(modified_at, id)| ModifiedCursor { modified_at, id });
+    }
+    for (_, id) in keys {
+        match _get_green_space(&id) {
+            Some(space) => result.spaces.push(space),
+            None => result.deleted_ids.push(id),
+        }
+    }
+    Ok(result)
+}
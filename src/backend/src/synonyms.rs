@@ -0,0 +1,112 @@
+use crate::block_log::log_config;
+use crate::fulltext::TokenizerConfig;
+use crate::maintenance::ensure_writable;
+use crate::{ensure_controller, get_memory, Error, Memory};
+use ic_stable_structures::Cell;
+use std::cell::RefCell;
+
+const SYNONYMS_MEMORY_ID: u8 = 51;
+
+const MAX_SYNONYM_GROUPS: usize = 100;
+const MAX_GROUP_SIZE: usize = 10;
+const MAX_PHRASE_LEN: usize = 50;
+
+// Groups of phrases that mean the same, e.g. "playground" and "play area";
+// each phrase is stored as its lowercased terms
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct SynonymGroups(Vec<Vec<Vec<String>>>);
+
+impl_storable!(SynonymGroups, 65536);
+
+thread_local! {
+    static SYNONYMS: RefCell<Cell<SynonymGroups, Memory>> = RefCell::new(
+        Cell::init(get_memory(SYNONYMS_MEMORY_ID), SynonymGroups::default())
+            .expect("Cannot initialize the synonyms")
+    );
+}
+
+fn contains_phrase(terms: &[String], phrase: &[String]) -> bool {
+    terms.windows(phrase.len()).any(|window| window == phrase)
+}
+
+// Adds to the query terms those of every synonym of a phrase the query
+// contains; phrases are tokenized like the query, under the current config
+pub(crate) fn expand_synonyms(config: &TokenizerConfig, terms: &[String]) -> Vec<String> {
+    let mut expanded = terms.to_vec();
+    SYNONYMS.with(|s| {
+        for group in &s.borrow().get().0 {
+            let phrases: Vec<Vec<String>> = group
+                .iter()
+                .map(|phrase| config.terms(&phrase.join(" ")))
+                .filter(|phrase| !phrase.is_empty())
+                .collect();
+            if !phrases.iter().any(|phrase| contains_phrase(terms, phrase)) {
+                continue;
+            }
+            for term in phrases.into_iter().flatten() {
+                if !expanded.contains(&term) {
+                    expanded.push(term);
+                }
+            }
+        }
+    });
+    expanded
+}
+
+fn to_text(groups: &SynonymGroups) -> Vec<Vec<String>> {
+    groups
+        .0
+        .iter()
+        .map(|group| group.iter().map(|phrase| phrase.join(" ")).collect())
+        .collect()
+}
+
+// Function to replace the synonym table search applies to queries
+#[ic_cdk::update]
+fn set_synonyms(groups: Vec<Vec<String>>) -> Result<Vec<Vec<String>>, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if groups.len() > MAX_SYNONYM_GROUPS {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} synonym groups can be set", MAX_SYNONYM_GROUPS),
+        });
+    }
+    let mut stored = Vec::with_capacity(groups.len());
+    for group in groups {
+        let mut phrases: Vec<Vec<String>> = Vec::new();
+        for phrase in group {
+            if phrase.len() > MAX_PHRASE_LEN {
+                return Err(Error::InvalidInput {
+                    msg: format!("Synonyms must be at most {} bytes", MAX_PHRASE_LEN),
+                });
+            }
+            // Stored as plain lowercased terms, whatever the tokenizer config
+            let terms = TokenizerConfig::default().terms(&phrase);
+            if !terms.is_empty() && !phrases.contains(&terms) {
+                phrases.push(terms);
+            }
+        }
+        if phrases.len() < 2 || phrases.len() > MAX_GROUP_SIZE {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "A synonym group needs between 2 and {} distinct phrases",
+                    MAX_GROUP_SIZE
+                ),
+            });
+        }
+        stored.push(phrases);
+    }
+    let groups = SynonymGroups(stored);
+    SYNONYMS.with(|s| {
+        s.borrow_mut()
+            .set(groups.clone())
+            .expect("Cannot store the synonyms")
+    });
+    log_config("synonyms", &groups);
+    Ok(to_text(&groups))
+}
+
+#[ic_cdk::query]
+fn get_synonyms() -> Result<Vec<Vec<String>>, Error> {
+    Ok(SYNONYMS.with(|s| to_text(s.borrow().get())))
+}
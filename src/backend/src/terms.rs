@@ -0,0 +1,133 @@
+use crate::block_log::log_config;
+use crate::maintenance::ensure_writable;
+use crate::{
+    authenticated_caller, ensure_controller, get_memory, principal_key, Error, Memory, PrincipalKey,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use std::cell::RefCell;
+
+const TERMS_CONFIG_MEMORY_ID: u8 = 70;
+const TERMS_ACCEPTANCES_MEMORY_ID: u8 = 71;
+
+const MAX_TERMS_URL_LEN: usize = 300;
+
+// The terms of use and privacy notice in force; version 0 means none have
+// been published and nothing has to be accepted
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct TermsConfig {
+    version: u32,
+    url: String,
+    published_at: u64,
+}
+
+impl_storable!(TermsConfig, 512);
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct TermsAcceptance {
+    version: u32,
+    accepted_at: u64,
+}
+
+impl_storable!(TermsAcceptance, 64);
+
+thread_local! {
+    static TERMS_CONFIG: RefCell<Cell<TermsConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(TERMS_CONFIG_MEMORY_ID), TermsConfig::default())
+            .expect("Cannot initialize the terms config")
+    );
+
+    // The latest version each principal accepted
+    pub(crate) static TERMS_ACCEPTANCES: RefCell<StableBTreeMap<PrincipalKey, TermsAcceptance, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(TERMS_ACCEPTANCES_MEMORY_ID)));
+}
+
+fn terms() -> TermsConfig {
+    TERMS_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+// Fails unless the caller accepted the terms in force. The anonymous principal
+// cannot accept anything, so its writes are gated by other means
+pub(crate) fn ensure_terms_accepted() -> Result<(), Error> {
+    let terms = terms();
+    let caller = ic_cdk::caller();
+    if terms.version == 0 || caller == Principal::anonymous() {
+        return Ok(());
+    }
+    let accepted = TERMS_ACCEPTANCES
+        .with(|a| a.borrow().get(&principal_key(&caller)))
+        .is_some_and(|acceptance| acceptance.version == terms.version);
+    if accepted {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: format!(
+                "Accept version {} of the terms ({}) with accept_terms first",
+                terms.version, terms.url
+            ),
+        })
+    }
+}
+
+pub(crate) fn forget_terms_acceptance(user: &PrincipalKey) -> bool {
+    TERMS_ACCEPTANCES
+        .with(|a| a.borrow_mut().remove(user))
+        .is_some()
+}
+
+// Function for controllers to publish new terms; everyone has to accept them
+// again before submitting content
+#[ic_cdk::update]
+fn publish_terms(url: String) -> Result<TermsConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    if url.trim().is_empty() || url.len() > MAX_TERMS_URL_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("The terms URL must be 1-{} bytes", MAX_TERMS_URL_LEN),
+        });
+    }
+    let config = TermsConfig {
+        version: terms().version + 1,
+        url,
+        published_at: time(),
+    };
+    TERMS_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the terms config")
+    });
+    log_config("terms_config", &config);
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_terms() -> Result<TermsConfig, Error> {
+    Ok(terms())
+}
+
+// Function to accept the terms in force; the version guards against accepting
+// terms replaced since the caller read them
+#[ic_cdk::update]
+fn accept_terms(version: u32) -> Result<TermsAcceptance, Error> {
+    ensure_writable()?;
+    let user = authenticated_caller()?;
+    let terms = terms();
+    if terms.version == 0 || version != terms.version {
+        return Err(Error::InvalidInput {
+            msg: format!("Version {} of the terms is the one in force", terms.version),
+        });
+    }
+    let acceptance = TermsAcceptance {
+        version,
+        accepted_at: time(),
+    };
+    TERMS_ACCEPTANCES.with(|a| a.borrow_mut().insert(principal_key(&user), acceptance));
+    Ok(acceptance)
+}
+
+#[ic_cdk::query]
+fn get_my_terms_acceptance() -> Result<Option<TermsAcceptance>, Error> {
+    let user = authenticated_caller()?;
+    Ok(TERMS_ACCEPTANCES.with(|a| a.borrow().get(&principal_key(&user))))
+}
@@ -0,0 +1,28 @@
+use candid::Principal;
+use std::cell::{Cell, RefCell};
+
+thread_local! {
+    static CALLER: RefCell<Principal> = const { RefCell::new(Principal::anonymous()) };
+    static IS_CONTROLLER: Cell<bool> = const { Cell::new(false) };
+}
+
+// Makes the following calls of the test come from the principal
+pub(crate) fn set_caller(principal: Principal, controller: bool) {
+    CALLER.with(|c| *c.borrow_mut() = principal);
+    IS_CONTROLLER.with(|c| c.set(controller));
+}
+
+pub(crate) fn caller() -> Principal {
+    CALLER.with(|c| *c.borrow())
+}
+
+pub(crate) fn is_controller(principal: &Principal) -> bool {
+    IS_CONTROLLER.with(|c| c.get()) && *principal == caller()
+}
+
+// Blocks appended by tests are stamped at 0 and not certified
+pub(crate) fn time() -> u64 {
+    0
+}
+
+pub(crate) fn certify_data() {}
@@ -0,0 +1,256 @@
+use crate::block_log::log_upsert;
+use crate::challenge::{ensure_challenge_solved, ChallengeSolution};
+use crate::content_filter::ensure_no_rejected_terms;
+use crate::contractors::open_work_order_id;
+use crate::maintenance::ensure_writable;
+use crate::paging::{keys_after_token, page_by_size, Page};
+use crate::terms::ensure_terms_accepted;
+use crate::{ensure_green_space_exists, ensure_space_admin, get_memory, next_id, Error, Memory};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const MAINTENANCE_TICKETS_MEMORY_ID: u8 = 7;
+
+const MAX_TITLE_LEN: usize = 150;
+const MAX_DESCRIPTION_LEN: usize = 1000;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum TicketStatus {
+    Open,
+    InProgress,
+    Resolved,
+}
+
+// Where a ticket came from; generated tickets point back at their origin record
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum TicketSource {
+    Manual,
+    AccessibilityAudit { audit_id: u64 },
+    // Opened by the scheduler when equipment passes its inspection interval
+    InspectionOverdue { equipment_id: u64 },
+    // Reported through the HTTP API by the app holding the key
+    ApiReport { key_id: u64 },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MaintenanceTicket {
+    pub(crate) id: u64,
+    pub(crate) space_id: u64,
+    pub(crate) title: String,
+    pub(crate) description: String,
+    pub(crate) status: TicketStatus,
+    pub(crate) source: TicketSource,
+    pub(crate) created_at: u64,
+    pub(crate) resolved_at: Option<u64>,
+}
+
+impl_storable!(MaintenanceTicket, 2048);
+
+// Tickets of a space that were generated rather than opened by hand
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct GeneratedTicketDigest {
+    space_id: u64,
+    unresolved: Vec<MaintenanceTicket>,
+    resolved_count: u64,
+    oldest_unresolved_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct MaintenanceTicketPayload {
+    space_id: u64,
+    title: String,
+    description: String,
+}
+
+thread_local! {
+    pub(crate) static MAINTENANCE_TICKETS: RefCell<StableBTreeMap<u64, MaintenanceTicket, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(MAINTENANCE_TICKETS_MEMORY_ID)));
+}
+
+fn ticket_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A maintenance ticket with id={} not found", id),
+    }
+}
+
+fn do_insert_ticket(ticket: &MaintenanceTicket) {
+    MAINTENANCE_TICKETS.with(|s| s.borrow_mut().insert(ticket.id, ticket.clone()));
+    log_upsert("maintenance_ticket", &ticket.id, ticket);
+}
+
+pub(crate) fn validate_ticket_text(title: &str, description: &str) -> Result<(), Error> {
+    if title.trim().is_empty() || title.len() > MAX_TITLE_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Ticket title must be 1-{} characters", MAX_TITLE_LEN),
+        });
+    }
+    if description.len() > MAX_DESCRIPTION_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Ticket description must be at most {} characters",
+                MAX_DESCRIPTION_LEN
+            ),
+        });
+    }
+    ensure_no_rejected_terms(&[title, description])
+}
+
+// Opens a ticket on behalf of another subsystem; callers validate the text
+pub(crate) fn open_ticket(
+    space_id: u64,
+    title: String,
+    description: String,
+    source: TicketSource,
+) -> MaintenanceTicket {
+    let ticket = MaintenanceTicket {
+        id: next_id(MAINTENANCE_TICKETS_MEMORY_ID),
+        space_id,
+        title,
+        description,
+        status: TicketStatus::Open,
+        source,
+        created_at: time(),
+        resolved_at: None,
+    };
+    do_insert_ticket(&ticket);
+    ticket
+}
+
+pub(crate) fn tickets_matching(
+    filter: impl Fn(&MaintenanceTicket) -> bool,
+) -> Vec<MaintenanceTicket> {
+    MAINTENANCE_TICKETS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, ticket)| ticket)
+            .filter(|ticket| filter(ticket))
+            .collect()
+    })
+}
+
+// Pages through the tickets, in id order, that match
+pub(crate) fn tickets_page(
+    next_token: &Option<String>,
+    filter: impl Fn(&MaintenanceTicket) -> bool,
+) -> Result<Page<MaintenanceTicket>, Error> {
+    let range = keys_after_token::<u64>(next_token)?;
+    Ok(MAINTENANCE_TICKETS
+        .with(|s| page_by_size(s.borrow().range(range).filter(|(_, ticket)| filter(ticket)))))
+}
+
+// Resolves the unresolved tickets that match; returns the number resolved
+pub(crate) fn resolve_tickets_matching(filter: impl Fn(&MaintenanceTicket) -> bool) -> u64 {
+    let now = time();
+    let tickets =
+        tickets_matching(|ticket| ticket.status != TicketStatus::Resolved && filter(ticket));
+    for mut ticket in tickets.iter().cloned() {
+        ticket.status = TicketStatus::Resolved;
+        ticket.resolved_at = Some(now);
+        do_insert_ticket(&ticket);
+    }
+    tickets.len() as u64
+}
+
+pub(crate) fn get_ticket(id: u64) -> Result<MaintenanceTicket, Error> {
+    MAINTENANCE_TICKETS
+        .with(|s| s.borrow().get(&id))
+        .ok_or_else(|| ticket_not_found(id))
+}
+
+pub(crate) fn set_ticket_status(id: u64, status: TicketStatus) -> Result<MaintenanceTicket, Error> {
+    let mut ticket = get_ticket(id)?;
+    ticket.status = status;
+    ticket.resolved_at = match status {
+        TicketStatus::Resolved => Some(time()),
+        _ => None,
+    };
+    do_insert_ticket(&ticket);
+    Ok(ticket)
+}
+
+// Function to open a maintenance ticket for a green space; anonymous
+// reporters solve a challenge first
+#[ic_cdk::update]
+fn create_maintenance_ticket(
+    payload: MaintenanceTicketPayload,
+    challenge: Option<ChallengeSolution>,
+) -> Result<MaintenanceTicket, Error> {
+    ensure_writable()?;
+    ensure_terms_accepted()?;
+    ensure_green_space_exists(payload.space_id)?;
+    validate_ticket_text(&payload.title, &payload.description)?;
+    ensure_challenge_solved(challenge)?;
+    Ok(open_ticket(
+        payload.space_id,
+        payload.title,
+        payload.description,
+        TicketSource::Manual,
+    ))
+}
+
+#[ic_cdk::query]
+fn get_maintenance_ticket(id: u64) -> Result<MaintenanceTicket, Error> {
+    get_ticket(id)
+}
+
+#[ic_cdk::query]
+fn get_maintenance_tickets_for_space(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<MaintenanceTicket>, Error> {
+    ensure_green_space_exists(space_id)?;
+    tickets_page(&next_token, |ticket| ticket.space_id == space_id)
+}
+
+#[ic_cdk::query]
+fn get_open_maintenance_tickets(
+    next_token: Option<String>,
+) -> Result<Page<MaintenanceTicket>, Error> {
+    tickets_page(&next_token, |ticket| {
+        ticket.status != TicketStatus::Resolved
+    })
+}
+
+// Function for admins of the space to move a ticket through its workflow;
+// tickets with an open work order move with the order instead
+#[ic_cdk::update]
+fn update_maintenance_ticket_status(
+    id: u64,
+    status: TicketStatus,
+) -> Result<MaintenanceTicket, Error> {
+    ensure_writable()?;
+    let ticket = get_ticket(id)?;
+    ensure_space_admin(ticket.space_id)?;
+    if let Some(order_id) = open_work_order_id(id) {
+        return Err(Error::Conflict {
+            msg: format!(
+                "Ticket id={} follows work order id={}; complete, confirm or cancel it instead",
+                id, order_id
+            ),
+        });
+    }
+    set_ticket_status(id, status)
+}
+
+// Function to get the work generated for a space by audits and the scheduler
+#[ic_cdk::query]
+fn get_generated_ticket_digest(space_id: u64) -> Result<GeneratedTicketDigest, Error> {
+    ensure_green_space_exists(space_id)?;
+    let generated = tickets_matching(|ticket| {
+        ticket.space_id == space_id
+            && matches!(
+                ticket.source,
+                TicketSource::AccessibilityAudit { .. } | TicketSource::InspectionOverdue { .. }
+            )
+    });
+    let (resolved, unresolved): (Vec<_>, Vec<_>) = generated
+        .into_iter()
+        .partition(|ticket| ticket.status == TicketStatus::Resolved);
+    Ok(GeneratedTicketDigest {
+        space_id,
+        oldest_unresolved_at: unresolved.iter().map(|ticket| ticket.created_at).min(),
+        unresolved,
+        resolved_count: resolved.len() as u64,
+    })
+}
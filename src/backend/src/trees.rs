@@ -0,0 +1,159 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::maintenance::ensure_writable;
+use crate::paging::{group_keys_after_token, page_by_size, Page};
+use crate::{ensure_green_space_exists, ensure_space_admin, get_memory, next_id, Error, Memory};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::f64::consts::PI;
+
+const TREES_MEMORY_ID: u8 = 92;
+const TREES_BY_SPACE_MEMORY_ID: u8 = 93;
+
+const MAX_SPECIES_LEN: usize = 100;
+const MAX_CROWN_DIAMETER_M: f64 = 60.0;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Tree {
+    id: u64,
+    pub(crate) space_id: u64,
+    species: String,
+    // Average spread of the crown as surveyed
+    crown_diameter_m: f64,
+    recorded_at: u64,
+    updated_at: u64,
+}
+
+impl_storable!(Tree, 512);
+
+impl Tree {
+    // The crown taken as a circle of its diameter
+    pub(crate) fn crown_area_m2(&self) -> f64 {
+        PI * (self.crown_diameter_m / 2.0).powi(2)
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct TreePayload {
+    space_id: u64,
+    species: String,
+    crown_diameter_m: f64,
+}
+
+thread_local! {
+    pub(crate) static TREES: RefCell<StableBTreeMap<u64, Tree, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(TREES_MEMORY_ID)));
+
+    // Tree ids keyed by (space id, tree id) so a space's trees are one range scan
+    pub(crate) static TREES_BY_SPACE: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(TREES_BY_SPACE_MEMORY_ID)));
+}
+
+fn tree_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A tree with id={} not found", id),
+    }
+}
+
+pub(crate) fn trees_in_space(space_id: u64) -> Vec<Tree> {
+    let ids: Vec<u64> = TREES_BY_SPACE.with(|index| {
+        index
+            .borrow()
+            .range((space_id, 0)..=(space_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    TREES.with(|t| {
+        let trees = t.borrow();
+        ids.iter().filter_map(|id| trees.get(id)).collect()
+    })
+}
+
+fn validate_tree(payload: &TreePayload) -> Result<(), Error> {
+    if payload.species.trim().is_empty() || payload.species.len() > MAX_SPECIES_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Tree species must be 1-{} characters", MAX_SPECIES_LEN),
+        });
+    }
+    if !(payload.crown_diameter_m > 0.0 && payload.crown_diameter_m <= MAX_CROWN_DIAMETER_M) {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "The crown diameter must be above 0 and at most {} m",
+                MAX_CROWN_DIAMETER_M
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Function for admins of a space to add a tree to its inventory
+#[ic_cdk::update]
+fn add_tree(payload: TreePayload) -> Result<Tree, Error> {
+    ensure_writable()?;
+    ensure_space_admin(payload.space_id)?;
+    validate_tree(&payload)?;
+    let now = time();
+    let tree = Tree {
+        id: next_id(TREES_MEMORY_ID),
+        space_id: payload.space_id,
+        species: payload.species,
+        crown_diameter_m: payload.crown_diameter_m,
+        recorded_at: now,
+        updated_at: now,
+    };
+    TREES.with(|t| t.borrow_mut().insert(tree.id, tree.clone()));
+    TREES_BY_SPACE.with(|index| index.borrow_mut().insert((tree.space_id, tree.id), ()));
+    log_upsert("tree", &tree.id, &tree);
+    Ok(tree)
+}
+
+// Function to record a new survey of a tree's crown
+#[ic_cdk::update]
+fn update_tree_crown(id: u64, crown_diameter_m: f64) -> Result<Tree, Error> {
+    ensure_writable()?;
+    let mut tree = TREES
+        .with(|t| t.borrow().get(&id))
+        .ok_or_else(|| tree_not_found(id))?;
+    ensure_space_admin(tree.space_id)?;
+    validate_tree(&TreePayload {
+        space_id: tree.space_id,
+        species: tree.species.clone(),
+        crown_diameter_m,
+    })?;
+    tree.crown_diameter_m = crown_diameter_m;
+    tree.updated_at = time();
+    TREES.with(|t| t.borrow_mut().insert(id, tree.clone()));
+    log_upsert("tree", &id, &tree);
+    Ok(tree)
+}
+
+// Function to remove a tree that was felled or recorded by mistake
+#[ic_cdk::update]
+fn remove_tree(id: u64) -> Result<Tree, Error> {
+    ensure_writable()?;
+    let tree = TREES
+        .with(|t| t.borrow().get(&id))
+        .ok_or_else(|| tree_not_found(id))?;
+    ensure_space_admin(tree.space_id)?;
+    TREES.with(|t| t.borrow_mut().remove(&id));
+    TREES_BY_SPACE.with(|index| index.borrow_mut().remove(&(tree.space_id, id)));
+    log_delete("tree", &id);
+    Ok(tree)
+}
+
+#[ic_cdk::query]
+fn get_trees_for_space(space_id: u64, next_token: Option<String>) -> Result<Page<Tree>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let range = group_keys_after_token(space_id, &next_token)?;
+    Ok(TREES_BY_SPACE.with(|index| {
+        TREES.with(|t| {
+            let trees = t.borrow();
+            page_by_size(
+                index
+                    .borrow()
+                    .range(range)
+                    .filter_map(|((_, id), _)| trees.get(&id).map(|tree| (id, tree))),
+            )
+        })
+    }))
+}
@@ -0,0 +1,194 @@
+use crate::{_get_green_space, get_memory, Error, GreenSpace, Memory, NANOS_PER_DAY};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::f64::consts::LN_2;
+
+const TRENDING_MEMORY_ID: u8 = 90;
+const TREND_RANK_MEMORY_ID: u8 = 91;
+
+const MAX_TRENDING: u32 = 50;
+// Activity halves in weight every two days, and spaces without any for a
+// week leave the ranking
+const HALF_LIFE_DAYS: f64 = 2.0;
+const TREND_WINDOW: u64 = 7 * NANOS_PER_DAY;
+const MAX_EXPIRED_PER_RUN: usize = 1000;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Activity {
+    // A number of views
+    Views(u64),
+    CheckIn,
+    Review,
+}
+
+impl Activity {
+    fn weight(self) -> f64 {
+        match self {
+            Activity::Views(views) => views as f64,
+            Activity::CheckIn => 5.0,
+            Activity::Review => 10.0,
+        }
+    }
+}
+
+// A decayed score stored as its logarithm plus the time since the epoch in
+// decay time constants: adding activity never needs the whole ranking
+// rescaled, and the order of the stored values is the order of the scores
+// at any moment
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct TrendEntry {
+    log_score: f64,
+    last_activity_at: u64,
+}
+
+impl_storable!(TrendEntry, 64);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct TrendingSpace {
+    space: GreenSpace,
+    // Weighted activity, each item counted at half its weight per two days of
+    // age: a view 1, a check-in 5 and a published review 10
+    score: f64,
+}
+
+thread_local! {
+    static TRENDING: RefCell<StableBTreeMap<u64, TrendEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(TRENDING_MEMORY_ID)));
+
+    // Spaces by the order-preserving bits of their log score
+    static TREND_RANK: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(TREND_RANK_MEMORY_ID)));
+}
+
+fn decay_constants(at: u64) -> f64 {
+    at as f64 / NANOS_PER_DAY as f64 * LN_2 / HALF_LIFE_DAYS
+}
+
+// Maps a float to an integer that sorts the same way
+fn ordered_bits(value: f64) -> u64 {
+    let bits = value.to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | (1 << 63)
+    }
+}
+
+// ln(e^a + e^b) without overflowing
+fn log_add(a: f64, b: f64) -> f64 {
+    let (high, low) = if a > b { (a, b) } else { (b, a) };
+    high + (low - high).exp().ln_1p()
+}
+
+fn remove_entry(id: u64, entry: &TrendEntry) {
+    TRENDING.with(|t| t.borrow_mut().remove(&id));
+    TREND_RANK.with(|r| r.borrow_mut().remove(&(ordered_bits(entry.log_score), id)));
+}
+
+// Adds activity on a space to its trend
+pub(crate) fn record_activity(id: u64, activity: Activity) {
+    let weight = activity.weight();
+    if weight <= 0.0 {
+        return;
+    }
+    let now = time();
+    let added = weight.ln() + decay_constants(now);
+    let previous = TRENDING.with(|t| t.borrow().get(&id));
+    let log_score = match &previous {
+        Some(entry) => {
+            remove_entry(id, entry);
+            log_add(entry.log_score, added)
+        }
+        None => added,
+    };
+    TRENDING.with(|t| {
+        t.borrow_mut().insert(
+            id,
+            TrendEntry {
+                log_score,
+                last_activity_at: now,
+            },
+        )
+    });
+    TREND_RANK.with(|r| r.borrow_mut().insert((ordered_bits(log_score), id), ()));
+}
+
+// Forgets all activity, for when the spaces it was recorded against are
+// replaced; activity is not part of snapshots, so it cannot be rebuilt
+pub(crate) fn clear_trends() {
+    TRENDING.with(|t| {
+        let ids: Vec<u64> = t.borrow().iter().map(|(id, _)| id).collect();
+        let mut trending = t.borrow_mut();
+        for id in ids {
+            trending.remove(&id);
+        }
+    });
+    TREND_RANK.with(|r| {
+        let keys: Vec<(u64, u64)> = r.borrow().iter().map(|(key, _)| key).collect();
+        let mut rank = r.borrow_mut();
+        for key in keys {
+            rank.remove(&key);
+        }
+    });
+}
+
+// Drops the spaces without activity in the window, and deleted ones; returns
+// the number dropped
+pub(crate) fn expire_trends() -> u64 {
+    let cutoff = time().saturating_sub(TREND_WINDOW);
+    let expired: Vec<(u64, TrendEntry)> = TRENDING.with(|t| {
+        t.borrow()
+            .iter()
+            .filter(|(id, entry)| entry.last_activity_at < cutoff || _get_green_space(id).is_none())
+            .take(MAX_EXPIRED_PER_RUN)
+            .collect()
+    });
+    for (id, entry) in &expired {
+        remove_entry(*id, entry);
+    }
+    expired.len() as u64
+}
+
+// Function to get the spaces with the most activity lately: views, check-ins
+// and published reviews of the past week, recent ones counting more
+#[ic_cdk::query]
+fn get_trending(limit: u32) -> Result<Vec<TrendingSpace>, Error> {
+    if limit == 0 || limit > MAX_TRENDING {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_TRENDING),
+        });
+    }
+    let now = time();
+    let cutoff = now.saturating_sub(TREND_WINDOW);
+    let mut trending = vec![];
+    TREND_RANK.with(|r| {
+        let rank = r.borrow();
+        // Walks down from the highest score
+        let mut next = rank.last_key_value().map(|(key, _)| key);
+        while let Some(key) = next {
+            if trending.len() >= limit as usize {
+                break;
+            }
+            next = rank
+                .iter_upper_bound(&key)
+                .next()
+                .map(|(key, _)| key)
+                .filter(|lower| *lower < key);
+            let (_, id) = key;
+            let Some(entry) = TRENDING.with(|t| t.borrow().get(&id)) else {
+                continue;
+            };
+            if entry.last_activity_at < cutoff {
+                continue;
+            }
+            if let Some(space) = _get_green_space(&id) {
+                trending.push(TrendingSpace {
+                    space,
+                    score: (entry.log_score - decay_constants(now)).exp(),
+                });
+            }
+        }
+    });
+    Ok(trending)
+}
@@ -0,0 +1,145 @@
+use crate::certification::certify_space;
+use crate::{
+    _get_green_space, get_memory, next_id, Error, GreenSpace, Memory, GREEN_SPACE_STORAGE,
+};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::time;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::time::Duration;
+
+const ULID_SEED_MEMORY_ID: u8 = 65;
+const ULID_INDEX_MEMORY_ID: u8 = 66;
+
+const CROCKFORD_BASE32: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ULID_LEN: usize = 26;
+
+type Seed = Blob<32>;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct UlidKey(String);
+
+impl_storable!(UlidKey, 40);
+
+thread_local! {
+    // Randomness from raw_rand, refreshed daily, that ULIDs are derived from
+    static ULID_SEED: RefCell<Cell<Seed, Memory>> = RefCell::new(
+        Cell::init(get_memory(ULID_SEED_MEMORY_ID), Seed::default())
+            .expect("Cannot initialize the ULID seed")
+    );
+
+    // Space ids by ULID; rebuilt from the spaces when empty
+    static ULID_INDEX: RefCell<StableBTreeMap<UlidKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(ULID_INDEX_MEMORY_ID)));
+}
+
+// 48 bits of milliseconds since the epoch and 80 bits of randomness, in
+// Crockford's base 32; ULIDs sort by creation time across deployments.
+// Creating a space cannot await raw_rand, so the random part hashes the seed
+// with a counter
+pub(crate) fn new_ulid() -> String {
+    let seed = ULID_SEED.with(|s| *s.borrow().get());
+    let mut hasher = Sha256::new();
+    hasher.update(seed.as_slice());
+    hasher.update(next_id(ULID_SEED_MEMORY_ID).to_be_bytes());
+    hasher.update(time().to_be_bytes());
+    let random = hasher.finalize();
+    let mut value = u128::from(time() / 1_000_000) << 80;
+    for (i, byte) in random[..10].iter().enumerate() {
+        value |= u128::from(*byte) << (8 * (9 - i));
+    }
+    (0..ULID_LEN)
+        .rev()
+        .map(|i| CROCKFORD_BASE32[((value >> (5 * i)) & 31) as usize] as char)
+        .collect()
+}
+
+// Replaces the seed with fresh randomness
+pub(crate) async fn refresh_ulid_seed() -> Result<String, Error> {
+    let (random,) = raw_rand()
+        .await
+        .map_err(|(code, msg)| Error::OutcallFailed {
+            msg: format!("Cannot get randomness: {:?} {}", code, msg),
+        })?;
+    let seed = Seed::try_from(Sha256::digest(&random).as_slice()).unwrap();
+    ULID_SEED.with(|s| {
+        s.borrow_mut()
+            .set(seed)
+            .expect("Cannot store the ULID seed")
+    });
+    Ok("ULID seed refreshed".to_string())
+}
+
+// Seeds ULIDs right away rather than at the first scheduled refresh
+pub(crate) fn seed_ulids_soon() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            // The daily refresh retries on failure
+            let _ = refresh_ulid_seed().await;
+        })
+    });
+}
+
+pub(crate) fn index_ulid(previous: Option<&GreenSpace>, current: Option<&GreenSpace>) {
+    ULID_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(ulid) = previous.and_then(|space| space.ulid.clone()) {
+            index.remove(&UlidKey(ulid));
+        }
+        if let Some(space) = current {
+            if let Some(ulid) = space.ulid.clone() {
+                index.insert(UlidKey(ulid), space.id);
+            }
+        }
+    });
+}
+
+// Gives every space created before ULIDs one, and indexes them all when the
+// index is empty; the records are rewritten without a change-log entry, so
+// replicas learn a ULID with the next write of its space
+pub(crate) fn ensure_ulids() {
+    let missing: Vec<GreenSpace> = GREEN_SPACE_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, space)| space)
+            .filter(|space| space.ulid.is_none())
+            .collect()
+    });
+    for mut space in missing {
+        space.ulid = Some(new_ulid());
+        certify_space(space.id, Some(&space));
+        GREEN_SPACE_STORAGE.with(|s| s.borrow_mut().insert(space.id, space));
+    }
+    if ULID_INDEX.with(|index| index.borrow().is_empty()) {
+        rebuild_ulid_index();
+    }
+}
+
+pub(crate) fn rebuild_ulid_index() {
+    ULID_INDEX.with(|index| {
+        let keys: Vec<UlidKey> = index.borrow().iter().map(|(key, _)| key).collect();
+        let mut index = index.borrow_mut();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    GREEN_SPACE_STORAGE.with(|s| {
+        for (_, space) in s.borrow().iter() {
+            index_ulid(None, Some(&space));
+        }
+    });
+}
+
+// Function to get a green space by its ULID, which stays unique when data of
+// several deployments is merged
+#[ic_cdk::query]
+fn get_green_space_by_ulid(ulid: String) -> Result<GreenSpace, Error> {
+    ULID_INDEX
+        .with(|index| index.borrow().get(&UlidKey(ulid.to_uppercase())))
+        .and_then(|id| _get_green_space(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("A green space with ulid={} not found", ulid),
+        })
+}
@@ -0,0 +1,166 @@
+use crate::block_log::log_config;
+use crate::geo::BoundingBox;
+use crate::maintenance::ensure_writable;
+use crate::{
+    ensure_controller, get_memory, Error, GreenSpace, GreenSpaceCategory, GreenSpaceUpdatePayload,
+    Memory,
+};
+use ic_stable_structures::{BoundedStorable, Cell};
+use std::cell::RefCell;
+
+const VALIDATION_CONFIG_MEMORY_ID: u8 = 44;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) enum RequiredField {
+    Name,
+    Location,
+    Description,
+    Coordinates,
+    Category,
+    District,
+}
+
+// Rules every added or updated green space is checked against
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ValidationConfig {
+    max_name_len: u32,
+    max_location_len: u32,
+    max_description_len: u32,
+    required_fields: Vec<RequiredField>,
+    // None allows every category
+    allowed_categories: Option<Vec<GreenSpaceCategory>>,
+    // Coordinates must lie inside, e.g. the area the deployment serves
+    coordinate_bounds: Option<BoundingBox>,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            max_name_len: 200,
+            max_location_len: 300,
+            max_description_len: 500,
+            required_fields: vec![],
+            allowed_categories: None,
+            coordinate_bounds: None,
+        }
+    }
+}
+
+impl_storable!(ValidationConfig, 1024);
+
+thread_local! {
+    static VALIDATION_CONFIG: RefCell<Cell<ValidationConfig, Memory>> = RefCell::new(
+        Cell::init(get_memory(VALIDATION_CONFIG_MEMORY_ID), ValidationConfig::default())
+            .expect("Cannot initialize the validation config")
+    );
+}
+
+pub(crate) fn validation_config() -> ValidationConfig {
+    VALIDATION_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+fn validate_text_len(field: &str, value: &str, max_len: u32) -> Result<(), Error> {
+    if value.len() > max_len as usize {
+        return Err(Error::InvalidInput {
+            msg: format!("The {} must be at most {} bytes", field, max_len),
+        });
+    }
+    Ok(())
+}
+
+fn is_set(payload: &GreenSpaceUpdatePayload, field: RequiredField) -> bool {
+    match field {
+        RequiredField::Name => !payload.name.trim().is_empty(),
+        RequiredField::Location => !payload.location.trim().is_empty(),
+        RequiredField::Description => !payload.description.trim().is_empty(),
+        RequiredField::Coordinates => payload.coordinates.is_some(),
+        RequiredField::Category => payload.category.is_some(),
+        RequiredField::District => payload.district_id.is_some(),
+    }
+}
+
+// Checks a payload against the configured rules
+pub(crate) fn validate_against_config(payload: &GreenSpaceUpdatePayload) -> Result<(), Error> {
+    let config = validation_config();
+    validate_text_len("name", &payload.name, config.max_name_len)?;
+    validate_text_len("location", &payload.location, config.max_location_len)?;
+    validate_text_len(
+        "description",
+        &payload.description,
+        config.max_description_len,
+    )?;
+    if let Some(field) = config
+        .required_fields
+        .iter()
+        .find(|field| !is_set(payload, **field))
+    {
+        return Err(Error::InvalidInput {
+            msg: format!("The {:?} of a green space is required", field),
+        });
+    }
+    if let (Some(allowed), Some(category)) = (&config.allowed_categories, payload.category) {
+        if !allowed.contains(&category) {
+            return Err(Error::InvalidInput {
+                msg: format!("The category {:?} is not allowed", category),
+            });
+        }
+    }
+    if let (Some(bounds), Some(coordinates)) = (&config.coordinate_bounds, &payload.coordinates) {
+        if !bounds.contains(coordinates) {
+            return Err(Error::InvalidInput {
+                msg: "The coordinates lie outside the area this canister serves".to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn unique<T: PartialEq>(items: Vec<T>) -> Vec<T> {
+    let mut unique = Vec::new();
+    for item in items {
+        if !unique.contains(&item) {
+            unique.push(item);
+        }
+    }
+    unique
+}
+
+// Function to change the validation rules without an upgrade; spaces stored
+// before a change are not rechecked
+#[ic_cdk::update]
+fn update_validation_config(config: ValidationConfig) -> Result<ValidationConfig, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut config = config;
+    config.required_fields = unique(config.required_fields);
+    config.allowed_categories = config.allowed_categories.map(unique);
+    // No length may exceed what a stored record can hold
+    let max_len = GreenSpace::MAX_SIZE;
+    if [
+        config.max_name_len,
+        config.max_location_len,
+        config.max_description_len,
+    ]
+    .into_iter()
+    .any(|len| len == 0 || len > max_len)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!("Maximum lengths must be between 1 and {} bytes", max_len),
+        });
+    }
+    if let Some(bounds) = &config.coordinate_bounds {
+        bounds.validate()?;
+    }
+    VALIDATION_CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the validation config")
+    });
+    log_config("validation_config", &config);
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_validation_config() -> Result<ValidationConfig, Error> {
+    Ok(validation_config())
+}
@@ -0,0 +1,143 @@
+use crate::cycles::conserving_cycles;
+use crate::maintenance::ensure_writable;
+use crate::trending::{record_activity, Activity};
+use crate::{_get_green_space, get_memory, Error, Memory, NANOS_PER_DAY};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const VIEW_COUNTS_MEMORY_ID: u8 = 39;
+
+const MAX_RECORDED_VIEWS: usize = 100;
+const MAX_MOST_VIEWED: u32 = 100;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum ViewPeriod {
+    // The current UTC day
+    Day,
+    // The last 7 days, today included
+    Week,
+    // The last 30 days, today included
+    Month,
+    AllTime,
+}
+
+impl ViewPeriod {
+    // The first day since the epoch the period covers
+    pub(crate) fn first_day(self, today: u64) -> u64 {
+        match self {
+            ViewPeriod::Day => today,
+            ViewPeriod::Week => today.saturating_sub(6),
+            ViewPeriod::Month => today.saturating_sub(29),
+            ViewPeriod::AllTime => 0,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SpaceViews {
+    space_id: u64,
+    name: String,
+    views: u64,
+}
+
+thread_local! {
+    // Views since the last flush, by space; kept on the heap so counting a
+    // view costs no stable memory write
+    static PENDING_VIEWS: RefCell<BTreeMap<u64, u64>> = const { RefCell::new(BTreeMap::new()) };
+
+    // Flushed views keyed by (day since the epoch, space id)
+    pub(crate) static VIEW_COUNTS: RefCell<StableBTreeMap<(u64, u64), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(VIEW_COUNTS_MEMORY_ID)));
+}
+
+pub(crate) fn today() -> u64 {
+    time() / NANOS_PER_DAY
+}
+
+// Counts a view of a space; query calls discard it along with every other
+// state change, so only views made through update calls are kept; none are
+// counted while cycles are low
+pub(crate) fn count_view(id: u64) {
+    if conserving_cycles() {
+        return;
+    }
+    PENDING_VIEWS.with(|p| *p.borrow_mut().entry(id).or_insert(0) += 1);
+}
+
+pub(crate) fn discard_pending_views() {
+    PENDING_VIEWS.with(|p| p.borrow_mut().clear());
+}
+
+// Adds the pending views to today's counts; returns the number of spaces flushed
+pub(crate) fn flush_view_counts() -> u64 {
+    let pending = PENDING_VIEWS.with(|p| std::mem::take(&mut *p.borrow_mut()));
+    let day = today();
+    VIEW_COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        for (&id, &views) in &pending {
+            let total = counts.get(&(day, id)).unwrap_or(0).saturating_add(views);
+            counts.insert((day, id), total);
+            record_activity(id, Activity::Views(views));
+        }
+    });
+    pending.len() as u64
+}
+
+// Views of each space from a day on, including views not flushed yet
+pub(crate) fn views_since(first_day: u64) -> BTreeMap<u64, u64> {
+    let mut totals: BTreeMap<u64, u64> = PENDING_VIEWS.with(|p| p.borrow().clone());
+    VIEW_COUNTS.with(|counts| {
+        for ((_, id), views) in counts.borrow().range((first_day, 0)..) {
+            *totals.entry(id).or_insert(0) += views;
+        }
+    });
+    totals
+}
+
+// Function for clients to report the spaces they displayed, batched, since
+// views read through queries cannot be counted
+#[ic_cdk::update]
+fn record_green_space_views(ids: Vec<u64>) -> Result<(), Error> {
+    ensure_writable()?;
+    if ids.len() > MAX_RECORDED_VIEWS {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "At most {} views can be recorded at once",
+                MAX_RECORDED_VIEWS
+            ),
+        });
+    }
+    for id in ids {
+        if _get_green_space(&id).is_some() {
+            count_view(id);
+        }
+    }
+    Ok(())
+}
+
+// Function to rank the spaces by their views in a period, including views not
+// flushed yet
+#[ic_cdk::query]
+fn get_most_viewed(period: ViewPeriod, limit: u32) -> Result<Vec<SpaceViews>, Error> {
+    if limit == 0 || limit > MAX_MOST_VIEWED {
+        return Err(Error::InvalidInput {
+            msg: format!("The limit must be between 1 and {}", MAX_MOST_VIEWED),
+        });
+    }
+    let totals = views_since(period.first_day(today()));
+    let mut ranked: Vec<(u64, u64)> = totals.into_iter().collect();
+    ranked.sort_by_key(|&(id, views)| (std::cmp::Reverse(views), id));
+    Ok(ranked
+        .into_iter()
+        .filter_map(|(space_id, views)| {
+            _get_green_space(&space_id).map(|space| SpaceViews {
+                space_id,
+                name: space.name,
+                views,
+            })
+        })
+        .take(limit as usize)
+        .collect())
+}
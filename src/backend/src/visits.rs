@@ -0,0 +1,112 @@
+use crate::maintenance::ensure_writable;
+use crate::trending::{record_activity, Activity};
+use crate::{
+    _get_green_space, authenticated_caller, ensure_green_space_exists, get_memory, principal_key,
+    Error, Memory, PrincipalKey,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const VISITS_MEMORY_ID: u8 = 88;
+
+const MAX_VISITED_SPACES: usize = 1000;
+// Repeated check-ins within this are one visit
+const MIN_CHECK_IN_INTERVAL: u64 = 60 * 60 * 1_000_000_000;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct Visit {
+    first_visited_at: u64,
+    pub(crate) last_visited_at: u64,
+    pub(crate) visits: u64,
+}
+
+impl_storable!(Visit, 64);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct VisitedSpace {
+    space_id: u64,
+    name: String,
+    visit: Visit,
+}
+
+thread_local! {
+    // Each user's visits of each space, keyed by (user, space id); entries of
+    // deleted spaces are skipped on read
+    pub(crate) static VISITS: RefCell<StableBTreeMap<(PrincipalKey, u64), Visit, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(VISITS_MEMORY_ID)));
+}
+
+// The spaces a user has visited with their visits, deleted ones included
+pub(crate) fn visits_of(user: &Principal) -> Vec<(u64, Visit)> {
+    let key = principal_key(user);
+    VISITS.with(|v| {
+        v.borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, id), visit)| (id, visit))
+            .collect()
+    })
+}
+
+// Function for a user to check in at a space they are visiting
+#[ic_cdk::update]
+fn check_in(space_id: u64) -> Result<Visit, Error> {
+    ensure_writable()?;
+    let user = authenticated_caller()?;
+    ensure_green_space_exists(space_id)?;
+    let key = (principal_key(&user), space_id);
+    let now = time();
+    let visit = match VISITS.with(|v| v.borrow().get(&key)) {
+        Some(visit) if now.saturating_sub(visit.last_visited_at) < MIN_CHECK_IN_INTERVAL => {
+            return Ok(visit)
+        }
+        Some(visit) => Visit {
+            last_visited_at: now,
+            visits: visit.visits + 1,
+            ..visit
+        },
+        None => {
+            if visits_of(&user).len() >= MAX_VISITED_SPACES {
+                return Err(Error::InvalidInput {
+                    msg: format!("Visits of at most {} spaces are kept", MAX_VISITED_SPACES),
+                });
+            }
+            Visit {
+                first_visited_at: now,
+                last_visited_at: now,
+                visits: 1,
+            }
+        }
+    };
+    VISITS.with(|v| v.borrow_mut().insert(key, visit));
+    record_activity(space_id, Activity::CheckIn);
+    Ok(visit)
+}
+
+// Function for a user to forget their visits of a space
+#[ic_cdk::update]
+fn forget_visit(space_id: u64) -> Result<(), Error> {
+    ensure_writable()?;
+    let user = authenticated_caller()?;
+    VISITS.with(|v| v.borrow_mut().remove(&(principal_key(&user), space_id)));
+    Ok(())
+}
+
+// Function to list the spaces the caller has visited, most recent first
+#[ic_cdk::query]
+fn get_my_visits() -> Result<Vec<VisitedSpace>, Error> {
+    let user = authenticated_caller()?;
+    let mut visited: Vec<VisitedSpace> = visits_of(&user)
+        .into_iter()
+        .filter_map(|(space_id, visit)| {
+            _get_green_space(&space_id).map(|space| VisitedSpace {
+                space_id,
+                name: space.name,
+                visit,
+            })
+        })
+        .collect();
+    visited.sort_by_key(|v| std::cmp::Reverse(v.visit.last_visited_at));
+    Ok(visited)
+}
@@ -0,0 +1,373 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::maintenance::ensure_writable;
+use crate::paging::{group_keys_after_token, keys_after_token, page_by_size, Page};
+use crate::{
+    ensure_green_space_exists, ensure_space_admin, get_memory, next_id, Error, Memory,
+    NANOS_PER_DAY,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const WATER_FEATURES_MEMORY_ID: u8 = 3;
+const WATER_QUALITY_TESTS_MEMORY_ID: u8 = 4;
+
+// A quality test older than this no longer vouches for a feature (30 days)
+const WATER_TEST_VALIDITY_NS: u64 = 30 * NANOS_PER_DAY;
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_NOTES_LEN: usize = 300;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum WaterFeatureKind {
+    Pond,
+    Fountain,
+    SplashPad,
+    SwimmingArea,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum OperationalStatus {
+    Operational,
+    UnderMaintenance,
+    Closed,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum WaterQualityResult {
+    Safe,
+    Advisory,
+    Unsafe,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WaterFeature {
+    id: u64,
+    pub(crate) space_id: u64,
+    name: String,
+    kind: WaterFeatureKind,
+    status: OperationalStatus,
+    // Whether people are allowed in the water (swimming, wading, splashing)
+    contact_allowed: bool,
+    created_at: u64,
+    updated_at: u64,
+}
+
+impl_storable!(WaterFeature, 512);
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WaterQualityTest {
+    id: u64,
+    feature_id: u64,
+    tested_at: u64,
+    result: WaterQualityResult,
+    ph: Option<f64>,
+    e_coli_cfu_per_100ml: Option<f64>,
+    notes: String,
+}
+
+impl_storable!(WaterQualityTest, 512);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct WaterFeaturePayload {
+    space_id: u64,
+    name: String,
+    kind: WaterFeatureKind,
+    status: OperationalStatus,
+    contact_allowed: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct WaterQualityTestPayload {
+    result: WaterQualityResult,
+    ph: Option<f64>,
+    e_coli_cfu_per_100ml: Option<f64>,
+    notes: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SafeWaterLocation {
+    feature: WaterFeature,
+    latest_test: WaterQualityTest,
+}
+
+thread_local! {
+    pub(crate) static WATER_FEATURES: RefCell<StableBTreeMap<u64, WaterFeature, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(WATER_FEATURES_MEMORY_ID)));
+
+    // Tests keyed by (feature id, test id) so a feature's history is one range scan
+    pub(crate) static WATER_QUALITY_TESTS: RefCell<StableBTreeMap<(u64, u64), WaterQualityTest, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(WATER_QUALITY_TESTS_MEMORY_ID)));
+}
+
+fn water_feature_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A water feature with id={} not found", id),
+    }
+}
+
+fn _get_water_feature(id: &u64) -> Option<WaterFeature> {
+    WATER_FEATURES.with(|s| s.borrow().get(id))
+}
+
+fn do_insert_water_feature(feature: &WaterFeature) {
+    WATER_FEATURES.with(|s| s.borrow_mut().insert(feature.id, feature.clone()));
+    log_upsert("water_feature", &feature.id, feature);
+}
+
+fn latest_test_for_feature(feature_id: u64) -> Option<WaterQualityTest> {
+    WATER_QUALITY_TESTS.with(|s| {
+        s.borrow()
+            .range((feature_id, 0)..=(feature_id, u64::MAX))
+            .map(|(_, test)| test)
+            .last()
+    })
+}
+
+fn validate_feature_name(name: &str) -> Result<(), Error> {
+    if name.trim().is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Water feature name must be 1-{} characters", MAX_NAME_LEN),
+        });
+    }
+    Ok(())
+}
+
+fn validate_quality_test(payload: &WaterQualityTestPayload) -> Result<(), Error> {
+    if payload.notes.len() > MAX_NOTES_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Test notes must be at most {} characters", MAX_NOTES_LEN),
+        });
+    }
+    if let Some(ph) = payload.ph {
+        if !(0.0..=14.0).contains(&ph) {
+            return Err(Error::InvalidInput {
+                msg: format!("pH must be between 0 and 14, got {}", ph),
+            });
+        }
+    }
+    if let Some(count) = payload.e_coli_cfu_per_100ml {
+        if !count.is_finite() || count < 0.0 {
+            return Err(Error::InvalidInput {
+                msg: format!("E. coli count must be a non-negative number, got {}", count),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Function for admins of a green space to register a water feature in it
+#[ic_cdk::update]
+fn add_water_feature(payload: WaterFeaturePayload) -> Result<WaterFeature, Error> {
+    ensure_writable()?;
+    ensure_space_admin(payload.space_id)?;
+    validate_feature_name(&payload.name)?;
+
+    let now = time();
+    let feature = WaterFeature {
+        id: next_id(WATER_FEATURES_MEMORY_ID),
+        space_id: payload.space_id,
+        name: payload.name,
+        kind: payload.kind,
+        status: payload.status,
+        contact_allowed: payload.contact_allowed,
+        created_at: now,
+        updated_at: now,
+    };
+
+    do_insert_water_feature(&feature);
+    Ok(feature)
+}
+
+#[ic_cdk::query]
+fn get_water_feature(id: u64) -> Result<WaterFeature, Error> {
+    _get_water_feature(&id).ok_or_else(|| water_feature_not_found(id))
+}
+
+#[ic_cdk::query]
+fn get_water_features_for_space(
+    space_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<WaterFeature>, Error> {
+    ensure_green_space_exists(space_id)?;
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(WATER_FEATURES.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, feature)| feature.space_id == space_id),
+        )
+    }))
+}
+
+// Function to change whether a water feature is running
+#[ic_cdk::update]
+fn update_water_feature_status(id: u64, status: OperationalStatus) -> Result<WaterFeature, Error> {
+    ensure_writable()?;
+    let mut feature = _get_water_feature(&id).ok_or_else(|| water_feature_not_found(id))?;
+    ensure_space_admin(feature.space_id)?;
+    feature.status = status;
+    feature.updated_at = time();
+    do_insert_water_feature(&feature);
+    Ok(feature)
+}
+
+// Function to delete a water feature together with its test history
+#[ic_cdk::update]
+fn delete_water_feature(id: u64) -> Result<WaterFeature, Error> {
+    ensure_writable()?;
+    let feature = _get_water_feature(&id).ok_or_else(|| water_feature_not_found(id))?;
+    ensure_space_admin(feature.space_id)?;
+    WATER_FEATURES.with(|s| s.borrow_mut().remove(&id));
+    log_delete("water_feature", &id);
+
+    WATER_QUALITY_TESTS.with(|s| {
+        let mut tests = s.borrow_mut();
+        let keys: Vec<_> = tests
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            tests.remove(&key);
+            log_delete("water_quality_test", &key);
+        }
+    });
+    Ok(feature)
+}
+
+// Function to record the result of a water quality test
+#[ic_cdk::update]
+fn record_water_quality_test(
+    feature_id: u64,
+    payload: WaterQualityTestPayload,
+) -> Result<WaterQualityTest, Error> {
+    ensure_writable()?;
+    let feature =
+        _get_water_feature(&feature_id).ok_or_else(|| water_feature_not_found(feature_id))?;
+    ensure_space_admin(feature.space_id)?;
+    validate_quality_test(&payload)?;
+
+    let test = WaterQualityTest {
+        id: next_id(WATER_QUALITY_TESTS_MEMORY_ID),
+        feature_id,
+        tested_at: time(),
+        result: payload.result,
+        ph: payload.ph,
+        e_coli_cfu_per_100ml: payload.e_coli_cfu_per_100ml,
+        notes: payload.notes,
+    };
+
+    WATER_QUALITY_TESTS.with(|s| s.borrow_mut().insert((feature_id, test.id), test.clone()));
+    log_upsert("water_quality_test", &(feature_id, test.id), &test);
+    Ok(test)
+}
+
+// Function to get the quality test history of a water feature, oldest first
+#[ic_cdk::query]
+fn get_water_quality_history(
+    feature_id: u64,
+    next_token: Option<String>,
+) -> Result<Page<WaterQualityTest>, Error> {
+    if _get_water_feature(&feature_id).is_none() {
+        return Err(water_feature_not_found(feature_id));
+    }
+    let range = group_keys_after_token(feature_id, &next_token)?;
+    Ok(WATER_QUALITY_TESTS
+        .with(|s| page_by_size(s.borrow().range(range).map(|((_, id), test)| (id, test)))))
+}
+
+// Function to list the water features people can currently get into: operational,
+// open for contact, and with a recent passing quality test
+#[ic_cdk::query]
+fn get_safe_water_locations(next_token: Option<String>) -> Result<Page<SafeWaterLocation>, Error> {
+    let now = time();
+    let range = keys_after_token::<u64>(&next_token)?;
+    Ok(WATER_FEATURES.with(|s| {
+        page_by_size(
+            s.borrow()
+                .range(range)
+                .filter(|(_, feature)| {
+                    feature.contact_allowed && feature.status == OperationalStatus::Operational
+                })
+                .filter_map(|(id, feature)| {
+                    let latest_test = latest_test_for_feature(feature.id)?;
+                    let is_recent =
+                        now.saturating_sub(latest_test.tested_at) <= WATER_TEST_VALIDITY_NS;
+                    if latest_test.result == WaterQualityResult::Safe && is_recent {
+                        Some((
+                            id,
+                            SafeWaterLocation {
+                                feature,
+                                latest_test,
+                            },
+                        ))
+                    } else {
+                        None
+                    }
+                }),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cities::insert_test_city;
+    use crate::test_caller::set_caller;
+    use crate::{GreenSpace, GREEN_SPACE_STORAGE};
+    use candid::Principal;
+
+    #[test]
+    fn admins_of_another_city_cannot_write_water_records() {
+        let admin = Principal::from_slice(&[1]);
+        let other_admin = Principal::from_slice(&[2]);
+        insert_test_city(1, vec![admin]);
+        insert_test_city(2, vec![other_admin]);
+        GREEN_SPACE_STORAGE.with(|s| {
+            s.borrow_mut().insert(
+                1,
+                GreenSpace {
+                    id: 1,
+                    city_id: Some(1),
+                    ..Default::default()
+                },
+            )
+        });
+        do_insert_water_feature(&WaterFeature {
+            id: 1,
+            space_id: 1,
+            name: "Lido".to_string(),
+            kind: WaterFeatureKind::SwimmingArea,
+            status: OperationalStatus::Closed,
+            contact_allowed: true,
+            created_at: 0,
+            updated_at: 0,
+        });
+
+        set_caller(other_admin, false);
+        let safe_test = WaterQualityTestPayload {
+            result: WaterQualityResult::Safe,
+            ph: Some(7.0),
+            e_coli_cfu_per_100ml: Some(0.0),
+            notes: String::new(),
+        };
+        assert!(matches!(
+            record_water_quality_test(1, safe_test),
+            Err(Error::Unauthorized { .. })
+        ));
+        assert!(matches!(
+            update_water_feature_status(1, OperationalStatus::Operational),
+            Err(Error::Unauthorized { .. })
+        ));
+        assert!(matches!(
+            delete_water_feature(1),
+            Err(Error::Unauthorized { .. })
+        ));
+        assert!(WATER_QUALITY_TESTS.with(|s| s
+            .borrow()
+            .range((1, 0)..=(1, u64::MAX))
+            .next()
+            .is_none()));
+        assert!(_get_water_feature(&1).is_some_and(|f| f.status == OperationalStatus::Closed));
+    }
+}
@@ -0,0 +1,250 @@
+use crate::block_log::{log_delete, log_upsert};
+use crate::changelog::{changes_after, count_changes_after, last_change_seq, ChangeLogEntry};
+use crate::cycles::conserving_cycles;
+use crate::maintenance::ensure_writable;
+use crate::outcalls::{canonical_response, http_post_json};
+use crate::{ensure_controller, get_memory, next_id, Error, Memory};
+use candid::Principal;
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const WEBHOOKS_MEMORY_ID: u8 = 29;
+
+const WEBHOOK_BATCH_SIZE: usize = 50;
+const MAX_WEBHOOKS: u64 = 20;
+const MAX_URL_LEN: usize = 500;
+const MAX_RESPONSE_BYTES: u64 = 16 * 1024;
+// Retries wait 30s, 1m, 2m, ... up to about two hours
+const BASE_RETRY_DELAY_NANOS: u64 = 30 * 1_000_000_000;
+const MAX_RETRY_DOUBLINGS: u32 = 8;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Webhook {
+    id: u64,
+    url: String,
+    created_by: Principal,
+    created_at: u64,
+    active: bool,
+    // Sequence number of the last change-log entry the endpoint accepted
+    last_delivered_seq: Option<u64>,
+    last_delivered_at: Option<u64>,
+    consecutive_failures: u32,
+    next_attempt_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+impl_storable!(Webhook, 2048);
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct WebhookDeliveryStatus {
+    webhook: Webhook,
+    pending_entries: u64,
+}
+
+// Body of a delivery
+#[derive(Serialize)]
+struct WebhookDelivery {
+    webhook_id: u64,
+    entries: Vec<ChangeLogEntry>,
+}
+
+thread_local! {
+    static WEBHOOKS: RefCell<StableBTreeMap<u64, Webhook, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(WEBHOOKS_MEMORY_ID)));
+}
+
+fn webhook_not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("A webhook with id={} not found", id),
+    }
+}
+
+fn _get_webhook(id: &u64) -> Option<Webhook> {
+    WEBHOOKS.with(|s| s.borrow().get(id))
+}
+
+// First change-log entry some webhook, paused or not, has yet to accept
+pub(crate) fn first_undelivered_seq() -> Option<u64> {
+    WEBHOOKS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, webhook)| webhook.last_delivered_seq.map_or(0, |seq| seq + 1))
+            .min()
+    })
+}
+
+fn do_insert_webhook(webhook: &Webhook) {
+    WEBHOOKS.with(|s| s.borrow_mut().insert(webhook.id, webhook.clone()));
+    log_upsert("webhook", &webhook.id, webhook);
+}
+
+pub(crate) fn validate_webhook_url(url: &str) -> Result<(), Error> {
+    if !url.starts_with("https://") || url.len() > MAX_URL_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Webhook URLs must start with https:// and be at most {} characters",
+                MAX_URL_LEN
+            ),
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn retry_delay_nanos(consecutive_failures: u32) -> u64 {
+    BASE_RETRY_DELAY_NANOS
+        << consecutive_failures
+            .saturating_sub(1)
+            .min(MAX_RETRY_DOUBLINGS)
+}
+
+// Delivers the next batch of changes to one webhook and records the outcome;
+// returns whether a batch was delivered
+async fn deliver(webhook: Webhook) -> bool {
+    let entries = changes_after(webhook.last_delivered_seq, WEBHOOK_BATCH_SIZE);
+    let (Some(first), Some(last)) = (
+        entries.first().map(|e| e.seq),
+        entries.last().map(|e| e.seq),
+    ) else {
+        return false;
+    };
+    let body = serde_json::to_vec(&WebhookDelivery {
+        webhook_id: webhook.id,
+        entries,
+    })
+    .expect("Cannot encode a webhook delivery");
+    let result = http_post_json(
+        webhook.url.clone(),
+        body,
+        format!("{}-{}-{}", webhook.id, first, last),
+        MAX_RESPONSE_BYTES,
+        "transform_webhook_response",
+    )
+    .await;
+
+    // The webhook may have been changed or removed while awaiting
+    let Some(mut current) = _get_webhook(&webhook.id) else {
+        return false;
+    };
+    if current.url != webhook.url || current.last_delivered_seq != webhook.last_delivered_seq {
+        return false;
+    }
+    let now = time();
+    let delivered = result.is_ok();
+    match result {
+        Ok(()) => {
+            current.last_delivered_seq = Some(last);
+            current.last_delivered_at = Some(now);
+            current.consecutive_failures = 0;
+            current.next_attempt_at = None;
+            current.last_error = None;
+        }
+        Err(error) => {
+            current.consecutive_failures += 1;
+            current.next_attempt_at = Some(now + retry_delay_nanos(current.consecutive_failures));
+            current.last_error = Some(error.message().to_string());
+        }
+    }
+    // Delivery progress is bookkeeping, not a change to the webhook; it is not logged
+    WEBHOOKS.with(|s| s.borrow_mut().insert(current.id, current));
+    delivered
+}
+
+// Delivers pending changes to every active webhook that is not waiting for a
+// retry; returns the number of batches delivered. Deliveries pause while
+// cycles are low, and resume from where they stopped
+pub(crate) async fn deliver_due_webhooks() -> u64 {
+    if conserving_cycles() {
+        return 0;
+    }
+    let now = time();
+    let due: Vec<Webhook> = WEBHOOKS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, webhook)| webhook)
+            .filter(|webhook| webhook.active && webhook.next_attempt_at.is_none_or(|at| at <= now))
+            .collect()
+    });
+    let mut delivered = 0;
+    for webhook in due {
+        if deliver(webhook).await {
+            delivered += 1;
+        }
+    }
+    delivered
+}
+
+// Function to register an endpoint that receives every change from now on, as
+// JSON batches POSTed with an Idempotency-Key header
+#[ic_cdk::update]
+fn register_webhook(url: String) -> Result<Webhook, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    validate_webhook_url(&url)?;
+    if WEBHOOKS.with(|s| s.borrow().len()) >= MAX_WEBHOOKS {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} webhooks can be registered", MAX_WEBHOOKS),
+        });
+    }
+    let webhook = Webhook {
+        id: next_id(WEBHOOKS_MEMORY_ID),
+        url,
+        created_by: ic_cdk::caller(),
+        created_at: time(),
+        active: true,
+        // Past changes are not delivered; a new endpoint is expected to start from
+        // an export
+        last_delivered_seq: last_change_seq(),
+        last_delivered_at: None,
+        consecutive_failures: 0,
+        next_attempt_at: None,
+        last_error: None,
+    };
+    do_insert_webhook(&webhook);
+    Ok(webhook)
+}
+
+// Function to pause or resume deliveries; resuming retries right away
+#[ic_cdk::update]
+fn set_webhook_active(id: u64, active: bool) -> Result<Webhook, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let mut webhook = _get_webhook(&id).ok_or_else(|| webhook_not_found(id))?;
+    webhook.active = active;
+    webhook.next_attempt_at = None;
+    do_insert_webhook(&webhook);
+    Ok(webhook)
+}
+
+#[ic_cdk::update]
+fn delete_webhook(id: u64) -> Result<Webhook, Error> {
+    ensure_writable()?;
+    ensure_controller()?;
+    let webhook = WEBHOOKS
+        .with(|s| s.borrow_mut().remove(&id))
+        .ok_or_else(|| webhook_not_found(id))?;
+    log_delete("webhook", &id);
+    Ok(webhook)
+}
+
+// Function to get every webhook with the number of changes it has yet to receive
+#[ic_cdk::query]
+fn get_webhook_delivery_status() -> Result<Vec<WebhookDeliveryStatus>, Error> {
+    ensure_controller()?;
+    Ok(WEBHOOKS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, webhook)| WebhookDeliveryStatus {
+                pending_entries: count_changes_after(webhook.last_delivered_seq),
+                webhook,
+            })
+            .collect()
+    }))
+}
+
+// Endpoints answer differently on every replica; only the status matters
+#[ic_cdk::query]
+fn transform_webhook_response(args: TransformArgs) -> HttpResponse {
+    canonical_response(&args.response, vec![])
+}
@@ -0,0 +1,124 @@
+use crate::geo::{Coordinates, Polygon};
+use crate::Error;
+
+// A minimal reader for the WKT subset we accept: a single two-dimensional
+// POLYGON with an exterior ring and optional holes
+struct WktReader<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> WktReader<'a> {
+    fn error(&self, msg: &str) -> Error {
+        Error::InvalidGeometry {
+            msg: format!("Malformed WKT at position {}: {}", self.pos, msg),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.rest().chars().next()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += c.len_utf8();
+                Ok(())
+            }
+            Some(c) => Err(self.error(&format!("expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(&format!("expected '{}', found end of input", expected))),
+        }
+    }
+
+    fn word(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn number(&mut self) -> Result<f64, Error> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E')))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a number"));
+        }
+        let value = rest[..len]
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| self.error(&format!("'{}' is not a valid number", &rest[..len])))?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    // WKT orders each vertex as "x y", i.e. longitude before latitude
+    fn ring(&mut self) -> Result<Vec<Coordinates>, Error> {
+        self.expect('(')?;
+        let mut ring = Vec::new();
+        loop {
+            let lng = self.number()?;
+            let lat = self.number()?;
+            ring.push(Coordinates { lat, lng });
+            match self.peek() {
+                Some(',') => self.expect(',')?,
+                Some(')') => break,
+                Some(c) if c.is_ascii_digit() || matches!(c, '+' | '-' | '.') => {
+                    return Err(self.error("only two-dimensional coordinates are supported"))
+                }
+                _ => return Err(self.error("expected ',' or ')' after a vertex")),
+            }
+        }
+        self.expect(')')?;
+        Ok(ring)
+    }
+}
+
+// Parses and validates a `POLYGON((lng lat, ...), (hole...))` string
+pub(crate) fn parse_wkt_polygon(wkt: &str) -> Result<Polygon, Error> {
+    let mut reader = WktReader { input: wkt, pos: 0 };
+    let kind = reader.word();
+    if !kind.eq_ignore_ascii_case("POLYGON") {
+        return Err(reader.error(&format!("expected POLYGON, found '{}'", kind)));
+    }
+    if reader.peek() != Some('(') {
+        let modifier = reader.word();
+        return Err(reader.error(&match modifier.to_ascii_uppercase().as_str() {
+            "EMPTY" => "empty polygons cannot be used as a boundary".to_string(),
+            "Z" | "M" | "ZM" => "only two-dimensional coordinates are supported".to_string(),
+            _ => "expected '(' after POLYGON".to_string(),
+        }));
+    }
+
+    reader.expect('(')?;
+    let exterior = reader.ring()?;
+    let mut holes = Vec::new();
+    while reader.peek() == Some(',') {
+        reader.expect(',')?;
+        holes.push(reader.ring()?);
+    }
+    reader.expect(')')?;
+    if reader.peek().is_some() {
+        return Err(reader.error("unexpected text after the polygon"));
+    }
+
+    let polygon = Polygon { exterior, holes };
+    polygon.validate()?;
+    Ok(polygon)
+}